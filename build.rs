@@ -0,0 +1,23 @@
+use std::process::Command;
+
+/// Bakes the git commit of the build into `RELAYER_UTILS_GIT_COMMIT`, for `version_info`. A build
+/// outside a git checkout can set the variable itself, and is `unknown` otherwise.
+fn main() {
+    println!("cargo:rerun-if-env-changed=RELAYER_UTILS_GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    let commit = std::env::var("RELAYER_UTILS_GIT_COMMIT")
+        .ok()
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+        })
+        .map(|commit| commit.trim().to_string())
+        .filter(|commit| !commit.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=RELAYER_UTILS_GIT_COMMIT={}", commit);
+}
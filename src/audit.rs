@@ -0,0 +1,382 @@
+use crate::*;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "node")]
+use neon::prelude::*;
+#[cfg(feature = "async-runtime")]
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "async-runtime")]
+use std::sync::RwLock;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "async-runtime")]
+use tokio::sync::mpsc;
+
+/// Records queued for the audit sink before new ones are dropped.
+pub const AUDIT_QUEUE_CAPACITY: usize = 1024;
+
+/// What one generation produced, for the audit log. It identifies the inputs by their public
+/// fingerprints only: the account code appears as the account salt, its commitment in the email
+/// auth circuit. The fingerprints are those of the parsed email, so a generation that failed after
+/// parsing still has them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Unix time of the end of the generation, in seconds.
+    pub timestamp: u64,
+    pub crate_version: String,
+    pub correlation_id: Option<String>,
+    /// Poseidon hash of the DKIM public key, as the circuit computes it.
+    pub public_key_hash: Option<String>,
+    pub account_salt: Option<String>,
+    pub email_nullifier: Option<String>,
+    /// `ErrorCode` of a failed generation.
+    pub error_code: Option<u8>,
+}
+
+impl AuditRecord {
+    pub fn of(
+        parsed_email: Option<&ParsedEmail>,
+        account_code: &AccountCode,
+        correlation_id: Option<&str>,
+        result: &Result<String>,
+    ) -> Self {
+        let hex = |field: Result<Fr, PoseidonError>| field.ok().map(|field| field2hex(&field));
+        let (public_key_hash, email_nullifier, account_salt) = match parsed_email {
+            Some(parsed_email) => {
+                let account_salt = extract_sender(&parsed_email.canonicalized_header)
+                    .ok()
+                    .and_then(|sender| {
+                        AccountSalt::new(
+                            &PaddedEmailAddr::from_email_addr(&sender.from_addr),
+                            *account_code,
+                        )
+                        .ok()
+                    })
+                    .map(|account_salt| field2hex(&account_salt.0));
+                (
+                    hex(public_key_hash_from_modulus(&parsed_email.public_key)),
+                    hex(email_nullifier_from_signature(&parsed_email.signature)),
+                    account_salt,
+                )
+            }
+            None => (None, None, None),
+        };
+        AuditRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            correlation_id: correlation_id.map(str::to_string),
+            public_key_hash,
+            account_salt,
+            email_nullifier,
+            error_code: result
+                .as_ref()
+                .err()
+                .map(|e| error_code(e, ErrorCode::CircuitInputFailed).as_u8()),
+        }
+    }
+}
+
+/// Destination of the audit records. `write` runs on a blocking thread of the runtime, one record
+/// at a time and in order, so it may block.
+pub trait AuditSink: Send + Sync {
+    fn write(&self, record: &AuditRecord) -> Result<()>;
+}
+
+/// Appends the records to a file, one JSON object per line.
+pub struct JsonlAuditSink {
+    file: Mutex<File>,
+}
+
+impl JsonlAuditSink {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow!("could not open the audit log {}: {}", path.display(), e))?;
+        Ok(JsonlAuditSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl AuditSink for JsonlAuditSink {
+    fn write(&self, record: &AuditRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(&line)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Receives the JSON of every audit record.
+pub type AuditCallback = Arc<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+/// Passes the records to a callback, such as a host-language function.
+pub struct CallbackAuditSink(pub AuditCallback);
+
+impl AuditSink for CallbackAuditSink {
+    fn write(&self, record: &AuditRecord) -> Result<()> {
+        (self.0)(&serde_json::to_string(record)?)
+            .map_err(|e| anyhow!("the audit callback failed: {}", e))
+    }
+}
+
+#[cfg(feature = "async-runtime")]
+static AUDIT_QUEUE: Lazy<RwLock<Option<mpsc::Sender<AuditRecord>>>> =
+    Lazy::new(|| RwLock::new(None));
+
+static AUDIT_RECORDS_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Sends the records of every generation to `sink`, replacing any previous sink; `None` stops
+/// auditing. The records go through a queue of `capacity` records to a writer task, spawned on
+/// the current Tokio runtime or else the shared one, so that generation never waits on the sink.
+/// The records queued for a replaced sink are still written to it.
+#[cfg(feature = "async-runtime")]
+pub fn install_audit_sink(sink: Option<Arc<dyn AuditSink>>, capacity: usize) -> Result<()> {
+    let sink = match sink {
+        Some(sink) => sink,
+        None => {
+            *AUDIT_QUEUE.write().unwrap_or_else(|e| e.into_inner()) = None;
+            return Ok(());
+        }
+    };
+    if capacity == 0 {
+        return Err(anyhow!("the audit queue capacity must be positive"));
+    }
+    let (sender, mut receiver) = mpsc::channel::<AuditRecord>(capacity);
+    let writer = async move {
+        while let Some(record) = receiver.recv().await {
+            let sink = sink.clone();
+            match tokio::task::spawn_blocking(move || sink.write(&record)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    slog::warn!(LOG, "failed to write an audit record"; "error" => e.to_string())
+                }
+                Err(_) => slog::warn!(LOG, "the audit sink panicked"),
+            }
+        }
+    };
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => drop(handle.spawn(writer)),
+        Err(_) => drop(shared_runtime()?.spawn(writer)),
+    }
+    *AUDIT_QUEUE.write().unwrap_or_else(|e| e.into_inner()) = Some(sender);
+    Ok(())
+}
+
+/// Queues the record built by `record` for the audit sink, if one is installed. A full queue, or
+/// one whose writer has stopped, drops the record and counts it in `audit_records_dropped`.
+#[cfg(feature = "async-runtime")]
+pub fn record_audit(record: impl FnOnce() -> AuditRecord) {
+    let sender = match &*AUDIT_QUEUE.read().unwrap_or_else(|e| e.into_inner()) {
+        Some(sender) => sender.clone(),
+        None => return,
+    };
+    if sender.try_send(record()).is_err() {
+        AUDIT_RECORDS_DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Number of audit records dropped since the process started.
+pub fn audit_records_dropped() -> u64 {
+    AUDIT_RECORDS_DROPPED.load(Ordering::Relaxed)
+}
+
+/// `registerAuditSink(callback?)` sends every audit record to `callback(recordJson)` on the
+/// JavaScript thread; a callback that throws loses the record. Without a callback, auditing
+/// stops.
+#[cfg(feature = "node")]
+pub fn register_audit_sink_node(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let callback = match cx.argument_opt(0) {
+        Some(callback)
+            if !callback.is_a::<JsUndefined, _>(&mut cx)
+                && !callback.is_a::<JsNull, _>(&mut cx) =>
+        {
+            callback.downcast_or_throw::<JsFunction, _>(&mut cx)?
+        }
+        _ => {
+            if let Err(e) = install_audit_sink(None, AUDIT_QUEUE_CAPACITY) {
+                return cx.throw_error(e.to_string());
+            }
+            return Ok(cx.undefined());
+        }
+    };
+    let callback = Arc::new(callback.root(&mut cx));
+    let mut channel = cx.channel();
+    channel.unref(&mut cx);
+    let sink = CallbackAuditSink(Arc::new(move |record| {
+        let callback = callback.clone();
+        let record = record.to_string();
+        channel
+            .try_send(move |mut cx| {
+                let record = cx.string(&record);
+                let callback = callback.to_inner(&mut cx);
+                let this = cx.undefined();
+                if cx
+                    .try_catch(|cx| callback.call(cx, this, vec![record.upcast::<JsValue>()]))
+                    .is_err()
+                {
+                    slog::warn!(LOG, "the audit callback threw");
+                }
+                Ok(())
+            })
+            .map_err(|e| e.to_string())
+    }));
+    if let Err(e) = install_audit_sink(Some(Arc::new(sink)), AUDIT_QUEUE_CAPACITY) {
+        return cx.throw_error(e.to_string());
+    }
+    Ok(cx.undefined())
+}
+
+/// `auditRecordsDropped()` returns `audit_records_dropped`.
+#[cfg(feature = "node")]
+pub fn audit_records_dropped_node(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    Ok(cx.number(saturating_safe_integer(audit_records_dropped() as u128) as f64))
+}
+
+#[cfg(all(test, feature = "dns", feature = "async-runtime"))]
+mod tests {
+    use super::*;
+    use rand_core::{OsRng, RngCore};
+    use std::time::Duration;
+
+    struct CollectingSink(Arc<Mutex<Vec<AuditRecord>>>);
+
+    impl AuditSink for CollectingSink {
+        fn write(&self, record: &AuditRecord) -> Result<()> {
+            self.0.lock().unwrap().push(record.clone());
+            Ok(())
+        }
+    }
+
+    struct BlockingSink(Mutex<std::sync::mpsc::Receiver<()>>);
+
+    impl AuditSink for BlockingSink {
+        fn write(&self, _record: &AuditRecord) -> Result<()> {
+            let _ = self.0.lock().unwrap().recv();
+            Ok(())
+        }
+    }
+
+    async fn audited(records: &Mutex<Vec<AuditRecord>>, correlation_id: &str) -> AuditRecord {
+        for _ in 0..200 {
+            let record = records
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|record| record.correlation_id.as_deref() == Some(correlation_id))
+                .cloned();
+            if let Some(record) = record {
+                return record;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no audit record for {}", correlation_id);
+    }
+
+    #[tokio::test]
+    async fn test_audit_records() {
+        let fixture = test_fixture("send_eth").unwrap();
+        cache_dkim_key(
+            DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+            decode_hex(&fixture.public_key).unwrap(),
+        );
+        let account_code = AccountCode(hex2field(&fixture.account_code).unwrap());
+        let records = Arc::new(Mutex::new(vec![]));
+        install_audit_sink(
+            Some(Arc::new(CollectingSink(records.clone()))),
+            AUDIT_QUEUE_CAPACITY,
+        )
+        .unwrap();
+
+        let options = InputGenerationOptions {
+            correlation_id: Some("audit-test-success".to_string()),
+            ..Default::default()
+        };
+        generate_email_auth_input_with_options(&fixture.raw_email, &account_code, &options)
+            .await
+            .unwrap();
+        let record = audited(&records, "audit-test-success").await;
+        assert_eq!(record.error_code, None);
+        assert_eq!(record.crate_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            record.public_key_hash.as_ref(),
+            Some(&fixture.expected.public_key_hash)
+        );
+        assert_eq!(
+            record.email_nullifier.as_ref(),
+            Some(&fixture.expected.email_nullifier)
+        );
+        assert_eq!(
+            record.account_salt.as_ref(),
+            Some(&fixture.expected.account_salt)
+        );
+
+        let options = InputGenerationOptions {
+            correlation_id: Some("audit-test-failure".to_string()),
+            max_header_length: 64,
+            ..Default::default()
+        };
+        generate_email_auth_input_with_options(&fixture.raw_email, &account_code, &options)
+            .await
+            .unwrap_err();
+        let record = audited(&records, "audit-test-failure").await;
+        assert_eq!(
+            record.error_code,
+            Some(ErrorCode::CircuitInputFailed.as_u8())
+        );
+        // The email was parsed before the generation failed.
+        assert_eq!(
+            record.public_key_hash.as_ref(),
+            Some(&fixture.expected.public_key_hash)
+        );
+
+        // A sink that does not keep up drops records instead of blocking.
+        let (unblock, blocked) = std::sync::mpsc::channel();
+        install_audit_sink(Some(Arc::new(BlockingSink(Mutex::new(blocked)))), 1).unwrap();
+        let dropped = audit_records_dropped();
+        let record = || AuditRecord::of(None, &account_code, None, &Ok(String::new()));
+        for _ in 0..10 {
+            record_audit(record);
+        }
+        assert!(audit_records_dropped() >= dropped + 8);
+        install_audit_sink(None, AUDIT_QUEUE_CAPACITY).unwrap();
+        drop(unblock);
+        record_audit(|| panic!("no sink is installed"));
+    }
+
+    #[test]
+    fn test_jsonl_audit_sink() {
+        let path =
+            std::env::temp_dir().join(format!("relayer-utils-audit-{}.jsonl", OsRng.next_u64()));
+        let sink = JsonlAuditSink::open(&path).unwrap();
+        let account_code = AccountCode(Fr::from(1));
+        let record = AuditRecord::of(
+            None,
+            &account_code,
+            Some("jsonl"),
+            &Err(coded(ErrorCode::ParseEmailFailed, "no signature")),
+        );
+        sink.write(&record).unwrap();
+        sink.write(&record).unwrap();
+        let lines = std::fs::read_to_string(&path).unwrap();
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let written: AuditRecord = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(written, record);
+        assert_eq!(
+            written.error_code,
+            Some(ErrorCode::ParseEmailFailed.as_u8())
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,739 @@
+use crate::*;
+use anyhow::Result;
+use base64::{engine::general_purpose, Engine as _};
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Deepest multipart nesting that is searched.
+const MAX_MIME_DEPTH: usize = 8;
+
+/// Default cap on the decoded size of one searched part.
+pub const DEFAULT_MAX_PART_DECODED_BYTES: usize = 8 << 20;
+
+/// Number of trailing bytes hashed into `TrailingContent::preview_sha256`.
+pub const TRAILING_PREVIEW_BYTES: usize = 64;
+
+/// How `find_in_body` searches a body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BodySearchOptions {
+    /// Also search parts with `Content-Disposition: attachment`.
+    pub search_attachments: bool,
+    /// A searched part whose decoded size may exceed this fails with `PartTooLarge`.
+    pub max_part_decoded_bytes: usize,
+}
+
+impl Default for BodySearchOptions {
+    fn default() -> Self {
+        BodySearchOptions {
+            search_attachments: false,
+            max_part_decoded_bytes: DEFAULT_MAX_PART_DECODED_BYTES,
+        }
+    }
+}
+
+/// A part that has to be searched decodes to more than `BodySearchOptions::max_part_decoded_bytes`.
+/// `estimated` is computed from the encoded size, without decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartTooLarge {
+    pub limit: usize,
+    pub estimated: usize,
+}
+
+impl PartTooLarge {
+    pub const CODE: &'static str = "PART_TOO_LARGE";
+}
+
+impl std::fmt::Display for PartTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: a body part decodes to about {} bytes but at most {} are allowed",
+            Self::CODE,
+            self.estimated,
+            self.limit
+        )
+    }
+}
+
+impl std::error::Error for PartTooLarge {}
+
+/// Size of one leaf part, as reported by `body_part_sizes`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BodyPartSize {
+    pub content_type: String,
+    pub transfer_encoding: String,
+    pub is_attachment: bool,
+    pub encoded_bytes: usize,
+    /// Upper bound of the decoded size.
+    pub estimated_decoded_bytes: usize,
+}
+
+/// Result of `verify_body_contains`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BodyContains {
+    /// The header signature and the `bh=` body hash both verify.
+    pub dkim_ok: bool,
+    pub body_contains: bool,
+    /// Offset of the first match in the decoded content of part `part_index`.
+    pub needle_idx: Option<usize>,
+    /// Index of the matching leaf part, in document order; a non-multipart body is part 0.
+    pub part_index: Option<usize>,
+}
+
+/// Content after the closing delimiter of a multipart body, such as the notice some anti-virus
+/// gateways append. It is outside every part, and the signer may not have seen it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TrailingContent {
+    /// Offset of the content in the body, just after the closing delimiter line.
+    pub offset: usize,
+    pub bytes: usize,
+    /// SHA-256 of the first `TRAILING_PREVIEW_BYTES` bytes, as hex, to recognize a known notice
+    /// without logging it.
+    pub preview_sha256: String,
+}
+
+/// A non-multipart MIME part of a body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LeafPart<'a> {
+    content: &'a str,
+    content_type: String,
+    transfer_encoding: String,
+    is_attachment: bool,
+}
+
+/// Unfolded header fields of a MIME part or email, with lowercase names, and the rest after the
+/// empty line.
+fn split_part_headers(part: &str) -> (Vec<(String, String)>, &str) {
+    let (header, content) = if let Some(content) = part.strip_prefix("\r\n") {
+        ("", content)
+    } else {
+        match part.find("\r\n\r\n") {
+            Some(pos) => (&part[..pos + 2], &part[pos + 4..]),
+            None => (part, ""),
+        }
+    };
+    let mut fields: Vec<(String, String)> = vec![];
+    for line in header.split("\r\n") {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push_str(line);
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            fields.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    (fields, content)
+}
+
+fn field<'a>(fields: &'a [(String, String)], name: &str) -> &'a str {
+    fields
+        .iter()
+        .find(|(field, _)| field == name)
+        .map_or("", |(_, value)| value.as_str())
+}
+
+/// The `boundary` parameter of a multipart Content-Type.
+fn multipart_boundary(content_type: &str) -> Option<&str> {
+    let (media_type, params) = content_type.split_once(';')?;
+    if !media_type.trim().to_ascii_lowercase().starts_with("multipart/") {
+        return None;
+    }
+    params.split(';').find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"'))
+            .filter(|boundary| !boundary.is_empty())
+    })
+}
+
+/// Contents of the parts of a multipart body, between the delimiter lines, and the offset just
+/// after the closing delimiter line when there is one.
+fn split_multipart<'a>(body: &'a str, boundary: &str) -> (Vec<&'a str>, Option<usize>) {
+    let delimiter = format!("--{}", boundary);
+    let mut parts = vec![];
+    let mut part_start = None;
+    let mut line_start = 0;
+    while line_start < body.len() {
+        let line_end = body[line_start..]
+            .find("\r\n")
+            .map_or(body.len(), |pos| line_start + pos);
+        let line = &body[line_start..line_end];
+        if let Some(rest) = line.strip_prefix(delimiter.as_str()) {
+            if let Some(start) = part_start {
+                // The CRLF before a delimiter belongs to the delimiter.
+                parts.push(&body[start..line_start.saturating_sub(2).max(start)]);
+            }
+            if rest.starts_with("--") {
+                return (parts, Some((line_end + 2).min(body.len())));
+            }
+            part_start = Some((line_end + 2).min(body.len()));
+        }
+        line_start = line_end + 2;
+    }
+    (parts, None)
+}
+
+fn collect_leaf_parts<'a>(
+    fields: &[(String, String)],
+    content: &'a str,
+    depth: usize,
+    leaves: &mut Vec<LeafPart<'a>>,
+) {
+    if depth < MAX_MIME_DEPTH {
+        if let Some(boundary) = multipart_boundary(field(fields, "content-type")) {
+            for part in split_multipart(content, boundary).0 {
+                let (part_fields, part_content) = split_part_headers(part);
+                collect_leaf_parts(&part_fields, part_content, depth + 1, leaves);
+            }
+            return;
+        }
+    }
+    let disposition = field(fields, "content-disposition");
+    leaves.push(LeafPart {
+        content,
+        content_type: field(fields, "content-type").to_string(),
+        transfer_encoding: field(fields, "content-transfer-encoding").to_ascii_lowercase(),
+        is_attachment: disposition
+            .split(';')
+            .next()
+            .map_or(false, |kind| kind.trim().eq_ignore_ascii_case("attachment")),
+    });
+}
+
+fn decode_quoted_printable(content: &str) -> Vec<u8> {
+    let bytes = content.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'=' {
+            if bytes[i + 1..].starts_with(b"\r\n") {
+                i += 3;
+                continue;
+            }
+            if let Some(byte) = content
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    decoded
+}
+
+/// Upper bound of the decoded size of a part, from its encoded size.
+fn estimated_decoded_len(part: &LeafPart) -> usize {
+    match part.transfer_encoding.as_str() {
+        "base64" => part.content.len() / 4 * 3 + 3,
+        _ => part.content.len(),
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Number of parts decoded by this thread.
+    static DECODED_PARTS: std::cell::Cell<usize> = std::cell::Cell::new(0);
+}
+
+/// Decodes base64 in chunks, skipping whitespace, so that no whitespace-free copy of the part is
+/// made. Content that is not valid base64 is searched as is.
+fn decode_base64(content: &str) -> Vec<u8> {
+    const CHUNK: usize = 4 << 10;
+    let mut decoded = Vec::with_capacity(content.len() / 4 * 3 + 3);
+    let mut chunk = Vec::with_capacity(CHUNK);
+    let mut symbols = content.bytes().filter(|b| !b.is_ascii_whitespace()).peekable();
+    while symbols.peek().is_some() {
+        chunk.clear();
+        chunk.extend(symbols.by_ref().take(CHUNK));
+        if general_purpose::STANDARD
+            .decode_vec(&chunk, &mut decoded)
+            .is_err()
+        {
+            return content.as_bytes().to_vec();
+        }
+    }
+    decoded
+}
+
+/// Content of a leaf part after its Content-Transfer-Encoding is undone, or `PartTooLarge` before
+/// any decoding when it may exceed `limit`.
+fn decode_part(part: &LeafPart, limit: usize) -> Result<Vec<u8>> {
+    let estimated = estimated_decoded_len(part);
+    if estimated > limit {
+        return Err(PartTooLarge { limit, estimated }.into());
+    }
+    #[cfg(test)]
+    DECODED_PARTS.with(|count| count.set(count.get() + 1));
+    Ok(match part.transfer_encoding.as_str() {
+        "base64" => decode_base64(part.content),
+        "quoted-printable" => decode_quoted_printable(part.content),
+        _ => part.content.as_bytes().to_vec(),
+    })
+}
+
+fn leaf_parts<'a>(header_fields: &[(String, String)], body: &'a str) -> Vec<LeafPart<'a>> {
+    let mut leaves = vec![];
+    collect_leaf_parts(header_fields, body, 0, &mut leaves);
+    leaves
+}
+
+/// Sizes of the leaf parts of `body`, in document order, without decoding any of them.
+pub fn body_part_sizes(header_fields: &[(String, String)], body: &str) -> Vec<BodyPartSize> {
+    leaf_parts(header_fields, body)
+        .iter()
+        .map(|part| BodyPartSize {
+            content_type: part.content_type.clone(),
+            transfer_encoding: part.transfer_encoding.clone(),
+            is_attachment: part.is_attachment,
+            encoded_bytes: part.content.len(),
+            estimated_decoded_bytes: estimated_decoded_len(part),
+        })
+        .collect()
+}
+
+/// The content after the closing delimiter of the top-level multipart of `body`, if any.
+pub fn trailing_content(header_fields: &[(String, String)], body: &str) -> Option<TrailingContent> {
+    let boundary = multipart_boundary(field(header_fields, "content-type"))?;
+    let offset = split_multipart(body, boundary).1?;
+    let trailing = &body.as_bytes()[offset..];
+    if trailing.is_empty() {
+        return None;
+    }
+    let preview = &trailing[..trailing.len().min(TRAILING_PREVIEW_BYTES)];
+    Some(TrailingContent {
+        offset,
+        bytes: trailing.len(),
+        preview_sha256: hex::encode(Sha256::digest(preview)),
+    })
+}
+
+/// Searches the decoded leaf parts of `body` for `needle`, skipping attachment parts
+/// (`Content-Disposition: attachment`) unless `options.search_attachments` is set. Parts are
+/// decoded one at a time, only when searched. `header_fields` are the top-level fields giving
+/// the Content-Type and Content-Transfer-Encoding. Only the boundaries come from them; every byte
+/// searched is part of `body`.
+///
+/// Returns the index of the first matching part and the offset in its decoded content.
+pub fn find_in_body(
+    header_fields: &[(String, String)],
+    body: &str,
+    needle: &[u8],
+    options: &BodySearchOptions,
+) -> Result<Option<(usize, usize)>> {
+    for (index, part) in leaf_parts(header_fields, body).iter().enumerate() {
+        if part.is_attachment && !options.search_attachments {
+            continue;
+        }
+        let decoded = decode_part(part, options.max_part_decoded_bytes)?;
+        let offset = if needle.is_empty() {
+            Some(0)
+        } else {
+            decoded
+                .windows(needle.len())
+                .position(|window| window == needle)
+        };
+        if let Some(offset) = offset {
+            return Ok(Some((index, offset)));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns whether the `bh=` tag of the canonicalized header is the SHA-256 of the
+/// canonicalized body.
+pub fn check_body_hash(parsed_email: &ParsedEmail) -> Result<bool> {
+    body_hash_matches(parsed_email, &parsed_email.canonicalized_body)
+}
+
+/// Whether the `bh=` tag of the canonicalized header of `parsed_email` is the SHA-256 of `body`.
+fn body_hash_matches(parsed_email: &ParsedEmail, body: &str) -> Result<bool> {
+    let idxes = parsed_email.get_dkim_signature_idxes()?;
+    let body_hash = &parsed_email.canonicalized_header[idxes.body_hash.0..idxes.body_hash.1];
+    let digest = Sha256::digest(body.as_bytes());
+    Ok(general_purpose::STANDARD.encode(digest) == body_hash)
+}
+
+/// Explains a body hash that does not verify, when the body verifies once truncated after the
+/// closing delimiter of the top-level multipart or at the `l=` length: the content past that
+/// point was added after signing. `header_fields` are the top-level fields of the raw email.
+/// Returns `None` when the body hash verifies or no truncation makes it verify.
+pub fn body_hash_hint(
+    parsed_email: &ParsedEmail,
+    header_fields: &[(String, String)],
+) -> Result<Option<String>> {
+    let body = parsed_email.canonicalized_body.as_str();
+    if body_hash_matches(parsed_email, body)? {
+        return Ok(None);
+    }
+    if let Some(trailing) = trailing_content(header_fields, body) {
+        if body_hash_matches(parsed_email, &body[..trailing.offset])? {
+            return Ok(Some(format!(
+                "body modified after signing: trailing {} bytes after final boundary",
+                trailing.bytes
+            )));
+        }
+    }
+    let length = crate::parse_email::dkim_signature_tag(header_fields, "l")
+        .and_then(|length| length.parse::<usize>().ok());
+    if let Some(length) = length.filter(|&length| length < body.len()) {
+        if let Some(signed) = body.get(..length) {
+            if body_hash_matches(parsed_email, signed)? {
+                return Ok(Some(format!(
+                    "body modified after signing: trailing {} bytes beyond the l={} limit",
+                    body.len() - length,
+                    length
+                )));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Verifies the DKIM signature of `raw_email`, header and body hash, and searches its body for
+/// `needle` as `find_in_body` does. No circuit input is built.
+#[cfg(feature = "dns")]
+pub async fn verify_body_contains(
+    raw_email: &str,
+    needle: &[u8],
+    options: &BodySearchOptions,
+) -> Result<BodyContains> {
+    let parsed_email = ParsedEmail::new_from_raw_email(raw_email).await?;
+    let header_ok = parsed_email.verify_signature().is_ok();
+    let dkim_ok = header_ok && check_body_hash(&parsed_email)?;
+    let raw_header = raw_email
+        .find("\r\n\r\n")
+        .map_or(raw_email, |pos| &raw_email[..pos + 2]);
+    let (header_fields, _) = split_part_headers(raw_header);
+    let found = find_in_body(
+        &header_fields,
+        &parsed_email.canonicalized_body,
+        needle,
+        options,
+    )?;
+    Ok(BodyContains {
+        dkim_ok,
+        body_contains: found.is_some(),
+        needle_idx: found.map(|(_, offset)| offset),
+        part_index: found.map(|(index, _)| index),
+    })
+}
+
+#[cfg(feature = "node")]
+pub fn verify_body_contains_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let email = cx.argument::<JsString>(0)?.value(&mut cx);
+    let needle = cx.argument::<JsString>(1)?.value(&mut cx);
+    let options = match cx.argument_opt(2) {
+        Some(options) if !options.is_a::<JsUndefined, _>(&mut cx) => {
+            let options = options.downcast_or_throw::<JsString, _>(&mut cx)?.value(&mut cx);
+            match serde_json::from_str::<BodySearchOptions>(&options) {
+                Ok(options) => options,
+                Err(e) => return cx.throw_error(format!("invalid body search options: {}", e)),
+            }
+        }
+        _ => BodySearchOptions::default(),
+    };
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(&mut cx)?;
+
+    rt.spawn(async move {
+        let result = verify_body_contains(&email, needle.as_bytes(), &options)
+            .await
+            .and_then(|result| Ok(serde_json::to_string(&result)?));
+        deferred.settle_with(&channel, move |mut cx| match result {
+            Ok(result) => Ok(cx.string(result)),
+            Err(err) => cx.throw_error(format!("Could not verify the body: {}", err)),
+        });
+    });
+
+    Ok(promise)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    #[test]
+    fn test_find_in_body() {
+        let fields = |content_type: &str| {
+            vec![
+                ("content-type".to_string(), content_type.to_string()),
+                ("subject".to_string(), "Hi".to_string()),
+            ]
+        };
+        let find = |fields: &[(String, String)], body: &str, needle: &[u8], search_attachments| {
+            let options = BodySearchOptions {
+                search_attachments,
+                ..Default::default()
+            };
+            find_in_body(fields, body, needle, &options).unwrap()
+        };
+        let digest = b"sha256:5f2b0c";
+        let plain = fields("text/plain");
+        assert_eq!(find(&plain, "see sha256:5f2b0c\r\n", digest, false), Some((0, 4)));
+        assert_eq!(find(&plain, "nothing here\r\n", digest, false), None);
+
+        let attachment = general_purpose::STANDARD.encode("digest sha256:5f2b0c\n");
+        let body = format!(
+            "--outer\r\n\
+             Content-Type: multipart/alternative; boundary=inner\r\n\r\n\
+             --inner\r\nContent-Type: text/plain\r\n\r\nplain text\r\n\
+             --inner\r\nContent-Type: text/html\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\n\
+             <p>html =3D text</p>\r\n\
+             --inner--\r\n\
+             --outer\r\nContent-Type: text/plain\r\nContent-Disposition: attachment; filename=d.txt\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n{}\r\n\
+             --outer--\r\n",
+            attachment
+        );
+        let mixed = fields(r#"multipart/mixed; boundary="outer""#);
+        // Only in the attachment: found when attachments are searched.
+        assert_eq!(find(&mixed, &body, digest, false), None);
+        assert_eq!(find(&mixed, &body, digest, true), Some((2, 7)));
+        // Quoted-printable parts are decoded.
+        assert_eq!(find(&mixed, &body, b"html = text", false), Some((1, 3)));
+        // Delimiters and part headers are not content.
+        assert_eq!(find(&mixed, &body, b"--inner", true), None);
+    }
+
+    #[test]
+    fn test_large_attachments_are_not_decoded() {
+        let attachment = general_purpose::STANDARD
+            .encode(vec![0x42; 3 << 20])
+            .as_bytes()
+            .chunks(76)
+            .map(|line| std::str::from_utf8(line).unwrap())
+            .join("\r\n");
+        let body = format!(
+            "--b\r\nContent-Type: text/plain\r\n\r\nsha256:5f2b0c\r\n\
+             --b\r\nContent-Type: image/png\r\nContent-Disposition: attachment\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n{}\r\n--b--\r\n",
+            attachment
+        );
+        let fields = vec![(
+            "content-type".to_string(),
+            "multipart/mixed; boundary=b".to_string(),
+        )];
+        let options = BodySearchOptions {
+            max_part_decoded_bytes: 1 << 20,
+            ..Default::default()
+        };
+        let decoded_parts = || DECODED_PARTS.with(|count| count.get());
+
+        let before = decoded_parts();
+        assert_eq!(
+            find_in_body(&fields, &body, b"5f2b0c", &options).unwrap(),
+            Some((0, 7))
+        );
+        assert_eq!(find_in_body(&fields, &body, b"absent", &options).unwrap(), None);
+        // Only the text part was decoded, once per search.
+        assert_eq!(decoded_parts() - before, 2);
+
+        let sizes = body_part_sizes(&fields, &body);
+        assert_eq!(decoded_parts() - before, 2);
+        assert_eq!(sizes.len(), 2);
+        assert!(sizes[1].is_attachment && sizes[1].estimated_decoded_bytes >= 3 << 20);
+
+        let options = BodySearchOptions {
+            search_attachments: true,
+            ..options
+        };
+        let err = find_in_body(&fields, &body, b"absent", &options).unwrap_err();
+        let err = err.downcast::<PartTooLarge>().unwrap();
+        assert_eq!(err.limit, 1 << 20);
+        assert_eq!(err.estimated, sizes[1].estimated_decoded_bytes);
+        assert_eq!(decoded_parts() - before, 3);
+    }
+
+    #[test]
+    fn test_check_body_hash() {
+        let body = "Hello\r\n";
+        let body_hash = general_purpose::STANDARD.encode(Sha256::digest(body.as_bytes()));
+        let parsed_email = |body: &str| ParsedEmail {
+            canonicalized_header: format!(
+                "from:alice@gmail.com\r\ndkim-signature:v=1; a=rsa-sha256; bh={}; b=",
+                body_hash
+            ),
+            canonicalized_body: body.to_string(),
+            signature: vec![],
+            public_key: vec![],
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
+        };
+        assert!(check_body_hash(&parsed_email(body)).unwrap());
+        assert!(!check_body_hash(&parsed_email("Hello, tampered\r\n")).unwrap());
+    }
+
+    #[test]
+    fn test_body_hash_hint() {
+        let signed = "--b\r\nContent-Type: text/plain\r\n\r\nHello\r\n--b--\r\n";
+        let body_hash = general_purpose::STANDARD.encode(Sha256::digest(signed.as_bytes()));
+        let parsed_email = |body: &str| ParsedEmail {
+            canonicalized_header: format!(
+                "from:alice@gmail.com\r\ndkim-signature:v=1; a=rsa-sha256; bh={}; b=",
+                body_hash
+            ),
+            canonicalized_body: body.to_string(),
+            signature: vec![],
+            public_key: vec![],
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
+        };
+        let fields = |length: Option<usize>| {
+            let length = length.map_or(String::new(), |length| format!(" l={};", length));
+            vec![
+                (
+                    "content-type".to_string(),
+                    "multipart/mixed; boundary=b".to_string(),
+                ),
+                (
+                    "dkim-signature".to_string(),
+                    format!("v=1; a=rsa-sha256;{} bh={}; b=", length, body_hash),
+                ),
+            ]
+        };
+        let footer = "Scanned by MailGuard.\r\n";
+        let modified = format!("{}{}", signed, footer);
+
+        assert_eq!(trailing_content(&fields(None), signed), None);
+        let trailing = trailing_content(&fields(None), &modified).unwrap();
+        assert_eq!(trailing.offset, signed.len());
+        assert_eq!(trailing.bytes, footer.len());
+        assert_eq!(
+            trailing.preview_sha256,
+            hex::encode(Sha256::digest(footer.as_bytes()))
+        );
+
+        assert_eq!(body_hash_hint(&parsed_email(signed), &fields(None)).unwrap(), None);
+        assert_eq!(
+            body_hash_hint(&parsed_email(&modified), &fields(None)).unwrap(),
+            Some(format!(
+                "body modified after signing: trailing {} bytes after final boundary",
+                footer.len()
+            ))
+        );
+        // Content inside a part is a modification no truncation explains.
+        let tampered = signed.replace("Hello", "Hullo");
+        assert_eq!(body_hash_hint(&parsed_email(&tampered), &fields(None)).unwrap(), None);
+
+        // Without a multipart body, the l= limit is where the signed body ends.
+        let plain = |length| {
+            fields(length)
+                .into_iter()
+                .filter(|(name, _)| name != "content-type")
+                .collect_vec()
+        };
+        assert_eq!(body_hash_hint(&parsed_email(&modified), &plain(None)).unwrap(), None);
+        assert_eq!(
+            body_hash_hint(&parsed_email(&modified), &plain(Some(signed.len()))).unwrap(),
+            Some(format!(
+                "body modified after signing: trailing {} bytes beyond the l={} limit",
+                footer.len(),
+                signed.len()
+            ))
+        );
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    async fn test_footer_after_the_closing_boundary() {
+        let mut previews = vec![];
+        for (name, valid) in [
+            ("send_eth_footer_signed", true),
+            ("send_eth_footer_after_signing", false),
+        ] {
+            let fixture = test_fixture(name).unwrap();
+            cache_dkim_key(
+                DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+                decode_hex(&fixture.public_key).unwrap(),
+            );
+            let untrusted = ParsedEmail::parse_untrusted(&fixture.raw_email).unwrap();
+            let trailing = untrusted.trailing_content.unwrap();
+            assert!(untrusted.canonicalized_body[trailing.offset..].starts_with("This message"));
+            previews.push(trailing.preview_sha256);
+
+            let verification = verify_dkim(&fixture.raw_email).await.unwrap();
+            assert_eq!(verification.valid, valid, "{}", name);
+            // The header signature verifies either way, so the alignment is judged.
+            assert_eq!(
+                verification.dmarc_alignment,
+                Some(AlignmentVerdict::AlignedStrict),
+                "{}",
+                name
+            );
+            if !valid {
+                assert_eq!(
+                    verification.failure.clone().unwrap(),
+                    format!(
+                        "the body hash does not match the body: body modified after signing: \
+                         trailing {} bytes after final boundary",
+                        trailing.bytes
+                    )
+                );
+                let err = verification.into_verified().unwrap_err();
+                assert_eq!(
+                    error_code(&err, ErrorCode::ParseEmailFailed),
+                    ErrorCode::DkimVerificationFailed
+                );
+            } else {
+                assert!(verification.into_verified().is_ok());
+            }
+        }
+        // The same notice is recognized whether or not it was signed.
+        assert_eq!(previews[0], previews[1]);
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    async fn test_verify_body_contains() {
+        let search = |name: &'static str, needle: &'static str| async move {
+            let fixture = test_fixture(name).unwrap();
+            cache_dkim_key(
+                DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+                decode_hex(&fixture.public_key).unwrap(),
+            );
+            let options = BodySearchOptions::default();
+            verify_body_contains(&fixture.raw_email, needle.as_bytes(), &options)
+                .await
+                .unwrap()
+        };
+
+        assert_eq!(
+            search("send_eth", "send 1 ETH").await,
+            BodyContains {
+                dkim_ok: true,
+                body_contains: true,
+                needle_idx: Some("Please ".len()),
+                part_index: Some(0),
+            }
+        );
+        assert_eq!(
+            search("send_eth", "send 2 ETH").await,
+            BodyContains {
+                dkim_ok: true,
+                body_contains: false,
+                needle_idx: None,
+                part_index: None,
+            }
+        );
+        // The phrase is in the signed part, but the appended notice breaks the body hash.
+        assert_eq!(
+            search("send_eth_footer_after_signing", "send 1 ETH").await,
+            BodyContains {
+                dkim_ok: false,
+                body_contains: true,
+                needle_idx: Some("Please ".len()),
+                part_index: Some(0),
+            }
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use crate::*;
+use anyhow::Result;
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+
+#[derive(Debug, Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+/// Cooperative cancellation of one call. The pipeline checks it at its stage boundaries and
+/// stops waiting on the DKIM key fetch as soon as it is cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<TokenState>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *self.0.wakers.lock().unwrap_or_else(|e| e.into_inner()));
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fails with `ErrorCode::Cancelled` once the token is cancelled. `stage` is the stage that
+    /// would have run next.
+    pub fn check(&self, stage: &str) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(coded(
+                ErrorCode::Cancelled,
+                format!("the call was cancelled before {}", stage),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Runs `future` until it completes or the token is cancelled, whichever comes first.
+    pub async fn run<F: Future>(&self, stage: &str, future: F) -> Result<F::Output> {
+        let mut future = std::pin::pin!(future);
+        std::future::poll_fn(|cx| {
+            self.check(stage)?;
+            if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                return Poll::Ready(Ok(output));
+            }
+            {
+                let mut wakers = self.0.wakers.lock().unwrap_or_else(|e| e.into_inner());
+                if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+            }
+            // `cancel` may have drained the wakers before this one was added.
+            match self.check(stage) {
+                Ok(()) => Poll::Pending,
+                Err(e) => Poll::Ready(Err(e)),
+            }
+        })
+        .await
+    }
+}
+
+/// Tokens of the calls in flight by correlation id, with the number of calls sharing each.
+static CANCELLATION_TOKENS: Lazy<Mutex<HashMap<String, (CancellationToken, usize)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn cancellation_tokens(
+) -> std::sync::MutexGuard<'static, HashMap<String, (CancellationToken, usize)>> {
+    CANCELLATION_TOKENS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Keeps the token of a call cancellable by its correlation id until dropped.
+#[derive(Debug)]
+pub struct CancellationRegistration {
+    correlation_id: Option<String>,
+    pub token: CancellationToken,
+}
+
+impl Drop for CancellationRegistration {
+    fn drop(&mut self) {
+        let correlation_id = match &self.correlation_id {
+            Some(correlation_id) => correlation_id,
+            None => return,
+        };
+        let mut tokens = cancellation_tokens();
+        if let Some((_, calls)) = tokens.get_mut(correlation_id) {
+            *calls -= 1;
+            if *calls == 0 {
+                tokens.remove(correlation_id);
+            }
+        }
+    }
+}
+
+/// Creates the token of a call, cancellable with `cancel(correlation_id)` while the returned
+/// registration lives. Calls with the same correlation id share a token. Without a correlation
+/// id, the token can only be cancelled directly.
+pub fn register_cancellation(correlation_id: Option<&str>) -> CancellationRegistration {
+    let correlation_id = match correlation_id {
+        Some(correlation_id) => correlation_id,
+        None => {
+            return CancellationRegistration {
+                correlation_id: None,
+                token: CancellationToken::new(),
+            }
+        }
+    };
+    let mut tokens = cancellation_tokens();
+    let (token, calls) = tokens
+        .entry(correlation_id.to_string())
+        .or_insert_with(|| (CancellationToken::new(), 0));
+    *calls += 1;
+    CancellationRegistration {
+        correlation_id: Some(correlation_id.to_string()),
+        token: token.clone(),
+    }
+}
+
+/// The token of the call in flight with `correlation_id`, if any.
+pub fn cancellation_token(correlation_id: Option<&str>) -> Option<CancellationToken> {
+    let tokens = cancellation_tokens();
+    tokens.get(correlation_id?).map(|(token, _)| token.clone())
+}
+
+/// `CancellationToken::check` on the token of `correlation_id`; passes when no such call is
+/// registered.
+pub fn check_cancelled(correlation_id: Option<&str>, stage: &str) -> Result<()> {
+    match cancellation_token(correlation_id) {
+        Some(token) => token.check(stage),
+        None => Ok(()),
+    }
+}
+
+/// Cancels the calls in flight with `correlation_id`. Returns false when there are none.
+pub fn cancel(correlation_id: &str) -> bool {
+    match cancellation_token(Some(correlation_id)) {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+/// `cancel(correlationId)` returns whether a call with the id was in flight.
+#[cfg(feature = "node")]
+pub fn cancel_node(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let correlation_id = cx.argument::<JsString>(0)?.value(&mut cx);
+    Ok(cx.boolean(cancel(&correlation_id)))
+}
+
+#[cfg(all(test, feature = "async-runtime"))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn test_cancel_in_flight_call() {
+        let stages = Arc::new(AtomicUsize::new(0));
+        let registration = register_cancellation(Some("cancel-test"));
+        let call = {
+            let stages = stages.clone();
+            let token = registration.token.clone();
+            tokio::spawn(async move {
+                // A DKIM key fetch that would take far longer than the test.
+                token
+                    .run(
+                        "the DKIM key fetch",
+                        tokio::time::sleep(Duration::from_secs(30)),
+                    )
+                    .await?;
+                stages.fetch_add(1, Ordering::SeqCst);
+                token.check("circuit")?;
+                stages.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, anyhow::Error>(())
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let start = Instant::now();
+        assert!(cancel("cancel-test"));
+        let err = call.await.unwrap().unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(
+            error_code(&err, ErrorCode::InternalPanic),
+            ErrorCode::Cancelled
+        );
+        assert!(err.to_string().contains("the DKIM key fetch"), "{}", err);
+        assert_eq!(stages.load(Ordering::SeqCst), 0);
+
+        drop(registration);
+        assert!(!cancel("cancel-test"));
+        assert!(check_cancelled(Some("cancel-test"), "circuit").is_ok());
+    }
+
+    #[test]
+    fn test_cancelled_generation_stops_before_the_circuit() {
+        let parsed_email =
+            fixture_signed_email("from:alice@gmail.com\r\nsubject:Send 1 ETH\r\n", "");
+        let options = InputGenerationOptions {
+            correlation_id: Some("cancel-generation-test".to_string()),
+            ..Default::default()
+        };
+        let account_code = AccountCode(Fr::from(1u64));
+        let registration = register_cancellation(options.correlation_id.as_deref());
+        assert!(generate_email_auth_input_from_parsed_email(
+            &parsed_email,
+            &account_code,
+            &options
+        )
+        .is_ok());
+        registration.token.cancel();
+        let err =
+            generate_email_auth_input_from_parsed_email(&parsed_email, &account_code, &options)
+                .unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::InternalPanic),
+            ErrorCode::Cancelled
+        );
+        assert!(err.to_string().contains("circuit"), "{}", err);
+    }
+}
@@ -4,6 +4,8 @@ use crate::*;
 use anyhow::Result;
 use num_bigint::BigInt;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "dns")]
+use tracing::Instrument;
 
 use crate::{generate_partial_sha, sha256_pad, to_circom_bigint_bytes, uint8_array_to_char_array};
 
@@ -11,6 +13,8 @@ pub const MAX_HEADER_PADDED_BYTES: usize = 1024;
 pub const MAX_BODY_PADDED_BYTES: usize = 1536;
 pub const CIRCOM_BIGINT_N: usize = 121;
 pub const CIRCOM_BIGINT_K: usize = 17;
+/// Maximum number of subject bytes the email auth circuit accepts as a command.
+pub const MAX_SUBJECT_BYTES: usize = 605;
 
 #[derive(Serialize, Deserialize)]
 struct EmailSenderInput {
@@ -71,16 +75,124 @@ pub struct CircuitInputParams {
 
 #[derive(Serialize, Deserialize)]
 pub struct EmailAuthInput {
-    padded_header: Vec<String>,
-    public_key: Vec<String>,
-    signature: Vec<String>,
-    padded_header_len: String,
-    account_code: String,
-    from_addr_idx: usize,
-    subject_idx: usize,
-    domain_idx: usize,
-    timestamp_idx: usize,
-    code_idx: usize,
+    pub(crate) padded_header: Vec<String>,
+    pub(crate) public_key: Vec<String>,
+    pub(crate) signature: Vec<String>,
+    pub(crate) padded_header_len: String,
+    pub(crate) account_code: String,
+    #[serde(serialize_with = "serialize_number")]
+    pub(crate) from_addr_idx: usize,
+    #[serde(serialize_with = "serialize_number")]
+    pub(crate) subject_idx: usize,
+    #[serde(serialize_with = "serialize_number")]
+    pub(crate) domain_idx: usize,
+    #[serde(serialize_with = "serialize_number")]
+    pub(crate) timestamp_idx: usize,
+    #[serde(serialize_with = "serialize_number")]
+    pub(crate) code_idx: usize,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_number",
+        default
+    )]
+    pub(crate) dkim_signature_idx: Option<usize>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_number",
+        default
+    )]
+    pub(crate) dkim_body_hash_idx: Option<usize>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_number",
+        default
+    )]
+    pub(crate) dkim_signature_tag_idx: Option<usize>,
+    /// The body fields are set when the body hash is checked (`ignore_body_hash` false).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) precomputed_sha: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) padded_body: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) padded_body_len: Option<String>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_number",
+        default
+    )]
+    pub(crate) body_hash_idx: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) email_nullifier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) public_key_hash: Option<String>,
+    /// The public signals the circuit outputs, as decimal strings in the verifier order.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) public_signals: Option<Vec<String>>,
+    /// The `d=` domain, set when it replaces the from domain (`DomainSource::DkimDomain`).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) signing_domain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) correlation_id: Option<String>,
+}
+
+impl EmailAuthInput {
+    /// Fails, naming the field, when an index or length is over `MAX_EMITTED_INDEX`.
+    pub(crate) fn check_emitted_indexes(&self) -> Result<()> {
+        let padded_header_len = self.padded_header_len.parse::<usize>()?;
+        let padded_body_len = match &self.padded_body_len {
+            Some(len) => Some(len.parse::<usize>()?),
+            None => None,
+        };
+        for (field, value) in [
+            ("padded_header_len", Some(padded_header_len)),
+            ("padded_body_len", padded_body_len),
+            ("body_hash_idx", self.body_hash_idx),
+            ("from_addr_idx", Some(self.from_addr_idx)),
+            ("subject_idx", Some(self.subject_idx)),
+            ("domain_idx", Some(self.domain_idx)),
+            ("timestamp_idx", Some(self.timestamp_idx)),
+            ("code_idx", Some(self.code_idx)),
+            ("dkim_signature_idx", self.dkim_signature_idx),
+            ("dkim_body_hash_idx", self.dkim_body_hash_idx),
+            ("dkim_signature_tag_idx", self.dkim_signature_tag_idx),
+        ] {
+            if let Some(value) = value {
+                check_emitted_index(field, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Upper bound of the serialized length: every string array element is quoted and
+    /// comma-separated, and the field names and integers fit in the fixed overhead.
+    pub(crate) fn serialized_len_hint(&self) -> usize {
+        let strings_len = |strings: &[String]| strings.iter().map(|s| s.len() + 3).sum::<usize>();
+        strings_len(&self.padded_header)
+            + self.precomputed_sha.as_deref().map_or(0, strings_len)
+            + self.padded_body.as_deref().map_or(0, strings_len)
+            + strings_len(&self.public_key)
+            + strings_len(&self.signature)
+            + self.public_signals.as_deref().map_or(0, strings_len)
+            + self.padded_header_len.len()
+            + self.account_code.len()
+            + self.email_nullifier.as_ref().map_or(0, |hash| hash.len())
+            + self.public_key_hash.as_ref().map_or(0, |hash| hash.len())
+            + self
+                .signing_domain
+                .as_ref()
+                .map_or(0, |domain| domain.len() * 6)
+            + self.correlation_id.as_ref().map_or(0, |id| id.len() * 6)
+            + 512
+    }
+}
+
+/// Serializes `value` into a buffer allocated once with `capacity` bytes. Unlike
+/// `serde_json::to_string`, whose buffer grows by doubling, the peak memory stays at the
+/// capacity when it is an upper bound of the output length.
+pub fn to_json_with_capacity<T: Serialize>(value: &T, capacity: usize) -> Result<String> {
+    let mut buf = Vec::with_capacity(capacity);
+    serde_json::to_writer(&mut buf, value)?;
+    Ok(String::from_utf8(buf)?)
 }
 
 impl CircuitInputParams {
@@ -103,14 +215,141 @@ impl CircuitInputParams {
             rsa_signature,
             rsa_public_key,
             sha_precompute_selector,
-            max_message_length: max_message_length.unwrap_or(MAX_HEADER_PADDED_BYTES),
-            max_body_length: max_body_length.unwrap_or(MAX_BODY_PADDED_BYTES),
+            max_message_length: max_message_length
+                .unwrap_or_else(|| active_circuit_profile().max_header_bytes),
+            max_body_length: max_body_length
+                .unwrap_or_else(|| active_circuit_profile().max_body_bytes),
             ignore_body_hash_check: ignore_body_hash_check.unwrap_or(false),
         }
     }
 }
 
+/// Sizes that predict the proving cost of an email, computed without generating its inputs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProvingCostEstimate {
+    /// `padded_header_len` of the generated input.
+    pub padded_header_len: usize,
+    /// Length of the zero-padded header array, i.e. the circuit's max header length.
+    pub max_header_len: usize,
+    /// Length of the padded body when the body hash is checked.
+    pub padded_body_len: Option<usize>,
+    pub num_pubkey_limbs: usize,
+    pub num_signature_limbs: usize,
+    pub key_bits: usize,
+    pub score: f64,
+}
+
+/// Weights of the proving cost score, which is computed as
+/// `header * max_header_len + body * padded_body_len + key * key_bits`. Set for the process with
+/// the `proving_cost_weights` of the init config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProvingCostWeights {
+    pub header: f64,
+    pub body: f64,
+    pub key: f64,
+}
+
+impl Default for ProvingCostWeights {
+    fn default() -> Self {
+        ProvingCostWeights {
+            header: 1.0,
+            body: 1.0,
+            key: 0.25,
+        }
+    }
+}
+
+impl ProvingCostWeights {
+    /// Fails when a weight is negative or not finite.
+    pub fn validate(&self) -> Result<()> {
+        for (name, weight) in [("header", self.header), ("body", self.body), ("key", self.key)] {
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(anyhow::anyhow!(
+                    "the {} proving cost weight must be a non-negative number, not {}",
+                    name,
+                    weight
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+static ACTIVE_PROVING_COST_WEIGHTS: once_cell::sync::Lazy<std::sync::RwLock<ProvingCostWeights>> =
+    once_cell::sync::Lazy::new(|| std::sync::RwLock::new(ProvingCostWeights::default()));
+
+/// The weights `estimateProvingCost` scores with; the defaults unless changed with
+/// `set_proving_cost_weights`.
+pub fn active_proving_cost_weights() -> ProvingCostWeights {
+    ACTIVE_PROVING_COST_WEIGHTS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Selects the proving cost weights for the whole process, as `init` does.
+pub fn set_proving_cost_weights(weights: ProvingCostWeights) -> Result<()> {
+    weights.validate()?;
+    *ACTIVE_PROVING_COST_WEIGHTS.write().unwrap_or_else(|e| e.into_inner()) = weights;
+    Ok(())
+}
+
+/// Estimates the proving cost of `parsed_email` with the same padding rules as
+/// `generate_circuit_inputs`, but without padding, hashing or limb decomposition.
+pub fn estimate_proving_cost(
+    parsed_email: &ParsedEmail,
+    options: &InputGenerationOptions,
+    weights: &ProvingCostWeights,
+) -> ProvingCostEstimate {
+    let padded_header_len = sha256_padded_len(parsed_email.canonicalized_header.len());
+    let padded_body_len = if options.ignore_body_hash {
+        None
+    } else {
+        Some(sha256_padded_len(parsed_email.canonicalized_body.len()))
+    };
+    let key_bits = match parsed_email.public_key.iter().position(|&byte| byte != 0) {
+        Some(i) => {
+            (parsed_email.public_key.len() - i) * 8
+                - parsed_email.public_key[i].leading_zeros() as usize
+        }
+        None => 0,
+    };
+    let score = weights.header * options.max_header_length as f64
+        + weights.body * padded_body_len.unwrap_or(0) as f64
+        + weights.key * key_bits as f64;
+    ProvingCostEstimate {
+        padded_header_len,
+        max_header_len: options.max_header_length,
+        padded_body_len,
+        num_pubkey_limbs: CIRCOM_BIGINT_K,
+        num_signature_limbs: CIRCOM_BIGINT_K,
+        key_bits,
+        score,
+    }
+}
+
+/// Panics where `try_generate_circuit_inputs` fails.
 pub fn generate_circuit_inputs(params: CircuitInputParams) -> CircuitInput {
+    try_generate_circuit_inputs(params).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Pads and decomposes the header and body as the circuit expects them. Fails with
+/// `ErrorCode::CircuitInputFailed` when the header or the body does not fit in its maximum
+/// length, or the body hash is not in the header.
+pub fn try_generate_circuit_inputs(params: CircuitInputParams) -> Result<CircuitInput> {
+    let padded_message_len = sha256_padded_len(params.message.len());
+    if padded_message_len > params.max_message_length {
+        return Err(coded(
+            ErrorCode::CircuitInputFailed,
+            format!(
+                "the header is too long: {} bytes, padded to {}, but max_header_length is {}",
+                params.message.len(),
+                padded_message_len,
+                params.max_message_length
+            ),
+        ));
+    }
     let (message_padded, message_padded_len) =
         sha256_pad(params.message.clone(), params.max_message_length);
     let body_sha_length = ((params.body.len() + 63 + 65) / 64) * 64;
@@ -128,7 +367,12 @@ pub fn generate_circuit_inputs(params: CircuitInputParams) -> CircuitInput {
 
     let (precomputed_sha, body_remaining, body_remaining_length) = match result {
         Ok((sha, remaining, len)) => (sha, remaining, len),
-        Err(e) => panic!("Failed to generate partial SHA: {:?}", e),
+        Err(e) => {
+            return Err(coded(
+                ErrorCode::CircuitInputFailed,
+                format!("Failed to generate partial SHA: {}", e),
+            ))
+        }
     };
 
     let mut circuit_input = CircuitInput {
@@ -145,19 +389,30 @@ pub fn generate_circuit_inputs(params: CircuitInputParams) -> CircuitInput {
     if !params.ignore_body_hash_check {
         circuit_input.precomputed_sha = Some(uint8_array_to_char_array(precomputed_sha));
         // Convert message into a string
-        let message_string = String::from_utf8(params.message).expect("Found invalid UTF-8");
-        let body_hash_idx = message_string
-            .find(&params.body_hash)
-            .unwrap_or_else(|| panic!("Body hash not found in message"));
+        let message_string = String::from_utf8(params.message).map_err(|e| {
+            coded(
+                ErrorCode::CircuitInputFailed,
+                format!("Found invalid UTF-8: {}", e),
+            )
+        })?;
+        let body_hash_idx = message_string.find(&params.body_hash).ok_or_else(|| {
+            coded(
+                ErrorCode::CircuitInputFailed,
+                "Body hash not found in message",
+            )
+        })?;
         circuit_input.body_hash_idx = Some(body_hash_idx.to_string());
         circuit_input.in_body_padded = Some(uint8_array_to_char_array(body_remaining));
         circuit_input.in_body_len_padded_bytes = Some(body_remaining_length.to_string());
     }
-    circuit_input
+    Ok(circuit_input)
 }
 
+#[cfg(feature = "dns")]
 pub async fn generate_email_sender_input(email: &str, account_code: &str) -> Result<String> {
     let parsed_email = ParsedEmail::new_from_raw_email(&email).await?;
+    check_provable_exponent(parsed_email.public_exponent)?;
+    let options = InputGenerationOptions::default();
     let circuit_input_params = circuit::CircuitInputParams::new(
         vec![],
         parsed_email.canonicalized_header.as_bytes().to_vec(),
@@ -165,22 +420,24 @@ pub async fn generate_email_sender_input(email: &str, account_code: &str) -> Res
         vec_u8_to_bigint(parsed_email.clone().signature),
         vec_u8_to_bigint(parsed_email.clone().public_key),
         None,
-        Some(1024),
-        Some(64),
-        Some(true),
+        Some(options.max_header_length),
+        Some(options.max_body_length),
+        Some(options.ignore_body_hash),
     );
-    let email_circuit_inputs = circuit::generate_circuit_inputs(circuit_input_params);
+    let email_circuit_inputs = circuit::try_generate_circuit_inputs(circuit_input_params)?;
 
-    let sender_email_idx = parsed_email.get_from_addr_idxes().unwrap();
-    let domain_idx = parsed_email.get_email_domain_idxes().unwrap();
-    let subject_idx = parsed_email.get_subject_all_idxes().unwrap();
+    let sender_email_idx = parsed_email.get_from_addr_idxes()?;
+    let domain_idx = parsed_email.get_email_domain_idxes()?;
+    let subject_idx = parsed_email.get_subject_all_idxes()?;
     let recipient_email_idx = match parsed_email.get_email_addr_in_subject_idxes() {
         Ok(idx) => idx.0,
         Err(_) => {
             0 // Assuming 0 is a safe default or placeholder value
         }
     };
-    let timestamp_idx = parsed_email.get_timestamp_idxes().unwrap();
+    let timestamp_idx = parsed_email
+        .get_timestamp_idxes_or_warn()
+        .map_or(0, |idxes| idxes.0);
 
     let email_sender_input = EmailSenderInput {
         in_padded: email_circuit_inputs.in_padded,
@@ -192,14 +449,17 @@ pub async fn generate_email_sender_input(email: &str, account_code: &str) -> Res
         subject_idx: subject_idx.0,
         recipient_email_idx: recipient_email_idx,
         domain_idx: domain_idx.0,
-        timestamp_idx: timestamp_idx.0,
+        timestamp_idx,
     };
 
     Ok(serde_json::to_string(&email_sender_input)?)
 }
 
+#[cfg(feature = "dns")]
 pub async fn generate_account_creation_input(email: &str, relayer_rand: &str) -> Result<String> {
     let parsed_email = ParsedEmail::new_from_raw_email(&email).await?;
+    check_provable_exponent(parsed_email.public_exponent)?;
+    let options = InputGenerationOptions::default();
     let circuit_input_params = circuit::CircuitInputParams::new(
         vec![],
         parsed_email.canonicalized_header.as_bytes().to_vec(),
@@ -207,17 +467,19 @@ pub async fn generate_account_creation_input(email: &str, relayer_rand: &str) ->
         vec_u8_to_bigint(parsed_email.clone().signature),
         vec_u8_to_bigint(parsed_email.clone().public_key),
         None,
-        Some(1024),
-        Some(64),
-        Some(true),
+        Some(options.max_header_length),
+        Some(options.max_body_length),
+        Some(options.ignore_body_hash),
     );
-    let email_circuit_inputs = circuit::generate_circuit_inputs(circuit_input_params);
+    let email_circuit_inputs = circuit::try_generate_circuit_inputs(circuit_input_params)?;
 
-    let sender_email_idx = parsed_email.get_from_addr_idxes().unwrap();
-    let domain_idx = parsed_email.get_email_domain_idxes().unwrap();
+    let sender_email_idx = parsed_email.get_from_addr_idxes()?;
+    let domain_idx = parsed_email.get_email_domain_idxes()?;
     // let subject_idx = parsed_email.get_subject_all_idxes().unwrap();
-    let code_idx = parsed_email.get_invitation_code_idxes().unwrap();
-    let timestamp_idx = parsed_email.get_timestamp_idxes().unwrap();
+    let code_idx = parsed_email.get_invitation_code_idxes()?;
+    let timestamp_idx = parsed_email
+        .get_timestamp_idxes_or_warn()
+        .map_or(0, |idxes| idxes.0);
 
     let account_creation_input = AccountCreationInput {
         in_padded: email_circuit_inputs.in_padded,
@@ -228,7 +490,7 @@ pub async fn generate_account_creation_input(email: &str, relayer_rand: &str) ->
         sender_email_idx: sender_email_idx.0,
         code_idx: code_idx.0,
         domain_idx: domain_idx.0,
-        timestamp_idx: timestamp_idx.0,
+        timestamp_idx,
     };
 
     Ok(serde_json::to_string(&account_creation_input)?)
@@ -255,6 +517,7 @@ pub async fn generate_claim_input(
     Ok(serde_json::to_string(&claim_input)?)
 }
 
+#[cfg(feature = "node")]
 pub fn generate_account_creation_input_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let email = cx.argument::<JsString>(0)?.value(&mut cx);
     let relayer_rand = cx.argument::<JsString>(1)?.value(&mut cx);
@@ -270,13 +533,18 @@ pub fn generate_account_creation_input_node(mut cx: FunctionContext) -> JsResult
                 let account_creation_input = cx.string(account_creation_input);
                 Ok(account_creation_input)
             }
-            Err(err) => cx.throw_error(format!("Could not generate email sender input: {}", err)),
+            Err(err) => throw_coded(
+                &mut cx,
+                error_code(&err, ErrorCode::CircuitInputFailed),
+                format!("Could not generate email sender input: {}", err),
+            ),
         });
     });
 
     Ok(promise)
 }
 
+#[cfg(feature = "node")]
 pub fn generate_email_sender_input_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let email = cx.argument::<JsString>(0)?.value(&mut cx);
     let account_code = cx.argument::<JsString>(1)?.value(&mut cx);
@@ -292,71 +560,1464 @@ pub fn generate_email_sender_input_node(mut cx: FunctionContext) -> JsResult<JsP
                 let email_sender_input = cx.string(email_sender_input);
                 Ok(email_sender_input)
             }
-            Err(err) => cx.throw_error(format!("Could not generate email sender input: {}", err)),
+            Err(err) => throw_coded(
+                &mut cx,
+                error_code(&err, ErrorCode::CircuitInputFailed),
+                format!("Could not generate email sender input: {}", err),
+            ),
         });
     });
 
     Ok(promise)
 }
 
+/// Generates the email auth input of `email` with the default options, running every stage from
+/// `RawEmail::parse` on.
+#[cfg(feature = "dns")]
 pub async fn generate_email_auth_input(email: &str, account_code: &AccountCode) -> Result<String> {
-    let parsed_email = ParsedEmail::new_from_raw_email(&email).await?;
-    let circuit_input_params = circuit::CircuitInputParams::new(
-        vec![],
-        parsed_email.canonicalized_header.as_bytes().to_vec(),
-        "".to_string(),
-        vec_u8_to_bigint(parsed_email.clone().signature),
-        vec_u8_to_bigint(parsed_email.clone().public_key),
-        None,
-        Some(1024),
-        Some(64),
-        Some(true),
-    );
-    let email_circuit_inputs = circuit::generate_circuit_inputs(circuit_input_params);
-
-    let from_addr_idx = parsed_email.get_from_addr_idxes().unwrap().0;
-    let domain_idx = parsed_email.get_email_domain_idxes().unwrap().0;
-    let subject_idx = parsed_email.get_subject_all_idxes().unwrap().0;
-    let code_idx = match parsed_email.get_invitation_code_idxes() {
-        Ok(indexes) => indexes.0,
-        Err(_) => 0,
+    let options = InputGenerationOptions::default();
+    RawEmail::from(email)
+        .parse()?
+        .resolve_and_verify()
+        .await?
+        .extract(account_code, &options)?
+        .circuit_inputs(&options)?
+        .to_json()
+}
+
+/// Like `generate_email_auth_input`, but honoring `options`. When `debug_dump_dir` is set, the
+/// pipeline artifacts are dumped on failure (or always) and the error carries the correlation id.
+#[cfg(feature = "dns")]
+pub async fn generate_email_auth_input_with_options(
+    email: &str,
+    account_code: &AccountCode,
+    options: &InputGenerationOptions,
+) -> Result<String> {
+    generate_email_auth_input_from_bytes(email.as_bytes(), account_code, options).await
+}
+
+/// `generate_email_auth_input_with_options` for the exact on-the-wire bytes of the email, which
+/// is the way to pass emails whose body is not UTF-8; see `ParsedEmail::new_from_raw_bytes`.
+#[cfg(feature = "dns")]
+pub async fn generate_email_auth_input_from_bytes(
+    email: &[u8],
+    account_code: &AccountCode,
+    options: &InputGenerationOptions,
+) -> Result<String> {
+    let _registration = register_cancellation(options.correlation_id.as_deref());
+    generate_email_auth_input_with_options_inner(email, account_code, options, None)
+        .instrument(email_auth_input_span(options.correlation_id.as_deref()))
+        .await
+}
+
+/// Default number of emails of a batch processed at once.
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 8;
+
+/// One result of `generate_email_auth_input_batch`: `code` 0 with the input in `data`, or the
+/// `ErrorCode` of the failure with the error in `msg`. The input is a JSON string, or the nested
+/// object itself once the entry is `structured`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchEntry {
+    pub code: u8,
+    pub msg: String,
+    pub data: Option<serde_json::Value>,
+}
+
+impl BatchEntry {
+    /// A successful entry with `data`, either a JSON string or an already structured value.
+    pub fn success(data: impl Into<serde_json::Value>) -> Self {
+        BatchEntry {
+            code: 0,
+            msg: "success".to_string(),
+            data: Some(data.into()),
+        }
+    }
+
+    /// The entry with a JSON string `data` replaced by the value it encodes, so that the input
+    /// is not encoded twice when the entry is serialized.
+    pub fn structured(self) -> Self {
+        let data = match self.data {
+            Some(serde_json::Value::String(json)) => match serde_json::from_str(&json) {
+                Ok(data) => Some(data),
+                Err(_) => Some(serde_json::Value::String(json)),
+            },
+            data => data,
+        };
+        BatchEntry { data, ..self }
+    }
+
+    fn error(code: ErrorCode, msg: String) -> Self {
+        BatchEntry {
+            code: code.as_u8(),
+            msg,
+            data: None,
+        }
+    }
+}
+
+/// Text of a panic payload, which is a `&str` or a `String` for `panic!` with a message.
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|msg| msg.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// Generates the email auth inputs of `(email, account code)` pairs on the current runtime, at
+/// most `max_concurrency` at a time. The entries are in the order of `items`. Each email runs in
+/// its own task, so that an error or a panic only fails its own entry.
+#[cfg(all(feature = "dns", feature = "async-runtime"))]
+pub async fn generate_email_auth_input_batch(
+    items: Vec<(String, AccountCode)>,
+    options: &InputGenerationOptions,
+    max_concurrency: usize,
+) -> Vec<BatchEntry> {
+    generate_email_auth_input_batch_with_progress(items, options, max_concurrency, |_, _| {})
+        .await
+}
+
+/// `generate_email_auth_input_batch`, calling `on_progress(completed, total)` once per entry in
+/// the order of `items`, so that `completed` goes up by one to `total`.
+#[cfg(all(feature = "dns", feature = "async-runtime"))]
+pub async fn generate_email_auth_input_batch_with_progress(
+    items: Vec<(String, AccountCode)>,
+    options: &InputGenerationOptions,
+    max_concurrency: usize,
+    on_progress: impl FnMut(usize, usize),
+) -> Vec<BatchEntry> {
+    let options = std::sync::Arc::new(options.clone());
+    run_batch(items, max_concurrency, on_progress, move |email, account_code| {
+        let options = options.clone();
+        async move { generate_email_auth_input_with_options(&email, &account_code, &options).await }
+    })
+    .await
+}
+
+/// Runs `generate` on each item in its own task, at most `max_concurrency` at a time.
+#[cfg(all(feature = "dns", feature = "async-runtime"))]
+async fn run_batch<G, F>(
+    items: Vec<(String, AccountCode)>,
+    max_concurrency: usize,
+    mut on_progress: impl FnMut(usize, usize),
+    generate: G,
+) -> Vec<BatchEntry>
+where
+    G: Fn(String, AccountCode) -> F,
+    F: std::future::Future<Output = Result<String>> + Send + 'static,
+{
+    let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(max_concurrency.max(1)));
+    let tasks = items
+        .into_iter()
+        .map(|(email, account_code)| {
+            let semaphore = semaphore.clone();
+            let input = generate(email, account_code);
+            // Caught in the task, a panic carries the location and backtrace of the hook.
+            tokio::spawn(catch_panic("input generation", async move {
+                let _permit = semaphore.acquire_owned().await;
+                input.await
+            }))
+        })
+        .collect::<Vec<_>>();
+    let total = tasks.len();
+    let mut entries = Vec::with_capacity(total);
+    for task in tasks {
+        entries.push(match task.await {
+            Ok(Ok(Ok(input))) => BatchEntry::success(input),
+            Ok(Ok(Err(e))) | Ok(Err(e)) => BatchEntry::error(
+                error_code(&e, ErrorCode::CircuitInputFailed),
+                e.to_string(),
+            ),
+            Err(e) if e.is_panic() => BatchEntry::error(
+                ErrorCode::InternalPanic,
+                format!("input generation panicked: {}", panic_message(&*e.into_panic())),
+            ),
+            Err(e) => BatchEntry::error(ErrorCode::InternalPanic, e.to_string()),
+        });
+        on_progress(entries.len(), total);
+    }
+    entries
+}
+
+/// Generates the input of `email`, resolving its DKIM key over DNS, or from `recorded_keys` for a
+/// replay.
+#[cfg(feature = "dns")]
+pub(crate) async fn generate_email_auth_input_with_options_inner(
+    email: &[u8],
+    account_code: &AccountCode,
+    options: &InputGenerationOptions,
+    recorded_keys: Option<&[ResolverAnswer]>,
+) -> Result<String> {
+    let checked =
+        generate_email_auth_input_checked(email, account_code, options, recorded_keys).await;
+    let (parsed_email, result) = match checked {
+        Ok((parsed_email, result)) => (Some(parsed_email), result),
+        Err(e) => (None, Err(e)),
+    };
+    if let Some(path) = &options.record_replay {
+        let recorded = record_replay(
+            path,
+            email,
+            account_code,
+            options,
+            parsed_email.as_ref(),
+            &result,
+        );
+        if let Err(record_err) = recorded {
+            slog::warn!(LOG, "failed to record the replay bundle";
+                "error" => record_err.to_string());
+        }
+    }
+    // A replay reproduces a generation that was already audited.
+    #[cfg(feature = "async-runtime")]
+    if recorded_keys.is_none() {
+        record_audit(|| {
+            AuditRecord::of(
+                parsed_email.as_ref().and_then(|parsed| parsed.as_ref().ok()),
+                account_code,
+                options.correlation_id.as_deref(),
+                &result,
+            )
+        });
+    }
+    // Emails rejected before parsing have no artifacts.
+    let parsed_email = match parsed_email {
+        Some(parsed_email) => parsed_email,
+        None => return result,
+    };
+    let dump_dir = match &options.debug_dump_dir {
+        Some(dump_dir) if result.is_err() || options.debug_dump_always => dump_dir,
+        _ => return result,
+    };
+    match dump_debug_artifacts(dump_dir, parsed_email.as_ref().ok(), &result) {
+        Ok(correlation_id) => result.map_err(|e| {
+            coded(
+                error_code(&e, ErrorCode::CircuitInputFailed),
+                format!("{} (debug artifacts: correlation id {})", e, correlation_id),
+            )
+        }),
+        Err(dump_err) => {
+            slog::warn!(LOG, "failed to dump debug artifacts"; "error" => dump_err.to_string());
+            result
+        }
+    }
+}
+
+/// Runs the stages of `generate_email_auth_input_with_options_inner`. Fails early on a rejected
+/// email or a cancellation; otherwise returns the verified email and the result, for the replay
+/// bundle and the debug artifacts.
+#[cfg(feature = "dns")]
+async fn generate_email_auth_input_checked(
+    email: &[u8],
+    account_code: &AccountCode,
+    options: &InputGenerationOptions,
+    recorded_keys: Option<&[ResolverAnswer]>,
+) -> Result<(Result<ParsedEmail>, Result<String>)> {
+    let raw_email = RawEmail::new(email.to_vec()).check(options)?;
+    let token = cancellation_token(options.correlation_id.as_deref()).unwrap_or_default();
+    token.check("parsing")?;
+    let verified_email = parse_and_verify(raw_email, options, recorded_keys, &token)
+        .instrument(tracing::info_span!("parse"))
+        .await?;
+    token.check("the header indexes")?;
+    let result = match &verified_email {
+        Ok(verified_email) => verified_email
+            .clone()
+            .extract(account_code, options)
+            .and_then(|extracted| extracted.circuit_inputs(options))
+            .and_then(|inputs| inputs.to_json()),
+        Err(e) => Err(coded(error_code(e, ErrorCode::ParseEmailFailed), e)),
     };
-    let timestamp_idx = parsed_email.get_timestamp_idxes().unwrap().0;
+    Ok((verified_email.map(VerifiedEmail::into_parsed_email), result))
+}
 
-    let email_auth_input = EmailAuthInput {
-        padded_header: email_circuit_inputs.in_padded,
-        public_key: email_circuit_inputs.pubkey,
-        signature: email_circuit_inputs.signature,
-        padded_header_len: email_circuit_inputs.in_len_padded_bytes,
-        account_code: field2hex(&account_code.0),
-        from_addr_idx: from_addr_idx,
-        subject_idx: subject_idx,
-        domain_idx: domain_idx,
-        timestamp_idx: timestamp_idx,
-        code_idx,
+/// The `RawEmail::parse` and `ParsedHeaders::verify` stages, with the key resolved over DNS or
+/// taken from `recorded_keys`. Only a cancellation fails the outer result.
+#[cfg(feature = "dns")]
+async fn parse_and_verify(
+    raw_email: RawEmail,
+    options: &InputGenerationOptions,
+    recorded_keys: Option<&[ResolverAnswer]>,
+    token: &CancellationToken,
+) -> Result<Result<VerifiedEmail>> {
+    let parsed_headers = match raw_email.parse().and_then(|parsed| parsed.check(options)) {
+        Ok(parsed_headers) => parsed_headers,
+        Err(e) => return Ok(Err(e)),
+    };
+    let fetch = match recorded_keys {
+        Some(recorded_keys) => {
+            recorded_public_key(parsed_headers.raw_email().as_bytes(), recorded_keys).map(
+                |public_key| DkimKeyFetch {
+                    public_key,
+                    public_exponent: DEFAULT_PUBLIC_EXPONENT,
+                    dnssec_validated: None,
+                },
+            )
+        }
+        None => {
+            let fetch = parsed_headers.resolve_key_fetch(options.require_dnssec);
+            token.run("the DKIM key fetch", fetch).await?
+        }
+    };
+    Ok(fetch.and_then(|fetch| parsed_headers.verify_fetched(fetch)))
+}
+
+/// Generates the email auth input from an email cached with `ParsedEmail::to_cached_bytes`,
+/// skipping email parsing and the DNS lookup. The output is the same as for the raw email.
+pub fn generate_input_from_cached(
+    cached_email: &[u8],
+    account_code: &AccountCode,
+    options: &InputGenerationOptions,
+) -> Result<String> {
+    let parsed_email = ParsedEmail::from_cached_bytes(cached_email)?;
+    let _span = email_auth_input_span(options.correlation_id.as_deref()).entered();
+    generate_email_auth_input_from_parsed_email(&parsed_email, account_code, options)
+}
+
+/// Generates the email auth input from a header that is already canonicalized, skipping email
+/// parsing and the DNS lookup. The signature is verified against `canonical_header` first.
+/// `signature` and `public_key` are the big-endian bytes stored in `ParsedEmail`.
+pub fn generate_email_auth_input_from_canonical(
+    canonical_header: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+    account_code: &AccountCode,
+) -> Result<String> {
+    verify_rsa_sha256(canonical_header, signature, public_key)?;
+    let parsed_email = ParsedEmail {
+        canonicalized_header: String::from_utf8(canonical_header.to_vec())?,
+        canonicalized_body: String::new(),
+        signature: signature.to_vec(),
+        public_key: public_key.to_vec(),
+        public_exponent: DEFAULT_PUBLIC_EXPONENT,
+        dnssec_validated: None,
+    };
+    generate_email_auth_input_from_parsed_email(
+        &parsed_email,
+        account_code,
+        &InputGenerationOptions::default(),
+    )
+}
+
+/// Fails with `CircuitInputFailed` before any circuit input is built when the subject is longer
+/// than `profile` allows.
+pub fn check_subject_len(indexes: &HeaderIndexes, profile: &CircuitProfile) -> Result<()> {
+    if let Some((start, end)) = indexes.subject {
+        if end - start > profile.max_subject_bytes {
+            return Err(coded(
+                ErrorCode::CircuitInputFailed,
+                format!(
+                    "the subject is {} bytes long but must be at most {} bytes",
+                    end - start,
+                    profile.max_subject_bytes
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Mismatch between a value in `InputGenerationOptions::expected` and the email.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpectationMismatch {
+    FromDomain { expected: String, actual: String },
+    CommandTemplate { template: String, command: String },
+    AccountSalt { expected: String, actual: String },
+    /// `rotation` is the last key seen for the signing (domain, selector) when it has replaced
+    /// another one and is the key of the email.
+    PublicKeyHash {
+        expected: String,
+        actual: String,
+        rotation: Option<KeySighting>,
+    },
+}
+
+impl ExpectationMismatch {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ExpectationMismatch::FromDomain { .. } => "EXPECTED_FROM_DOMAIN_MISMATCH",
+            ExpectationMismatch::CommandTemplate { .. } => "EXPECTED_COMMAND_TEMPLATE_MISMATCH",
+            ExpectationMismatch::AccountSalt { .. } => "EXPECTED_ACCOUNT_SALT_MISMATCH",
+            ExpectationMismatch::PublicKeyHash { .. } => "EXPECTED_PUBLIC_KEY_HASH_MISMATCH",
+        }
+    }
+}
+
+impl std::fmt::Display for ExpectationMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpectationMismatch::FromDomain { expected, actual } => write!(
+                f,
+                "{}: the from domain is `{}` but `{}` was expected",
+                self.code(),
+                actual,
+                expected
+            ),
+            ExpectationMismatch::CommandTemplate { template, command } => write!(
+                f,
+                "{}: the subject `{}` does not match the template `{}`",
+                self.code(),
+                command,
+                template
+            ),
+            ExpectationMismatch::AccountSalt { expected, actual } => write!(
+                f,
+                "{}: the account salt is {} but {} was expected",
+                self.code(),
+                actual,
+                expected
+            ),
+            ExpectationMismatch::PublicKeyHash {
+                expected,
+                actual,
+                rotation,
+            } => {
+                write!(
+                    f,
+                    "{}: the public key hash is {} but {} was expected",
+                    self.code(),
+                    actual,
+                    expected
+                )?;
+                if let Some(KeySighting {
+                    public_key_hash,
+                    first_seen,
+                    rotated_from: Some(old),
+                    ..
+                }) = rotation
+                {
+                    write!(
+                        f,
+                        "; key appears rotated: old={}, new={}, first seen {}",
+                        old, public_key_hash, first_seen
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExpectationMismatch {}
+
+/// Checks each value set in `expected` against the header. A mismatch fails with an
+/// `ExpectationMismatch`, which callers can downcast to.
+pub fn check_expected_values(
+    canonical_header: &str,
+    indexes: &HeaderIndexes,
+    account_code: &AccountCode,
+    expected: &ExpectedValues,
+) -> Result<()> {
+    let from_addr = indexes
+        .from_addr
+        .map(|(start, end)| &canonical_header[start..end]);
+    if let Some(expected_domain) = &expected.from_domain {
+        let actual = match (from_addr, indexes.domain) {
+            (Some(from_addr), Some((start, end))) => &from_addr[start..end],
+            _ => "",
+        };
+        if !actual.eq_ignore_ascii_case(expected_domain) {
+            return Err(ExpectationMismatch::FromDomain {
+                expected: expected_domain.clone(),
+                actual: actual.to_string(),
+            }
+            .into());
+        }
+    }
+    if let Some(template) = &expected.command_template {
+        let command = indexes
+            .subject
+            .map_or("", |(start, end)| &canonical_header[start..end]);
+        if !matches_command_template(template, command) {
+            return Err(ExpectationMismatch::CommandTemplate {
+                template: template.clone(),
+                command: command.to_string(),
+            }
+            .into());
+        }
+    }
+    if let Some(expected_salt) = &expected.account_salt {
+        let from_addr =
+            from_addr.ok_or_else(|| anyhow::anyhow!("from address is not found in the header"))?;
+        let salt = AccountSalt::new(&PaddedEmailAddr::from_email_addr(from_addr), *account_code)
+            .map_err(|e| anyhow::anyhow!("AccountSalt failed: {}", e))?;
+        if salt.0 != hex2field(expected_salt)? {
+            return Err(ExpectationMismatch::AccountSalt {
+                expected: expected_salt.to_lowercase(),
+                actual: field2hex(&salt.0),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Checks `expected.public_key_hash`, if set, against the key of `parsed_email`. The mismatch
+/// carries the last key seen for the (domain, selector) of its DKIM-Signature when that key is
+/// the email's and replaced another one, since a caller expecting the old hash is the usual
+/// cause.
+pub fn check_expected_public_key_hash(
+    parsed_email: &ParsedEmail,
+    expected: &ExpectedValues,
+) -> Result<()> {
+    let expected_hash = match &expected.public_key_hash {
+        Some(expected_hash) => expected_hash,
+        None => return Ok(()),
     };
+    let hash = public_key_hash_from_modulus(&parsed_email.public_key)
+        .map_err(|e| anyhow::anyhow!("public_key_hash failed: {}", e))?;
+    if hash == hex2field(expected_hash)? {
+        return Ok(());
+    }
+    let actual = field2hex(&hash);
+    let rotation = DkimKeyId::from_raw_email(&parsed_email.canonicalized_header)
+        .and_then(|key_id| dkim_key_sighting(&key_id))
+        .filter(|sighting| sighting.rotated_from.is_some() && sighting.public_key_hash == actual);
+    Err(ExpectationMismatch::PublicKeyHash {
+        expected: expected_hash.to_lowercase(),
+        actual,
+        rotation,
+    }
+    .into())
+}
 
-    Ok(serde_json::to_string(&email_auth_input)?)
+/// Generates the email auth input of an email that is already parsed, with the stages from
+/// `VerifiedEmail::from_parsed_email` on, so that an email whose signature does not verify with
+/// its key is rejected.
+pub fn generate_email_auth_input_from_parsed_email(
+    parsed_email: &ParsedEmail,
+    account_code: &AccountCode,
+    options: &InputGenerationOptions,
+) -> Result<String> {
+    VerifiedEmail::from_parsed_email(parsed_email.clone())?
+        .extract(account_code, options)?
+        .circuit_inputs(options)?
+        .to_json()
 }
 
+/// `check_header_digest_invariant` for the inputs generated from `parsed_email`.
+pub(crate) fn header_digest_invariant_of(
+    inputs: &CircuitInput,
+    parsed_email: &ParsedEmail,
+) -> Result<()> {
+    let padded_header = inputs
+        .in_padded
+        .iter()
+        .map(|byte| byte.parse::<u8>())
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    check_header_digest_invariant(
+        &padded_header,
+        inputs.in_len_padded_bytes.parse()?,
+        None,
+        &parsed_email.signature,
+        &parsed_email.public_key,
+    )
+}
+
+#[cfg(feature = "node")]
 pub fn generate_email_auth_input_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let email = cx.argument::<JsString>(0)?.value(&mut cx);
     let account_code = cx.argument::<JsString>(1)?.value(&mut cx);
     let account_code = AccountCode::from(hex2field_node(&mut cx, &account_code)?);
+    let correlation_id = match cx.argument_opt(2) {
+        Some(id) if !id.is_a::<JsUndefined, _>(&mut cx) => {
+            Some(id.downcast_or_throw::<JsString, _>(&mut cx)?.value(&mut cx))
+        }
+        _ => None,
+    };
+    let options = InputGenerationOptions {
+        correlation_id,
+        ..Default::default()
+    };
     let channel = cx.channel();
     let (deferred, promise) = cx.promise();
     let rt = runtime(&mut cx)?;
 
     rt.spawn(async move {
-        let email_auth_input = generate_email_auth_input(&email, &account_code).await;
+        let email_auth_input =
+            generate_email_auth_input_with_options(&email, &account_code, &options).await;
         deferred.settle_with(&channel, move |mut cx| match email_auth_input {
             Ok(email_auth_input) => {
                 let email_auth_input = cx.string(email_auth_input);
                 Ok(email_auth_input)
             }
-            Err(err) => cx.throw_error(format!("Could not generate email auth input: {}", err)),
+            Err(err) => throw_coded(
+                &mut cx,
+                error_code(&err, ErrorCode::CircuitInputFailed),
+                format!("Could not generate email auth input: {}", err),
+            ),
         });
     });
 
     Ok(promise)
 }
+
+/// `generateEmailInputWithOptions(email, accountCode, optionsJson)` takes the options of
+/// `InputGenerationOptions`, such as `max_header_length`, `max_body_length`, `ignore_body_hash`
+/// and `sha_precompute_selector`. Unset options keep the defaults of `genEmailAuthInput`.
+#[cfg(feature = "node")]
+pub fn generate_email_input_with_options_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let email = cx.argument::<JsString>(0)?.value(&mut cx);
+    let account_code = cx.argument::<JsString>(1)?.value(&mut cx);
+    let account_code = AccountCode::from(hex2field_node(&mut cx, &account_code)?);
+    let options_json = cx.argument::<JsString>(2)?.value(&mut cx);
+    let options = match InputGenerationOptions::from_json(&options_json) {
+        Ok(options) => options,
+        Err(e) => return throw_coded(&mut cx, ErrorCode::InvalidArgument, e.to_string()),
+    };
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(&mut cx)?;
+
+    rt.spawn(async move {
+        let email_auth_input =
+            generate_email_auth_input_with_options(&email, &account_code, &options).await;
+        deferred.settle_with(&channel, move |mut cx| match email_auth_input {
+            Ok(email_auth_input) => Ok(cx.string(email_auth_input)),
+            Err(err) => throw_coded(
+                &mut cx,
+                error_code(&err, ErrorCode::CircuitInputFailed),
+                format!("Could not generate email auth input: {}", err),
+            ),
+        });
+    });
+
+    Ok(promise)
+}
+
+/// `genEmailAuthInputBytes(buffer, accountCode, options?)` takes the exact bytes of the email,
+/// which is the documented way for large emails or emails whose body is not UTF-8.
+#[cfg(feature = "node")]
+pub fn generate_email_auth_input_bytes_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let email = cx.argument::<JsBuffer>(0)?;
+    let email = email.as_slice(&cx).to_vec();
+    let account_code = cx.argument::<JsString>(1)?.value(&mut cx);
+    let account_code = AccountCode::from(hex2field_node(&mut cx, &account_code)?);
+    let options = options_argument(&mut cx, 2)?;
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(&mut cx)?;
+
+    rt.spawn(async move {
+        let email_auth_input =
+            generate_email_auth_input_from_bytes(&email, &account_code, &options).await;
+        deferred.settle_with(&channel, move |mut cx| match email_auth_input {
+            Ok(email_auth_input) => Ok(cx.string(email_auth_input)),
+            Err(err) => throw_coded(
+                &mut cx,
+                error_code(&err, ErrorCode::CircuitInputFailed),
+                format!("Could not generate email auth input: {}", err),
+            ),
+        });
+    });
+
+    Ok(promise)
+}
+
+/// `generateEmailAuthInputBatch(emails, accountCodes, options?, maxConcurrency?, onProgress?)`
+/// resolves to the JSON array of `BatchEntry`, with each input as a JSON string. `onProgress` is
+/// called with `(completed, total)` on the JavaScript thread after each entry, before the promise
+/// resolves; a callback that throws does not stop the batch.
+#[cfg(feature = "node")]
+pub fn generate_email_auth_input_batch_node(cx: FunctionContext) -> JsResult<JsPromise> {
+    generate_email_auth_input_batch_js(cx, false)
+}
+
+/// `generateEmailAuthInputBatchV2` is `generateEmailAuthInputBatch` with each input nested as an
+/// object, so that it is parsed once.
+#[cfg(feature = "node")]
+pub fn generate_email_auth_input_batch_v2_node(cx: FunctionContext) -> JsResult<JsPromise> {
+    generate_email_auth_input_batch_js(cx, true)
+}
+
+#[cfg(feature = "node")]
+fn generate_email_auth_input_batch_js(
+    mut cx: FunctionContext,
+    structured: bool,
+) -> JsResult<JsPromise> {
+    let emails = cx.argument::<JsArray>(0)?.to_vec(&mut cx)?;
+    let account_codes = cx.argument::<JsArray>(1)?.to_vec(&mut cx)?;
+    if emails.len() != account_codes.len() {
+        return cx.throw_error(format!(
+            "got {} emails but {} account codes",
+            emails.len(),
+            account_codes.len()
+        ));
+    }
+    let mut items = Vec::with_capacity(emails.len());
+    for (email, account_code) in emails.into_iter().zip(account_codes) {
+        let email = email.downcast_or_throw::<JsString, _>(&mut cx)?.value(&mut cx);
+        let account_code = account_code
+            .downcast_or_throw::<JsString, _>(&mut cx)?
+            .value(&mut cx);
+        let account_code = AccountCode::from(hex2field_node(&mut cx, &account_code)?);
+        items.push((email, account_code));
+    }
+    let options = options_argument(&mut cx, 2)?;
+    let max_concurrency = match cx.argument_opt(3) {
+        Some(n) if !n.is_a::<JsUndefined, _>(&mut cx) => {
+            let n = n.downcast_or_throw::<JsNumber, _>(&mut cx)?.value(&mut cx);
+            if n < 1.0 || n.fract() != 0.0 {
+                return cx.throw_error("maxConcurrency must be a positive integer");
+            }
+            n as usize
+        }
+        _ => DEFAULT_BATCH_CONCURRENCY,
+    };
+    let on_progress = match cx.argument_opt(4) {
+        Some(callback)
+            if !callback.is_a::<JsUndefined, _>(&mut cx)
+                && !callback.is_a::<JsNull, _>(&mut cx) =>
+        {
+            let callback = callback.downcast_or_throw::<JsFunction, _>(&mut cx)?;
+            Some(std::sync::Arc::new(callback.root(&mut cx)))
+        }
+        _ => None,
+    };
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(&mut cx)?;
+
+    // Sent on the channel of the promise, so that every call runs before it settles.
+    let progress_channel = channel.clone();
+    let report_progress = move |completed: usize, total: usize| {
+        let callback = match &on_progress {
+            Some(callback) => callback.clone(),
+            None => return,
+        };
+        progress_channel.send(move |mut cx| {
+            let callback = callback.to_inner(&mut cx);
+            let this = cx.undefined();
+            let args = vec![
+                cx.number(completed as f64).upcast::<JsValue>(),
+                cx.number(total as f64).upcast(),
+            ];
+            if cx.try_catch(|cx| callback.call(cx, this, args)).is_err() {
+                slog::warn!(LOG, "the batch progress callback threw");
+            }
+            Ok(())
+        });
+    };
+    rt.spawn(async move {
+        let mut entries = generate_email_auth_input_batch_with_progress(
+            items,
+            &options,
+            max_concurrency,
+            report_progress,
+        )
+        .await;
+        if structured {
+            entries = entries.into_iter().map(BatchEntry::structured).collect();
+        }
+        let entries = serde_json::to_string(&entries);
+        deferred.settle_with(&channel, move |mut cx| match entries {
+            Ok(entries) => Ok(cx.string(entries)),
+            Err(err) => cx.throw_error(format!("Could not serialize the batch: {}", err)),
+        });
+    });
+
+    Ok(promise)
+}
+
+#[cfg(feature = "node")]
+pub fn estimate_proving_cost_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let email = cx.argument::<JsString>(0)?.value(&mut cx);
+    let options = options_argument(&mut cx, 1)?;
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(&mut cx)?;
+
+    rt.spawn(async move {
+        let estimate = ParsedEmail::new_from_raw_email(&email).await.and_then(|parsed_email| {
+            let weights = active_proving_cost_weights();
+            let estimate = estimate_proving_cost(&parsed_email, &options, &weights);
+            Ok(serde_json::to_string(&estimate)?)
+        });
+        deferred.settle_with(&channel, move |mut cx| match estimate {
+            Ok(estimate) => Ok(cx.string(estimate)),
+            Err(err) => cx.throw_error(format!("Could not estimate the proving cost: {}", err)),
+        });
+    });
+
+    Ok(promise)
+}
+
+#[cfg(feature = "node")]
+pub fn generate_email_auth_input_from_canonical_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let canonical_header = cx.argument::<JsString>(0)?.value(&mut cx);
+    let signature = trimmed_argument(&mut cx, 1, "signature")?;
+    let public_key = trimmed_argument(&mut cx, 2, "public_key")?;
+    let account_code = cx.argument::<JsString>(3)?.value(&mut cx);
+    let signature = match decode_hex(&signature) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return throw_coded(
+                &mut cx,
+                ErrorCode::InvalidArgument,
+                format!("signature is an invalid hex string: {}", e),
+            )
+        }
+    };
+    let public_key = match decode_hex(&public_key) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return throw_coded(
+                &mut cx,
+                ErrorCode::InvalidArgument,
+                format!("public_key is an invalid hex string: {}", e),
+            )
+        }
+    };
+    let account_code = AccountCode::from(hex2field_node(&mut cx, &account_code)?);
+    match generate_email_auth_input_from_canonical(
+        canonical_header.as_bytes(),
+        &signature,
+        &public_key,
+        &account_code,
+    ) {
+        Ok(email_auth_input) => Ok(cx.string(email_auth_input)),
+        Err(err) => throw_coded(
+            &mut cx,
+            error_code(&err, ErrorCode::CircuitInputFailed),
+            format!("Could not generate email auth input: {}", err),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_proving_cost() {
+        let header = "from:alice@gmail.com\r\nsubject:Send 1 ETH\r\n".repeat(5);
+        let parsed_email = fixture_signed_email(&header, "");
+        let options = InputGenerationOptions::default();
+        let estimate =
+            estimate_proving_cost(&parsed_email, &options, &ProvingCostWeights::default());
+
+        let circuit_input = generate_circuit_inputs(CircuitInputParams::new(
+            vec![],
+            parsed_email.canonicalized_header.as_bytes().to_vec(),
+            "".to_string(),
+            vec_u8_to_bigint(parsed_email.signature.clone()),
+            vec_u8_to_bigint(parsed_email.public_key.clone()),
+            None,
+            Some(options.max_header_length),
+            Some(options.max_body_length),
+            Some(options.ignore_body_hash),
+        ));
+        assert_eq!(
+            estimate.padded_header_len.to_string(),
+            circuit_input.in_len_padded_bytes
+        );
+        assert_eq!(estimate.max_header_len, circuit_input.in_padded.len());
+        assert_eq!(estimate.num_pubkey_limbs, circuit_input.pubkey.len());
+        assert_eq!(estimate.num_signature_limbs, circuit_input.signature.len());
+        assert_eq!(estimate.key_bits, 2048);
+        assert_eq!(estimate.padded_body_len, None);
+    }
+
+    #[test]
+    fn test_check_subject_len() {
+        let subject = "A".repeat(4096);
+        let header = format!("from:alice@gmail.com\r\nsubject:{}\r\n", subject);
+        let indexes = extract_indexes(header.as_bytes()).unwrap();
+        let err = check_subject_len(&indexes, &CircuitProfile::default())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("4096") && err.contains(&MAX_SUBJECT_BYTES.to_string()));
+
+        let header = "from:alice@gmail.com\r\nsubject:Send 1 ETH\r\n";
+        let indexes = extract_indexes(header.as_bytes()).unwrap();
+        assert!(check_subject_len(&indexes, &CircuitProfile::default()).is_ok());
+    }
+
+    #[test]
+    fn test_generation_rejects_a_long_subject() {
+        // A 4 KB base64-like subject on one line, with and without the final CRLF.
+        let subject = "QUFB".repeat(1024);
+        for header in [
+            format!("from:alice@gmail.com\r\nsubject:{}\r\n", subject),
+            format!("from:alice@gmail.com\r\nsubject:{}", subject),
+        ] {
+            let parsed_email = fixture_signed_email(&header, "");
+            let options = InputGenerationOptions::default();
+            let start = std::time::Instant::now();
+            let err = generate_email_auth_input_from_parsed_email(
+                &parsed_email,
+                &AccountCode(Fr::from(1u64)),
+                &options,
+            )
+            .unwrap_err();
+            // Fails before any padding or hashing; far below this even in debug builds.
+            assert!(start.elapsed() < std::time::Duration::from_secs(1), "{:?}", start.elapsed());
+            assert_eq!(
+                error_code(&err, ErrorCode::InternalPanic),
+                ErrorCode::CircuitInputFailed
+            );
+            let err = err.to_string();
+            assert!(
+                err.contains("4096") && err.contains(&MAX_SUBJECT_BYTES.to_string()),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_expected_values() {
+        let header = "from:alice@gmail.com\r\nsubject:Send 1 ETH to bob@example.com\r\n";
+        let indexes = extract_indexes(header.as_bytes()).unwrap();
+        let account_code = fixture_account_code();
+        let salt = AccountSalt::new(
+            &PaddedEmailAddr::from_email_addr("alice@gmail.com"),
+            account_code,
+        )
+        .unwrap();
+        let check = |expected: ExpectedValues| {
+            check_expected_values(header, &indexes, &account_code, &expected)
+                .map_err(|e| e.downcast::<ExpectationMismatch>().unwrap().code())
+        };
+
+        assert_eq!(check(ExpectedValues::default()), Ok(()));
+        let from_domain = |domain: &str| ExpectedValues {
+            from_domain: Some(domain.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(check(from_domain("Gmail.com")), Ok(()));
+        assert_eq!(
+            check(from_domain("example.com")),
+            Err("EXPECTED_FROM_DOMAIN_MISMATCH")
+        );
+        let command_template = |template: &str| ExpectedValues {
+            command_template: Some(template.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(check(command_template("Send {uint} {string} to {string}")), Ok(()));
+        assert_eq!(
+            check(command_template("Send {uint} {string} to {ethAddr}")),
+            Err("EXPECTED_COMMAND_TEMPLATE_MISMATCH")
+        );
+        let account_salt = |salt: String| ExpectedValues {
+            account_salt: Some(salt),
+            ..Default::default()
+        };
+        assert_eq!(check(account_salt(field2hex(&salt.0))), Ok(()));
+        assert_eq!(
+            check(account_salt(field2hex(&Fr::from(1u64)))),
+            Err("EXPECTED_ACCOUNT_SALT_MISMATCH")
+        );
+    }
+
+    #[test]
+    fn test_include_nullifier_and_public_key_hash() {
+        let parsed_email =
+            fixture_signed_email("from:alice@gmail.com\r\nsubject:Send 1 ETH\r\n", "");
+        let account_code = fixture_account_code();
+        let generate = |options: &InputGenerationOptions| -> serde_json::Value {
+            let input =
+                generate_email_auth_input_from_parsed_email(&parsed_email, &account_code, options)
+                    .unwrap();
+            serde_json::from_str(&input).unwrap()
+        };
+
+        let input = generate(&InputGenerationOptions::default());
+        assert!(input.get("email_nullifier").is_none());
+        assert!(input.get("public_key_hash").is_none());
+
+        let input = generate(&InputGenerationOptions {
+            include_nullifier: true,
+            include_public_key_hash: true,
+            ..Default::default()
+        });
+        // The values of the standalone `emailNullifier` and `publicKeyHash` exports.
+        assert_eq!(
+            input["email_nullifier"],
+            field2hex(&email_nullifier_from_hex(&hex::encode(&parsed_email.signature)).unwrap())
+        );
+        assert_eq!(
+            input["public_key_hash"],
+            field2hex(&public_key_hash_from_modulus(&parsed_email.public_key).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_domain_source() {
+        // A proxy rewrote From but signed with the corporate domain.
+        let header = "from:alice@mail-proxy.example\r\nsubject:Send 1 ETH\r\n\
+                      dkim-signature:v=1; a=rsa-sha256; d=Corp.example; s=s1; \
+                      h=from:subject; bh=47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=; b=";
+        let parsed_email = fixture_signed_email(header, "");
+        let account_code = AccountCode(Fr::from(1u64));
+        let generate = |domain_source: DomainSource, from_domain: &str| {
+            let options = InputGenerationOptions {
+                domain_source,
+                expected: Some(ExpectedValues {
+                    from_domain: Some(from_domain.to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            generate_email_auth_input_from_parsed_email(&parsed_email, &account_code, &options)
+                .map(|input| serde_json::from_str::<serde_json::Value>(&input).unwrap())
+        };
+
+        let input = generate(DomainSource::FromHeader, "mail-proxy.example").unwrap();
+        let from_addr_idx = input["from_addr_idx"].as_u64().unwrap() as usize;
+        let domain_idx = input["domain_idx"].as_u64().unwrap() as usize;
+        assert!(header[from_addr_idx + domain_idx..].starts_with("mail-proxy.example"));
+        assert!(input.get("signing_domain").is_none());
+        assert!(input.get("dkim_signature_idx").is_none());
+        assert!(generate(DomainSource::FromHeader, "corp.example").is_err());
+
+        let input = generate(DomainSource::DkimDomain, "corp.example").unwrap();
+        let dkim_signature_idx = input["dkim_signature_idx"].as_u64().unwrap() as usize;
+        let domain_idx = input["domain_idx"].as_u64().unwrap() as usize;
+        assert!(header[dkim_signature_idx..].starts_with("dkim-signature:"));
+        assert!(header[dkim_signature_idx + domain_idx..].starts_with("Corp.example;"));
+        assert_eq!(input["signing_domain"], "Corp.example");
+        let err = generate(DomainSource::DkimDomain, "mail-proxy.example").unwrap_err();
+        assert!(
+            err.to_string().contains("EXPECTED_FROM_DOMAIN_MISMATCH"),
+            "{}",
+            err
+        );
+
+        let unsigned_domain = fixture_signed_email(
+            "from:alice@gmail.com\r\nsubject:Send 1 ETH\r\ndkim-signature:v=1; bh=; b=",
+            "",
+        );
+        let options = InputGenerationOptions {
+            domain_source: DomainSource::DkimDomain,
+            ..Default::default()
+        };
+        let err =
+            generate_email_auth_input_from_parsed_email(&unsigned_domain, &account_code, &options)
+                .unwrap_err();
+        assert!(err.to_string().contains("d= tag is not found"), "{}", err);
+    }
+
+    #[test]
+    fn test_body_hash_options() {
+        use base64::{engine::general_purpose, Engine as _};
+        use sha2::{Digest, Sha256};
+        use std::convert::TryInto;
+
+        let body = format!("{}\r\nTransfer 1 ETH to bob@example.com\r\n", "x".repeat(80));
+        let body_hash = general_purpose::STANDARD.encode(Sha256::digest(body.as_bytes()));
+        let header = format!(
+            "from:alice@gmail.com\r\nsubject:Send 1 ETH\r\n\
+             dkim-signature:v=1; a=rsa-sha256; d=gmail.com; s=s1; h=from:subject; bh={}; b=",
+            body_hash
+        );
+        let parsed_email = fixture_signed_email(&header, &body);
+        let account_code = AccountCode(Fr::from(1u64));
+        let generate = |options_json: &str| {
+            let options = InputGenerationOptions::from_json(options_json)?;
+            generate_email_auth_input_from_parsed_email(&parsed_email, &account_code, &options)
+                .map(|input| serde_json::from_str::<serde_json::Value>(&input).unwrap())
+        };
+        let words = |bytes: &serde_json::Value| -> [u32; 8] {
+            let bytes = bytes
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|byte| byte.as_str().unwrap().parse::<u8>().unwrap())
+                .collect::<Vec<_>>();
+            let mut state = [0u32; 8];
+            for (word, bytes) in state.iter_mut().zip(bytes.chunks(4)) {
+                *word = u32::from_be_bytes(bytes.try_into().unwrap());
+            }
+            state
+        };
+        let padded_body = |input: &serde_json::Value| -> Vec<u8> {
+            let len = input["padded_body_len"].as_str().unwrap().parse().unwrap();
+            input["padded_body"].as_array().unwrap()[..len]
+                .iter()
+                .map(|byte| byte.as_str().unwrap().parse::<u8>().unwrap())
+                .collect()
+        };
+
+        let input = generate("{}").unwrap();
+        assert!(input.get("padded_body").is_none());
+        assert!(input.get("body_hash_idx").is_none());
+        assert!(input.get("dkim_signature_idx").is_none());
+
+        let input = generate(r#"{"ignore_body_hash": false, "max_body_length": 192}"#).unwrap();
+        let body_hash_idx = input["body_hash_idx"].as_u64().unwrap() as usize;
+        assert!(header[body_hash_idx..].starts_with(&body_hash));
+        assert_eq!(words(&input["precomputed_sha"]), SHA256_INITIAL_STATE);
+        assert_eq!(input["padded_body"].as_array().unwrap().len(), 192);
+        assert_eq!(
+            sha256_continue(SHA256_INITIAL_STATE, &padded_body(&input)).unwrap()[..],
+            Sha256::digest(body.as_bytes())[..]
+        );
+        assert!(input.get("dkim_signature_idx").is_none());
+
+        // The first block is precomputed, so the rest fits in fewer bytes.
+        let input = generate(
+            r#"{"ignore_body_hash": false, "max_body_length": 128,
+                "sha_precompute_selector": "Transfer"}"#,
+        )
+        .unwrap();
+        let state = words(&input["precomputed_sha"]);
+        assert_ne!(state, SHA256_INITIAL_STATE);
+        assert_eq!(
+            sha256_continue(state, &padded_body(&input)).unwrap()[..],
+            Sha256::digest(body.as_bytes())[..]
+        );
+
+        let err = generate(r#"{"ignore_body_hash": false, "max_body_length": 64}"#).unwrap_err();
+        assert!(err.to_string().contains("max_body_length is 64"), "{}", err);
+        let err = generate(r#"{"max_header_length": 64}"#).unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::InternalPanic),
+            ErrorCode::CircuitInputFailed
+        );
+        assert!(err.to_string().contains("max_header_length is 64"), "{}", err);
+        let err = generate(
+            r#"{"ignore_body_hash": false, "sha_precompute_selector": "Withdraw",
+                "max_body_length": 192}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("`Withdraw` is not found"), "{}", err);
+    }
+
+    #[test]
+    fn test_generate_input_from_cached() {
+        let parsed_email = ParsedEmail {
+            signature: vec![0xab; 256],
+            ..fixture_signed_email("from:alice@gmail.com\r\nsubject:Send 1 ETH\r\n", "body\r\n")
+        };
+        let account_code = fixture_account_code();
+        let options = InputGenerationOptions {
+            include_nullifier: true,
+            ..Default::default()
+        };
+
+        // A forged signature is rejected by generation.
+        let err =
+            generate_email_auth_input_from_parsed_email(&parsed_email, &account_code, &options)
+                .unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::InternalPanic),
+            ErrorCode::ParseEmailFailed
+        );
+        assert!(err.to_string().contains("does not verify"), "{}", err);
+
+        // The signature does not verify, so it is cached as unverified and rejected.
+        let unverified = parsed_email.to_cached_bytes().unwrap();
+        let cached = CachedParsedEmail::from_bytes(&unverified).unwrap();
+        assert!(!cached.dkim_verified);
+        assert_eq!(cached.parsed_email, parsed_email);
+        assert!(generate_input_from_cached(&unverified, &account_code, &options).is_err());
+
+        // A forged flag does not make it verify.
+        let forged = CachedParsedEmail {
+            parsed_email: parsed_email.clone(),
+            dkim_verified: true,
+        }
+        .to_bytes()
+        .unwrap();
+        let err = generate_input_from_cached(&forged, &account_code, &options).unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::InternalPanic),
+            ErrorCode::ParseEmailFailed
+        );
+
+        let parsed_email = test_fixture("send_eth").unwrap().parsed_email().unwrap();
+        let bytes = parsed_email.to_cached_bytes().unwrap();
+        assert!(CachedParsedEmail::from_bytes(&bytes).unwrap().dkim_verified);
+        assert_eq!(
+            generate_input_from_cached(&bytes, &account_code, &options).unwrap(),
+            generate_email_auth_input_from_parsed_email(&parsed_email, &account_code, &options)
+                .unwrap()
+        );
+
+        // bincode encodes the version as the leading little-endian u32.
+        let mut future = bytes.clone();
+        future[..4].copy_from_slice(&(PARSED_EMAIL_CACHE_VERSION + 1).to_le_bytes());
+        let err = ParsedEmail::from_cached_bytes(&future).unwrap_err().to_string();
+        let version = format!("format version {}", PARSED_EMAIL_CACHE_VERSION + 1);
+        assert!(err.contains(&version), "{}", err);
+        assert!(ParsedEmail::from_cached_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[cfg(feature = "dns")]
+    #[test]
+    fn test_untrusted_email_is_not_generated_from() {
+        let email = "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=gmail.com;\r\n\
+                     \ts=20230601; h=from:subject; bh=Zm9v; b=YmFy\r\n\
+                     From: alice@gmail.com\r\nSubject: Send 1 ETH\r\n\r\nbody\r\n";
+        let untrusted = ParsedEmail::parse_untrusted(email).unwrap();
+        assert_eq!(untrusted.dkim_tags["d"], "gmail.com");
+        assert_eq!(untrusted.dkim_tags["s"], "20230601");
+        assert_eq!(untrusted.get_from_addr().unwrap(), "alice@gmail.com");
+        assert_eq!(untrusted.get_subject_all().unwrap(), "Send 1 ETH");
+        #[cfg(feature = "body")]
+        assert_eq!(untrusted.body_parts.len(), 1);
+
+        let bytes = untrusted.to_cached_bytes().unwrap();
+        assert!(!CachedParsedEmail::from_bytes(&bytes).unwrap().dkim_verified);
+        let account_code = fixture_account_code();
+        let options = InputGenerationOptions::default();
+        assert!(generate_input_from_cached(&bytes, &account_code, &options).is_err());
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    async fn test_batch_entries_fail_independently() {
+        let account_code = fixture_account_code();
+        let dkim = "DKIM-Signature: v=1; a=rsa-sha256; d=gmail.com; s=20230601;\r\n\th=from:subject; bh=Zm9v; b=YmFy\r\n";
+        let unsigned_from = format!(
+            "From: Mallory <mallory@evil.com>\r\n{}From: alice@gmail.com\r\nSubject: Hi\r\n\r\nbody\r\n",
+            dkim
+        );
+        let items = vec![
+            ("Subject: no signature\r\n\r\nbody\r\n".to_string(), account_code),
+            (unsigned_from, account_code),
+            ("".to_string(), account_code),
+        ];
+        let mut progress = vec![];
+        let entries = generate_email_auth_input_batch_with_progress(
+            items,
+            &InputGenerationOptions::default(),
+            2,
+            |completed, total| progress.push((completed, total)),
+        )
+        .await;
+        assert_eq!(progress, vec![(1, 3), (2, 3), (3, 3)]);
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(|entry| {
+            entry.code == ErrorCode::ParseEmailFailed.as_u8() && entry.data.is_none()
+        }));
+        assert!(entries[0].msg.contains("DKIM-Signature"), "{}", entries[0].msg);
+        assert!(
+            entries[1].msg.contains(UnsignedFromHeader::CODE),
+            "{}",
+            entries[1].msg
+        );
+
+        assert_eq!(panic_message(&"boom"), "boom");
+        assert_eq!(panic_message(&"boom".to_string()), "boom");
+        assert_eq!(panic_message(&42), "unknown panic");
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    async fn test_batch_survives_a_panicking_item() {
+        let items = (0..4)
+            .map(|i| (format!("email {}", i), AccountCode(Fr::from(1u64))))
+            .collect();
+        let mut progress = vec![];
+        let entries = run_batch(
+            items,
+            2,
+            |completed, total| progress.push((completed, total)),
+            |email, _| async move {
+                if email == "email 2" {
+                    panic!("injected panic");
+                }
+                Ok::<_, anyhow::Error>(email)
+            },
+        )
+        .await;
+        assert_eq!(progress, vec![(1, 4), (2, 4), (3, 4), (4, 4)]);
+        for (i, entry) in entries.iter().enumerate().filter(|(i, _)| *i != 2) {
+            assert_eq!(*entry, BatchEntry::success(format!("email {}", i)));
+        }
+        assert_eq!(entries[2].code, ErrorCode::InternalPanic.as_u8());
+        assert!(entries[2].msg.contains("injected panic"), "{}", entries[2].msg);
+        assert!(entries[2].msg.contains(file!()), "{}", entries[2].msg);
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    async fn test_structured_batch_entries() {
+        let fixture = test_fixture("send_eth").unwrap();
+        cache_dkim_key(
+            DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+            decode_hex(&fixture.public_key).unwrap(),
+        );
+        let account_code = AccountCode(hex2field(&fixture.account_code).unwrap());
+        let options = InputGenerationOptions::default();
+        let items = vec![
+            (fixture.raw_email.clone(), account_code),
+            ("".to_string(), account_code),
+        ];
+        let entries = generate_email_auth_input_batch(items, &options, 2).await;
+        let input =
+            generate_email_auth_input_with_options(&fixture.raw_email, &account_code, &options)
+                .await
+                .unwrap();
+        assert_eq!(entries[0].data, Some(serde_json::Value::String(input.clone())));
+
+        let structured = entries
+            .iter()
+            .cloned()
+            .map(BatchEntry::structured)
+            .collect::<Vec<_>>();
+        let nested = structured[0].data.as_ref().unwrap();
+        assert_eq!(*nested, serde_json::from_str::<serde_json::Value>(&input).unwrap());
+        assert!(nested["padded_header"].is_array());
+        assert_eq!(structured[1], entries[1]);
+        // The nested input is not escaped, which makes the batch JSON smaller.
+        let legacy_len = serde_json::to_string(&entries).unwrap().len();
+        let structured_len = serde_json::to_string(&structured).unwrap().len();
+        assert!(structured_len < legacy_len, "{} >= {}", structured_len, legacy_len);
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    async fn test_canonical_input_matches_the_raw_email_input() {
+        for name in test_fixture_names() {
+            let fixture = test_fixture(name).unwrap();
+            if fixture.public_exponent != DEFAULT_PUBLIC_EXPONENT {
+                continue;
+            }
+            let public_key = decode_hex(&fixture.public_key).unwrap();
+            cache_dkim_key(
+                DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+                public_key.clone(),
+            );
+            let account_code = AccountCode(hex2field(&fixture.account_code).unwrap());
+            let from_raw = generate_email_auth_input(&fixture.raw_email, &account_code)
+                .await
+                .unwrap();
+            let from_canonical = generate_email_auth_input_from_canonical(
+                fixture.canonicalized_header.as_bytes(),
+                &decode_hex(&fixture.signature).unwrap(),
+                &public_key,
+                &account_code,
+            )
+            .unwrap();
+            assert_eq!(from_canonical, from_raw, "{}", name);
+        }
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    async fn test_generation_failures_carry_their_cause() {
+        let err = hex2field("0x22zz").unwrap_err();
+        assert!(err.to_string().contains("invalid hex"), "{}", err);
+
+        // `.invalid` never resolves (RFC 2606).
+        let email = "DKIM-Signature: v=1; a=rsa-sha256; d=relayer-utils.invalid; s=s1;\r\n\
+                     \th=from:subject; bh=Zm9v; b=YmFy\r\nFrom: alice@relayer-utils.invalid\r\n\
+                     Subject: Hi\r\n\r\nbody\r\n";
+        let err = generate_email_sender_input(email, "0x1").await.unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::CircuitInputFailed),
+            ErrorCode::DkimDnsFailed
+        );
+        assert!(
+            err.to_string().contains("s1._domainkey.relayer-utils.invalid"),
+            "{}",
+            err
+        );
+
+        let header = format!("from:alice@gmail.com\r\nsubject:{}\r\n", "A".repeat(2000));
+        let err = try_generate_circuit_inputs(CircuitInputParams::new(
+            vec![],
+            header.into_bytes(),
+            "".to_string(),
+            vec_u8_to_bigint(vec![0xab; 256]),
+            vec_u8_to_bigint(vec![0x7f; 256]),
+            None,
+            Some(1024),
+            Some(64),
+            Some(true),
+        ))
+        .err()
+        .unwrap();
+        assert_eq!(
+            error_code(&err, ErrorCode::InternalPanic),
+            ErrorCode::CircuitInputFailed
+        );
+        assert!(err.to_string().contains("the header is too long"), "{}", err);
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    async fn test_unsupported_public_exponent() {
+        let fixture = test_fixture("send_eth_e3").unwrap();
+        cache_dkim_key_fetch(
+            DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+            fixture.dkim_key_fetch().unwrap(),
+        );
+        // The email verifies with its exponent, but no circuit input is generated for it.
+        #[cfg(feature = "body")]
+        {
+            let verification = verify_dkim(&fixture.raw_email).await.unwrap();
+            assert!(verification.valid, "{:?}", verification.failure);
+            assert_eq!(verification.public_exponent, 3);
+        }
+        let parsed_email = ParsedEmail::new_from_raw_email(&fixture.raw_email)
+            .await
+            .unwrap();
+        assert_eq!(parsed_email.public_exponent, 3);
+
+        let account_code = AccountCode(hex2field(&fixture.account_code).unwrap());
+        let err = generate_email_auth_input(&fixture.raw_email, &account_code)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::InternalPanic),
+            ErrorCode::CircuitInputFailed
+        );
+        assert!(
+            err.to_string().contains(UnsupportedPublicExponent::CODE),
+            "{}",
+            err
+        );
+        let err = generate_email_sender_input(&fixture.raw_email, &fixture.account_code)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains(UnsupportedPublicExponent::CODE),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_emitted_indexes_fit_in_i32() {
+        let mut input = EmailAuthInput {
+            padded_header: vec![],
+            public_key: vec![],
+            signature: vec![],
+            padded_header_len: MAX_EMITTED_INDEX.to_string(),
+            account_code: "0x0".to_string(),
+            from_addr_idx: 0,
+            subject_idx: 0,
+            domain_idx: 0,
+            timestamp_idx: 0,
+            code_idx: 0,
+            dkim_signature_idx: None,
+            dkim_body_hash_idx: None,
+            dkim_signature_tag_idx: None,
+            precomputed_sha: None,
+            padded_body: None,
+            padded_body_len: None,
+            body_hash_idx: None,
+            email_nullifier: None,
+            public_key_hash: None,
+            signing_domain: None,
+            correlation_id: None,
+        };
+        input.check_emitted_indexes().unwrap();
+
+        input.padded_header_len = (MAX_EMITTED_INDEX + 1).to_string();
+        let err = input.check_emitted_indexes().unwrap_err();
+        assert!(err.to_string().starts_with("padded_header_len is 2147483648"), "{}", err);
+        input.padded_header_len = "1024".to_string();
+        input.dkim_body_hash_idx = Some(1 << 40);
+        let err = input.check_emitted_indexes().unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::InternalPanic),
+            ErrorCode::CircuitInputFailed
+        );
+        assert!(err.to_string().starts_with("dkim_body_hash_idx"), "{}", err);
+    }
+}
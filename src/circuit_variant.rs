@@ -0,0 +1,275 @@
+use crate::*;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Header, key and signature fields shared by the email auth circuits.
+const EMAIL_AUTH_SIGNALS: &[&str] = &[
+    "padded_header",
+    "public_key",
+    "signature",
+    "padded_header_len",
+    "account_code",
+    "from_addr_idx",
+    "subject_idx",
+    "domain_idx",
+    "timestamp_idx",
+    "code_idx",
+];
+/// Fields the email auth input carries when the body hash is checked.
+const BODY_SIGNALS: &[&str] = &[
+    "precomputed_sha",
+    "padded_body",
+    "padded_body_len",
+    "body_hash_idx",
+];
+/// Fields the email auth input carries only when an option asks for them.
+const EMAIL_AUTH_OPTIONAL_SIGNALS: &[&str] = &[
+    "dkim_signature_idx",
+    "dkim_body_hash_idx",
+    "dkim_signature_tag_idx",
+    "email_nullifier",
+    "public_key_hash",
+    "public_signals",
+    "signing_domain",
+    "correlation_id",
+];
+const EMAIL_SENDER_SIGNALS: &[&str] = &[
+    "in_padded",
+    "pubkey",
+    "signature",
+    "in_padded_len",
+    "sender_account_code",
+    "sender_email_idx",
+    "subject_idx",
+    "recipient_email_idx",
+    "domain_idx",
+    "timestamp_idx",
+];
+const ACCOUNT_CREATION_SIGNALS: &[&str] = &[
+    "in_padded",
+    "pubkey",
+    "signature",
+    "in_padded_len",
+    "relayer_rand",
+    "sender_email_idx",
+    "code_idx",
+    "domain_idx",
+    "timestamp_idx",
+];
+
+/// A circuit the library generates inputs for, named by the input fields it reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitVariant {
+    /// The email auth circuit checking the body hash (`ignore_body_hash` false).
+    EmailAuth,
+    /// The email auth circuit without the body.
+    EmailAuthNoBody,
+    /// Guardian acceptance and recovery emails. Their command is in the subject, so they go
+    /// through the email auth circuit without the body.
+    Recovery,
+    /// The email sender circuit (`generate_email_sender_input`).
+    EmailSender,
+    /// The account creation circuit (`generate_account_creation_input`).
+    AccountCreation,
+}
+
+impl CircuitVariant {
+    pub const NAMES: [&'static str; 5] = [
+        "email_auth",
+        "email_auth_no_body",
+        "recovery",
+        "email_sender",
+        "account_creation",
+    ];
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        serde_json::from_value(Value::from(name)).map_err(|_| {
+            anyhow!(
+                "unknown circuit variant `{}`, expected one of: {}",
+                name,
+                Self::NAMES.join(", ")
+            )
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CircuitVariant::EmailAuth => "email_auth",
+            CircuitVariant::EmailAuthNoBody => "email_auth_no_body",
+            CircuitVariant::Recovery => "recovery",
+            CircuitVariant::EmailSender => "email_sender",
+            CircuitVariant::AccountCreation => "account_creation",
+        }
+    }
+
+    /// Whether the input is generated by the email auth generators.
+    pub fn is_email_auth(&self) -> bool {
+        matches!(
+            self,
+            CircuitVariant::EmailAuth | CircuitVariant::EmailAuthNoBody | CircuitVariant::Recovery
+        )
+    }
+
+    /// The input fields the circuit cannot be proven without.
+    pub fn required_signals(&self) -> Vec<&'static str> {
+        match self {
+            CircuitVariant::EmailAuth => [EMAIL_AUTH_SIGNALS, BODY_SIGNALS].concat(),
+            CircuitVariant::EmailAuthNoBody | CircuitVariant::Recovery => {
+                EMAIL_AUTH_SIGNALS.to_vec()
+            }
+            CircuitVariant::EmailSender => EMAIL_SENDER_SIGNALS.to_vec(),
+            CircuitVariant::AccountCreation => ACCOUNT_CREATION_SIGNALS.to_vec(),
+        }
+    }
+
+    /// The input fields the generators may add on request, which the prover ignores or checks
+    /// against the public signals.
+    pub fn optional_signals(&self) -> Vec<&'static str> {
+        if self.is_email_auth() {
+            EMAIL_AUTH_OPTIONAL_SIGNALS.to_vec()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Fails, listing every missing field, unless `input` is an object with the required
+    /// signals of the variant.
+    pub fn validate(&self, input: &Value) -> Result<()> {
+        let object = input
+            .as_object()
+            .ok_or_else(|| anyhow!("the circuit input must be a JSON object"))?;
+        let missing = self
+            .required_signals()
+            .into_iter()
+            .filter(|signal| object.get(*signal).map_or(true, Value::is_null))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(coded(
+                ErrorCode::CircuitInputFailed,
+                format!(
+                    "the input misses the signals required by the {} circuit: {}",
+                    self.name(),
+                    missing.join(", ")
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The required and optional signals, as `circuitRequirements` returns them.
+    pub fn requirements(&self) -> Value {
+        serde_json::json!({
+            "variant": self,
+            "required": self.required_signals(),
+            "optional": self.optional_signals(),
+        })
+    }
+}
+
+/// `circuitRequirements(variant)` returns the JSON object of the required and optional signals
+/// of the variant.
+#[cfg(feature = "node")]
+pub fn circuit_requirements_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let variant = cx.argument::<JsString>(0)?.value(&mut cx);
+    match CircuitVariant::from_name(&variant) {
+        Ok(variant) => Ok(cx.string(variant.requirements().to_string())),
+        Err(e) => throw_coded(&mut cx, ErrorCode::InvalidArgument, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation_catches_missing_signals() {
+        for name in CircuitVariant::NAMES {
+            let variant = CircuitVariant::from_name(name).unwrap();
+            assert_eq!(variant.name(), name);
+            let mut input = serde_json::Map::new();
+            for signal in variant.required_signals() {
+                input.insert(signal.to_string(), Value::from("0"));
+            }
+            variant.validate(&Value::Object(input.clone())).unwrap();
+
+            for signal in variant.required_signals() {
+                let mut synthetic = input.clone();
+                synthetic.remove(signal);
+                let err = variant.validate(&Value::Object(synthetic)).unwrap_err();
+                assert_eq!(
+                    error_code(&err, ErrorCode::InvalidArgument),
+                    ErrorCode::CircuitInputFailed
+                );
+                assert!(
+                    err.to_string().ends_with(&format!("circuit: {}", signal)),
+                    "{}: {}",
+                    name,
+                    err
+                );
+            }
+        }
+        assert!(CircuitVariant::from_name("email_recovery").is_err());
+
+        let err = CircuitVariant::EmailAuth
+            .validate(&serde_json::json!({ "padded_header": [] }))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("public_key, signature,"), "{}", err);
+        assert!(err.ends_with("padded_body_len, body_hash_idx"), "{}", err);
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    async fn test_generation_validates_the_variant() {
+        let fixture = test_fixture("send_eth").unwrap();
+        cache_dkim_key(
+            DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+            decode_hex(&fixture.public_key).unwrap(),
+        );
+        let account_code = AccountCode(hex2field(&fixture.account_code).unwrap());
+        let options = |circuit_variant, ignore_body_hash| InputGenerationOptions {
+            circuit_variant: Some(circuit_variant),
+            ignore_body_hash,
+            max_body_length: 1024,
+            ..Default::default()
+        };
+        for (variant, ignore_body_hash) in [
+            (CircuitVariant::EmailAuth, false),
+            (CircuitVariant::EmailAuthNoBody, true),
+            (CircuitVariant::Recovery, true),
+        ] {
+            let input = generate_email_auth_input_with_options(
+                &fixture.raw_email,
+                &account_code,
+                &options(variant, ignore_body_hash),
+            )
+            .await
+            .unwrap();
+            variant
+                .validate(&serde_json::from_str(&input).unwrap())
+                .unwrap();
+        }
+
+        // Without the body hash check, the input misses the body the circuit reads.
+        let err = generate_email_auth_input_with_options(
+            &fixture.raw_email,
+            &account_code,
+            &options(CircuitVariant::EmailAuth, true),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("precomputed_sha, padded_body, padded_body_len, body_hash_idx"),
+            "{}",
+            err
+        );
+        assert!(
+            InputGenerationOptions::from_json(r#"{"circuit_variant": "email_sender"}"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,471 @@
+use crate::*;
+use anyhow::{anyhow, Result};
+use ethers::types::{Address, I256, U256};
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Placeholders of a command template, as used by the email auth contracts.
+const PLACEHOLDERS: [&str; 5] = ["{string}", "{uint}", "{int}", "{decimals}", "{ethAddr}"];
+
+fn matches_placeholder(placeholder: &str, word: &str) -> bool {
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    match placeholder {
+        "{string}" => !word.is_empty(),
+        "{uint}" => is_digits(word),
+        "{int}" => is_digits(word.strip_prefix('-').unwrap_or(word)),
+        "{decimals}" => match word.split_once('.') {
+            Some((integer, fraction)) => is_digits(integer) && is_digits(fraction),
+            None => is_digits(word),
+        },
+        "{ethAddr}" => {
+            word.len() == 42
+                && word.starts_with("0x")
+                && word[2..].bytes().all(|b| b.is_ascii_hexdigit())
+        }
+        _ => false,
+    }
+}
+
+/// Returns whether `command` is an instance of `template`.
+///
+/// Both are split on whitespace. Each template word is either a placeholder (`{string}`,
+/// `{uint}`, `{int}`, `{decimals}`, `{ethAddr}`), matching one command word of that kind, or a
+/// literal that must be equal.
+pub fn matches_command_template(template: &str, command: &str) -> bool {
+    let template = template.split_whitespace().collect::<Vec<_>>();
+    let command = command.split_whitespace().collect::<Vec<_>>();
+    template.len() == command.len()
+        && template.iter().zip(command.iter()).all(|(expected, word)| {
+            if PLACEHOLDERS.contains(expected) {
+                matches_placeholder(expected, word)
+            } else {
+                expected == word
+            }
+        })
+}
+
+/// Number of decimals of a `{decimals}` parameter, as in the contracts.
+pub const COMMAND_DECIMALS: usize = 18;
+
+/// How `{ethAddr}` parameters are written in the reconstructed command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressFormat {
+    /// EIP-55 mixed-case checksum, as produced by the contracts.
+    Checksum,
+    Lowercase,
+}
+
+/// Result of comparing the reconstructed command with the subject constrained by the circuit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReconstructionCheck {
+    pub command: String,
+    pub subject: String,
+    /// Byte offset of the first difference, or `None` when the command equals the subject.
+    pub first_difference: Option<usize>,
+}
+
+impl ReconstructionCheck {
+    pub fn matches(&self) -> bool {
+        self.first_difference.is_none()
+    }
+}
+
+fn param_string(param: &Value) -> Option<String> {
+    match param {
+        Value::String(param) => Some(param.clone()),
+        Value::Number(param) => Some(param.to_string()),
+        _ => None,
+    }
+}
+
+/// Formats one parameter the way the contracts write it into the command:
+///
+/// - `{string}`: as is.
+/// - `{uint}` and `{int}`: decimal without leading zeros, `-` for negative ints.
+/// - `{decimals}`: the integer value divided by 10^`COMMAND_DECIMALS`, with the fraction
+///   present only when non-zero and without trailing zeros (`1500000000000000000` is `1.5`).
+/// - `{ethAddr}`: `0x` and 40 hex digits in `address_format`.
+fn format_param(placeholder: &str, param: &Value, address_format: AddressFormat) -> Result<String> {
+    let value = param_string(param)
+        .ok_or_else(|| anyhow!("the parameter for {} must be a string or a number", placeholder))?;
+    check_invisible_chars(&value, "command parameter")?;
+    let invalid = |e: &dyn std::fmt::Display| {
+        anyhow!("`{}` is not a valid {} parameter: {}", value, placeholder, e)
+    };
+    Ok(match placeholder {
+        "{string}" => value,
+        "{uint}" => U256::from_dec_str(&value).map_err(|e| invalid(&e))?.to_string(),
+        "{int}" => I256::from_dec_str(&value).map_err(|e| invalid(&e))?.to_string(),
+        "{decimals}" => {
+            let value = U256::from_dec_str(&value).map_err(|e| invalid(&e))?;
+            let unit = U256::exp10(COMMAND_DECIMALS);
+            let (integer, fraction) = (value / unit, value % unit);
+            if fraction.is_zero() {
+                integer.to_string()
+            } else {
+                let fraction = format!("{:0>width$}", fraction.to_string(), width = COMMAND_DECIMALS);
+                format!("{}.{}", integer, fraction.trim_end_matches('0'))
+            }
+        }
+        "{ethAddr}" => {
+            let addr = value.parse::<Address>().map_err(|e| invalid(&e))?;
+            match address_format {
+                AddressFormat::Checksum => ethers::utils::to_checksum(&addr, None),
+                AddressFormat::Lowercase => format!("{:?}", addr),
+            }
+        }
+        _ => return Err(anyhow!("unknown placeholder {}", placeholder)),
+    })
+}
+
+/// Builds the command the contract reconstructs from `template` and the JSON array of its
+/// parameters, and compares it byte for byte with the subject of `email`.
+///
+/// Template words are joined with single spaces; each placeholder takes the next parameter (see
+/// `format_param` for the formatting rules).
+pub fn reconstruct_command(
+    template: &str,
+    params_json: &str,
+    email: &ParsedEmail,
+    address_format: AddressFormat,
+) -> Result<ReconstructionCheck> {
+    let params: Vec<Value> = serde_json::from_str(params_json)
+        .map_err(|e| anyhow!("the parameters must be a JSON array: {}", e))?;
+    let mut params = params.iter();
+    let mut words = vec![];
+    for word in template.split_whitespace() {
+        if PLACEHOLDERS.contains(&word) {
+            let param = params
+                .next()
+                .ok_or_else(|| anyhow!("the template has more placeholders than parameters"))?;
+            words.push(format_param(word, param, address_format)?);
+        } else {
+            words.push(word.to_string());
+        }
+    }
+    if params.next().is_some() {
+        return Err(anyhow!("the template has fewer placeholders than parameters"));
+    }
+    let command = words.join(" ");
+    let subject = email.get_subject_all()?;
+    let first_difference = if command == subject {
+        None
+    } else {
+        Some(
+            command
+                .bytes()
+                .zip(subject.bytes())
+                .position(|(a, b)| a != b)
+                .unwrap_or_else(|| command.len().min(subject.len())),
+        )
+    };
+    Ok(ReconstructionCheck {
+        command,
+        subject,
+        first_difference,
+    })
+}
+
+/// Reply and forward prefixes that a client may add in front of the command of a subject.
+const SUBJECT_PREFIXES: [&str; 3] = ["re:", "fwd:", "fw:"];
+
+/// The command of a subject, as the circuit sees it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubjectCommand {
+    pub subject: String,
+    pub command: String,
+    /// Index of the command in the padded header, which starts with the canonicalized header.
+    pub command_idx: usize,
+    /// The subject bytes with everything outside the command, and the email address inside it,
+    /// replaced by zero bytes.
+    pub masked_subject: Vec<u8>,
+    /// The template the command matched, when templates were given.
+    pub template: Option<String>,
+}
+
+/// Finds the command in the subject of `canonical_header`: the subject without its reply and
+/// forward prefixes and without the invitation code with its prefix. With `templates`, the
+/// command must match one of them (see `matches_command_template`). Fails with
+/// `ErrorCode::ParseEmailFailed` when no command is found or none of the templates matches.
+pub fn subject_command(canonical_header: &str, templates: &[String]) -> Result<SubjectCommand> {
+    let not_found = |message: String| coded(ErrorCode::ParseEmailFailed, message);
+    let (subject_start, subject_end) = extract_indexes(canonical_header.as_bytes())?
+        .subject
+        .ok_or_else(|| missing_subject_error(canonical_header))?;
+    let subject = &canonical_header[subject_start..subject_end];
+
+    let mut start = 0;
+    loop {
+        let rest = &subject[start..];
+        let trimmed = rest.trim_start();
+        let prefix = SUBJECT_PREFIXES.iter().find(|prefix| {
+            trimmed
+                .get(..prefix.len())
+                .map_or(false, |head| head.eq_ignore_ascii_case(prefix))
+        });
+        match prefix {
+            Some(prefix) => start += rest.len() - trimmed.len() + prefix.len(),
+            None => {
+                start += rest.len() - trimmed.len();
+                break;
+            }
+        }
+    }
+    let regex_config =
+        serde_json::from_str(include_str!("../regexes/invitation_code_with_prefix.json"))?;
+    let end = extract_substr_idxes(subject, &regex_config)
+        .ok()
+        .and_then(|idxes| idxes.first().copied())
+        .map_or(subject.len(), |(code_start, _)| code_start)
+        .max(start);
+    let command = subject[start..end].trim_end();
+    let end = start + command.len();
+    if command.is_empty() {
+        return Err(not_found(format!(
+            "no command is found in the subject `{}`",
+            subject
+        )));
+    }
+    let template = if templates.is_empty() {
+        None
+    } else {
+        Some(
+            templates
+                .iter()
+                .find(|template| matches_command_template(template, command))
+                .cloned()
+                .ok_or_else(|| {
+                    not_found(format!(
+                        "the command `{}` matches none of the templates",
+                        command
+                    ))
+                })?,
+        )
+    };
+
+    let mut masked_subject = vec![0; subject.len()];
+    masked_subject[start..end].copy_from_slice(command.as_bytes());
+    if let Some((addr_start, addr_end)) = extract_email_addr_idxes(command)
+        .ok()
+        .and_then(|idxes| idxes.first().copied())
+    {
+        masked_subject[start + addr_start..start + addr_end]
+            .iter_mut()
+            .for_each(|byte| *byte = 0);
+    }
+    Ok(SubjectCommand {
+        subject: subject.to_string(),
+        command: command.to_string(),
+        command_idx: subject_start + start,
+        masked_subject,
+        template,
+    })
+}
+
+/// `subject_command` of a raw email, canonicalized without DKIM verification or DNS.
+#[cfg(feature = "dns")]
+pub fn extract_subject_command(raw_email: &str, templates: &[String]) -> Result<SubjectCommand> {
+    subject_command(
+        &ParsedEmail::parse_untrusted(raw_email)?.canonicalized_header,
+        templates,
+    )
+}
+
+/// `extractSubjectCommand(rawEmail, templatesJson?)` returns the JSON `SubjectCommand`;
+/// `templatesJson` is a JSON array of command templates.
+#[cfg(feature = "node")]
+pub fn extract_subject_command_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let raw_email = cx.argument::<JsString>(0)?.value(&mut cx);
+    let templates = match cx.argument_opt(1) {
+        Some(templates) if !templates.is_a::<JsUndefined, _>(&mut cx) => {
+            let templates = templates
+                .downcast_or_throw::<JsString, _>(&mut cx)?
+                .value(&mut cx);
+            match serde_json::from_str::<Vec<String>>(&templates) {
+                Ok(templates) => templates,
+                Err(e) => {
+                    return throw_coded(
+                        &mut cx,
+                        ErrorCode::InvalidArgument,
+                        format!("the templates must be a JSON array of strings: {}", e),
+                    )
+                }
+            }
+        }
+        _ => vec![],
+    };
+    match extract_subject_command(&raw_email, &templates)
+        .and_then(|command| Ok(serde_json::to_string(&command)?))
+    {
+        Ok(command) => Ok(cx.string(command)),
+        Err(e) => throw_coded(
+            &mut cx,
+            error_code(&e, ErrorCode::ParseEmailFailed),
+            e.to_string(),
+        ),
+    }
+}
+
+#[cfg(feature = "node")]
+pub fn reconstruct_command_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let template = cx.argument::<JsString>(0)?.value(&mut cx);
+    let params_json = cx.argument::<JsString>(1)?.value(&mut cx);
+    let canonicalized_header = cx.argument::<JsString>(2)?.value(&mut cx);
+    let address_format = match cx.argument_opt(3) {
+        Some(format) if !format.is_a::<JsUndefined, _>(&mut cx) => {
+            let format = format.downcast_or_throw::<JsString, _>(&mut cx)?.value(&mut cx);
+            match serde_json::from_value(Value::String(format)) {
+                Ok(format) => format,
+                Err(e) => return cx.throw_error(format!("invalid address format: {}", e)),
+            }
+        }
+        _ => AddressFormat::Checksum,
+    };
+    let email = ParsedEmail {
+        canonicalized_header,
+        canonicalized_body: String::new(),
+        signature: vec![],
+        public_key: vec![],
+        public_exponent: DEFAULT_PUBLIC_EXPONENT,
+        dnssec_validated: None,
+    };
+    match reconstruct_command(&template, &params_json, &email, address_format)
+        .and_then(|check| Ok(serde_json::to_string(&check)?))
+    {
+        Ok(check) => Ok(cx.string(check)),
+        Err(e) => cx.throw_error(format!("reconstruct_command failed: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_command_template() {
+        let template = "Send {decimals} {string} to {ethAddr}";
+        let addr = "0x9401296121FC9B78F84fc856B1F8dC88f4415B2e";
+        let cases = [
+            (template, format!("Send 1.5 ETH to {}", addr), true),
+            (template, format!("Send  2 USDC  to {}", addr.to_lowercase()), true),
+            (template, "Send 1.5 ETH to bob@example.com".to_string(), false),
+            (template, format!("Send one ETH to {}", addr), false),
+            (template, "Send 1.5 ETH".to_string(), false),
+            ("Set {int}", "Set -3".to_string(), true),
+            ("Set {uint}", "Set -3".to_string(), false),
+        ];
+        for (template, command, expected) in cases.iter() {
+            assert_eq!(
+                matches_command_template(template, command),
+                *expected,
+                "{:?} {:?}",
+                template,
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn test_reconstruct_command() {
+        let email = |subject: &str| ParsedEmail {
+            canonicalized_header: format!("from:alice@gmail.com\r\nsubject:{}\r\n", subject),
+            canonicalized_body: String::new(),
+            signature: vec![],
+            public_key: vec![],
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
+        };
+        let template = "Send {decimals} {string} to {ethAddr}";
+        let addr = "0x9401296121fc9b78f84fc856b1f8dc88f4415b2e";
+        let checksummed = ethers::utils::to_checksum(&addr.parse().unwrap(), None);
+        let params = format!(r#"["1500000000000000000", "ETH", "{}"]"#, addr);
+        let check = |subject: &str, address_format| {
+            reconstruct_command(template, &params, &email(subject), address_format).unwrap()
+        };
+
+        let subject = format!("Send 1.5 ETH to {}", checksummed);
+        assert!(check(&subject, AddressFormat::Checksum).matches());
+
+        // The subject writes the amount with a trailing zero.
+        let subject = format!("Send 1.50 ETH to {}", checksummed);
+        assert_eq!(
+            check(&subject, AddressFormat::Checksum).first_difference,
+            Some("Send 1.5".len())
+        );
+
+        // The subject has the lowercase address but the contract writes the checksum.
+        let subject = format!("Send 1.5 ETH to {}", addr);
+        let first_upper = checksummed.find(|c: char| c.is_ascii_uppercase()).unwrap();
+        assert_eq!(
+            check(&subject, AddressFormat::Checksum).first_difference,
+            Some("Send 1.5 ETH to ".len() + first_upper)
+        );
+        assert!(check(&subject, AddressFormat::Lowercase).matches());
+
+        let subject = format!("Send 1.5 ETH to {}", checksummed);
+        assert!(
+            reconstruct_command(template, "[1]", &email(&subject), AddressFormat::Checksum)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_subject_command() {
+        let header = |subject: &str| {
+            format!(
+                "from:alice@gmail.com\r\nsubject:{}\r\ndate:Wed, 13 Dec 2023 10:00:00 +0000\r\n",
+                subject
+            )
+        };
+        let header_of_send = header("Re: FWD:  Send 1 ETH to bob@example.com");
+        let command = subject_command(&header_of_send, &[]).unwrap();
+        assert_eq!(command.command, "Send 1 ETH to bob@example.com");
+        assert_eq!(
+            &header_of_send[command.command_idx..][..command.command.len()],
+            command.command
+        );
+        let command_start = command.subject.find("Send").unwrap();
+        let mut expected = vec![0; command.subject.len()];
+        expected[command_start..command_start + 14].copy_from_slice(b"Send 1 ETH to ");
+        assert_eq!(command.masked_subject, expected);
+        assert_eq!(command.template, None);
+
+        let addr = "0x0C06688e61C06466E2a5C6fE4E15c359260a33f3";
+        let header_of_accept = header(&format!(
+            "Accept guardian request for {} Code 01eb9b204cc24c3baee11accc37d253a9c53e92b",
+            addr
+        ));
+        let templates = vec![
+            "Send {decimals} {string} to {string}".to_string(),
+            "Accept guardian request for {ethAddr}".to_string(),
+        ];
+        let command = subject_command(&header_of_accept, &templates).unwrap();
+        assert_eq!(command.command, format!("Accept guardian request for {}", addr));
+        assert_eq!(command.template.as_ref(), Some(&templates[1]));
+        // The invitation code is outside the command.
+        assert!(command.masked_subject[command.command.len()..]
+            .iter()
+            .all(|&byte| byte == 0));
+        assert_eq!(
+            subject_command(&header_of_send, &templates).unwrap().template.as_ref(),
+            Some(&templates[0])
+        );
+
+        for (subject, expected) in [
+            ("Re: ", "no command is found"),
+            ("Code 01eb9b204cc24c3baee11accc37d253a9c53e92b", "no command is found"),
+        ] {
+            let err = subject_command(&header(subject), &[]).unwrap_err();
+            assert!(err.to_string().contains(expected), "{}", err);
+        }
+        let err = subject_command(&header("Hello"), &templates).unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::CircuitInputFailed),
+            ErrorCode::ParseEmailFailed
+        );
+        assert!(err.to_string().contains("matches none of the templates"), "{}", err);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::*;
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+/// Path of the committed vectors, relative to the crate root.
+pub const CONFORMANCE_VECTORS_PATH: &str = "conformance_vectors.json";
+
+const EMAIL_ADDRS: [&str; 3] = [
+    "alice@gmail.com",
+    "bob.smith+wallet@example.co.jp",
+    "x@y.z",
+];
+const RELAYER_RAND: &str = "0x0cc1bd1d15c7ee8cef3d0db399bfcd9dc0fdcd8bd3f1b7b5ec7aadc5c12c2ebb";
+const COMMIT_RAND: &str = "0x1a3cbb4ca583c3fce36bdfef3b49c4698935bc9522c88a3cd0fb44fe6a78ea68";
+/// Fixture of `test_fixture` whose email auth input is in the vectors, with the default options.
+const EMAIL_AUTH_INPUT_FIXTURE: &str = "send_eth";
+const PUBLIC_KEY_N: &str = "0xcfb0520e4ad78c4adb0deb5e605162b6469349fc1fde9269b88d596ed9f3735c00c592317c982320874b987bcc38e8556ac544bdee169b66ae8fe639828ff5afb4f199017e3d8e675a077f21cd9e5c526c1866476e7ba74cd7bb16a1c3d93bc7bb1d576aedb4307c6b948d5b8c29f79307788d7a8ebf84585bf53994827c23a5";
+
+/// Generates the cross-language conformance vectors.
+///
+/// Every entry inlines its inputs so that other implementations can consume the file directly.
+/// Hex strings are big-endian with a `0x` prefix; field elements use the `field2hex` encoding.
+pub fn conformance_vectors() -> Result<Value> {
+    let poseidon_err = |e: PoseidonError| anyhow!("poseidon failed: {}", e);
+
+    let bytes2fields_vectors = EMAIL_ADDRS
+        .iter()
+        .map(|email_addr| {
+            json!({
+                "input": email_addr,
+                "input_bytes": "0x".to_string() + &hex::encode(email_addr.as_bytes()),
+                "fields": bytes2fields(email_addr.as_bytes()).iter().map(field2hex).collect_vec(),
+            })
+        })
+        .collect_vec();
+
+    let padded_email_addrs = EMAIL_ADDRS
+        .iter()
+        .map(|email_addr| {
+            let padded = PaddedEmailAddr::from_email_addr(email_addr);
+            json!({
+                "email_addr": email_addr,
+                "email_addr_len": padded.email_addr_len,
+                "padded_bytes": "0x".to_string() + &hex::encode(&padded.padded_bytes),
+                "fields": padded.to_email_addr_fields().iter().map(field2hex).collect_vec(),
+            })
+        })
+        .collect_vec();
+
+    let account_code = AccountCode::from(hex2field(FIXTURE_ACCOUNT_CODE)?);
+    let relayer_rand = RelayerRand(hex2field(RELAYER_RAND)?);
+    let relayer_rand_hash = relayer_rand.hash().map_err(poseidon_err)?;
+    let commit_rand = hex2field(COMMIT_RAND)?;
+
+    let mut account_salts = vec![];
+    let mut account_code_commits = vec![];
+    let mut email_addr_commits = vec![];
+    for email_addr in EMAIL_ADDRS.iter() {
+        let padded = PaddedEmailAddr::from_email_addr(email_addr);
+        let salt = AccountSalt::new(&padded, account_code).map_err(poseidon_err)?;
+        account_salts.push(json!({
+            "email_addr": email_addr,
+            "account_code": FIXTURE_ACCOUNT_CODE,
+            "account_salt": field2hex(&salt.0),
+        }));
+        let commit = account_code
+            .to_commitment(&padded, &relayer_rand_hash)
+            .map_err(poseidon_err)?;
+        account_code_commits.push(json!({
+            "email_addr": email_addr,
+            "account_code": FIXTURE_ACCOUNT_CODE,
+            "relayer_rand_hash": field2hex(&relayer_rand_hash),
+            "account_code_commit": field2hex(&commit),
+        }));
+        let commit = padded.to_commitment(&commit_rand).map_err(poseidon_err)?;
+        email_addr_commits.push(json!({
+            "email_addr": email_addr,
+            "rand": COMMIT_RAND,
+            "email_addr_commit": field2hex(&commit),
+        }));
+    }
+
+    let public_key_n = hex::decode(&PUBLIC_KEY_N[2..])?;
+    let public_key_hash = public_key_hash_from_modulus(&public_key_n).map_err(poseidon_err)?;
+    let public_key_hashes = vec![json!({
+        "public_key_n": PUBLIC_KEY_N,
+        "public_key_hash": field2hex(&public_key_hash),
+    })];
+
+    let signature = (0..128u32).map(|i| (i * 7 + 3) as u8).collect_vec();
+    let email_nullifier = email_nullifier_from_signature(&signature).map_err(poseidon_err)?;
+    let email_nullifiers = vec![json!({
+        "signature": "0x".to_string() + &hex::encode(&signature),
+        "email_nullifier": field2hex(&email_nullifier),
+    })];
+
+    let fixture = test_fixture(EMAIL_AUTH_INPUT_FIXTURE)?;
+    let email_auth_input = generate_email_auth_input_from_canonical(
+        fixture.canonicalized_header.as_bytes(),
+        &hex::decode(&fixture.signature)?,
+        &hex::decode(&fixture.public_key)?,
+        &AccountCode::from(hex2field(&fixture.account_code)?),
+    )?;
+    let email_auth_inputs = vec![json!({
+        "fixture": fixture.name,
+        "canonicalized_header": fixture.canonicalized_header,
+        "signature": "0x".to_string() + &fixture.signature,
+        "public_key_n": "0x".to_string() + &fixture.public_key,
+        "account_code": fixture.account_code,
+        "email_auth_input": serde_json::from_str::<Value>(&email_auth_input)?,
+    })];
+
+    Ok(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "poseidon_config_fingerprint": "0x".to_string()
+            + &hex::encode(config_fingerprint().map_err(poseidon_err)?),
+        "relayer_rand": {
+            "relayer_rand": RELAYER_RAND,
+            "relayer_rand_hash": field2hex(&relayer_rand_hash),
+        },
+        "bytes2fields": bytes2fields_vectors,
+        "padded_email_addrs": padded_email_addrs,
+        "account_salts": account_salts,
+        "account_code_commits": account_code_commits,
+        "email_addr_commits": email_addr_commits,
+        "public_key_hashes": public_key_hashes,
+        "email_nullifiers": email_nullifiers,
+        "email_auth_inputs": email_auth_inputs,
+    }))
+}
+
+/// Serializes the vectors exactly as they are committed.
+pub fn conformance_vectors_string() -> Result<String> {
+    Ok(serde_json::to_string_pretty(&conformance_vectors()?)? + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    /// Set to `1` to rewrite the committed vectors instead of checking them.
+    const UPDATE_VECTORS_ENV: &str = "UPDATE_VECTORS";
+
+    #[test]
+    fn test_conformance_vectors_are_up_to_date() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(CONFORMANCE_VECTORS_PATH);
+        let generated = conformance_vectors_string().unwrap();
+        if std::env::var(UPDATE_VECTORS_ENV).as_deref() == Ok("1") {
+            fs::write(&path, generated).unwrap();
+            return;
+        }
+        let committed = fs::read_to_string(&path).unwrap_or_else(|e| {
+            panic!(
+                "{} cannot be read: {}; run `cargo run -- gen-vectors` and commit it",
+                CONFORMANCE_VECTORS_PATH, e
+            )
+        });
+        assert_eq!(
+            committed, generated,
+            "{} is stale; run `cargo run -- gen-vectors` or the test with {}=1 and review the diff",
+            CONFORMANCE_VECTORS_PATH, UPDATE_VECTORS_ENV
+        );
+    }
+}
@@ -4,22 +4,75 @@ use anyhow;
 use ethers::types::U256;
 use halo2curves::ff::PrimeField;
 use itertools::Itertools;
+#[cfg(feature = "node")]
 use neon::prelude::*;
-use num_bigint::BigInt;
+use num_bigint::{BigInt, BigUint};
 use poseidon_rs::*;
 
 pub use zk_regex_apis::padding::pad_string;
 
 use crate::circuit::{CIRCOM_BIGINT_K, CIRCOM_BIGINT_N};
+#[cfg(feature = "node")]
+use crate::error_code::{throw_coded, ErrorCode};
 
-pub fn hex2field(input_hex: &str) -> anyhow::Result<Fr> {
-    if &input_hex[0..2] != "0x" {
-        return Err(anyhow::anyhow!(format!(
-            "the input string {} must be hex string with 0x prefix",
-            &input_hex
-        )));
+/// Zero-width characters (ZWSP, ZWNJ, ZWJ, word joiner, BOM) and non-ASCII whitespace such as
+/// NBSP. They render invisibly, so a pasted address can look right while hashing differently.
+pub fn is_invisible_char(c: char) -> bool {
+    matches!(c, '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{2060}' | '\u{FEFF}')
+        || (c.is_whitespace() && !c.is_ascii())
+}
+
+/// Fails when `value`, described as `what` in the error, has an invisible character, naming the
+/// first one and its byte offset.
+///
+/// The characters are never stripped: the circuit reads the from address, the subject and the
+/// command from the signed header bytes, so a value without them would not match what it proves.
+pub fn check_invisible_chars(value: &str, what: &str) -> anyhow::Result<()> {
+    match value.char_indices().find(|&(_, c)| is_invisible_char(c)) {
+        Some((offset, c)) => Err(anyhow::anyhow!(
+            "the {} contains the invisible character U+{:04X} at byte {}",
+            what,
+            c as u32,
+            offset
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Returns the digits after a `0x` or `0X` prefix, or `None` without one.
+///
+/// All hex parsing is case-insensitive; hex output is always lowercase.
+pub fn strip_hex_prefix(input_hex: &str) -> Option<&str> {
+    input_hex
+        .strip_prefix("0x")
+        .or_else(|| input_hex.strip_prefix("0X"))
+}
+
+/// Decodes a hex string with an optional `0x` prefix. An even number of digits is required.
+pub fn decode_hex(input_hex: &str) -> anyhow::Result<Vec<u8>> {
+    let digits = strip_hex_prefix(input_hex).unwrap_or(input_hex);
+    if digits.len() % 2 != 0 {
+        return Err(anyhow::anyhow!(
+            "the input string {} must have an even number of hex digits but has {}",
+            input_hex,
+            digits.len()
+        ));
     }
-    let mut bytes = match hex::decode(&input_hex[2..]) {
+    hex::decode(digits)
+        .map_err(|e| anyhow::anyhow!("the input string {} is invalid hex: {}", input_hex, e))
+}
+
+pub fn hex2field(input_hex: &str) -> anyhow::Result<Fr> {
+    let digits = match strip_hex_prefix(input_hex) {
+        Some(digits) => digits,
+        None => {
+            return Err(anyhow::anyhow!(format!(
+                "the input string {} must be hex string with 0x prefix",
+                &input_hex
+            )))
+        }
+    };
+    let mut bytes = match hex::decode(digits) {
         Ok(bytes) => bytes,
         Err(e) => {
             return Err(anyhow::anyhow!(format!(
@@ -45,14 +98,26 @@ pub fn hex2field(input_hex: &str) -> anyhow::Result<Fr> {
             )))
         }
     };
-    let field = Fr::from_bytes(&bytes).expect("fail to convert bytes to a field value");
-    Ok(field)
+    Option::from(Fr::from_bytes(&bytes)).ok_or_else(|| {
+        anyhow::anyhow!(
+            "the input string {} is not a field element: it is not smaller than the field modulus",
+            input_hex
+        )
+    })
 }
 
 pub fn field2hex(field: &Fr) -> String {
     format!("{:?}", field)
 }
 
+/// `field` as a decimal string, as snarkjs writes signals.
+pub fn field2decimal(field: &Fr) -> String {
+    let hex = field2hex(field);
+    BigUint::parse_bytes(hex.trim_start_matches("0x").as_bytes(), 16)
+        .unwrap_or_default()
+        .to_str_radix(10)
+}
+
 pub fn digits2int(input_digits: &str) -> anyhow::Result<u64> {
     Ok(input_digits.parse()?)
 }
@@ -117,13 +182,80 @@ pub fn bytes_chunk_fields(
     fields
 }
 
+/// Reconstructs the big-endian hex of a modulus or signature from its limbs, given as a JSON
+/// array of decimal strings in the order of the circuit inputs. Invalid arguments, including a
+/// limb size that is not an integer in `1..=MAX_BITS_PER_LIMB`, throw `InvalidArgument`.
+#[cfg(feature = "node")]
+pub fn limbs_to_hex_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let limbs = cx.argument::<JsString>(0)?.value(&mut cx);
+    let bits_per_limb = cx.argument::<JsNumber>(1)?.value(&mut cx);
+    if bits_per_limb.fract() != 0.0
+        || !(1.0..=MAX_BITS_PER_LIMB as f64).contains(&bits_per_limb)
+    {
+        return throw_coded(
+            &mut cx,
+            ErrorCode::InvalidArgument,
+            format!(
+                "the limb size {} is not an integer between 1 and {}",
+                bits_per_limb, MAX_BITS_PER_LIMB
+            ),
+        );
+    }
+    let limbs: Vec<String> = match serde_json::from_str(&limbs) {
+        Ok(limbs) => limbs,
+        Err(e) => {
+            return throw_coded(
+                &mut cx,
+                ErrorCode::InvalidArgument,
+                format!("limbs must be a JSON array of strings: {}", e),
+            )
+        }
+    };
+    match limbs_to_bigint(&limbs, bits_per_limb as usize) {
+        Ok(n) => Ok(cx.string("0x".to_string() + &hex::encode(n.to_bytes_be().1))),
+        Err(e) => throw_coded(&mut cx, ErrorCode::InvalidArgument, e.to_string()),
+    }
+}
+
+/// Trims the ASCII whitespace around a string argument, such as the trailing newline of a value
+/// read from a properties file, and rejects whitespace inside it rather than deleting it.
+/// Positions are byte offsets in `value`.
+pub fn trim_argument<'a>(value: &'a str, what: &str) -> anyhow::Result<&'a str> {
+    let trimmed_start = value.trim_start_matches(|c: char| c.is_ascii_whitespace());
+    let trimmed = trimmed_start.trim_end_matches(|c: char| c.is_ascii_whitespace());
+    if let Some(pos) = trimmed.find(char::is_whitespace) {
+        return Err(anyhow::anyhow!(
+            "{} contains whitespace at position {}",
+            what,
+            value.len() - trimmed_start.len() + pos
+        ));
+    }
+    Ok(trimmed)
+}
+
+/// The string argument `i`, passed through `trim_argument`.
+#[cfg(feature = "node")]
+pub fn trimmed_argument(cx: &mut FunctionContext, i: i32, what: &str) -> NeonResult<String> {
+    let value = cx.argument::<JsString>(i)?.value(cx);
+    match trim_argument(&value, what) {
+        Ok(trimmed) => Ok(trimmed.to_string()),
+        Err(e) => throw_coded(cx, ErrorCode::InvalidArgument, e.to_string()),
+    }
+}
+
+#[cfg(feature = "node")]
 pub fn hex2field_node(cx: &mut FunctionContext, input_strs: &str) -> NeonResult<Fr> {
+    let input_strs = match trim_argument(input_strs, "the hex string") {
+        Ok(trimmed) => trimmed,
+        Err(e) => return throw_coded(cx, ErrorCode::InvalidArgument, e.to_string()),
+    };
     match hex2field(input_strs) {
         Ok(field) => Ok(field),
-        Err(e) => cx.throw_error(e.to_string()),
+        Err(e) => throw_coded(cx, ErrorCode::InvalidArgument, e.to_string()),
     }
 }
 
+#[cfg(feature = "node")]
 pub fn bytes2fields_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsArray>(0)?;
     let input_vec = input_str.to_vec(&mut cx)?;
@@ -163,25 +295,53 @@ pub fn uint8_array_to_char_array(bytes: Vec<u8>) -> Vec<String> {
     bytes.iter().map(|&b| b.to_string()).collect()
 }
 
-fn big_int_to_chunked_bytes(num: BigInt, bits_per_chunk: usize, num_chunks: usize) -> Vec<String> {
-    let mut chunks = Vec::new();
-    let mut remainder = num;
-    let two = BigInt::from(2);
-    let chunk_size = two.pow(bits_per_chunk as u32);
+/// Splits `n` into `num_limbs` limbs of `bits_per_limb` bits as decimal strings, **least
+/// significant limb first**: `limbs[0]` holds bits `0..bits_per_limb` of `n`. This is the order of
+/// the `public_key` and `signature` arrays of every circuit input.
+///
+/// `n` must be non-negative and fit in `num_limbs * bits_per_limb` bits; higher bits are dropped.
+pub fn bigint_to_limbs(n: &BigInt, bits_per_limb: usize, num_limbs: usize) -> Vec<String> {
+    let mask = (BigInt::from(1) << bits_per_limb) - 1;
+    (0..num_limbs)
+        .map(|i| ((n >> (i * bits_per_limb)) & &mask).to_string())
+        .collect()
+}
 
-    // Divide the number into chunks and convert each to a decimal string
-    for _ in 0..num_chunks {
-        let chunk = &remainder % &chunk_size;
-        remainder = remainder >> bits_per_chunk;
-        // Convert chunk to decimal string
-        chunks.push(chunk.to_string());
-    }
+/// Largest limb size `limbs_to_bigint` accepts.
+pub const MAX_BITS_PER_LIMB: usize = 256;
 
-    chunks
+/// Inverse of `bigint_to_limbs`: `limbs` are decimal strings, least significant limb first, and
+/// each must be below `2^bits_per_limb`. `bits_per_limb` must be in `1..=MAX_BITS_PER_LIMB`.
+pub fn limbs_to_bigint<S: AsRef<str>>(limbs: &[S], bits_per_limb: usize) -> anyhow::Result<BigInt> {
+    if !(1..=MAX_BITS_PER_LIMB).contains(&bits_per_limb) {
+        return Err(anyhow::anyhow!(
+            "the limb size {} is not between 1 and {} bits",
+            bits_per_limb,
+            MAX_BITS_PER_LIMB
+        ));
+    }
+    let bound = BigInt::from(1) << bits_per_limb;
+    let mut n = BigInt::from(0);
+    for (i, limb) in limbs.iter().enumerate().rev() {
+        let limb = limb.as_ref();
+        let value = limb
+            .parse::<BigInt>()
+            .map_err(|e| anyhow::anyhow!("limb {} `{}` is not a decimal integer: {}", i, limb, e))?;
+        if value < BigInt::from(0) || value >= bound {
+            return Err(anyhow::anyhow!(
+                "limb {} `{}` does not fit in {} bits",
+                i,
+                limb,
+                bits_per_limb
+            ));
+        }
+        n = (n << bits_per_limb) | value;
+    }
+    Ok(n)
 }
 
 pub fn to_circom_bigint_bytes(num: BigInt) -> Vec<String> {
-    big_int_to_chunked_bytes(num, CIRCOM_BIGINT_N, CIRCOM_BIGINT_K)
+    bigint_to_limbs(&num, CIRCOM_BIGINT_N, CIRCOM_BIGINT_K)
 }
 
 pub fn vec_u8_to_bigint(bytes: Vec<u8>) -> BigInt {
@@ -201,7 +361,7 @@ pub fn u256_to_hex(x: &U256) -> String {
 }
 
 pub fn hex_to_u256(hex: &str) -> Result<U256, hex::FromHexError> {
-    let bytes: Vec<u8> = hex::decode(&hex[2..])?;
+    let bytes: Vec<u8> = hex::decode(strip_hex_prefix(hex).unwrap_or(hex))?;
     let mut array = [0u8; 32];
     array.copy_from_slice(&bytes);
     Ok(U256::from_big_endian(&array))
@@ -235,3 +395,43 @@ pub fn u256_to_bytes32_little(x: &U256) -> [u8; 32] {
     x.to_little_endian(&mut bytes);
     bytes
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::FIXTURE_ACCOUNT_CODE;
+    use rand_core::{OsRng, RngCore};
+
+    #[test]
+    fn test_limbs_round_trip() {
+        for len in [0, 1, 16, 128, 256] {
+            let mut bytes = vec![0u8; len];
+            OsRng.fill_bytes(&mut bytes);
+            let n = vec_u8_to_bigint(bytes);
+            let limbs = bigint_to_limbs(&n, CIRCOM_BIGINT_N, CIRCOM_BIGINT_K);
+            assert_eq!(limbs.len(), CIRCOM_BIGINT_K);
+            assert_eq!(limbs_to_bigint(&limbs, CIRCOM_BIGINT_N).unwrap(), n);
+        }
+
+        let limbs = bigint_to_limbs(&BigInt::from(0x1234u32), 8, 3);
+        assert_eq!(limbs, ["52", "18", "0"]);
+        assert!(limbs_to_bigint(&["256"], 8).is_err());
+        assert!(limbs_to_bigint(&["x"], 8).is_err());
+        assert!(limbs_to_bigint(&["0"], 0).is_err());
+        assert!(limbs_to_bigint(&["0"], MAX_BITS_PER_LIMB + 1).is_err());
+    }
+
+    #[test]
+    fn test_trim_argument() {
+        let account_code = FIXTURE_ACCOUNT_CODE;
+        let trimmed = trim_argument(&format!(" {}\r\n", account_code), "account code").unwrap();
+        assert_eq!(trimmed, account_code);
+        assert!(hex2field(trimmed).is_ok());
+        assert_eq!(trim_argument("alice@gmail.com\n", "email").unwrap(), "alice@gmail.com");
+
+        let err = trim_argument("\t0x22a2 d51a\n", "account code").unwrap_err();
+        assert_eq!(err.to_string(), "account code contains whitespace at position 7");
+        let err = trim_argument("alice@gmail.com\u{a0}", "email").unwrap_err();
+        assert!(err.to_string().contains("position 15"), "{}", err);
+    }
+}
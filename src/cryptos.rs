@@ -1,13 +1,26 @@
 use std::error::Error;
 
 use crate::converters::*;
+use crate::digest_invariant::SHA256_INITIAL_STATE;
+use crate::error_code::{coded, ErrorCode};
+use crate::logger::LOG;
+#[cfg(feature = "node")]
+use crate::error_code::{error_code, throw_coded};
 
+use base64::{engine::general_purpose, Engine as _};
 use ethers::types::Bytes;
 use halo2curves::ff::Field;
+#[cfg(feature = "node")]
 use neon::prelude::*;
 use poseidon_rs::*;
 use rand_core::{OsRng, RngCore};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::traits::PublicKeyParts;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use serde::Serialize;
 use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
 pub use zk_regex_apis::padding::pad_string;
 
 pub const MAX_EMAIL_ADDR_BYTES: usize = 256;
@@ -41,20 +54,38 @@ impl PaddedEmailAddr {
         let email_addr_len = email_addr.as_bytes().len();
         // let mut padded_bytes = email_addr.as_bytes().to_vec();
         // padded_bytes.append(&mut vec![0; MAX_EMAIL_ADDR_BYTES - email_addr_len]);
-        let padded_bytes = pad_string(email_addr, MAX_EMAIL_ADDR_BYTES);
+        let padded_bytes = pad_string(email_addr, active_circuit_profile().max_email_addr_bytes);
         Self {
             padded_bytes,
             email_addr_len,
         }
     }
 
+    /// Like `from_email_addr`, but fails with `EmailAddrTooLong` when the address does not fit in
+    /// the profile's `max_email_addr_bytes`.
+    pub fn try_from_email_addr(email_addr: &str) -> Result<Self, EmailAddrTooLong> {
+        let limit = active_circuit_profile().max_email_addr_bytes;
+        let actual = email_addr.len();
+        if actual > limit {
+            let normalized = normalize_email_addr(email_addr);
+            return Err(EmailAddrTooLong {
+                limit,
+                actual,
+                after_normalization: (normalized != email_addr).then(|| normalized.len()),
+            });
+        }
+        Ok(Self::from_email_addr(email_addr))
+    }
+
     pub fn to_email_addr_fields(&self) -> Vec<Fr> {
         bytes2fields(&self.padded_bytes)
     }
 
-    // pub fn to_pointer(&self, relayer_rand: &RelayerRand) -> Result<Fr, PoseidonError> {
-    //     self.to_commitment(&relayer_rand.0)
-    // }
+    /// The pointer of the address for a relayer: its commitment under the relayer rand, which
+    /// only that relayer can recompute, so it serves as the relayer's viewing key of the account.
+    pub fn to_pointer(&self, relayer_rand: &RelayerRand) -> Result<Fr, PoseidonError> {
+        self.to_commitment(&relayer_rand.0)
+    }
 
     pub fn to_commitment(&self, rand: &Fr) -> Result<Fr, PoseidonError> {
         let mut inputs = vec![*rand];
@@ -68,6 +99,52 @@ impl PaddedEmailAddr {
     }
 }
 
+/// An email address longer than the circuit's padding. `after_normalization` is its length after
+/// `normalize_email_addr`, or `None` when normalization does not change it; the salt is still
+/// derived from the address as given, so this only tells the caller whether another form of the
+/// address would fit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EmailAddrTooLong {
+    pub limit: usize,
+    pub actual: usize,
+    pub after_normalization: Option<usize>,
+}
+
+impl EmailAddrTooLong {
+    pub const CODE: &'static str = "EMAIL_ADDR_TOO_LONG";
+}
+
+/// Displayed as JSON with the `code`, so that callers can read the fields from the message.
+impl std::fmt::Display for EmailAddrTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json = serde_json::json!({
+            "code": Self::CODE,
+            "limit": self.limit,
+            "actual": self.actual,
+            "after_normalization": self.after_normalization,
+        });
+        write!(f, "{}", json)
+    }
+}
+
+impl std::error::Error for EmailAddrTooLong {}
+
+/// Provider normalizations of an email address: the domain is lowercased, and for Gmail the dots
+/// and the `+` tag of the local part are dropped, as Gmail delivers those forms to the same
+/// mailbox.
+pub fn normalize_email_addr(email_addr: &str) -> String {
+    let (local, domain) = match email_addr.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return email_addr.to_string(),
+    };
+    let domain = domain.to_ascii_lowercase();
+    if domain != "gmail.com" && domain != "googlemail.com" {
+        return format!("{}@{}", local, domain);
+    }
+    let local = local.split('+').next().unwrap_or_default().replace('.', "");
+    format!("{}@{}", local.to_ascii_lowercase(), domain)
+}
+
 pub fn extract_rand_from_signature(signature: &[u8]) -> Result<Fr, PoseidonError> {
     let mut signature = signature.to_vec();
     signature.reverse();
@@ -85,6 +162,12 @@ impl AccountCode {
         Self(Fr::random(rng))
     }
 
+    /// A deterministic account code derived from `seed`, for test fixtures. Like `new`, the value
+    /// is a canonical field element, so its `field2hex` is accepted by `hex2field`.
+    pub fn new_from_seed(seed: &[u8]) -> Result<Self, PoseidonError> {
+        Ok(Self(poseidon_bytes(seed)?))
+    }
+
     pub fn from(elem: Fr) -> Self {
         Self(elem)
     }
@@ -116,12 +199,98 @@ impl AccountSalt {
     }
 }
 
-/// `public_key_n` is little endian.
+/// Returns the index of the first of `candidate_codes` whose account salt with `email_addr` is
+/// `expected_salt_hex`. Every candidate is derived and compared in constant time, so that the
+/// timing does not reveal the position of the match. Candidates that are not valid hex field
+/// elements are skipped with a warning.
+pub fn find_matching_account_code(
+    email_addr: &str,
+    expected_salt_hex: &str,
+    candidate_codes: &[String],
+) -> anyhow::Result<Option<usize>> {
+    let expected_salt = hex2field(expected_salt_hex)?;
+    let padded_email_addr = PaddedEmailAddr::from_email_addr(email_addr);
+    let mut matching = None;
+    for (index, candidate) in candidate_codes.iter().enumerate() {
+        let account_code = match hex2field(candidate) {
+            Ok(account_code) => AccountCode(account_code),
+            Err(e) => {
+                slog::warn!(LOG, "skipping an invalid candidate account code";
+                    "index" => index, "error" => e.to_string());
+                continue;
+            }
+        };
+        let salt = AccountSalt::new(&padded_email_addr, account_code)
+            .map_err(|e| anyhow::anyhow!("AccountSalt failed: {}", e))?;
+        if bool::from(salt.0.ct_eq(&expected_salt)) && matching.is_none() {
+            matching = Some(index);
+        }
+    }
+    Ok(matching)
+}
+
+/// The public exponent of almost every DKIM key, and the only one the circuits support: they
+/// take the modulus alone and check the signature with this exponent.
+pub const DEFAULT_PUBLIC_EXPONENT: u64 = 65537;
+
+/// A DKIM key whose public exponent is not `DEFAULT_PUBLIC_EXPONENT`. Its signatures verify, but
+/// the circuits would check them with the wrong exponent, so no input is generated for them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UnsupportedPublicExponent {
+    pub public_exponent: u64,
+}
+
+impl UnsupportedPublicExponent {
+    pub const CODE: &'static str = "UNSUPPORTED_PUBLIC_EXPONENT";
+}
+
+impl std::fmt::Display for UnsupportedPublicExponent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: the DKIM key has the public exponent {} but the circuits only support {}",
+            Self::CODE,
+            self.public_exponent,
+            DEFAULT_PUBLIC_EXPONENT
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedPublicExponent {}
+
+/// Fails with `UnsupportedPublicExponent` unless a key with `public_exponent` can be proven with.
+pub fn check_provable_exponent(public_exponent: u64) -> anyhow::Result<()> {
+    if public_exponent != DEFAULT_PUBLIC_EXPONENT {
+        return Err(UnsupportedPublicExponent { public_exponent }.into());
+    }
+    Ok(())
+}
+
+/// The public exponent of `public_key`. The rsa crate rejects exponents of more than 33 bits, so
+/// it fits.
+pub fn rsa_public_exponent(public_key: &RsaPublicKey) -> u64 {
+    public_key
+        .e()
+        .to_bytes_be()
+        .iter()
+        .fold(0, |exponent, &byte| exponent << 8 | u64::from(byte))
+}
+
+/// `public_key_n` is little endian. Only the modulus is hashed: keys are only proven with
+/// `DEFAULT_PUBLIC_EXPONENT`, so the hash does not bind the exponent.
 pub fn public_key_hash(public_key_n: &[u8]) -> Result<Fr, PoseidonError> {
     let inputs = bytes_chunk_fields(public_key_n, 121, 2, 17);
     poseidon_fields(&inputs)
 }
 
+/// The public key hash of a big-endian modulus, the order of `ParsedEmail::public_key` and of
+/// the DKIM key record.
+pub fn public_key_hash_from_modulus(public_key_n: &[u8]) -> Result<Fr, PoseidonError> {
+    let mut public_key_n = public_key_n.to_vec();
+    public_key_n.reverse();
+    public_key_hash(&public_key_n)
+}
+
 /// `signature` is little endian.
 pub fn email_nullifier(signature: &[u8]) -> Result<Fr, PoseidonError> {
     let inputs = bytes_chunk_fields(signature, 121, 2, 17);
@@ -129,6 +298,92 @@ pub fn email_nullifier(signature: &[u8]) -> Result<Fr, PoseidonError> {
     poseidon_fields(&[sign_rand])
 }
 
+/// The email nullifier of a big-endian signature, the order of the DKIM `b=` tag.
+pub fn email_nullifier_from_signature(signature: &[u8]) -> Result<Fr, PoseidonError> {
+    let mut signature = signature.to_vec();
+    signature.reverse();
+    email_nullifier(&signature)
+}
+
+/// `email_nullifier_from_signature` of a hex signature, with or without a `0x` prefix. A string
+/// of an odd length or with non-hex characters fails with `ErrorCode::InvalidArgument`.
+pub fn email_nullifier_from_hex(signature_hex: &str) -> anyhow::Result<Fr> {
+    let signature = decode_hex(signature_hex.trim()).map_err(|e| {
+        coded(
+            ErrorCode::InvalidArgument,
+            format!("signature is an invalid hex string: {}", e),
+        )
+    })?;
+    email_nullifier_from_signature(&signature).map_err(|e| {
+        coded(
+            ErrorCode::CryptoFailed,
+            format!("email_nullifier failed: {}", e),
+        )
+    })
+}
+
+/// Parses a DER-encoded RSA public key, either as a SubjectPublicKeyInfo (the DKIM `p=` form)
+/// or as a bare PKCS#1 RSAPublicKey.
+pub fn rsa_public_key_from_der(der: &[u8]) -> anyhow::Result<RsaPublicKey> {
+    match RsaPublicKey::from_public_key_der(der) {
+        Ok(public_key) => Ok(public_key),
+        Err(_) => RsaPublicKey::from_pkcs1_der(der)
+            .map_err(|e| anyhow::anyhow!("invalid DER-encoded RSA public key: {}", e)),
+    }
+}
+
+/// Parses the RSA public key of a DKIM DNS TXT record such as `v=DKIM1; k=rsa; p=MIIBIj...`.
+/// Records split into several quoted strings and whitespace inside `p=` are accepted.
+pub fn rsa_public_key_from_dkim_txt(txt_record: &str) -> anyhow::Result<RsaPublicKey> {
+    let record = txt_record.replace('"', "");
+    let mut key_type = "rsa".to_string();
+    let mut encoded_key = None;
+    for tag in record.split(';') {
+        let (name, value) = match tag.split_once('=') {
+            Some(tag) => tag,
+            None => continue,
+        };
+        let value: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+        match name.trim() {
+            "k" => key_type = value,
+            "p" => encoded_key = Some(value),
+            _ => {}
+        }
+    }
+    if !key_type.eq_ignore_ascii_case("rsa") {
+        return Err(anyhow::anyhow!("unsupported DKIM key type {}", key_type));
+    }
+    let encoded_key =
+        encoded_key.ok_or_else(|| anyhow::anyhow!("the DKIM record has no p= tag"))?;
+    if encoded_key.is_empty() {
+        return Err(anyhow::anyhow!(
+            "the DKIM key has been revoked (empty p= tag)"
+        ));
+    }
+    let der = general_purpose::STANDARD
+        .decode(&encoded_key)
+        .map_err(|e| anyhow::anyhow!("the p= tag is invalid base64: {}", e))?;
+    rsa_public_key_from_der(&der)
+}
+
+/// `public_key_hash` of a base64 DER-encoded RSA public key, such as the `p=` tag of a DKIM
+/// record. The modulus is reversed to little endian, as `publicKeyHash` does with its big-endian
+/// hex modulus, so both give the same hash for the same key.
+pub fn public_key_hash_from_der(base64_der: &str) -> anyhow::Result<Fr> {
+    let base64_der: String = base64_der.chars().filter(|c| !c.is_whitespace()).collect();
+    let der = general_purpose::STANDARD.decode(&base64_der).map_err(|e| {
+        coded(
+            ErrorCode::InvalidArgument,
+            format!("the public key is invalid base64: {}", e),
+        )
+    })?;
+    let public_key = rsa_public_key_from_der(&der)
+        .map_err(|e| coded(ErrorCode::InvalidArgument, e))?;
+    public_key_hash_from_modulus(&public_key.n().to_bytes_be())
+        .map_err(|e| coded(ErrorCode::CryptoFailed, format!("public_key_hash failed: {}", e)))
+}
+
+#[cfg(feature = "node")]
 pub fn gen_relayer_rand_node(mut cx: FunctionContext) -> JsResult<JsString> {
     let mut rng = OsRng;
     let relayer_rand = RelayerRand::new(&mut rng);
@@ -162,8 +417,19 @@ pub fn gen_relayer_rand_node(mut cx: FunctionContext) -> JsResult<JsString> {
 //     Ok(padded_bytes)
 // }
 
+/// Reads an email address argument, rejecting invisible characters (see `is_invisible_char`).
+#[cfg(feature = "node")]
+fn email_addr_argument(cx: &mut FunctionContext, i: i32) -> NeonResult<String> {
+    let email_addr = trimmed_argument(cx, i, "the email address")?;
+    match check_invisible_chars(&email_addr, "email address") {
+        Ok(_) => Ok(email_addr),
+        Err(e) => cx.throw_error(e.to_string()),
+    }
+}
+
+#[cfg(feature = "node")]
 pub fn pad_email_addr_node(mut cx: FunctionContext) -> JsResult<JsArray> {
-    let email_addr = cx.argument::<JsString>(0)?.value(&mut cx);
+    let email_addr = email_addr_argument(&mut cx, 0)?;
     let padded_email_addr = PaddedEmailAddr::from_email_addr(&email_addr);
     let padded_email_addr_bytes =
         JsArray::new(&mut cx, padded_email_addr.padded_bytes.len() as u32);
@@ -174,19 +440,31 @@ pub fn pad_email_addr_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     Ok(padded_email_addr_bytes)
 }
 
-// pub fn email_addr_pointer_node(mut cx: FunctionContext) -> JsResult<JsString> {
-//     let email_addr = cx.argument::<JsString>(0)?.value(&mut cx);
-//     let relayer_rand = cx.argument::<JsString>(1)?.value(&mut cx);
-//     let relayer_rand = hex2field_node(&mut cx, &relayer_rand)?;
-//     let padded_email_addr = PaddedEmailAddr::from_email_addr(&email_addr);
-//     let email_addr_pointer = match padded_email_addr.to_pointer(&RelayerRand(relayer_rand)) {
-//         Ok(fr) => fr,
-//         Err(e) => return cx.throw_error(&format!("EmailAddrPointer failed: {}", e)),
-//     };
-//     let email_addr_pointer_str = field2hex(&email_addr_pointer);
-//     Ok(cx.string(email_addr_pointer_str))
-// }
+/// `emailAddrPointer(emailAddr, relayerRand)`: see `PaddedEmailAddr::to_pointer`.
+#[cfg(feature = "node")]
+pub fn email_addr_pointer_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let email_addr = email_addr_argument(&mut cx, 0)?;
+    let relayer_rand = cx.argument::<JsString>(1)?.value(&mut cx);
+    let relayer_rand = hex2field_node(&mut cx, &relayer_rand)?;
+    let padded_email_addr = match PaddedEmailAddr::try_from_email_addr(&email_addr) {
+        Ok(padded_email_addr) => padded_email_addr,
+        Err(e) => return throw_coded(&mut cx, ErrorCode::InvalidArgument, e.to_string()),
+    };
+    let email_addr_pointer = match padded_email_addr.to_pointer(&RelayerRand(relayer_rand)) {
+        Ok(fr) => fr,
+        Err(e) => {
+            return throw_coded(
+                &mut cx,
+                ErrorCode::CryptoFailed,
+                format!("EmailAddrPointer failed: {}", e),
+            )
+        }
+    };
+    let email_addr_pointer_str = field2hex(&email_addr_pointer);
+    Ok(cx.string(email_addr_pointer_str))
+}
 
+#[cfg(feature = "node")]
 pub fn email_addr_commit_rand_node(mut cx: FunctionContext) -> JsResult<JsString> {
     let mut rng = OsRng;
     let commit_rand = Fr::random(&mut rng);
@@ -194,23 +472,34 @@ pub fn email_addr_commit_rand_node(mut cx: FunctionContext) -> JsResult<JsString
     Ok(cx.string(commit_rand_str))
 }
 
+#[cfg(feature = "node")]
 pub fn email_addr_commit_node(mut cx: FunctionContext) -> JsResult<JsString> {
-    let email_addr = cx.argument::<JsString>(0)?.value(&mut cx);
+    let email_addr = email_addr_argument(&mut cx, 0)?;
     let rand = cx.argument::<JsString>(1)?.value(&mut cx);
     let rand = hex2field_node(&mut cx, &rand)?;
-    let padded_email_addr = PaddedEmailAddr::from_email_addr(&email_addr);
+    let padded_email_addr = match PaddedEmailAddr::try_from_email_addr(&email_addr) {
+        Ok(padded_email_addr) => padded_email_addr,
+        Err(e) => return throw_coded(&mut cx, ErrorCode::InvalidArgument, e.to_string()),
+    };
     let email_addr_commit = match padded_email_addr.to_commitment(&rand) {
         Ok(fr) => fr,
-        Err(e) => return cx.throw_error(&format!("EmailAddrCommit failed: {}", e)),
+        Err(e) => {
+            return throw_coded(
+                &mut cx,
+                ErrorCode::CryptoFailed,
+                format!("EmailAddrCommit failed: {}", e),
+            )
+        }
     };
     let email_addr_commit_str = field2hex(&email_addr_commit);
     Ok(cx.string(email_addr_commit_str))
 }
 
+#[cfg(feature = "node")]
 pub fn email_addr_commit_with_signature_node(mut cx: FunctionContext) -> JsResult<JsString> {
-    let email_addr = cx.argument::<JsString>(0)?.value(&mut cx);
-    let signature = cx.argument::<JsString>(1)?.value(&mut cx);
-    let signature = match hex::decode(&signature[2..]) {
+    let email_addr = email_addr_argument(&mut cx, 0)?;
+    let signature = trimmed_argument(&mut cx, 1, "signature")?;
+    let signature = match decode_hex(&signature) {
         Ok(bytes) => bytes,
         Err(e) => return cx.throw_error(&format!("signature is an invalid hex string: {}", e)),
     };
@@ -224,9 +513,10 @@ pub fn email_addr_commit_with_signature_node(mut cx: FunctionContext) -> JsResul
     Ok(cx.string(email_addr_commit_str))
 }
 
+#[cfg(feature = "node")]
 pub fn extract_rand_from_signature_node(mut cx: FunctionContext) -> JsResult<JsString> {
-    let signature = cx.argument::<JsString>(0)?.value(&mut cx);
-    let signature = match hex::decode(&signature[2..]) {
+    let signature = trimmed_argument(&mut cx, 0, "signature")?;
+    let signature = match decode_hex(&signature) {
         Ok(bytes) => bytes,
         Err(e) => return cx.throw_error(&format!("signature is an invalid hex string: {}", e)),
     };
@@ -239,38 +529,160 @@ pub fn extract_rand_from_signature_node(mut cx: FunctionContext) -> JsResult<JsS
     Ok(cx.string(rand_str))
 }
 
+/// `genAccountCode(seedHex?)`: a random account code, or the one derived from `seedHex`.
+#[cfg(feature = "node")]
 pub fn gen_account_code_node(mut cx: FunctionContext) -> JsResult<JsString> {
-    let mut rng = OsRng;
-    let account_code = AccountCode::new(&mut rng);
+    let account_code = match cx.argument_opt(0) {
+        Some(seed) if !seed.is_a::<JsUndefined, _>(&mut cx) => {
+            let seed = seed
+                .downcast_or_throw::<JsString, _>(&mut cx)?
+                .value(&mut cx);
+            let seed = match decode_hex(seed.trim()) {
+                Ok(seed) => seed,
+                Err(e) => return throw_coded(&mut cx, ErrorCode::InvalidArgument, e.to_string()),
+            };
+            match AccountCode::new_from_seed(&seed) {
+                Ok(account_code) => account_code,
+                Err(e) => {
+                    return throw_coded(
+                        &mut cx,
+                        ErrorCode::CryptoFailed,
+                        format!("AccountCode failed: {}", e),
+                    )
+                }
+            }
+        }
+        _ => AccountCode::new(OsRng),
+    };
     let account_code_str = field2hex(&account_code.0);
     Ok(cx.string(account_code_str))
 }
 
+#[cfg(feature = "node")]
 pub fn public_key_hash_node(mut cx: FunctionContext) -> JsResult<JsString> {
-    let public_key_n = cx.argument::<JsString>(0)?.value(&mut cx);
-    let mut public_key_n = match hex::decode(&public_key_n[2..]) {
+    let public_key_n = trimmed_argument(&mut cx, 0, "public_key_n")?;
+    let public_key_n = match decode_hex(&public_key_n) {
         Ok(bytes) => bytes,
-        Err(e) => return cx.throw_error(&format!("public_key_n is an invalid hex string: {}", e)),
+        Err(e) => {
+            return throw_coded(
+                &mut cx,
+                ErrorCode::InvalidArgument,
+                format!("public_key_n is an invalid hex string: {}", e),
+            )
+        }
     };
-    public_key_n.reverse();
-    let hash_field = match public_key_hash(&public_key_n) {
+    public_key_hash_js(&mut cx, public_key_n)
+}
+
+#[cfg(feature = "node")]
+pub fn public_key_hash_bytes_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let public_key_n = cx.argument::<JsBuffer>(0)?;
+    let public_key_n = public_key_n.as_slice(&cx).to_vec();
+    public_key_hash_js(&mut cx, public_key_n)
+}
+
+/// `public_key_n` is big endian.
+#[cfg(feature = "node")]
+fn public_key_hash_js<'a>(
+    cx: &mut FunctionContext<'a>,
+    public_key_n: Vec<u8>,
+) -> JsResult<'a, JsString> {
+    let hash_field = match public_key_hash_from_modulus(&public_key_n) {
         Ok(hash_field) => hash_field,
-        Err(e) => return cx.throw_error(&format!("public_key_hash failed: {}", e)),
+        Err(e) => {
+            return throw_coded(
+                cx,
+                ErrorCode::CryptoFailed,
+                format!("public_key_hash failed: {}", e),
+            )
+        }
     };
     let hash_str = field2hex(&hash_field);
     Ok(cx.string(hash_str))
 }
 
-pub fn email_nullifier_node(mut cx: FunctionContext) -> JsResult<JsString> {
-    let signature = cx.argument::<JsString>(0)?.value(&mut cx);
-    let mut signature = match hex::decode(&signature[2..]) {
-        Ok(bytes) => bytes,
-        Err(e) => return cx.throw_error(&format!("signature is an invalid hex string: {}", e)),
+/// `publicKeyHashFromDer(base64Der)` returns the hex `public_key_hash_from_der`.
+#[cfg(feature = "node")]
+pub fn public_key_hash_from_der_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let base64_der = cx.argument::<JsString>(0)?.value(&mut cx);
+    match public_key_hash_from_der(&base64_der) {
+        Ok(hash_field) => Ok(cx.string(field2hex(&hash_field))),
+        Err(e) => throw_coded(
+            &mut cx,
+            error_code(&e, ErrorCode::CryptoFailed),
+            e.to_string(),
+        ),
+    }
+}
+
+#[cfg(feature = "node")]
+pub fn public_key_hash_from_txt_node(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let txt_record = cx.argument::<JsString>(0)?.value(&mut cx);
+    let public_key = match rsa_public_key_from_dkim_txt(&txt_record) {
+        Ok(public_key) => public_key,
+        Err(e) => {
+            return throw_coded(
+                &mut cx,
+                ErrorCode::InvalidArgument,
+                format!("invalid DKIM record: {}", e),
+            )
+        }
     };
-    signature.reverse();
-    let nullifier = match email_nullifier(&signature) {
+    let hash_field = match public_key_hash_from_modulus(&public_key.n().to_bytes_be()) {
+        Ok(hash_field) => hash_field,
+        Err(e) => {
+            return throw_coded(
+                &mut cx,
+                ErrorCode::CryptoFailed,
+                format!("public_key_hash failed: {}", e),
+            )
+        }
+    };
+    let obj = cx.empty_object();
+    let hash_str = cx.string(field2hex(&hash_field));
+    obj.set(&mut cx, "publicKeyHash", hash_str)?;
+    let key_bits = cx.number(public_key.n().bits() as f64);
+    obj.set(&mut cx, "keyBits", key_bits)?;
+    let public_exponent = cx.number(rsa_public_exponent(&public_key) as f64);
+    obj.set(&mut cx, "publicExponent", public_exponent)?;
+    Ok(obj)
+}
+
+#[cfg(feature = "node")]
+pub fn email_nullifier_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let signature = trimmed_argument(&mut cx, 0, "signature")?;
+    match email_nullifier_from_hex(&signature) {
+        Ok(nullifier) => Ok(cx.string(field2hex(&nullifier))),
+        Err(e) => throw_coded(
+            &mut cx,
+            error_code(&e, ErrorCode::InvalidArgument),
+            e.to_string(),
+        ),
+    }
+}
+
+#[cfg(feature = "node")]
+pub fn email_nullifier_bytes_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let signature = cx.argument::<JsBuffer>(0)?;
+    let signature = signature.as_slice(&cx).to_vec();
+    email_nullifier_js(&mut cx, signature)
+}
+
+/// `signature` is big endian.
+#[cfg(feature = "node")]
+fn email_nullifier_js<'a>(
+    cx: &mut FunctionContext<'a>,
+    signature: Vec<u8>,
+) -> JsResult<'a, JsString> {
+    let nullifier = match email_nullifier_from_signature(&signature) {
         Ok(nullifier) => nullifier,
-        Err(e) => return cx.throw_error(&format!("email_nullifier failed: {}", e)),
+        Err(e) => {
+            return throw_coded(
+                cx,
+                ErrorCode::CryptoFailed,
+                format!("email_nullifier failed: {}", e),
+            )
+        }
     };
     let nullifier_str = field2hex(&nullifier);
     Ok(cx.string(nullifier_str))
@@ -312,6 +724,11 @@ pub fn sha256_pad(mut data: Vec<u8>, max_sha_bytes: usize) -> (Vec<u8>, usize) {
     (data, message_len)
 }
 
+/// Length of `data_len` bytes after SHA-256 padding, i.e. the `usize` returned by `sha256_pad`.
+pub fn sha256_padded_len(data_len: usize) -> usize {
+    ((data_len + 9 + 63) / 64) * 64
+}
+
 pub fn partial_sha(msg: &[u8], msg_len: usize) -> Vec<u8> {
     let mut hasher = Sha256::new();
     // Assuming msg_len is used to specify how much of msg to hash.
@@ -321,24 +738,34 @@ pub fn partial_sha(msg: &[u8], msg_len: usize) -> Vec<u8> {
     result.to_vec()
 }
 
+/// Splits the padded body at the 64-byte block holding `selector_string`, or at the start when
+/// there is no selector. Returns the SHA-256 state after the blocks before the split, as the
+/// big-endian bytes of its words, then the rest of the body zero-padded to
+/// `max_remaining_body_length`, and the length of the rest.
 pub fn generate_partial_sha(
     body: Vec<u8>,
     body_length: usize,
     selector_string: Option<String>,
     max_remaining_body_length: usize,
 ) -> Result<(Vec<u8>, Vec<u8>, usize), Box<dyn Error>> {
-    let selector_index = 0;
+    let mut selector_index = 0;
 
     if let Some(selector_str) = selector_string {
         let selector = selector_str.as_bytes();
         // Find selector in body and return the starting index
         let body_slice = &body[..body_length];
-        let _selector_index = match body_slice
+        selector_index = match body_slice
             .windows(selector.len())
             .position(|window| window == selector)
         {
             Some(index) => index,
-            None => return Err("Selector not found in body".into()),
+            None => {
+                return Err(format!(
+                    "sha_precompute_selector `{}` is not found in the body",
+                    selector_str
+                )
+                .into())
+            }
         };
     }
 
@@ -352,7 +779,7 @@ pub fn generate_partial_sha(
         return Err(Box::new(std::io::Error::new(
             std::io::ErrorKind::Other,
             format!(
-                "Remaining body {} after the selector is longer than max ({})",
+                "the body after the selector is {} bytes, padded, but max_body_length is {}",
                 body_remaining_length, max_remaining_body_length
             ),
         )));
@@ -369,17 +796,52 @@ pub fn generate_partial_sha(
         body_remaining.push(0);
     }
 
-    let precomputed_sha = partial_sha(precompute_text, sha_cutoff_index);
+    let blocks = precompute_text
+        .chunks(64)
+        .map(sha2::digest::generic_array::GenericArray::clone_from_slice)
+        .collect::<Vec<_>>();
+    let mut state = SHA256_INITIAL_STATE;
+    sha2::compress256(&mut state, &blocks);
+    let precomputed_sha = state.iter().flat_map(|word| word.to_be_bytes()).collect();
     Ok((precomputed_sha, body_remaining, body_remaining_length))
 }
 
+/// Verifies an RSASSA-PKCS1-v1_5 SHA-256 signature over `message`.
+/// `signature` and `public_key_n` are big endian; the public exponent is 65537.
+pub fn verify_rsa_sha256(
+    message: &[u8],
+    signature: &[u8],
+    public_key_n: &[u8],
+) -> anyhow::Result<()> {
+    verify_rsa_sha256_with_exponent(message, signature, public_key_n, DEFAULT_PUBLIC_EXPONENT)
+}
+
+/// `verify_rsa_sha256` with the public exponent of the key.
+pub fn verify_rsa_sha256_with_exponent(
+    message: &[u8],
+    signature: &[u8],
+    public_key_n: &[u8],
+    public_exponent: u64,
+) -> anyhow::Result<()> {
+    let public_key = RsaPublicKey::new(
+        BigUint::from_bytes_be(public_key_n),
+        BigUint::from(public_exponent),
+    )
+    .map_err(|e| anyhow::anyhow!("invalid RSA public key: {}", e))?;
+    let digest = Sha256::digest(message);
+    public_key
+        .verify(Pkcs1v15Sign::new::<Sha256>(), &digest, signature)
+        .map_err(|e| anyhow::anyhow!("RSA signature verification failed: {}", e))
+}
+
 pub fn keccak256(data: &[u8]) -> Bytes {
     Bytes::from(ethers::utils::keccak256(data))
 }
 
+#[cfg(feature = "node")]
 pub fn account_code_commit_node(mut cx: FunctionContext) -> JsResult<JsString> {
     let account_code = cx.argument::<JsString>(0)?.value(&mut cx);
-    let email_addr = cx.argument::<JsString>(1)?.value(&mut cx);
+    let email_addr = email_addr_argument(&mut cx, 1)?;
     let relayer_rand_hash = cx.argument::<JsString>(2)?.value(&mut cx);
     let account_code = hex2field_node(&mut cx, &account_code)?;
     let padded_email_addr = PaddedEmailAddr::from_email_addr(&email_addr);
@@ -393,20 +855,58 @@ pub fn account_code_commit_node(mut cx: FunctionContext) -> JsResult<JsString> {
     Ok(cx.string(account_code_commit_str))
 }
 
+#[cfg(feature = "node")]
 pub fn relayer_rand_hash_node(mut cx: FunctionContext) -> JsResult<JsString> {
     let relayer_rand = cx.argument::<JsString>(0)?.value(&mut cx);
     let relayer_rand = hex2field_node(&mut cx, &relayer_rand)?;
     let relayer_rand_hash = match RelayerRand(relayer_rand).hash() {
         Ok(fr) => fr,
-        Err(e) => return cx.throw_error(&format!("RelayerRand hash failed: {}", e)),
+        Err(e) => {
+            return throw_coded(
+                &mut cx,
+                ErrorCode::CryptoFailed,
+                format!("RelayerRand hash failed: {}", e),
+            )
+        }
     };
     let relayer_rand_hash_str = field2hex(&relayer_rand_hash);
     Ok(cx.string(relayer_rand_hash_str))
 }
 
+/// `findMatchingAccountCode(emailAddr, expectedSalt, codesJson)` returns the index of the matching
+/// code in the JSON array of hex account codes, or -1.
+#[cfg(feature = "node")]
+pub fn find_matching_account_code_node(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let email_addr = email_addr_argument(&mut cx, 0)?;
+    let expected_salt = cx.argument::<JsString>(1)?.value(&mut cx);
+    let codes_json = cx.argument::<JsString>(2)?.value(&mut cx);
+    let candidate_codes = match serde_json::from_str::<Vec<String>>(&codes_json) {
+        Ok(candidate_codes) => candidate_codes,
+        Err(e) => {
+            return throw_coded(
+                &mut cx,
+                ErrorCode::InvalidArgument,
+                format!("the account codes must be a JSON array of strings: {}", e),
+            )
+        }
+    };
+    match find_matching_account_code(&email_addr, &expected_salt, &candidate_codes) {
+        Ok(index) => Ok(cx.number(index.map_or(-1.0, |index| index as f64))),
+        Err(e) => throw_coded(
+            &mut cx,
+            error_code(&e, ErrorCode::InvalidArgument),
+            format!("find_matching_account_code failed: {}", e),
+        ),
+    }
+}
+
+#[cfg(feature = "node")]
 pub fn account_salt_node(mut cx: FunctionContext) -> JsResult<JsString> {
-    let email_addr = cx.argument::<JsString>(0)?.value(&mut cx);
-    let padded_email_addr = PaddedEmailAddr::from_email_addr(&email_addr);
+    let email_addr = email_addr_argument(&mut cx, 0)?;
+    let padded_email_addr = match PaddedEmailAddr::try_from_email_addr(&email_addr) {
+        Ok(padded_email_addr) => padded_email_addr,
+        Err(e) => return throw_coded(&mut cx, ErrorCode::InvalidArgument, e.to_string()),
+    };
     let account_code_str = cx.argument::<JsString>(1)?.value(&mut cx);
     let account_code = hex2field_node(&mut cx, &account_code_str)?;
     let account_salt = match AccountSalt::new(&padded_email_addr, AccountCode(account_code)) {
@@ -420,6 +920,8 @@ pub fn account_salt_node(mut cx: FunctionContext) -> JsResult<JsString> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error_code::error_code;
+    use crate::test_fixtures::{fixture_signed_email, FIXTURE_ACCOUNT_CODE};
 
     #[test]
     fn test_public_key_hash() {
@@ -435,4 +937,302 @@ mod tests {
         );
         assert_eq!(field2hex(&hash_field), expected_hash);
     }
+
+    #[test]
+    fn test_sha256_padded_len() {
+        for len in [0, 1, 54, 55, 56, 63, 64, 119, 120, 500] {
+            let (_, padded_len) = sha256_pad(vec![0x61; len], 1024);
+            assert_eq!(sha256_padded_len(len), padded_len, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_rsa_public_key_from_dkim_txt() {
+        use rsa::pkcs8::EncodePublicKey;
+
+        let public_key_n = hex::decode("cfb0520e4ad78c4adb0deb5e605162b6469349fc1fde9269b88d596ed9f3735c00c592317c982320874b987bcc38e8556ac544bdee169b66ae8fe639828ff5afb4f199017e3d8e675a077f21cd9e5c526c1866476e7ba74cd7bb16a1c3d93bc7bb1d576aedb4307c6b948d5b8c29f79307788d7a8ebf84585bf53994827c23a5").unwrap();
+        let public_key = RsaPublicKey::new(
+            BigUint::from_bytes_be(&public_key_n),
+            BigUint::from(65537u32),
+        )
+        .unwrap();
+        let der = public_key.to_public_key_der().unwrap();
+        let encoded = general_purpose::STANDARD.encode(der.as_bytes());
+        let (head, tail) = encoded.split_at(100);
+        let record = format!("\"v=DKIM1; k=rsa; \" \"p={}\" \" {}\"", head, tail);
+
+        let parsed = rsa_public_key_from_dkim_txt(&record).unwrap();
+        assert_eq!(parsed.n().to_bytes_be(), public_key_n);
+        assert_eq!(parsed.n().bits(), 1024);
+
+        let err = rsa_public_key_from_dkim_txt("v=DKIM1; k=rsa; t=y").unwrap_err();
+        assert!(err.to_string().contains("p="));
+    }
+
+    #[test]
+    fn test_verify_with_public_exponent() {
+        let fixture = crate::test_fixture("send_eth_e3").unwrap();
+        let public_key = rsa_public_key_from_dkim_txt(&fixture.dkim_txt_record).unwrap();
+        assert_eq!(rsa_public_exponent(&public_key), 3);
+        let signature = crate::nullifier_fast::dkim_signature_bytes(&fixture.raw_email).unwrap();
+        let message = fixture.canonicalized_header.as_bytes();
+        let public_key_n = public_key.n().to_bytes_be();
+        verify_rsa_sha256_with_exponent(message, &signature, &public_key_n, 3).unwrap();
+        assert!(verify_rsa_sha256(message, &signature, &public_key_n).is_err());
+
+        check_provable_exponent(DEFAULT_PUBLIC_EXPONENT).unwrap();
+        let err = check_provable_exponent(3).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<UnsupportedPublicExponent>(),
+            Some(&UnsupportedPublicExponent { public_exponent: 3 })
+        );
+    }
+
+    #[test]
+    fn test_public_key_hash_from_der() {
+        use crate::error_code::error_code;
+
+        let fixture = crate::test_fixtures::test_fixture("send_eth").unwrap();
+        let base64_der = fixture.dkim_txt_record.split("p=").nth(1).unwrap();
+        let hash = public_key_hash_from_der(base64_der).unwrap();
+        assert_eq!(field2hex(&hash), fixture.expected.public_key_hash);
+        // The same hash as from the big-endian hex modulus, as `publicKeyHash` computes it.
+        let public_key_n = decode_hex(&fixture.public_key).unwrap();
+        assert_eq!(hash, public_key_hash_from_modulus(&public_key_n).unwrap());
+
+        let (head, tail) = base64_der.split_at(40);
+        let wrapped = format!("{}\n  {}", head, tail);
+        assert_eq!(public_key_hash_from_der(&wrapped).unwrap(), hash);
+        for invalid in ["not base64!", "AAAA"] {
+            let err = public_key_hash_from_der(invalid).unwrap_err();
+            assert_eq!(
+                error_code(&err, ErrorCode::CryptoFailed),
+                ErrorCode::InvalidArgument
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_matching_account_code() {
+        let email_addr = "alice@gmail.com";
+        let codes = (1..=5u64)
+            .map(|i| field2hex(&Fr::from(i)))
+            .collect::<Vec<_>>();
+        let salt_of = |code: &str| {
+            let account_code = AccountCode(hex2field(code).unwrap());
+            let padded_email_addr = PaddedEmailAddr::from_email_addr(email_addr);
+            field2hex(&AccountSalt::new(&padded_email_addr, account_code).unwrap().0)
+        };
+        for index in [0, 2, 4] {
+            assert_eq!(
+                find_matching_account_code(email_addr, &salt_of(&codes[index]), &codes).unwrap(),
+                Some(index)
+            );
+        }
+        let absent = salt_of(&field2hex(&Fr::from(6)));
+        assert_eq!(
+            find_matching_account_code(email_addr, &absent, &codes).unwrap(),
+            None
+        );
+
+        // A malformed candidate is skipped, and the indexes still refer to the given list.
+        let mut with_malformed = codes.clone();
+        with_malformed.insert(1, "0xnot-hex".to_string());
+        assert_eq!(
+            find_matching_account_code(email_addr, &salt_of(&codes[1]), &with_malformed).unwrap(),
+            Some(2)
+        );
+        assert!(find_matching_account_code(email_addr, "0x1234", &codes).is_err());
+    }
+
+    #[test]
+    fn test_email_nullifier_from_hex() {
+        // A leading zero byte must survive the decoding for the nullifier to match.
+        let signature = (0..256u32).map(|i| (i * 13) as u8).collect::<Vec<_>>();
+        assert_eq!(signature[0], 0);
+        let mut signature_le = signature.clone();
+        signature_le.reverse();
+        let expected = email_nullifier(&signature_le).unwrap();
+        assert_eq!(email_nullifier_from_signature(&signature).unwrap(), expected);
+
+        let signature_hex = hex::encode(&signature);
+        for input in [
+            signature_hex.clone(),
+            format!("0x{}", signature_hex),
+            format!(" 0X{} ", signature_hex.to_uppercase()),
+        ] {
+            assert_eq!(email_nullifier_from_hex(&input).unwrap(), expected);
+        }
+
+        for malformed in ["0x123", "0x12zz", "signature"] {
+            let err = email_nullifier_from_hex(malformed).unwrap_err();
+            assert_eq!(
+                error_code(&err, ErrorCode::CryptoFailed),
+                ErrorCode::InvalidArgument
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_hex() {
+        let signature = (0..128u32).map(|i| (i * 7 + 3) as u8).collect::<Vec<_>>();
+        let signature_hex = hex::encode(&signature);
+        assert_eq!(decode_hex(&signature_hex).unwrap(), signature);
+        assert_eq!(
+            decode_hex(&format!("0x{}", signature_hex)).unwrap(),
+            signature
+        );
+
+        let err = decode_hex("0x123").unwrap_err().to_string();
+        assert!(err.contains("even number of hex digits"), "{}", err);
+        let err = decode_hex("0x12zz").unwrap_err().to_string();
+        assert!(err.contains("invalid hex"), "{}", err);
+    }
+
+    #[test]
+    fn test_hex_case_insensitivity() {
+        let email_addr = PaddedEmailAddr::from_email_addr("alice@gmail.com");
+        let lower = FIXTURE_ACCOUNT_CODE;
+        let upper = lower.to_uppercase();
+        let mixed = lower
+            .char_indices()
+            .map(|(i, c)| if i % 3 == 0 { c.to_ascii_uppercase() } else { c })
+            .collect::<String>();
+
+        let salts = [lower, upper.as_str(), mixed.as_str()]
+            .iter()
+            .map(|account_code| {
+                let account_code = AccountCode(hex2field(account_code).unwrap());
+                assert_eq!(field2hex(&account_code.0), lower);
+                field2hex(&AccountSalt::new(&email_addr, account_code).unwrap().0)
+            })
+            .collect::<Vec<_>>();
+        assert!(salts.iter().all(|salt| salt == &salts[0]));
+        assert_eq!(salts[0], salts[0].to_lowercase());
+
+        let signature = vec![0xab; 128];
+        for prefix in ["0x", "0X", ""] {
+            let signature_hex = prefix.to_string() + &"aB".repeat(128);
+            assert_eq!(decode_hex(&signature_hex).unwrap(), signature);
+        }
+    }
+
+    #[test]
+    fn test_email_addr_too_long() {
+        let limit = active_circuit_profile().max_email_addr_bytes;
+        let domain = "@example.com";
+        let at_limit = "a".repeat(limit - domain.len()) + domain;
+        let padded = PaddedEmailAddr::try_from_email_addr(&at_limit).unwrap();
+        assert_eq!(padded.email_addr_len, limit);
+        assert_eq!(padded.padded_bytes.len(), limit);
+
+        let over = "a".repeat(limit + 1 - domain.len()) + domain;
+        let err = PaddedEmailAddr::try_from_email_addr(&over).unwrap_err();
+        assert_eq!(
+            err,
+            EmailAddrTooLong {
+                limit,
+                actual: limit + 1,
+                after_normalization: None,
+            }
+        );
+        let json: serde_json::Value = serde_json::from_str(&err.to_string()).unwrap();
+        assert_eq!(json["code"], EmailAddrTooLong::CODE);
+        assert_eq!(json["actual"], limit + 1);
+        assert!(json["after_normalization"].is_null());
+
+        // Only fits once the `+` tag is dropped.
+        let tagged = format!("alice+{}@gmail.com", "t".repeat(limit));
+        let err = PaddedEmailAddr::try_from_email_addr(&tagged).unwrap_err();
+        assert_eq!(err.actual, tagged.len());
+        assert_eq!(err.after_normalization, Some("alice@gmail.com".len()));
+        assert_eq!(
+            normalize_email_addr("A.Lice+x@GMail.com"),
+            "alice@gmail.com"
+        );
+        assert_eq!(
+            normalize_email_addr("A.Lice+x@Example.com"),
+            "A.Lice+x@example.com"
+        );
+    }
+
+    #[test]
+    fn test_generated_account_codes_round_trip() {
+        let seed = hex::decode("00112233445566778899aabbccddeeff").unwrap();
+        let seeded = AccountCode::new_from_seed(&seed).unwrap();
+        assert_eq!(seeded.0, AccountCode::new_from_seed(&seed).unwrap().0);
+        assert_ne!(seeded.0, AccountCode::new_from_seed(&seed[1..]).unwrap().0);
+
+        let email_addr = PaddedEmailAddr::from_email_addr("alice@gmail.com");
+        for account_code in [seeded, AccountCode::new(OsRng), AccountCode::new(OsRng)] {
+            let account_code_hex = field2hex(&account_code.0);
+            let parsed = hex2field(&account_code_hex).unwrap();
+            assert_eq!(parsed, account_code.0);
+            let salt = AccountSalt::new(&email_addr, AccountCode(parsed)).unwrap();
+            assert_eq!(
+                find_matching_account_code(
+                    "alice@gmail.com",
+                    &field2hex(&salt.0),
+                    &[account_code_hex]
+                )
+                .unwrap(),
+                Some(0)
+            );
+        }
+
+        let parsed_email =
+            fixture_signed_email("from:alice@gmail.com\r\nsubject:Send 1 ETH\r\n", "");
+        let account_code = AccountCode(hex2field(&field2hex(&seeded.0)).unwrap());
+        let input = generate_email_auth_input_from_parsed_email(
+            &parsed_email,
+            &account_code,
+            &InputGenerationOptions::default(),
+        )
+        .unwrap();
+        let input: serde_json::Value = serde_json::from_str(&input).unwrap();
+        assert_eq!(input["account_code"], field2hex(&seeded.0));
+    }
+
+    #[test]
+    fn test_email_addr_commit() {
+        let email_addr = PaddedEmailAddr::from_email_addr("alice@gmail.com");
+        let signature = vec![0x5a; 256];
+        let rand = extract_rand_from_signature(&signature).unwrap();
+        let rand = hex2field(&field2hex(&rand)).unwrap();
+        assert_eq!(
+            email_addr.to_commitment(&rand).unwrap(),
+            email_addr.to_commitment_with_signature(&signature).unwrap()
+        );
+        let other = PaddedEmailAddr::from_email_addr("bob@gmail.com");
+        assert_ne!(
+            email_addr.to_commitment(&rand).unwrap(),
+            other.to_commitment(&rand).unwrap()
+        );
+
+        // The BN254 scalar field modulus itself, and the largest 32-byte value.
+        let modulus = "0x30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000001";
+        for rand_hex in [modulus, &format!("0x{}", "ff".repeat(32))] {
+            let err = hex2field(rand_hex).unwrap_err().to_string();
+            assert!(err.contains("not a field element"), "{}", err);
+        }
+        let below_modulus = "0x30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000000";
+        assert_eq!(field2hex(&hex2field(below_modulus).unwrap()), below_modulus);
+    }
+
+    #[test]
+    fn test_relayer_rand_hash_and_pointer() {
+        let relayer_rand = RelayerRand::new_from_seed(b"relayer-utils test relayer").unwrap();
+        let relayer_rand_hex = field2hex(&relayer_rand.0);
+        let parsed = RelayerRand(hex2field(&relayer_rand_hex).unwrap());
+        assert_eq!(
+            parsed.hash().unwrap(),
+            poseidon_fields(&[relayer_rand.0]).unwrap()
+        );
+
+        let email_addr = PaddedEmailAddr::from_email_addr("alice@gmail.com");
+        let pointer = email_addr.to_pointer(&parsed).unwrap();
+        assert_eq!(pointer, email_addr.to_commitment(&relayer_rand.0).unwrap());
+        let other_relayer = RelayerRand::new_from_seed(b"another relayer").unwrap();
+        assert_ne!(pointer, email_addr.to_pointer(&other_relayer).unwrap());
+    }
 }
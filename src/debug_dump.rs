@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::Path;
+
+use crate::*;
+use anyhow::Result;
+use rand_core::{OsRng, RngCore};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+/// Number of canonicalized body bytes kept in a debug dump.
+pub const DEBUG_DUMP_BODY_BYTES: usize = 1024;
+
+/// Writes the artifacts of one input generation to `dir/<correlation id>/`, one file per stage,
+/// and returns the correlation id.
+///
+/// The from address is replaced by its SHA-256 hash in the header and body dumps, and the body
+/// is truncated to `DEBUG_DUMP_BODY_BYTES`. `input.json` is written as generated.
+pub fn dump_debug_artifacts(
+    dir: &Path,
+    parsed_email: Option<&ParsedEmail>,
+    result: &Result<String>,
+) -> Result<String> {
+    let correlation_id = format!("{:016x}{:016x}", OsRng.next_u64(), OsRng.next_u64());
+    let dump_dir = dir.join(&correlation_id);
+    fs::create_dir_all(&dump_dir)?;
+
+    if let Some(parsed_email) = parsed_email {
+        let from_addr = parsed_email.get_from_addr().unwrap_or_default();
+        let redacted = format!(
+            "<redacted sha256:{}>",
+            hex::encode(Sha256::digest(from_addr.as_bytes()))
+        );
+        let redact = |text: &str| {
+            if from_addr.is_empty() {
+                text.to_string()
+            } else {
+                text.replace(&from_addr, &redacted)
+            }
+        };
+        fs::write(
+            dump_dir.join("canonicalized_header.txt"),
+            redact(&parsed_email.canonicalized_header),
+        )?;
+        let mut body_len = parsed_email
+            .canonicalized_body
+            .len()
+            .min(DEBUG_DUMP_BODY_BYTES);
+        while !parsed_email.canonicalized_body.is_char_boundary(body_len) {
+            body_len -= 1;
+        }
+        fs::write(
+            dump_dir.join("canonicalized_body.txt"),
+            redact(&parsed_email.canonicalized_body[..body_len]),
+        )?;
+        fs::write(
+            dump_dir.join("dkim.json"),
+            json!({
+                "signature": parsed_email.signature_string(),
+                "public_key": parsed_email.public_key_string(),
+                "public_exponent": parsed_email.public_exponent,
+                "dnssec_validated": parsed_email.dnssec_validated,
+            })
+            .to_string(),
+        )?;
+        fs::write(
+            dump_dir.join("indexes.json"),
+            serde_json::to_string_pretty(&parsed_email.get_header_indexes()?)?,
+        )?;
+    }
+
+    match result {
+        Ok(input) => fs::write(dump_dir.join("input.json"), input)?,
+        Err(e) => fs::write(dump_dir.join("error.txt"), format!("{:?}", e))?,
+    }
+    Ok(correlation_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_debug_artifacts() {
+        let dir = std::env::temp_dir().join(format!("relayer-utils-dump-{}", OsRng.next_u64()));
+        let parsed_email = ParsedEmail {
+            canonicalized_header: "from:alice@gmail.com\r\nsubject:Send 1 ETH\r\n".to_string(),
+            canonicalized_body: "x".repeat(DEBUG_DUMP_BODY_BYTES * 2),
+            signature: vec![1; 128],
+            public_key: vec![2; 128],
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
+        };
+        let result = Err(anyhow::anyhow!("something failed"));
+        let correlation_id = dump_debug_artifacts(&dir, Some(&parsed_email), &result).unwrap();
+
+        let dump_dir = dir.join(&correlation_id);
+        let header = fs::read_to_string(dump_dir.join("canonicalized_header.txt")).unwrap();
+        assert!(!header.contains("alice@gmail.com"));
+        assert!(header.contains("<redacted sha256:"));
+        let body = fs::read_to_string(dump_dir.join("canonicalized_body.txt")).unwrap();
+        assert_eq!(body.len(), DEBUG_DUMP_BODY_BYTES);
+        assert!(dump_dir.join("dkim.json").exists());
+        assert!(dump_dir.join("indexes.json").exists());
+        let error = fs::read_to_string(dump_dir.join("error.txt")).unwrap();
+        assert!(error.contains("something failed"));
+        assert!(!dump_dir.join("input.json").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+use crate::*;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use rsa::BigUint;
+use sha2::digest::generic_array::GenericArray;
+use std::convert::TryInto;
+
+/// SHA-256 initial hash value (FIPS 180-4 §5.3.3), the state before any block.
+pub const SHA256_INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// DER prefix of the PKCS#1 v1.5 DigestInfo of a SHA-256 digest (RFC 8017 §9.2).
+const SHA256_DIGEST_INFO_PREFIX: [u8; 19] = [
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+
+/// Continues SHA-256 from `state` over `padded`, which is whole 64-byte blocks already carrying
+/// the SHA padding, as the circuit does. Returns the digest.
+pub fn sha256_continue(mut state: [u32; 8], padded: &[u8]) -> Result<[u8; 32]> {
+    if padded.len() % 64 != 0 {
+        return Err(anyhow!(
+            "{} bytes are not a whole number of 64-byte SHA-256 blocks",
+            padded.len()
+        ));
+    }
+    let blocks = padded
+        .chunks(64)
+        .map(GenericArray::clone_from_slice)
+        .collect::<Vec<_>>();
+    sha2::compress256(&mut state, &blocks);
+    let mut digest = [0u8; 32];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    Ok(digest)
+}
+
+/// Recovers the SHA-256 digest an RSASSA-PKCS1-v1_5 signature commits to (RFC 8017 §8.2.2).
+/// `signature` and `public_key_n` are big endian; the public exponent is 65537.
+pub fn recover_signed_digest(signature: &[u8], public_key_n: &[u8]) -> Result<[u8; 32]> {
+    let n = BigUint::from_bytes_be(public_key_n);
+    let s = BigUint::from_bytes_be(signature);
+    if s >= n {
+        return Err(anyhow!("the signature is not smaller than the public key modulus"));
+    }
+    let k = (n.bits() + 7) / 8;
+    // EM = 0x00 || 0x01 || PS || 0x00 || DigestInfo, with at least 8 bytes of PS.
+    let digest_info_len = SHA256_DIGEST_INFO_PREFIX.len() + 32;
+    if k < 2 + 8 + 1 + digest_info_len {
+        return Err(anyhow!("the {}-bit public key is too small for SHA-256", n.bits()));
+    }
+    // The leading zero of EM is dropped from `m`.
+    let m = s.modpow(&BigUint::from(65537u32), &n).to_bytes_be();
+    let prefix_len = k - 1 - digest_info_len;
+    if m.len() != k - 1 {
+        return Err(anyhow!("the signature does not decode to a PKCS#1 v1.5 block"));
+    }
+    let (padding, digest_info) = m.split_at(prefix_len);
+    if padding[0] != 0x01
+        || padding[1..prefix_len - 1].iter().any(|&byte| byte != 0xff)
+        || padding[prefix_len - 1] != 0x00
+    {
+        return Err(anyhow!("the signature does not decode to a PKCS#1 v1.5 block"));
+    }
+    let digest = digest_info
+        .strip_prefix(&SHA256_DIGEST_INFO_PREFIX[..])
+        .ok_or_else(|| anyhow!("the signed DigestInfo is not for SHA-256"))?;
+    Ok(digest.try_into()?)
+}
+
+/// Checks that the SHA-256 padding of `padded_header[..padded_header_len]` covers exactly the
+/// header before it.
+fn check_sha_padding(padded_header: &[u8]) -> Result<()> {
+    let (rest, length) = padded_header.split_at(padded_header.len().saturating_sub(8));
+    let length_bits = u64::from_be_bytes(length.try_into()?);
+    let message_len = (length_bits / 8) as usize;
+    if length_bits % 8 != 0 || message_len >= rest.len() {
+        return Err(anyhow!(
+            "the SHA-256 length field says {} bits, which does not fit in padded_header_len {}",
+            length_bits,
+            padded_header.len()
+        ));
+    }
+    if rest[message_len] != 0x80 || rest[message_len + 1..].iter().any(|&byte| byte != 0) {
+        return Err(anyhow!(
+            "the SHA-256 padding of padded_header does not start right after the {}-byte header",
+            message_len
+        ));
+    }
+    if rest.len() - message_len > 64 {
+        return Err(anyhow!(
+            "padded_header_len {} leaves more than a block of padding after the {}-byte header",
+            padded_header.len(),
+            message_len
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that hashing `padded_header[..padded_header_len]` as the circuit does gives the digest
+/// the RSA signature commits to, which catches canonicalization and padding bugs before proving.
+///
+/// `precomputed_state` is the SHA-256 state after a precomputed prefix, for inputs whose
+/// `padded_header` holds only the rest; the padding then also counts the prefix, so only the
+/// digest is checked.
+pub fn check_header_digest_invariant(
+    padded_header: &[u8],
+    padded_header_len: usize,
+    precomputed_state: Option<[u32; 8]>,
+    signature: &[u8],
+    public_key_n: &[u8],
+) -> Result<()> {
+    if padded_header_len == 0 || padded_header_len > padded_header.len() {
+        return Err(anyhow!(
+            "padded_header_len {} is outside the {}-byte padded_header",
+            padded_header_len,
+            padded_header.len()
+        ));
+    }
+    if padded_header_len % 64 != 0 {
+        return Err(anyhow!(
+            "padded_header_len {} is not a multiple of 64",
+            padded_header_len
+        ));
+    }
+    let hashed = &padded_header[..padded_header_len];
+    if precomputed_state.is_none() {
+        check_sha_padding(hashed)?;
+    }
+    let digest = sha256_continue(precomputed_state.unwrap_or(SHA256_INITIAL_STATE), hashed)?;
+    let signed_digest = recover_signed_digest(signature, public_key_n)?;
+    if digest != signed_digest {
+        return Err(anyhow!(
+            "the SHA-256 of padded_header[..{}] is 0x{} but the signature commits to 0x{}",
+            padded_header_len,
+            hex::encode(digest),
+            hex::encode(signed_digest)
+        ));
+    }
+    Ok(())
+}
+
+/// Checks `check_header_digest_invariant` on an email auth input JSON as generated.
+pub fn verify_generated_input(input_json: &str) -> Result<()> {
+    let input: serde_json::Value = serde_json::from_str(input_json)
+        .map_err(|e| anyhow!("the input is not valid JSON: {}", e))?;
+    let strings = |field: &str| -> Result<Vec<String>> {
+        serde_json::from_value(input[field].clone())
+            .map_err(|e| anyhow!("{} must be an array of strings: {}", field, e))
+    };
+    let padded_header = strings("padded_header")?
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| {
+            byte.parse::<u8>()
+                .map_err(|e| anyhow!("padded_header[{}] `{}` is not a byte: {}", i, byte, e))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let padded_header_len = input["padded_header_len"]
+        .as_str()
+        .and_then(|len| len.parse::<usize>().ok())
+        .ok_or_else(|| anyhow!("padded_header_len must be a decimal string"))?;
+    let limbs_bytes = |field: &str| -> Result<Vec<u8>> {
+        Ok(limbs_to_bigint(&strings(field)?, CIRCOM_BIGINT_N)?
+            .to_bytes_be()
+            .1)
+    };
+    check_header_digest_invariant(
+        &padded_header,
+        padded_header_len,
+        None,
+        &limbs_bytes("signature")?,
+        &limbs_bytes("public_key")?,
+    )
+}
+
+/// `verifyGeneratedInput(inputJson)` returns true, or throws with the broken invariant.
+#[cfg(feature = "node")]
+pub fn verify_generated_input_node(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let input_json = cx.argument::<JsString>(0)?.value(&mut cx);
+    match verify_generated_input(&input_json) {
+        Ok(()) => Ok(cx.boolean(true)),
+        Err(e) => throw_coded(&mut cx, ErrorCode::CircuitInputFailed, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs1v15::SigningKey;
+    use rsa::signature::{SignatureEncoding, Signer};
+    use rsa::traits::PublicKeyParts;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_header_digest_invariant() {
+        let private_key = rsa::RsaPrivateKey::new(&mut rand_core::OsRng, 1024).unwrap();
+        let header = "from:alice@gmail.com\r\nsubject:Send 1 ETH to bob@example.com\r\n";
+        let parsed_email = ParsedEmail {
+            canonicalized_header: header.to_string(),
+            canonicalized_body: String::new(),
+            signature: SigningKey::<Sha256>::new(private_key.clone())
+                .sign(header.as_bytes())
+                .to_vec(),
+            public_key: private_key.n().to_bytes_be(),
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
+        };
+        let account_code = fixture_account_code();
+        let options = InputGenerationOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let input =
+            generate_email_auth_input_from_parsed_email(&parsed_email, &account_code, &options)
+                .unwrap();
+        verify_generated_input(&input).unwrap();
+
+        let mut input: serde_json::Value = serde_json::from_str(&input).unwrap();
+        let padded_header_len = input["padded_header_len"].as_str().unwrap().to_string();
+        // One block too many: the hashed bytes no longer end with the padding.
+        input["padded_header_len"] = (padded_header_len.parse::<usize>().unwrap() + 64)
+            .to_string()
+            .into();
+        let err = verify_generated_input(&input.to_string()).unwrap_err().to_string();
+        assert!(err.contains("SHA-256 padding"), "{}", err);
+
+        // A header shifted by one byte hashes to a different digest.
+        input["padded_header_len"] = padded_header_len.clone().into();
+        let shifted = format!(" {}", &header[..header.len() - 1]);
+        for (i, byte) in shifted.bytes().enumerate() {
+            input["padded_header"][i] = byte.to_string().into();
+        }
+        let err = verify_generated_input(&input.to_string()).unwrap_err().to_string();
+        assert!(
+            err.contains(&format!("the SHA-256 of padded_header[..{}]", padded_header_len)),
+            "{}",
+            err
+        );
+
+        // Hashing a precomputed first block and continuing gives the same digest.
+        let (padded, len) = sha256_pad(header.as_bytes().to_vec(), 1024);
+        let mut state = SHA256_INITIAL_STATE;
+        sha2::compress256(&mut state, &[GenericArray::clone_from_slice(&padded[..64])]);
+        check_header_digest_invariant(
+            &padded[64..],
+            len - 64,
+            Some(state),
+            &parsed_email.signature,
+            &parsed_email.public_key,
+        )
+        .unwrap();
+        assert_eq!(
+            sha256_continue(SHA256_INITIAL_STATE, &padded[..len]).unwrap()[..],
+            Sha256::digest(header.as_bytes())[..]
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use crate::*;
+use anyhow::Result;
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use once_cell::sync::Lazy;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPublicKey;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long a resolved DKIM key is reused before it is resolved again, so that rotated keys are
+/// picked up. This is the default of `HotConfig::dkim_key_cache_ttl_secs`.
+pub const DKIM_KEY_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Most keys the cache holds; inserting another one evicts the oldest.
+pub const DKIM_KEY_CACHE_CAPACITY: usize = 1024;
+
+/// A DKIM key record name: `<selector>._domainkey.<domain>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DkimKeyId {
+    pub domain: String,
+    pub selector: String,
+}
+
+impl DkimKeyId {
+    pub fn new(domain: &str, selector: &str) -> Self {
+        DkimKeyId {
+            domain: domain.trim_end_matches('.').to_ascii_lowercase(),
+            selector: selector.to_ascii_lowercase(),
+        }
+    }
+
+    pub fn record_name(&self) -> String {
+        format!("{}._domainkey.{}", self.selector, self.domain)
+    }
+
+    /// The `d=` and `s=` tags of the first DKIM-Signature of a raw email.
+    pub fn from_raw_email(raw_email: &str) -> Option<Self> {
+        let fields = raw_header_fields(raw_email);
+        let domain = dkim_signature_tag(&fields, "d")?;
+        let selector = dkim_signature_tag(&fields, "s")?;
+        Some(Self::new(&domain, &selector))
+    }
+}
+
+/// A resolved DKIM key: its big-endian modulus and public exponent, and whether its key record
+/// was DNSSEC-validated. `dnssec_validated` is `None` when no validation was attempted, and
+/// `Some(false)` when the zone is not signed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DkimKeyFetch {
+    pub public_key: Vec<u8>,
+    pub public_exponent: u64,
+    pub dnssec_validated: Option<bool>,
+}
+
+impl DkimKeyFetch {
+    /// The fetch of an RSA key that was not DNSSEC-validated.
+    pub fn from_rsa_public_key(public_key: &RsaPublicKey) -> Self {
+        DkimKeyFetch {
+            public_key: public_key.n().to_bytes_be(),
+            public_exponent: rsa_public_exponent(public_key),
+            dnssec_validated: None,
+        }
+    }
+}
+
+type DkimKeys = HashMap<DkimKeyId, (Instant, DkimKeyFetch)>;
+
+/// The resolved DKIM keys, with the time they were resolved.
+static DKIM_KEYS: Lazy<Mutex<DkimKeys>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The modulus of `key_id` when it was resolved less than the configured TTL ago.
+pub fn cached_dkim_key(key_id: &DkimKeyId) -> Option<Vec<u8>> {
+    cached_dkim_key_fetch(key_id).map(|fetch| fetch.public_key)
+}
+
+/// `cached_dkim_key` with the DNSSEC outcome of the fetch.
+pub fn cached_dkim_key_fetch(key_id: &DkimKeyId) -> Option<DkimKeyFetch> {
+    let ttl = hot_config().dkim_key_cache_ttl();
+    let keys = DKIM_KEYS.lock().unwrap_or_else(|e| e.into_inner());
+    keys.get(key_id)
+        .filter(|(resolved_at, _)| resolved_at.elapsed() < ttl)
+        .map(|(_, fetch)| fetch.clone())
+}
+
+/// Caches a key with the exponent `DEFAULT_PUBLIC_EXPONENT` that was not DNSSEC-validated.
+pub fn cache_dkim_key(key_id: DkimKeyId, public_key: Vec<u8>) {
+    cache_dkim_key_fetch(
+        key_id,
+        DkimKeyFetch {
+            public_key,
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
+        },
+    );
+}
+
+/// Caches a key, dropping the stale ones and, at `DKIM_KEY_CACHE_CAPACITY`, the oldest one, and
+/// records it as the last key seen for `key_id` (see `dkim_key_sighting`).
+pub fn cache_dkim_key_fetch(key_id: DkimKeyId, fetch: DkimKeyFetch) {
+    // Keys that cannot be hashed, such as moduli over 2057 bits, cannot be proven with either.
+    if let Ok(hash) = public_key_hash_from_modulus(&fetch.public_key) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        let mut sightings = KEY_SIGHTINGS.lock().unwrap_or_else(|e| e.into_inner());
+        let hash = field2hex(&hash);
+        let rotated_from = record_sighting(
+            &mut sightings,
+            key_id.clone(),
+            hash.clone(),
+            now,
+            DKIM_KEY_CACHE_CAPACITY,
+        );
+        if let Some(old) = rotated_from {
+            slog::warn!(LOG, "the DKIM key was rotated";
+                "record" => key_id.record_name(), "old" => old, "new" => hash);
+        }
+    }
+    let ttl = hot_config().dkim_key_cache_ttl();
+    let mut keys = DKIM_KEYS.lock().unwrap_or_else(|e| e.into_inner());
+    insert_key(&mut keys, key_id, fetch, ttl, DKIM_KEY_CACHE_CAPACITY);
+}
+
+/// Inserts a key resolved now into `keys`, after dropping the entries resolved `ttl` or more ago
+/// and, when `capacity` other keys are left, the one resolved first.
+fn insert_key(
+    keys: &mut DkimKeys,
+    key_id: DkimKeyId,
+    fetch: DkimKeyFetch,
+    ttl: Duration,
+    capacity: usize,
+) {
+    keys.retain(|_, (resolved_at, _)| resolved_at.elapsed() < ttl);
+    if !keys.contains_key(&key_id) && keys.len() >= capacity {
+        let oldest = keys
+            .iter()
+            .min_by_key(|(_, (resolved_at, _))| *resolved_at)
+            .map(|(oldest, _)| oldest.clone());
+        if let Some(oldest) = oldest {
+            keys.remove(&oldest);
+        }
+    }
+    keys.insert(key_id, (Instant::now(), fetch));
+}
+
+/// The last key resolved for a (domain, selector). Unlike the cached key, it is kept after the
+/// TTL, so that the fetch after a rotation is compared with the key it replaces.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeySighting {
+    pub key_id: DkimKeyId,
+    /// `public_key_hash` of the key, as a hex field element.
+    pub public_key_hash: String,
+    /// Unix time, in seconds, of the first fetch that returned the key.
+    pub first_seen: u64,
+    /// `public_key_hash` of the key seen before, when a fetch returned a different one.
+    pub rotated_from: Option<String>,
+}
+
+type KeySightings = HashMap<DkimKeyId, KeySighting>;
+
+static KEY_SIGHTINGS: Lazy<Mutex<KeySightings>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The last key seen for `key_id`, whether or not it is still cached.
+pub fn dkim_key_sighting(key_id: &DkimKeyId) -> Option<KeySighting> {
+    let sightings = KEY_SIGHTINGS.lock().unwrap_or_else(|e| e.into_inner());
+    sightings.get(key_id).cloned()
+}
+
+/// The last key seen for every (domain, selector), ordered by record name.
+pub fn dkim_key_sightings() -> Vec<KeySighting> {
+    let sightings = KEY_SIGHTINGS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut sightings: Vec<KeySighting> = sightings.values().cloned().collect();
+    sightings.sort_by_key(|sighting| sighting.key_id.record_name());
+    sightings
+}
+
+/// Records `public_key_hash` as seen for `key_id` at `now`. A hash that differs from the last one
+/// replaces it, keeping it as `rotated_from`, which is returned. When `capacity` other keys are
+/// recorded, the one first seen earliest is dropped.
+fn record_sighting(
+    sightings: &mut KeySightings,
+    key_id: DkimKeyId,
+    public_key_hash: String,
+    now: u64,
+    capacity: usize,
+) -> Option<String> {
+    let rotated_from = match sightings.get(&key_id) {
+        Some(last) if last.public_key_hash == public_key_hash => return None,
+        Some(last) => Some(last.public_key_hash.clone()),
+        None => {
+            if sightings.len() >= capacity {
+                let oldest = sightings
+                    .values()
+                    .min_by_key(|sighting| sighting.first_seen)
+                    .map(|oldest| oldest.key_id.clone());
+                if let Some(oldest) = oldest {
+                    sightings.remove(&oldest);
+                }
+            }
+            None
+        }
+    };
+    sightings.insert(
+        key_id.clone(),
+        KeySighting {
+            key_id,
+            public_key_hash,
+            first_seen: now,
+            rotated_from: rotated_from.clone(),
+        },
+    );
+    rotated_from
+}
+
+/// `dkimKeySightings()` returns the JSON array of `dkim_key_sightings`.
+#[cfg(feature = "node")]
+pub fn dkim_key_sightings_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let sightings = serde_json::to_string(&dkim_key_sightings()).unwrap();
+    Ok(cx.string(sightings))
+}
+
+/// Returns the modulus of `key_id` from the cache, or resolves it with `resolver` and caches it.
+pub fn resolve_dkim_key_cached(resolver: &dyn TxtResolver, key_id: &DkimKeyId) -> Result<Vec<u8>> {
+    if let Some(public_key) = cached_dkim_key(key_id) {
+        return Ok(public_key);
+    }
+    let records = resolver.resolve_txt(&key_id.record_name())?;
+    let record = records
+        .iter()
+        .find(|record| record.contains("p="))
+        .ok_or_else(|| anyhow::anyhow!("no DKIM key is published at {}", key_id.record_name()))?;
+    let fetch = DkimKeyFetch::from_rsa_public_key(&rsa_public_key_from_dkim_txt(record)?);
+    cache_dkim_key_fetch(key_id.clone(), fetch.clone());
+    Ok(fetch.public_key)
+}
+
+/// Like `resolve_dkim_key_cached`, resolving over DNS with the resolver `ParsedEmail` uses. The
+/// resolver reads the key location from a DKIM-Signature, so a minimal one is built.
+#[cfg(feature = "dns")]
+pub async fn fetch_dkim_key_cached(key_id: &DkimKeyId) -> Result<Vec<u8>> {
+    if let Some(public_key) = cached_dkim_key(key_id) {
+        return Ok(public_key);
+    }
+    let email = format!(
+        "DKIM-Signature: v=1; a=rsa-sha256; d={}; s={}; h=from; bh=; b=\r\n\r\n",
+        key_id.domain, key_id.selector
+    );
+    let logger = slog::Logger::root(slog::Discard, slog::o!());
+    let fetch = match cfdkim::resolve_public_key(&logger, email.as_bytes()).await {
+        Ok(cfdkim::DkimPublicKey::Rsa(public_key)) => {
+            DkimKeyFetch::from_rsa_public_key(&public_key)
+        }
+        Ok(_) => return Err(anyhow::anyhow!("{} is not an RSA key", key_id.record_name())),
+        Err(e) => {
+            return Err(anyhow::anyhow!(
+                "could not resolve {}: {:?}",
+                key_id.record_name(),
+                e
+            ))
+        }
+    };
+    cache_dkim_key_fetch(key_id.clone(), fetch.clone());
+    Ok(fetch.public_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_key_evicts_stale_and_oldest_keys() {
+        let key_id = |i: usize| DkimKeyId::new("example.com", &format!("s{}", i));
+        let fetch = |i: usize| DkimKeyFetch {
+            public_key: vec![i as u8],
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
+        };
+        let mut keys = DkimKeys::new();
+        for i in 0..5 {
+            insert_key(&mut keys, key_id(i), fetch(i), DKIM_KEY_CACHE_TTL, 3);
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        assert_eq!(keys.len(), 3);
+        assert!((2..5).all(|i| keys.contains_key(&key_id(i))));
+
+        // A key already cached is replaced without evicting another one.
+        insert_key(&mut keys, key_id(2), fetch(7), DKIM_KEY_CACHE_TTL, 3);
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[&key_id(2)].1, fetch(7));
+
+        // Stale keys are dropped on insert, whatever the capacity.
+        insert_key(&mut keys, key_id(5), fetch(5), Duration::ZERO, 3);
+        assert_eq!(keys.keys().collect::<Vec<_>>(), [&key_id(5)]);
+    }
+
+    #[test]
+    fn test_record_sighting_keeps_the_rotated_key() {
+        let key_id = |i: usize| DkimKeyId::new("example.com", &format!("s{}", i));
+        let mut sightings = KeySightings::new();
+        assert_eq!(record_sighting(&mut sightings, key_id(0), "0x01".into(), 10, 2), None);
+        // The same key again keeps its first sighting.
+        assert_eq!(record_sighting(&mut sightings, key_id(0), "0x01".into(), 20, 2), None);
+        assert_eq!(sightings[&key_id(0)].first_seen, 10);
+
+        let rotated_from = record_sighting(&mut sightings, key_id(0), "0x02".into(), 30, 2);
+        assert_eq!(rotated_from.as_deref(), Some("0x01"));
+        assert_eq!(
+            sightings[&key_id(0)],
+            KeySighting {
+                key_id: key_id(0),
+                public_key_hash: "0x02".to_string(),
+                first_seen: 30,
+                rotated_from: Some("0x01".to_string()),
+            }
+        );
+
+        // At capacity, the key first seen earliest is dropped.
+        record_sighting(&mut sightings, key_id(1), "0x03".into(), 40, 2);
+        record_sighting(&mut sightings, key_id(2), "0x04".into(), 50, 2);
+        assert_eq!(sightings.len(), 2);
+        assert!(!sightings.contains_key(&key_id(0)));
+    }
+
+    /// Serves `record` for every name, so that a test can rotate it.
+    struct MockResolver(Mutex<String>);
+
+    impl TxtResolver for MockResolver {
+        fn resolve_txt(&self, _name: &str) -> Result<Vec<String>> {
+            Ok(vec![self.0.lock().unwrap().clone()])
+        }
+    }
+
+    #[test]
+    fn test_rotation_hint_on_public_key_hash_mismatch() {
+        let old = test_fixture("send_eth_e3").unwrap();
+        let new = test_fixture("send_eth").unwrap();
+        let key_id = DkimKeyId::new("rotation.fixtures.zkemail.example", "s2024");
+        let resolver = MockResolver(Mutex::new(old.dkim_txt_record.clone()));
+        resolve_dkim_key_cached(&resolver, &key_id).unwrap();
+        let sighting = dkim_key_sighting(&key_id).unwrap();
+        assert_eq!(sighting.public_key_hash, old.expected.public_key_hash);
+        assert_eq!(sighting.rotated_from, None);
+
+        // The provider rotates the key, and the next fetch after the TTL returns the new one.
+        *resolver.0.lock().unwrap() = new.dkim_txt_record.clone();
+        DKIM_KEYS.lock().unwrap().remove(&key_id);
+        let public_key = resolve_dkim_key_cached(&resolver, &key_id).unwrap();
+        assert_eq!(hex::encode(&public_key), new.public_key);
+        let sighting = dkim_key_sighting(&key_id).unwrap();
+        assert_eq!(sighting.public_key_hash, new.expected.public_key_hash);
+        assert_eq!(
+            sighting.rotated_from.as_deref(),
+            Some(old.expected.public_key_hash.as_str())
+        );
+        assert!(dkim_key_sightings().contains(&sighting));
+
+        // An email signed with the new key, from a caller still expecting the old hash.
+        let header = format!(
+            "from:alice@gmail.com\r\nsubject:Send 1 ETH\r\ndkim-signature:v=1; a=rsa-sha256; \
+             d={}; s={}; h=from:subject; bh=; b=",
+            key_id.domain, key_id.selector
+        );
+        let options = InputGenerationOptions {
+            expected: Some(ExpectedValues {
+                public_key_hash: Some(old.expected.public_key_hash.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let err = VerifiedEmail::from_parsed_email(fixture_signed_email(&header, ""))
+            .unwrap()
+            .extract(&fixture_account_code(), &options)
+            .unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::InternalPanic),
+            ErrorCode::CircuitInputFailed
+        );
+        let hint = format!(
+            "key appears rotated: old={}, new={}, first seen {}",
+            old.expected.public_key_hash, new.expected.public_key_hash, sighting.first_seen
+        );
+        assert!(err.to_string().contains(&hint), "{}", err);
+        assert_eq!(
+            err.downcast_ref::<ExpectationMismatch>().unwrap().code(),
+            "EXPECTED_PUBLIC_KEY_HASH_MISMATCH"
+        );
+
+        // Expecting the new hash, the email passes.
+        let options = InputGenerationOptions {
+            expected: Some(ExpectedValues {
+                public_key_hash: Some(new.expected.public_key_hash.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        VerifiedEmail::from_parsed_email(fixture_signed_email(&header, ""))
+            .unwrap()
+            .extract(&fixture_account_code(), &options)
+            .unwrap();
+    }
+}
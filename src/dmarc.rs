@@ -0,0 +1,375 @@
+use crate::*;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Two-label public suffixes under which the organizational domain has three labels. A stand-in
+/// for the Public Suffix List that covers the suffixes seen in practice.
+const MULTI_LABEL_SUFFIXES: [&str; 16] = [
+    "co.uk", "org.uk", "ac.uk", "gov.uk", "co.jp", "ne.jp", "or.jp", "ac.jp", "com.au", "net.au",
+    "org.au", "co.nz", "com.br", "com.cn", "co.in", "co.kr",
+];
+
+/// DKIM identifier alignment mode (RFC 7489 §3.1.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlignmentMode {
+    /// `d=` equals the From domain.
+    Strict,
+    /// `d=` and the From domain have the same organizational domain.
+    Relaxed,
+}
+
+/// How the DKIM signature of an email aligns with its From domain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlignmentVerdict {
+    AlignedStrict,
+    /// Aligned in relaxed mode only.
+    AlignedRelaxed,
+    Unaligned,
+    /// The signature does not verify, so there is nothing to align.
+    NoPassingDkim,
+}
+
+impl AlignmentVerdict {
+    /// Whether the verdict passes DMARC's DKIM check under `mode`.
+    pub fn passes(&self, mode: AlignmentMode) -> bool {
+        match self {
+            AlignmentVerdict::AlignedStrict => true,
+            AlignmentVerdict::AlignedRelaxed => mode == AlignmentMode::Relaxed,
+            AlignmentVerdict::Unaligned | AlignmentVerdict::NoPassingDkim => false,
+        }
+    }
+}
+
+/// The DKIM-related tags of a published DMARC record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DmarcPolicy {
+    /// The `p=` tag: `none`, `quarantine` or `reject`.
+    pub policy: String,
+    /// The `adkim=` tag, relaxed when absent.
+    pub dkim_alignment: AlignmentMode,
+}
+
+/// The alignment verdict together with the policy of the From domain, when one was fetched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DmarcVerdict {
+    pub alignment: AlignmentVerdict,
+    pub policy: Option<DmarcPolicy>,
+    /// `alignment` passes under the policy's mode, or under relaxed mode without a policy.
+    pub dkim_pass: bool,
+}
+
+/// Resolves DNS TXT records. Lets callers plug in their own resolver, or a mock in tests.
+pub trait TxtResolver {
+    /// The TXT records of `name`, each with its strings concatenated. An empty list when the
+    /// name has none.
+    fn resolve_txt(&self, name: &str) -> Result<Vec<String>>;
+}
+
+/// A `TxtResolver` over DNS, without DNSSEC validation. It blocks on the lookups, so it must not
+/// be used from an async task; see `dmarc_verdict_node`.
+#[cfg(feature = "dns")]
+pub struct DnsTxtResolver(hickory_resolver::Resolver);
+
+#[cfg(feature = "dns")]
+impl DnsTxtResolver {
+    pub fn new() -> Result<Self> {
+        use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+
+        let resolver =
+            hickory_resolver::Resolver::new(ResolverConfig::default(), ResolverOpts::default())
+                .map_err(|e| anyhow!("could not build the DNS resolver: {}", e))?;
+        Ok(DnsTxtResolver(resolver))
+    }
+}
+
+#[cfg(feature = "dns")]
+impl TxtResolver for DnsTxtResolver {
+    /// A network error is `ErrorCode::DkimDnsFailed`.
+    fn resolve_txt(&self, name: &str) -> Result<Vec<String>> {
+        use hickory_resolver::error::ResolveErrorKind;
+
+        match self.0.txt_lookup(format!("{}.", name.trim_end_matches('.'))) {
+            Ok(lookup) => Ok(lookup
+                .iter()
+                .map(|txt| {
+                    txt.txt_data()
+                        .iter()
+                        .map(|data| String::from_utf8_lossy(data))
+                        .collect::<String>()
+                })
+                .collect()),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(vec![]),
+            Err(e) => Err(coded(
+                ErrorCode::DkimDnsFailed,
+                format!("could not resolve {}: {}", name, e),
+            )),
+        }
+    }
+}
+
+/// Approximates the organizational domain (RFC 7489 §3.2) as the last two labels, or three under
+/// one of `MULTI_LABEL_SUFFIXES`.
+pub fn organizational_domain(domain: &str) -> String {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let labels = domain.split('.').collect::<Vec<_>>();
+    let suffix_labels = match labels.len() {
+        n if n >= 2 && MULTI_LABEL_SUFFIXES.contains(&labels[n - 2..].join(".").as_str()) => 2,
+        _ => 1,
+    };
+    let keep = (suffix_labels + 1).min(labels.len());
+    labels[labels.len() - keep..].join(".")
+}
+
+/// The `d=` tag of the DKIM-Signature of a canonicalized header.
+pub fn dkim_signing_domain(canonical_header: &str) -> Result<String> {
+    let idxes = extract_dkim_signature_idxes(canonical_header)?;
+    let signature = &canonical_header[idxes.header.0..idxes.header.1];
+    let tags = &signature[signature.find(':').map_or(0, |colon| colon + 1)..];
+    tags.split(';')
+        .find_map(|tag| {
+            let (name, value) = tag.split_once('=')?;
+            (name.trim() == "d").then(|| value.trim().to_ascii_lowercase())
+        })
+        .ok_or_else(|| anyhow!("d= tag is not found in the DKIM-Signature"))
+}
+
+/// Computes the DKIM alignment of `parsed_email`: whether its signature verifies and whether the
+/// signing domain `d=` aligns with the From domain, strictly or only in relaxed mode.
+pub fn dmarc_dkim_alignment(parsed_email: &ParsedEmail) -> Result<AlignmentVerdict> {
+    let passes = parsed_email.verify_signature().is_ok();
+    if !passes {
+        return Ok(AlignmentVerdict::NoPassingDkim);
+    }
+    let from_domain = parsed_email.get_email_domain()?.to_ascii_lowercase();
+    let signing_domain = dkim_signing_domain(&parsed_email.canonicalized_header)?;
+    Ok(if signing_domain == from_domain {
+        AlignmentVerdict::AlignedStrict
+    } else if organizational_domain(&signing_domain) == organizational_domain(&from_domain) {
+        AlignmentVerdict::AlignedRelaxed
+    } else {
+        AlignmentVerdict::Unaligned
+    })
+}
+
+/// Parses the `p=` and `adkim=` tags of a DMARC record, or `None` for a TXT record that is not
+/// DMARC.
+pub fn parse_dmarc_record(record: &str) -> Result<Option<DmarcPolicy>> {
+    let mut tags = record.split(';').filter_map(|tag| {
+        let (name, value) = tag.split_once('=')?;
+        Some((name.trim(), value.trim()))
+    });
+    if tags.next() != Some(("v", "DMARC1")) {
+        return Ok(None);
+    }
+    let mut policy = None;
+    let mut dkim_alignment = AlignmentMode::Relaxed;
+    for (name, value) in tags {
+        match name {
+            "p" => policy = Some(value.to_ascii_lowercase()),
+            "adkim" => {
+                dkim_alignment = match value {
+                    "s" => AlignmentMode::Strict,
+                    "r" => AlignmentMode::Relaxed,
+                    _ => return Err(anyhow!("invalid adkim= tag `{}`", value)),
+                }
+            }
+            _ => {}
+        }
+    }
+    let policy = policy.ok_or_else(|| anyhow!("the DMARC record has no p= tag"))?;
+    Ok(Some(DmarcPolicy {
+        policy,
+        dkim_alignment,
+    }))
+}
+
+/// Fetches the DMARC policy of `from_domain` from `_dmarc.<from_domain>`, falling back to the
+/// organizational domain (RFC 7489 §6.6.3). `None` when neither publishes one.
+pub fn fetch_dmarc_policy(
+    resolver: &dyn TxtResolver,
+    from_domain: &str,
+) -> Result<Option<DmarcPolicy>> {
+    let from_domain = from_domain.to_ascii_lowercase();
+    let org_domain = organizational_domain(&from_domain);
+    let mut domains = vec![from_domain.as_str()];
+    if org_domain != from_domain {
+        domains.push(&org_domain);
+    }
+    for domain in domains {
+        for record in resolver.resolve_txt(&format!("_dmarc.{}", domain))? {
+            if let Some(policy) = parse_dmarc_record(&record)? {
+                return Ok(Some(policy));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// `dmarc_dkim_alignment`, judged under the From domain's published policy when `resolver` is
+/// given.
+pub fn dmarc_verdict(
+    parsed_email: &ParsedEmail,
+    resolver: Option<&dyn TxtResolver>,
+) -> Result<DmarcVerdict> {
+    let alignment = dmarc_dkim_alignment(parsed_email)?;
+    let policy = match resolver {
+        Some(resolver) => fetch_dmarc_policy(resolver, &parsed_email.get_email_domain()?)?,
+        None => None,
+    };
+    let mode = policy
+        .as_ref()
+        .map_or(AlignmentMode::Relaxed, |policy| policy.dkim_alignment);
+    Ok(DmarcVerdict {
+        alignment,
+        dkim_pass: alignment.passes(mode),
+        policy,
+    })
+}
+
+/// The `(canonicalizedHeader, signature, publicKey)` arguments of the DMARC exports, with hex
+/// signature and modulus.
+#[cfg(feature = "node")]
+fn parsed_email_arguments(cx: &mut FunctionContext) -> NeonResult<ParsedEmail> {
+    let canonicalized_header = cx.argument::<JsString>(0)?.value(cx);
+    let signature = trimmed_argument(cx, 1, "signature")?;
+    let public_key = trimmed_argument(cx, 2, "public_key")?;
+    let (signature, public_key) = match (decode_hex(&signature), decode_hex(&public_key)) {
+        (Ok(signature), Ok(public_key)) => (signature, public_key),
+        (Err(e), _) | (_, Err(e)) => return cx.throw_error(e.to_string()),
+    };
+    Ok(ParsedEmail {
+        canonicalized_header,
+        canonicalized_body: String::new(),
+        signature,
+        public_key,
+        public_exponent: DEFAULT_PUBLIC_EXPONENT,
+        dnssec_validated: None,
+    })
+}
+
+#[cfg(feature = "node")]
+pub fn dmarc_dkim_alignment_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let parsed_email = parsed_email_arguments(&mut cx)?;
+    match dmarc_dkim_alignment(&parsed_email) {
+        Ok(verdict) => Ok(cx.string(serde_json::to_value(verdict).unwrap().as_str().unwrap())),
+        Err(e) => cx.throw_error(format!("dmarc_dkim_alignment failed: {}", e)),
+    }
+}
+
+/// `dmarcVerdict(canonicalizedHeader, signature, publicKey, fetchPolicy)` resolves to the JSON
+/// `DmarcVerdict`. With `fetchPolicy`, the From domain's DMARC policy is looked up with
+/// `DnsTxtResolver` on a blocking thread of the shared runtime.
+#[cfg(feature = "node")]
+pub fn dmarc_verdict_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let parsed_email = parsed_email_arguments(&mut cx)?;
+    let fetch_policy = cx.argument::<JsBoolean>(3)?.value(&mut cx);
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(&mut cx)?;
+
+    rt.spawn_blocking(move || {
+        let verdict = if fetch_policy {
+            DnsTxtResolver::new()
+                .and_then(|resolver| dmarc_verdict(&parsed_email, Some(&resolver)))
+        } else {
+            dmarc_verdict(&parsed_email, None)
+        };
+        let verdict = verdict.and_then(|verdict| Ok(serde_json::to_string(&verdict)?));
+        deferred.settle_with(&channel, move |mut cx| match verdict {
+            Ok(verdict) => Ok(cx.string(verdict)),
+            Err(err) => throw_coded(
+                &mut cx,
+                error_code(&err, ErrorCode::ParseEmailFailed),
+                format!("dmarc_verdict failed: {}", err),
+            ),
+        });
+    });
+
+    Ok(promise)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs1v15::SigningKey;
+    use rsa::signature::{SignatureEncoding, Signer};
+    use rsa::traits::PublicKeyParts;
+    use rsa::RsaPrivateKey;
+    use sha2::Sha256;
+    use std::collections::HashMap;
+
+    struct MockResolver(HashMap<&'static str, Vec<String>>);
+
+    impl TxtResolver for MockResolver {
+        fn resolve_txt(&self, name: &str) -> Result<Vec<String>> {
+            Ok(self.0.get(name).cloned().unwrap_or_default())
+        }
+    }
+
+    #[test]
+    fn test_dmarc_dkim_alignment() {
+        let private_key = RsaPrivateKey::new(&mut rand_core::OsRng, 1024).unwrap();
+        let signing_key = SigningKey::<Sha256>::new(private_key.clone());
+        let signed_email = |from: &str, d: &str| {
+            let canonicalized_header = format!(
+                "from:{}\r\ndkim-signature:v=1; a=rsa-sha256; d={}; s=s1; bh=Zm9v; b=",
+                from, d
+            );
+            ParsedEmail {
+                signature: signing_key
+                    .sign(canonicalized_header.as_bytes())
+                    .to_vec(),
+                public_key: private_key.n().to_bytes_be(),
+                canonicalized_header,
+                canonicalized_body: String::new(),
+                public_exponent: DEFAULT_PUBLIC_EXPONENT,
+                dnssec_validated: None,
+            }
+        };
+
+        let strict = signed_email("alice@example.com", "Example.com");
+        let relaxed = signed_email("alice@mail.example.co.uk", "example.co.uk");
+        let unaligned = signed_email("alice@example.com", "sender.net");
+        let mut unsigned = signed_email("alice@example.com", "example.com");
+        unsigned.signature[0] ^= 1;
+        let cases = [
+            (&strict, AlignmentVerdict::AlignedStrict),
+            (&relaxed, AlignmentVerdict::AlignedRelaxed),
+            (&unaligned, AlignmentVerdict::Unaligned),
+            (&unsigned, AlignmentVerdict::NoPassingDkim),
+        ];
+        for (email, expected) in cases.iter() {
+            assert_eq!(dmarc_dkim_alignment(email).unwrap(), *expected);
+        }
+        assert_eq!(organizational_domain("mail.example.co.uk"), "example.co.uk");
+        assert_eq!(organizational_domain("mail.example.com."), "example.com");
+
+        let resolver: &dyn TxtResolver = &MockResolver(HashMap::from([
+            (
+                "_dmarc.example.co.uk",
+                vec!["v=DMARC1; p=reject; adkim=s".to_string()],
+            ),
+            (
+                "_dmarc.example.com",
+                vec!["v=spf1 -all".to_string(), "v=DMARC1; p=none".to_string()],
+            ),
+        ]));
+        // The policy of the organizational domain applies and requires strict alignment.
+        let verdict = dmarc_verdict(&relaxed, Some(resolver)).unwrap();
+        assert_eq!(verdict.policy.unwrap().dkim_alignment, AlignmentMode::Strict);
+        assert!(!verdict.dkim_pass);
+        let verdict = dmarc_verdict(&strict, Some(resolver)).unwrap();
+        assert_eq!(verdict.policy.unwrap().policy, "none");
+        assert!(verdict.dkim_pass);
+        // No policy published: relaxed alignment is enough.
+        let resolver: &dyn TxtResolver = &MockResolver(HashMap::new());
+        let verdict = dmarc_verdict(&relaxed, Some(resolver)).unwrap();
+        assert_eq!(verdict.policy, None);
+        assert!(verdict.dkim_pass);
+        assert!(!dmarc_verdict(&unsigned, None).unwrap().dkim_pass);
+    }
+}
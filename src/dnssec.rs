@@ -0,0 +1,270 @@
+use crate::*;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "dnssec")]
+use tracing::Instrument;
+
+/// DNSSEC status of an answer, as a validating resolver reports it (RFC 4035 §4.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnssecProof {
+    /// The chain of trust validates from the root.
+    Secure,
+    /// The zone is provably not signed.
+    Insecure,
+    /// The resolver could not tell, e.g. for lack of a trust anchor.
+    Indeterminate,
+    /// The zone is signed but the signatures do not validate.
+    Bogus,
+}
+
+/// TXT records of a name, each with its strings concatenated, and the weakest DNSSEC status of
+/// them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnssecTxtAnswer {
+    pub records: Vec<String>,
+    pub proof: DnssecProof,
+}
+
+/// The DKIM key of `key_id` in a DNSSEC-validated answer. Fails with `ErrorCode::DnssecFailed`
+/// on a bogus answer, however the key is then used; an unsigned zone is left to
+/// `DkimKeyFetch::check_dnssec`.
+pub fn dkim_key_from_dnssec_answer(
+    key_id: &DkimKeyId,
+    answer: &DnssecTxtAnswer,
+) -> Result<DkimKeyFetch> {
+    if answer.proof == DnssecProof::Bogus {
+        return Err(coded(
+            ErrorCode::DnssecFailed,
+            format!(
+                "the DNSSEC signatures of {} do not validate",
+                key_id.record_name()
+            ),
+        ));
+    }
+    let record = answer
+        .records
+        .iter()
+        .find(|record| record.contains("p="))
+        .ok_or_else(|| {
+            coded(
+                ErrorCode::DkimDnsFailed,
+                format!("no DKIM key is published at {}", key_id.record_name()),
+            )
+        })?;
+    Ok(DkimKeyFetch {
+        dnssec_validated: Some(answer.proof == DnssecProof::Secure),
+        ..DkimKeyFetch::from_rsa_public_key(&rsa_public_key_from_dkim_txt(record)?)
+    })
+}
+
+impl DkimKeyFetch {
+    /// Fails with `ErrorCode::DnssecFailed` when `require_dnssec` is set and the key of `key_id`
+    /// was not validated as secure.
+    pub fn check_dnssec(&self, key_id: &DkimKeyId, require_dnssec: bool) -> Result<()> {
+        let reason = match self.dnssec_validated {
+            _ if !require_dnssec => return Ok(()),
+            Some(true) => return Ok(()),
+            Some(false) => "is not DNSSEC-signed",
+            None => "was not DNSSEC-validated",
+        };
+        Err(coded(
+            ErrorCode::DnssecFailed,
+            format!(
+                "the DKIM key record {} {}, but require_dnssec is set",
+                key_id.record_name(),
+                reason
+            ),
+        ))
+    }
+}
+
+/// Resolves the TXT records of `name` with a DNSSEC-validating resolver. A validation error is
+/// `ErrorCode::DnssecFailed`; a network error or a missing name is `ErrorCode::DkimDnsFailed`.
+#[cfg(feature = "dnssec")]
+pub async fn resolve_txt_dnssec(name: &str) -> Result<DnssecTxtAnswer> {
+    use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+    use hickory_resolver::error::ResolveErrorKind;
+    use hickory_resolver::proto::rr::dnssec::Proof;
+    use hickory_resolver::TokioAsyncResolver;
+
+    let mut opts = ResolverOpts::default();
+    opts.validate = true;
+    let resolver = TokioAsyncResolver::tokio(ResolverConfig::default(), opts);
+    let lookup = resolver
+        .txt_lookup(format!("{}.", name.trim_end_matches('.')))
+        .await
+        .map_err(|e| {
+            let code = match e.kind() {
+                ResolveErrorKind::Proto(_) => ErrorCode::DnssecFailed,
+                _ => ErrorCode::DkimDnsFailed,
+            };
+            coded(
+                code,
+                format!("could not resolve {} with DNSSEC: {}", name, e),
+            )
+        })?;
+    let records = lookup
+        .iter()
+        .map(|txt| {
+            txt.txt_data()
+                .iter()
+                .map(|data| String::from_utf8_lossy(data))
+                .collect::<String>()
+        })
+        .collect();
+    let proof = lookup
+        .as_lookup()
+        .record_iter()
+        .map(|record| match record.proof() {
+            Proof::Secure => DnssecProof::Secure,
+            Proof::Insecure => DnssecProof::Insecure,
+            Proof::Bogus => DnssecProof::Bogus,
+            Proof::Indeterminate => DnssecProof::Indeterminate,
+        })
+        .max()
+        .unwrap_or(DnssecProof::Indeterminate);
+    Ok(DnssecTxtAnswer { records, proof })
+}
+
+/// Fetches the DKIM key of `key_id` with `resolve_txt_dnssec` and caches it with its DNSSEC
+/// outcome. An unsigned zone is cached too, so that `check_dnssec` can reject it from the cache.
+#[cfg(feature = "dnssec")]
+pub async fn fetch_dkim_key_dnssec(key_id: &DkimKeyId) -> Result<DkimKeyFetch> {
+    let answer = resolve_txt_dnssec(&key_id.record_name())
+        .instrument(tracing::info_span!("dkim_fetch", dnssec = true))
+        .await?;
+    let fetch = dkim_key_from_dnssec_answer(key_id, &answer)?;
+    cache_dkim_key_fetch(key_id.clone(), fetch.clone());
+    Ok(fetch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(proof: DnssecProof) -> DnssecTxtAnswer {
+        let fixture = test_fixture("send_eth").unwrap();
+        DnssecTxtAnswer {
+            records: vec!["v=spf1 -all".to_string(), fixture.dkim_txt_record],
+            proof,
+        }
+    }
+
+    #[test]
+    fn test_dnssec_outcomes() {
+        let fixture = test_fixture("send_eth").unwrap();
+        let key_id = DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector);
+        let public_key = decode_hex(&fixture.public_key).unwrap();
+
+        let validated = dkim_key_from_dnssec_answer(&key_id, &answer(DnssecProof::Secure)).unwrap();
+        assert_eq!(validated.public_key, public_key);
+        assert_eq!(validated.dnssec_validated, Some(true));
+        validated.check_dnssec(&key_id, true).unwrap();
+
+        // An unsigned zone is only rejected when DNSSEC is required.
+        for proof in [DnssecProof::Insecure, DnssecProof::Indeterminate] {
+            let unsigned = dkim_key_from_dnssec_answer(&key_id, &answer(proof)).unwrap();
+            assert_eq!(unsigned.dnssec_validated, Some(false));
+            unsigned.check_dnssec(&key_id, false).unwrap();
+            let err = unsigned.check_dnssec(&key_id, true).unwrap_err();
+            assert_eq!(
+                error_code(&err, ErrorCode::DkimDnsFailed),
+                ErrorCode::DnssecFailed
+            );
+            assert!(err.to_string().contains("is not DNSSEC-signed"), "{}", err);
+        }
+
+        let err = dkim_key_from_dnssec_answer(&key_id, &answer(DnssecProof::Bogus)).unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::DkimDnsFailed),
+            ErrorCode::DnssecFailed
+        );
+        assert!(err.to_string().contains("do not validate"), "{}", err);
+
+        let unvalidated = DkimKeyFetch {
+            public_key,
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
+        };
+        let err = unvalidated.check_dnssec(&key_id, true).unwrap_err();
+        assert!(
+            err.to_string().contains("was not DNSSEC-validated"),
+            "{}",
+            err
+        );
+        let empty = DnssecTxtAnswer {
+            records: vec![],
+            proof: DnssecProof::Secure,
+        };
+        let err = dkim_key_from_dnssec_answer(&key_id, &empty).unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::DnssecFailed),
+            ErrorCode::DkimDnsFailed
+        );
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    async fn test_require_dnssec_on_cached_keys() {
+        let fixture = test_fixture("send_eth").unwrap();
+        let public_key = decode_hex(&fixture.public_key).unwrap();
+        let email = |selector: &str| {
+            format!(
+                "DKIM-Signature: v=1; a=rsa-sha256; d=dnssec-test.example; s={}; h=from; bh=; \
+                 b=\r\nFrom: alice@dnssec-test.example\r\n\r\nbody\r\n",
+                selector
+            )
+        };
+        for (selector, dnssec_validated) in [("secure", Some(true)), ("unsigned", Some(false))] {
+            cache_dkim_key_fetch(
+                DkimKeyId::new("dnssec-test.example", selector),
+                DkimKeyFetch {
+                    public_key: public_key.clone(),
+                    public_exponent: DEFAULT_PUBLIC_EXPONENT,
+                    dnssec_validated,
+                },
+            );
+        }
+
+        let fetch = resolve_dkim_key_fetch(email("secure").as_bytes(), true)
+            .await
+            .unwrap();
+        assert_eq!(fetch.dnssec_validated, Some(true));
+        let fetch = resolve_dkim_key_fetch(email("unsigned").as_bytes(), false)
+            .await
+            .unwrap();
+        assert_eq!(fetch.dnssec_validated, Some(false));
+        let err = resolve_dkim_key_fetch(email("unsigned").as_bytes(), true)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::DkimDnsFailed),
+            ErrorCode::DnssecFailed
+        );
+
+        // The verified email keeps the outcome of the fetch.
+        let verified = RawEmail::from(fixture.raw_email.as_str())
+            .parse()
+            .unwrap()
+            .verify_fetched(DkimKeyFetch {
+                public_key,
+                public_exponent: DEFAULT_PUBLIC_EXPONENT,
+                dnssec_validated: Some(true),
+            })
+            .unwrap();
+        assert_eq!(verified.parsed_email().dnssec_validated, Some(true));
+    }
+
+    /// Resolves a DKIM key record of a signed zone over the network.
+    #[cfg(feature = "dnssec")]
+    #[tokio::test]
+    #[ignore]
+    async fn test_live_dnssec_fetch() {
+        let key_id = DkimKeyId::new("ietf.org", "ietf1");
+        let answer = resolve_txt_dnssec(&key_id.record_name()).await.unwrap();
+        assert_eq!(answer.proof, DnssecProof::Secure);
+        let fetch = dkim_key_from_dnssec_answer(&key_id, &answer).unwrap();
+        assert_eq!(fetch.dnssec_validated, Some(true));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::*;
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Stable numeric failure classes, so that callers can decide whether to retry without matching
+/// on error messages. The values are part of the interface and must not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum ErrorCode {
+    /// The email has no DKIM-Signature, or its signature or body hash does not verify (see
+    /// `verifyDkim`). Not worth retrying.
+    DkimVerificationFailed = 1,
+    /// An argument could not be decoded, e.g. an invalid hex string.
+    InvalidArgument = 10,
+    /// The email could not be parsed or canonicalized, or its signature does not cover it.
+    ParseEmailFailed = 20,
+    /// The DKIM key could not be resolved. Usually worth retrying.
+    DkimDnsFailed = 21,
+    /// DNSSEC validation of the DKIM key record failed, or `require_dnssec` is set and the zone
+    /// is not signed. Not worth retrying.
+    DnssecFailed = 22,
+    /// The circuit input could not be generated from the parsed email.
+    CircuitInputFailed = 30,
+    /// A hash, commitment or nullifier could not be computed.
+    CryptoFailed = 40,
+    /// The call was cancelled (see `cancel`).
+    Cancelled = 50,
+    /// The computation panicked.
+    InternalPanic = 99,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 9] = [
+        ErrorCode::DkimVerificationFailed,
+        ErrorCode::InvalidArgument,
+        ErrorCode::ParseEmailFailed,
+        ErrorCode::DkimDnsFailed,
+        ErrorCode::DnssecFailed,
+        ErrorCode::CircuitInputFailed,
+        ErrorCode::CryptoFailed,
+        ErrorCode::Cancelled,
+        ErrorCode::InternalPanic,
+    ];
+
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+/// An error raised with an explicit `ErrorCode`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodedError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl std::fmt::Display for CodedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+/// An `anyhow::Error` with the message `message` that `error_code` classifies as `code`.
+pub fn coded(code: ErrorCode, message: impl std::fmt::Display) -> anyhow::Error {
+    CodedError {
+        code,
+        message: message.to_string(),
+    }
+    .into()
+}
+
+/// The class of `err`: the code it was raised with by `coded`, the class of a typed error of this
+/// crate, or `default`.
+pub fn error_code(err: &anyhow::Error, default: ErrorCode) -> ErrorCode {
+    if let Some(coded) = err.chain().find_map(|cause| cause.downcast_ref::<CodedError>()) {
+        return coded.code;
+    }
+    if err.downcast_ref::<UnsignedFromHeader>().is_some() {
+        return ErrorCode::ParseEmailFailed;
+    }
+    if err.downcast_ref::<ExpectationMismatch>().is_some()
+        || err.downcast_ref::<UnsupportedPublicExponent>().is_some()
+    {
+        return ErrorCode::CircuitInputFailed;
+    }
+    default
+}
+
+/// Throws a JS `Error` with `message` and the numeric `code` property.
+#[cfg(feature = "node")]
+pub fn throw_coded<'a, C: Context<'a>, T>(
+    cx: &mut C,
+    code: ErrorCode,
+    message: impl AsRef<str>,
+) -> NeonResult<T> {
+    let error = JsError::error(cx, message)?;
+    let code = cx.number(code.as_u8());
+    error.set(cx, "code", code)?;
+    cx.throw(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_code_values_are_stable() {
+        let codes = ErrorCode::ALL.iter().map(|code| code.as_u8()).collect::<Vec<_>>();
+        assert_eq!(codes, [1, 10, 20, 21, 22, 30, 40, 50, 99]);
+        assert_eq!(
+            serde_json::to_string(&ErrorCode::DkimDnsFailed).unwrap(),
+            "\"DkimDnsFailed\""
+        );
+    }
+
+    #[test]
+    fn test_error_code_classification() {
+        let err = coded(ErrorCode::DkimDnsFailed, "could not resolve the DKIM key");
+        assert_eq!(err.to_string(), "could not resolve the DKIM key");
+        assert_eq!(
+            error_code(&err, ErrorCode::CircuitInputFailed),
+            ErrorCode::DkimDnsFailed
+        );
+        let err = err.context("generation failed");
+        assert_eq!(
+            error_code(&err, ErrorCode::CircuitInputFailed),
+            ErrorCode::DkimDnsFailed
+        );
+
+        let err = anyhow::Error::from(UnsignedFromHeader {
+            from_headers: 2,
+            signed_from_headers: 1,
+        });
+        assert_eq!(
+            error_code(&err, ErrorCode::CircuitInputFailed),
+            ErrorCode::ParseEmailFailed
+        );
+        let err = anyhow::anyhow!("something else");
+        assert_eq!(error_code(&err, ErrorCode::CryptoFailed), ErrorCode::CryptoFailed);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::*;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Settings that can be changed while the process runs with `reload_config`. The runtime and the
+/// circuit profile are set once at initialization and are not part of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HotConfig {
+    /// Largest raw email accepted by input generation, in bytes. `None` for no limit.
+    pub max_email_bytes: Option<usize>,
+    /// DKIM signing domains (`d=`) accepted by input generation. Empty accepts every domain.
+    pub allowed_signing_domains: Vec<String>,
+    /// DKIM signing domains rejected by input generation, checked before the allowlist.
+    pub denied_signing_domains: Vec<String>,
+    /// How long a resolved DKIM key is reused; see `DKIM_KEY_CACHE_TTL`.
+    pub dkim_key_cache_ttl_secs: u64,
+    /// Fetch DKIM keys with a DNSSEC-validating resolver and record whether the key record
+    /// validated (`DkimKeyFetch::dnssec_validated`). Needs the `dnssec` feature.
+    pub dnssec_validation: bool,
+}
+
+impl Default for HotConfig {
+    fn default() -> Self {
+        HotConfig {
+            max_email_bytes: None,
+            allowed_signing_domains: vec![],
+            denied_signing_domains: vec![],
+            dkim_key_cache_ttl_secs: DKIM_KEY_CACHE_TTL.as_secs(),
+            dnssec_validation: false,
+        }
+    }
+}
+
+impl HotConfig {
+    pub fn from_json(config_json: &str) -> Result<Self> {
+        let mut config: HotConfig = serde_json::from_str(config_json)
+            .map_err(|e| anyhow!("invalid hot config: {}", e))?;
+        for domain in config
+            .allowed_signing_domains
+            .iter_mut()
+            .chain(config.denied_signing_domains.iter_mut())
+        {
+            *domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        }
+        if config.max_email_bytes == Some(0) {
+            return Err(anyhow!("max_email_bytes must be positive"));
+        }
+        // Reported as a JSON number by `reload_config`.
+        if config.dkim_key_cache_ttl_secs > MAX_SAFE_INTEGER {
+            return Err(anyhow!(
+                "dkim_key_cache_ttl_secs must be at most {}",
+                MAX_SAFE_INTEGER
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn dkim_key_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.dkim_key_cache_ttl_secs)
+    }
+
+    /// Fails when `raw_email` is over `max_email_bytes` or its DKIM signing domain is not
+    /// accepted. Emails without a `d=` tag are left to the DKIM check.
+    pub fn check_email(&self, raw_email: &[u8]) -> Result<()> {
+        if let Some(max_email_bytes) = self.max_email_bytes {
+            if raw_email.len() > max_email_bytes {
+                return Err(coded(
+                    ErrorCode::InvalidArgument,
+                    format!(
+                        "the email is {} bytes but at most {} are allowed",
+                        raw_email.len(),
+                        max_email_bytes
+                    ),
+                ));
+            }
+        }
+        let domain = match DkimKeyId::from_raw_email(&raw_header_text(raw_email)) {
+            Some(key_id) => key_id.domain,
+            None => return Ok(()),
+        };
+        if self.denied_signing_domains.contains(&domain)
+            || !(self.allowed_signing_domains.is_empty()
+                || self.allowed_signing_domains.contains(&domain))
+        {
+            return Err(coded(
+                ErrorCode::InvalidArgument,
+                format!("the signing domain {} is not accepted", domain),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// One setting changed by `reload_config`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigChange {
+    pub field: String,
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+static HOT_CONFIG: Lazy<RwLock<Arc<HotConfig>>> =
+    Lazy::new(|| RwLock::new(Arc::new(HotConfig::default())));
+
+/// The current hot config. A call takes the snapshot once and uses it throughout, so that a
+/// reload does not change the settings under a call in flight.
+pub fn hot_config() -> Arc<HotConfig> {
+    HOT_CONFIG
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Replaces the hot config at once and returns the settings that changed, by field name.
+pub fn reload_config(config: HotConfig) -> Vec<ConfigChange> {
+    let new = serde_json::to_value(&config).unwrap_or_default();
+    let old = {
+        let mut current = HOT_CONFIG.write().unwrap_or_else(|e| e.into_inner());
+        std::mem::replace(&mut *current, Arc::new(config))
+    };
+    let old = serde_json::to_value(&*old).unwrap_or_default();
+    let (old, new) = match (old, new) {
+        (serde_json::Value::Object(old), serde_json::Value::Object(new)) => (old, new),
+        _ => return vec![],
+    };
+    new.into_iter()
+        .filter(|(field, value)| old.get(field) != Some(value))
+        .map(|(field, value)| ConfigChange {
+            old: old.get(&field).cloned().unwrap_or_default(),
+            field,
+            new: value,
+        })
+        .collect()
+}
+
+/// `reloadConfig(configJson)` returns the JSON array of `ConfigChange`. Unset fields take their
+/// defaults.
+#[cfg(feature = "node")]
+pub fn reload_config_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let config_json = cx.argument::<JsString>(0)?.value(&mut cx);
+    let config = match HotConfig::from_json(&config_json) {
+        Ok(config) => config,
+        Err(e) => return throw_coded(&mut cx, ErrorCode::InvalidArgument, e.to_string()),
+    };
+    let changes = reload_config(config);
+    match serde_json::to_string(&changes) {
+        Ok(changes) => Ok(cx.string(changes)),
+        Err(e) => cx.throw_error(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_config() {
+        let email = "DKIM-Signature: v=1; a=rsa-sha256; d=Reload-Test.example; s=s1;\r\n\
+                     \th=from; bh=; b=\r\nFrom: alice@reload-test.example\r\n\r\nbody\r\n";
+        let before = hot_config();
+        assert!(before.check_email(email.as_bytes()).is_ok());
+
+        let config = HotConfig::from_json(
+            r#"{"denied_signing_domains": ["reload-test.example."],
+                "dkim_key_cache_ttl_secs": 7200}"#,
+        )
+        .unwrap();
+        let changes = reload_config(config);
+        let fields = changes.iter().map(|change| change.field.as_str()).collect::<Vec<_>>();
+        assert_eq!(fields, ["denied_signing_domains", "dkim_key_cache_ttl_secs"]);
+        assert_eq!(changes[1].old, serde_json::json!(3600));
+        assert_eq!(changes[1].new, serde_json::json!(7200));
+
+        // A call that took its snapshot before the reload keeps the old settings.
+        assert!(before.check_email(email.as_bytes()).is_ok());
+        let err = hot_config().check_email(email.as_bytes()).unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::CircuitInputFailed),
+            ErrorCode::InvalidArgument
+        );
+        assert!(err.to_string().contains("reload-test.example"), "{}", err);
+        let limited = HotConfig::from_json(r#"{"max_email_bytes": 64}"#).unwrap();
+        assert!(limited.check_email(email.as_bytes()).is_err());
+        assert!(HotConfig::from_json(r#"{"max_email_bytes": 0}"#).is_err());
+        assert!(HotConfig::from_json(r#"{"max_email_byte": 1}"#).is_err());
+
+        reload_config(HotConfig::default());
+        assert!(hot_config().check_email(email.as_bytes()).is_ok());
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use num_bigint::BigInt;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// How `diff_email_auth_inputs` compares two inputs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiffOptions {
+    /// Number of differing elements reported per array.
+    pub max_elements: usize,
+    /// Compare numbers by value, so that `"0x1f"`, `"31"` and `31` are equal.
+    pub normalize: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            max_elements: 10,
+            normalize: false,
+        }
+    }
+}
+
+/// One differing element of an array field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ElementDiff {
+    pub index: usize,
+    pub a: Option<Value>,
+    pub b: Option<Value>,
+}
+
+/// A top-level field that differs. For arrays, `first_differences` holds the first
+/// `DiffOptions::max_elements` differing elements out of `differing_count`; an element missing
+/// from the shorter array counts as differing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldDiff {
+    pub field: String,
+    pub a: Option<Value>,
+    pub b: Option<Value>,
+    pub differing_count: usize,
+    pub first_differences: Vec<ElementDiff>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputDiff {
+    pub fields: Vec<FieldDiff>,
+}
+
+impl InputDiff {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+/// Decimal form of a field string in decimal or `0x` hex, or of a JSON number.
+fn normalized_number(value: &Value) -> Option<BigInt> {
+    match value {
+        Value::Number(n) => BigInt::parse_bytes(n.to_string().as_bytes(), 10),
+        Value::String(s) => match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => BigInt::parse_bytes(hex.as_bytes(), 16),
+            None => BigInt::parse_bytes(s.as_bytes(), 10),
+        },
+        _ => None,
+    }
+}
+
+fn values_equal(a: &Value, b: &Value, options: &DiffOptions) -> bool {
+    if a == b {
+        return true;
+    }
+    options.normalize
+        && match (normalized_number(a), normalized_number(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+}
+
+fn diff_field(
+    field: &str,
+    a: Option<&Value>,
+    b: Option<&Value>,
+    options: &DiffOptions,
+) -> Option<FieldDiff> {
+    let summary = |value: Option<&Value>| match value {
+        Some(Value::Array(elements)) => Some(Value::from(format!("[{} elements]", elements.len()))),
+        value => value.cloned(),
+    };
+    match (a, b) {
+        (Some(Value::Array(a_elements)), Some(Value::Array(b_elements))) => {
+            let mut differing_count = 0;
+            let mut first_differences = vec![];
+            for index in 0..a_elements.len().max(b_elements.len()) {
+                let (a, b) = (a_elements.get(index), b_elements.get(index));
+                let equal = match (a, b) {
+                    (Some(a), Some(b)) => values_equal(a, b, options),
+                    _ => false,
+                };
+                if !equal {
+                    differing_count += 1;
+                    if first_differences.len() < options.max_elements {
+                        first_differences.push(ElementDiff {
+                            index,
+                            a: a.cloned(),
+                            b: b.cloned(),
+                        });
+                    }
+                }
+            }
+            (differing_count > 0).then(|| FieldDiff {
+                field: field.to_string(),
+                a: summary(a),
+                b: summary(b),
+                differing_count,
+                first_differences,
+            })
+        }
+        (Some(a_value), Some(b_value)) if values_equal(a_value, b_value, options) => None,
+        _ => Some(FieldDiff {
+            field: field.to_string(),
+            a: summary(a),
+            b: summary(b),
+            differing_count: 1,
+            first_differences: vec![],
+        }),
+    }
+}
+
+fn parse_input(json: &str, which: &str) -> Result<Map<String, Value>> {
+    match serde_json::from_str(json) {
+        Ok(Value::Object(fields)) => Ok(fields),
+        Ok(_) => Err(anyhow!("input {} is not a JSON object", which)),
+        Err(e) => Err(anyhow!("input {} is not valid JSON: {}", which, e)),
+    }
+}
+
+/// Compares two email auth input JSONs field by field. Differing fields are reported in key
+/// order.
+pub fn diff_email_auth_inputs(
+    a_json: &str,
+    b_json: &str,
+    options: &DiffOptions,
+) -> Result<InputDiff> {
+    let a = parse_input(a_json, "a")?;
+    let b = parse_input(b_json, "b")?;
+    let fields = a
+        .keys()
+        .chain(b.keys().filter(|field| !a.contains_key(*field)))
+        .sorted()
+        .filter_map(|field| diff_field(field, a.get(field), b.get(field), options))
+        .collect();
+    Ok(InputDiff { fields })
+}
+
+#[cfg(feature = "node")]
+pub fn diff_inputs_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let a_json = cx.argument::<JsString>(0)?.value(&mut cx);
+    let b_json = cx.argument::<JsString>(1)?.value(&mut cx);
+    let options = match cx.argument_opt(2) {
+        Some(options) if !options.is_a::<JsUndefined, _>(&mut cx) => {
+            let options = options
+                .downcast_or_throw::<JsString, _>(&mut cx)?
+                .value(&mut cx);
+            match serde_json::from_str(&options) {
+                Ok(options) => options,
+                Err(e) => return cx.throw_error(format!("invalid diff options: {}", e)),
+            }
+        }
+        _ => DiffOptions::default(),
+    };
+    match diff_email_auth_inputs(&a_json, &b_json, &options)
+        .and_then(|diff| Ok(serde_json::to_string(&diff)?))
+    {
+        Ok(diff) => Ok(cx.string(diff)),
+        Err(e) => cx.throw_error(format!("diff_email_auth_inputs failed: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_email_auth_inputs() {
+        let input = json!({
+            "padded_header": (0..300).map(|i| (i % 256).to_string()).collect::<Vec<_>>(),
+            "signature": ["1234", "5678", "9012"],
+            "account_code": "31",
+            "subject_idx": "5",
+        });
+        let a = input.to_string();
+        let options = DiffOptions::default();
+        assert!(diff_email_auth_inputs(&a, &a, &options).unwrap().is_empty());
+
+        let mut corrupted = input.clone();
+        corrupted["signature"][1] = json!("5679");
+        corrupted["subject_idx"] = json!("6");
+        let diff = diff_email_auth_inputs(&a, &corrupted.to_string(), &options).unwrap();
+        assert_eq!(diff.fields.len(), 2);
+        let signature = &diff.fields[0];
+        assert_eq!(signature.field, "signature");
+        assert_eq!(signature.differing_count, 1);
+        assert_eq!(
+            signature.first_differences,
+            [ElementDiff {
+                index: 1,
+                a: Some(json!("5678")),
+                b: Some(json!("5679")),
+            }]
+        );
+        assert_eq!(diff.fields[1].field, "subject_idx");
+
+        let mut shifted = input.clone();
+        for byte in shifted["padded_header"].as_array_mut().unwrap() {
+            *byte = json!("0");
+        }
+        let diff = diff_email_auth_inputs(&a, &shifted.to_string(), &options).unwrap();
+        assert!(diff.fields[0].differing_count > options.max_elements);
+        assert_eq!(diff.fields[0].first_differences.len(), options.max_elements);
+
+        // The same values in hex.
+        let mut hex = input.clone();
+        hex["signature"] = json!(["0x4d2", "0x162e", "0x2334"]);
+        hex["account_code"] = json!("0x1F");
+        hex["subject_idx"] = json!(5);
+        let hex = hex.to_string();
+        assert_eq!(
+            diff_email_auth_inputs(&a, &hex, &options)
+                .unwrap()
+                .fields
+                .len(),
+            3
+        );
+        let normalize = DiffOptions {
+            normalize: true,
+            ..Default::default()
+        };
+        assert!(diff_email_auth_inputs(&a, &hex, &normalize)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,222 @@
+use crate::*;
+use num_bigint::BigInt;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+
+/// Largest integer a JavaScript number holds exactly, `Number.MAX_SAFE_INTEGER` (2^53 - 1).
+pub const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Ceiling of every index and length in the emitted inputs, `i32::MAX`, so that consumers that
+/// read them into a 32-bit signed integer (a Java `int`) cannot wrap.
+pub const MAX_EMITTED_INDEX: usize = i32::MAX as usize;
+
+/// Fails with `ErrorCode::CircuitInputFailed`, naming `field`, when `value` is over
+/// `MAX_EMITTED_INDEX`.
+pub fn check_emitted_index(field: &str, value: usize) -> anyhow::Result<()> {
+    if value > MAX_EMITTED_INDEX {
+        return Err(coded(
+            ErrorCode::CircuitInputFailed,
+            format!(
+                "{} is {}, over the limit of {} for emitted indexes and lengths",
+                field, value, MAX_EMITTED_INDEX
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Integer types that may be emitted as JSON numbers. These are the index and length types, which
+/// are bounded by the email and circuit sizes and so stay far below `MAX_SAFE_INTEGER`. Field
+/// elements, limbs and other big integers are emitted as strings, with `serialize_field` and
+/// `serialize_bigint`, and `u64` is left out so that it cannot be emitted by mistake.
+pub trait JsonSafeNumber: Serialize {}
+
+impl JsonSafeNumber for u8 {}
+impl JsonSafeNumber for u16 {}
+impl JsonSafeNumber for u32 {}
+impl JsonSafeNumber for usize {}
+impl<T: JsonSafeNumber> JsonSafeNumber for Option<T> {}
+
+/// serde `serialize_with` for an index or length field, which only compiles for a
+/// `JsonSafeNumber` type.
+pub fn serialize_number<T: JsonSafeNumber, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    value.serialize(serializer)
+}
+
+/// serde `serialize_with` emitting a field element as its `field2hex` string.
+pub fn serialize_field<S: Serializer>(field: &Fr, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&field2hex(field))
+}
+
+/// serde `serialize_with` emitting a big integer as a decimal string.
+pub fn serialize_bigint<S: Serializer>(value: &BigInt, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&value.to_str_radix(10))
+}
+
+/// `value` capped at `MAX_SAFE_INTEGER`, for counters such as durations that are emitted as
+/// numbers.
+pub fn saturating_safe_integer(value: u128) -> u64 {
+    value.min(MAX_SAFE_INTEGER as u128) as u64
+}
+
+/// JSON pointers of the integers in `value` that a JavaScript number cannot hold exactly.
+pub fn unsafe_json_numbers(value: &Value) -> Vec<String> {
+    fn walk(value: &Value, pointer: &mut String, found: &mut Vec<String>) {
+        match value {
+            Value::Number(n) => {
+                let safe = match (n.as_u64(), n.as_i64()) {
+                    (Some(n), _) => n <= MAX_SAFE_INTEGER,
+                    (None, Some(n)) => n.unsigned_abs() <= MAX_SAFE_INTEGER,
+                    // Floats are not indexes and lose precision anyway.
+                    (None, None) => true,
+                };
+                if !safe {
+                    found.push(pointer.clone());
+                }
+            }
+            Value::Array(elements) => {
+                for (i, element) in elements.iter().enumerate() {
+                    let len = pointer.len();
+                    pointer.push_str(&format!("/{}", i));
+                    walk(element, pointer, found);
+                    pointer.truncate(len);
+                }
+            }
+            Value::Object(fields) => {
+                for (key, field) in fields {
+                    let len = pointer.len();
+                    pointer.push('/');
+                    pointer.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                    walk(field, pointer, found);
+                    pointer.truncate(len);
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut found = vec![];
+    walk(value, &mut String::new(), &mut found);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs1v15::SigningKey;
+    use rsa::signature::{SignatureEncoding, Signer};
+    use rsa::traits::PublicKeyParts;
+    use serde_json::json;
+    use sha2::Sha256;
+
+    fn assert_json_safe<T: Serialize>(value: &T) {
+        let value = serde_json::to_value(value).unwrap();
+        assert_eq!(
+            unsafe_json_numbers(&value),
+            Vec::<String>::new(),
+            "{}",
+            value
+        );
+    }
+
+    #[test]
+    fn test_unsafe_json_numbers() {
+        let value = json!({
+            "idx": MAX_SAFE_INTEGER,
+            "a/b": [1, MAX_SAFE_INTEGER + 1],
+            "neg": -(MAX_SAFE_INTEGER as i64) - 1,
+            "score": 1e300,
+        });
+        assert_eq!(unsafe_json_numbers(&value), ["/a~1b/1", "/neg"]);
+        assert_eq!(saturating_safe_integer(u128::MAX), MAX_SAFE_INTEGER);
+
+        #[derive(Serialize)]
+        struct Big {
+            #[serde(serialize_with = "serialize_field")]
+            field: Fr,
+            #[serde(serialize_with = "serialize_bigint")]
+            limb: BigInt,
+        }
+        let big = Big {
+            field: Fr::from(u64::MAX),
+            limb: BigInt::from(u128::MAX),
+        };
+        assert_eq!(
+            serde_json::to_value(&big).unwrap(),
+            json!({
+                "field": field2hex(&Fr::from(u64::MAX)),
+                "limb": u128::MAX.to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_emitted_structs_are_json_safe() {
+        let private_key = rsa::RsaPrivateKey::new(&mut rand_core::OsRng, 1024).unwrap();
+        let header = "from:alice@gmail.com\r\nsubject:Send 1 ETH to bob@example.com\r\n\
+                      date:Wed, 13 Dec 2023 10:00:00 +0000\r\n";
+        let parsed_email = ParsedEmail {
+            canonicalized_header: header.to_string(),
+            canonicalized_body: String::new(),
+            signature: SigningKey::<Sha256>::new(private_key.clone())
+                .sign(header.as_bytes())
+                .to_vec(),
+            public_key: private_key.n().to_bytes_be(),
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
+        };
+        let account_code = AccountCode(Fr::from(u64::MAX));
+        let options = InputGenerationOptions {
+            include_nullifier: true,
+            include_public_key_hash: true,
+            ..Default::default()
+        };
+        let input =
+            generate_email_auth_input_from_parsed_email(&parsed_email, &account_code, &options)
+                .unwrap();
+        let input: Value = serde_json::from_str(&input).unwrap();
+        assert_eq!(unsafe_json_numbers(&input), Vec::<String>::new());
+        // The limbs are over 2^53 and must stay strings.
+        for field in ["public_key", "signature", "padded_header"] {
+            assert!(input[field]
+                .as_array()
+                .unwrap()
+                .iter()
+                .all(Value::is_string));
+        }
+        for field in [
+            "account_code",
+            "padded_header_len",
+            "email_nullifier",
+            "public_key_hash",
+        ] {
+            assert!(input[field].is_string(), "{}", field);
+        }
+
+        assert_json_safe(&parsed_email);
+        assert_json_safe(&parsed_email.get_header_indexes().unwrap());
+        assert_json_safe(&extract_sender(header).unwrap());
+        assert_json_safe(&extract_subject(header).unwrap());
+        assert_json_safe(&estimate_proving_cost(
+            &parsed_email,
+            &options,
+            &ProvingCostWeights::default(),
+        ));
+        let config = HotConfig {
+            dkim_key_cache_ttl_secs: MAX_SAFE_INTEGER,
+            ..Default::default()
+        };
+        assert_json_safe(&config);
+        assert!(HotConfig::from_json(&format!(
+            r#"{{"dkim_key_cache_ttl_secs": {}}}"#,
+            MAX_SAFE_INTEGER + 1
+        ))
+        .is_err());
+        assert_json_safe(&PrewarmStage {
+            name: "poseidon".to_string(),
+            micros: saturating_safe_integer(u128::MAX),
+        });
+    }
+}
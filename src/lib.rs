@@ -1,34 +1,99 @@
+pub mod audit;
+#[cfg(feature = "body")]
+pub mod body;
+pub mod cancellation;
 pub mod circuit;
+pub mod circuit_variant;
+pub mod command;
+pub mod conformance;
 pub mod converters;
 pub mod cryptos;
+pub mod debug_dump;
+pub mod digest_invariant;
+pub mod dkim_cache;
+pub mod dmarc;
+pub mod dnssec;
+pub mod error_code;
+pub mod hot_config;
+pub mod input_diff;
+pub mod json_number;
 pub mod logger;
+pub mod nullifier_fast;
+pub mod options;
+pub mod panic_hook;
 pub mod parse_email;
+pub mod platform;
+pub mod poseidon_config;
+pub mod prewarm;
+pub mod profile;
+pub mod public_signals;
 pub mod regex;
+pub mod replay;
+pub mod stages;
 pub mod statics;
+pub mod test_fixtures;
+pub mod tracing_bridge;
 
+pub use audit::*;
+#[cfg(feature = "body")]
+pub use body::*;
+pub use cancellation::*;
 pub use circuit::*;
+pub use circuit_variant::*;
+pub use command::*;
+pub use conformance::*;
 pub use converters::*;
 pub use cryptos::*;
+pub use debug_dump::*;
+pub use digest_invariant::*;
+pub use dkim_cache::*;
+pub use dmarc::*;
+pub use dnssec::*;
+pub use error_code::*;
+pub use hot_config::*;
+pub use input_diff::*;
+pub use json_number::*;
 pub use logger::*;
+pub use nullifier_fast::*;
+pub use options::*;
+pub use panic_hook::*;
 pub use parse_email::*;
+pub use platform::*;
+pub use poseidon_config::*;
+pub use prewarm::*;
+pub use profile::*;
+pub use public_signals::*;
 pub use regex::*;
+pub use replay::*;
+pub use stages::*;
 pub use statics::*;
+pub use test_fixtures::*;
+pub use tracing_bridge::*;
 
+#[cfg(feature = "node")]
 pub use neon::prelude::*;
 pub use poseidon_rs::*;
 pub use zk_regex_apis::extract_substrs::*;
 pub use zk_regex_apis::padding::*;
 
+#[cfg(feature = "node")]
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
+    // A failure surfaces from the first call that needs the runtime, or from `init`.
+    let _ = init_library();
     cx.export_function(
         "genAccountCreationInput",
         generate_account_creation_input_node,
     )?;
     cx.export_function("genEmailSenderInput", generate_email_sender_input_node)?;
     cx.export_function("parseEmail", parse_email_node)?;
+    cx.export_function("canonicalizedHeader", canonicalized_header_node)?;
+    cx.export_function("extractIndexes", extract_indexes_node)?;
+    cx.export_function("extractEmailIndexes", extract_email_indexes_node)?;
+    cx.export_function("extractDkimInfo", extract_dkim_info_node)?;
     cx.export_function("padString", pad_string_node)?;
     cx.export_function("bytes2Fields", bytes2fields_node)?;
+    cx.export_function("limbsToHex", limbs_to_hex_node)?;
     cx.export_function("extractSubstrIdxes", extract_substr_idxes_node)?;
     cx.export_function("extractEmailAddrIdxes", extract_email_addr_idxes_node)?;
     cx.export_function("extractEmailDomainIdxes", extract_email_domain_idxes_node)?;
@@ -56,16 +121,78 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("padEmailAddr", pad_email_addr_node)?;
     cx.export_function("emailAddrCommitRand", email_addr_commit_rand_node)?;
     cx.export_function("emailAddrCommit", email_addr_commit_node)?;
+    cx.export_function("emailAddrPointer", email_addr_pointer_node)?;
     cx.export_function(
         "emailAddrCommitWithSignature",
         email_addr_commit_with_signature_node,
     )?;
     cx.export_function("genAccountCode", gen_account_code_node)?;
     cx.export_function("genEmailAuthInput", generate_email_auth_input_node)?;
+    cx.export_function("genEmailAuthInputBytes", generate_email_auth_input_bytes_node)?;
+    cx.export_function(
+        "generateEmailInputWithOptions",
+        generate_email_input_with_options_node,
+    )?;
+    cx.export_function("estimateProvingCost", estimate_proving_cost_node)?;
+    cx.export_function("circuitRequirements", circuit_requirements_node)?;
+    cx.export_function(
+        "inputGenerationOptionsSchema",
+        input_generation_options_schema_node,
+    )?;
+    cx.export_function(
+        "genEmailAuthInputFromCanonical",
+        generate_email_auth_input_from_canonical_node,
+    )?;
     cx.export_function("extractRandFromSignature", extract_rand_from_signature_node)?;
     cx.export_function("accountCodeCommit", account_code_commit_node)?;
     cx.export_function("accountSalt", account_salt_node)?;
+    cx.export_function("findMatchingAccountCode", find_matching_account_code_node)?;
     cx.export_function("publicKeyHash", public_key_hash_node)?;
+    cx.export_function("publicKeyHashBytes", public_key_hash_bytes_node)?;
+    cx.export_function("publicKeyHashFromDer", public_key_hash_from_der_node)?;
+    cx.export_function("publicKeyHashFromTxt", public_key_hash_from_txt_node)?;
+    cx.export_function("publicKeyHashFromEmail", public_key_hash_from_email_node)?;
     cx.export_function("emailNullifier", email_nullifier_node)?;
+    cx.export_function("emailNullifierBytes", email_nullifier_bytes_node)?;
+    cx.export_function("emailNullifierFast", email_nullifier_fast_node)?;
+    cx.export_function("reconstructCommand", reconstruct_command_node)?;
+    cx.export_function("extractSubjectCommand", extract_subject_command_node)?;
+    cx.export_function("verifyBodyContains", verify_body_contains_node)?;
+    cx.export_function("verifyDkim", verify_dkim_node)?;
+    cx.export_function("extractSender", extract_sender_node)?;
+    cx.export_function("extractSenderBytes", extract_sender_bytes_node)?;
+    cx.export_function("extractSubject", extract_subject_node)?;
+    cx.export_function("receivedChain", received_chain_node)?;
+    cx.export_function("diffInputs", diff_inputs_node)?;
+    cx.export_function("verifyGeneratedInput", verify_generated_input_node)?;
+    cx.export_function(
+        "generateEmailAuthInputBatch",
+        generate_email_auth_input_batch_node,
+    )?;
+    cx.export_function(
+        "generateEmailAuthInputBatchV2",
+        generate_email_auth_input_batch_v2_node,
+    )?;
+    cx.export_function("dmarcDkimAlignment", dmarc_dkim_alignment_node)?;
+    cx.export_function("dmarcVerdict", dmarc_verdict_node)?;
+    cx.export_function("platformInfo", platform_info_node)?;
+    cx.export_function("versionInfo", version_info_node)?;
+    cx.export_function("setCircuitProfile", set_circuit_profile_node)?;
+    cx.export_function("reloadConfig", reload_config_node)?;
+    cx.export_function("prewarm", prewarm_node)?;
+    cx.export_function("dkimKeySightings", dkim_key_sightings_node)?;
+    cx.export_function("init", init_node)?;
+    cx.export_function("cancel", cancel_node)?;
+    cx.export_function("registerLogger", register_logger_node)?;
+    cx.export_function("registerAuditSink", register_audit_sink_node)?;
+    cx.export_function("auditRecordsDropped", audit_records_dropped_node)?;
+    cx.export_function("expectedPublicSignals", expected_public_signals_node)?;
+    cx.export_function("testFixture", test_fixture_node)?;
+    cx.export_function("shutdown", shutdown_node)?;
+    cx.export_function("reinit", reinit_node)?;
+    cx.export_function(
+        "poseidonConfigFingerprint",
+        poseidon_config_fingerprint_node,
+    )?;
     Ok(())
 }
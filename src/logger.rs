@@ -4,11 +4,17 @@ use file_rotate::{
     ContentLimit, FileRotate,
 };
 use lazy_static::lazy_static;
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use once_cell::sync::Lazy;
 use slog::{o, Drain};
 use slog_async;
 use slog_json;
 use slog_term;
+use std::cell::Cell;
 use std::env;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
 
 pub const JSON_LOGGER_KEY: &str = "JSON_LOGGER";
 
@@ -16,6 +22,142 @@ lazy_static! {
     pub static ref LOG: slog::Logger = init_logger();
 }
 
+/// Receives every record of `LOG` with its level, its module and its message followed by its
+/// ` key=value` pairs. An error falls back to writing the record to stderr.
+pub type LogCallback = Arc<dyn Fn(slog::Level, &str, &str) -> Result<(), String> + Send + Sync>;
+
+static LOG_CALLBACK: Lazy<RwLock<Option<LogCallback>>> = Lazy::new(|| RwLock::new(None));
+
+thread_local! {
+    /// Set while this thread runs the log callback, so that a callback that logs does not recurse.
+    static IN_LOG_CALLBACK: Cell<bool> = Cell::new(false);
+}
+
+/// Registers the callback receiving the records of `LOG` in addition to its drains, replacing
+/// any previous one; `None` unregisters it. The callback runs synchronously on the thread that
+/// logs, which may be a Tokio worker, and must not block.
+pub fn register_log_callback(callback: Option<LogCallback>) {
+    *LOG_CALLBACK.write().unwrap_or_else(|e| e.into_inner()) = callback;
+}
+
+struct KvFormatter(String);
+
+impl slog::Serializer for KvFormatter {
+    fn emit_arguments(&mut self, key: slog::Key, val: &std::fmt::Arguments) -> slog::Result {
+        let _ = write!(self.0, " {}={}", key, val);
+        Ok(())
+    }
+}
+
+/// Forwards records to the registered `LogCallback`, outside of the asynchronous drains so that
+/// the callback sees them in order and cannot block the drain thread.
+struct CallbackDrain;
+
+impl Drain for CallbackDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &slog::Record, _values: &slog::OwnedKVList) -> Result<(), slog::Never> {
+        let callback = match &*LOG_CALLBACK.read().unwrap_or_else(|e| e.into_inner()) {
+            Some(callback) => callback.clone(),
+            None => return Ok(()),
+        };
+        let mut message = KvFormatter(record.msg().to_string());
+        let _ = slog::KV::serialize(record.kv(), record, &mut message);
+        let level = record.level();
+        let target = record.module();
+        if IN_LOG_CALLBACK.with(Cell::get) {
+            eprintln!("{} {}: {}", level.as_short_str(), target, message.0);
+            return Ok(());
+        }
+        IN_LOG_CALLBACK.with(|in_callback| in_callback.set(true));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            callback(level, target, &message.0)
+        }));
+        IN_LOG_CALLBACK.with(|in_callback| in_callback.set(false));
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => eprintln!(
+                "{} {}: {} (log callback failed: {})",
+                level.as_short_str(),
+                target,
+                message.0,
+                e
+            ),
+            Err(_) => eprintln!(
+                "{} {}: {} (log callback panicked)",
+                level.as_short_str(),
+                target,
+                message.0
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// `registerLogger(callback?)` forwards every log record to `callback(level, target, message)`,
+/// where `level` is the slog level from 1 (critical) to 6 (trace). The records reach the callback
+/// on the JavaScript thread; one that throws is written to stderr. Without a callback, the
+/// registered one is removed.
+#[cfg(feature = "node")]
+pub fn register_logger_node(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let callback = match cx.argument_opt(0) {
+        Some(callback)
+            if !callback.is_a::<JsUndefined, _>(&mut cx)
+                && !callback.is_a::<JsNull, _>(&mut cx) =>
+        {
+            callback.downcast_or_throw::<JsFunction, _>(&mut cx)?
+        }
+        _ => {
+            register_log_callback(None);
+            return Ok(cx.undefined());
+        }
+    };
+    let callback = Arc::new(callback.root(&mut cx));
+    let mut channel = cx.channel();
+    // A logger must not keep the process alive.
+    channel.unref(&mut cx);
+    register_log_callback(Some(Arc::new(move |level, target, message| {
+        let callback = callback.clone();
+        let (target, message) = (target.to_string(), message.to_string());
+        channel
+            .try_send(move |mut cx| {
+                let level_value = cx.number(level.as_usize() as f64);
+                let target_value = cx.string(&target);
+                let message_value = cx.string(&message);
+                let callback = callback.to_inner(&mut cx);
+                let this = cx.undefined();
+                let args: Vec<Handle<JsValue>> = vec![
+                    level_value.upcast(),
+                    target_value.upcast(),
+                    message_value.upcast(),
+                ];
+                if cx.try_catch(|cx| callback.call(cx, this, args)).is_err() {
+                    eprintln!(
+                        "{} {}: {} (log callback threw)",
+                        level.as_short_str(),
+                        target,
+                        message
+                    );
+                }
+                Ok(())
+            })
+            .map_err(|e| e.to_string())
+    })));
+    Ok(cx.undefined())
+}
+
+/// Android apps cannot write to a relative `logs/` directory, so only the terminal drain is
+/// used there; it ends up in logcat through stdout redirection, if any.
+#[cfg(target_os = "android")]
+fn init_logger() -> slog::Logger {
+    let log_terminal_drain = slog_json::Json::default(std::io::stdout()).fuse();
+    let log_drain = slog_async::Async::new(log_terminal_drain).build().fuse();
+    let log_drain = slog::Duplicate(log_drain, CallbackDrain).fuse();
+    slog::Logger::root(log_drain, o!("version" => env!("CARGO_PKG_VERSION")))
+}
+
+#[cfg(not(target_os = "android"))]
 fn init_logger() -> slog::Logger {
     let directory = std::path::Path::new("logs");
     let log_path = directory.join("relayer.log");
@@ -46,6 +188,7 @@ fn init_logger() -> slog::Logger {
             slog_async::Async::new(slog::Duplicate(log_terminal_json_drain, log_file_drain).fuse())
                 .build()
                 .fuse();
+        let log_drain = slog::Duplicate(log_drain, CallbackDrain).fuse();
         return slog::Logger::root(log_drain, o!("version" => env!("CARGO_PKG_VERSION")));
     }
     let log_drain =
@@ -54,5 +197,44 @@ fn init_logger() -> slog::Logger {
             .overflow_strategy(slog_async::OverflowStrategy::Block) // Change overflow strategy to block
             .build()
             .fuse();
+    let log_drain = slog::Duplicate(log_drain, CallbackDrain).fuse();
     slog::Logger::root(log_drain, o!("version" => env!("CARGO_PKG_VERSION")))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_log_callback() {
+        let records = Arc::new(Mutex::new(vec![]));
+        let captured = records.clone();
+        register_log_callback(Some(Arc::new(move |level, target, message| {
+            if message.starts_with("log-callback-test") {
+                captured
+                    .lock()
+                    .unwrap()
+                    .push((level, target.to_string(), message.to_string()));
+                // Logging from the callback reaches stderr instead of recursing.
+                slog::warn!(LOG, "log-callback-test from the callback");
+            }
+            Err("the callback failed".to_string())
+        })));
+        std::thread::spawn(|| slog::warn!(LOG, "log-callback-test"; "code" => 7))
+            .join()
+            .unwrap();
+        register_log_callback(None);
+        slog::warn!(LOG, "log-callback-test after unregistering");
+
+        let records = records.lock().unwrap();
+        assert_eq!(
+            *records,
+            [(
+                slog::Level::Warning,
+                module_path!().to_string(),
+                "log-callback-test code=7".to_string()
+            )]
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use relayer_utils::conformance::{conformance_vectors_string, CONFORMANCE_VECTORS_PATH};
+
+const USAGE: &str = "usage: relayer-utils gen-vectors [output path]
+       relayer-utils replay <file>";
+
+/// Replays a bundle recorded with the `record_replay` option and prints its outcome.
+#[cfg(all(feature = "dns", feature = "async-runtime"))]
+fn replay(path: &str) -> anyhow::Result<()> {
+    let outcome = relayer_utils::shared_runtime()?
+        .block_on(relayer_utils::replay_from_file(std::path::Path::new(path)))?;
+    println!("reproduced {}", outcome);
+    Ok(())
+}
+
+#[cfg(not(all(feature = "dns", feature = "async-runtime")))]
+fn replay(_path: &str) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!(
+        "replay needs the dns and async-runtime features"
+    ))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let result = match (args.get(1).map(String::as_str), args.get(2)) {
+        (Some("gen-vectors"), path) => {
+            let path = path.map(String::as_str).unwrap_or(CONFORMANCE_VECTORS_PATH);
+            conformance_vectors_string()
+                .and_then(|vectors| fs::write(path, vectors).map_err(Into::into))
+        }
+        (Some("replay"), Some(path)) => replay(path),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::*;
+use anyhow::Result;
+use base64::{engine::general_purpose, Engine as _};
+#[cfg(feature = "node")]
+use neon::prelude::*;
+
+/// The decoded `b=` tag of the first DKIM-Signature of `raw_email`. Only the header fields are
+/// read, and the signature is not verified.
+pub fn dkim_signature_bytes(raw_email: &str) -> Result<Vec<u8>> {
+    let fields = crate::parse_email::raw_header_fields(raw_email);
+    let signature = crate::parse_email::dkim_signature_tag(&fields, "b").ok_or_else(|| {
+        coded(
+            ErrorCode::ParseEmailFailed,
+            "the email has no DKIM-Signature header with the b= tag",
+        )
+    })?;
+    let signature = signature
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    general_purpose::STANDARD.decode(signature).map_err(|e| {
+        coded(
+            ErrorCode::ParseEmailFailed,
+            format!("the b= tag of the DKIM-Signature is invalid base64: {}", e),
+        )
+    })
+}
+
+/// The email nullifier and the public key hash of `raw_email`, as hex, for endpoints that only
+/// look up whether an email was seen before. The values are those a full input generation emits
+/// as `email_nullifier` and `public_key_hash`, but only the DKIM-Signature is read: the header
+/// is not canonicalized, the body is not read and no index is extracted.
+///
+/// The signature is not verified, so the result identifies the email without proving anything
+/// about it. The DKIM key comes from the key cache or else DNS, as for `ParsedEmail`.
+#[cfg(feature = "dns")]
+pub async fn email_nullifier_fast(raw_email: &str) -> Result<(String, String)> {
+    let signature = dkim_signature_bytes(raw_email)?;
+    let nullifier = email_nullifier_from_signature(&signature).map_err(|e| {
+        coded(
+            ErrorCode::CryptoFailed,
+            format!("email_nullifier failed: {}", e),
+        )
+    })?;
+    let public_key_n = resolve_dkim_public_key(raw_email.as_bytes()).await?;
+    let public_key_hash = public_key_hash_from_modulus(&public_key_n).map_err(|e| {
+        coded(
+            ErrorCode::CryptoFailed,
+            format!("public_key_hash failed: {}", e),
+        )
+    })?;
+    Ok((field2hex(&nullifier), field2hex(&public_key_hash)))
+}
+
+/// `emailNullifierFast(email)` resolves to `{ emailNullifier, publicKeyHash }`; see
+/// `email_nullifier_fast`.
+#[cfg(feature = "node")]
+pub fn email_nullifier_fast_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let raw_email = cx.argument::<JsString>(0)?.value(&mut cx);
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(&mut cx)?;
+
+    rt.spawn(async move {
+        let result = email_nullifier_fast(&raw_email).await;
+        deferred.settle_with(&channel, move |mut cx| match result {
+            Ok((nullifier, public_key_hash)) => {
+                let obj = cx.empty_object();
+                let nullifier = cx.string(nullifier);
+                obj.set(&mut cx, "emailNullifier", nullifier)?;
+                let public_key_hash = cx.string(public_key_hash);
+                obj.set(&mut cx, "publicKeyHash", public_key_hash)?;
+                Ok(obj)
+            }
+            Err(err) => throw_coded(
+                &mut cx,
+                error_code(&err, ErrorCode::ParseEmailFailed),
+                format!("Could not compute the email nullifier: {}", err),
+            ),
+        });
+    });
+
+    Ok(promise)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dkim_signature_bytes() {
+        let fixture = test_fixture("send_eth").unwrap();
+        let signature = dkim_signature_bytes(&fixture.raw_email).unwrap();
+        assert_eq!(
+            field2hex(&email_nullifier_from_signature(&signature).unwrap()),
+            fixture.expected.email_nullifier
+        );
+
+        // A folded b= tag decodes to the same signature.
+        let encoded = general_purpose::STANDARD.encode(&signature);
+        let folded = fixture.raw_email.replace(
+            &encoded,
+            &format!("{}\r\n\t{}", &encoded[..40], &encoded[40..]),
+        );
+        assert_eq!(dkim_signature_bytes(&folded).unwrap(), signature);
+
+        for email in [
+            "From: alice@gmail.com\r\n\r\nbody\r\n",
+            "DKIM-Signature: v=1; d=gmail.com; s=s; b=not base64!\r\n\r\n",
+        ] {
+            let err = dkim_signature_bytes(email).unwrap_err();
+            assert_eq!(
+                error_code(&err, ErrorCode::CryptoFailed),
+                ErrorCode::ParseEmailFailed
+            );
+        }
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    async fn full_generation(fixture: &TestFixture) -> serde_json::Value {
+        let options = InputGenerationOptions {
+            include_nullifier: true,
+            include_public_key_hash: true,
+            ..Default::default()
+        };
+        let account_code = AccountCode(hex2field(&fixture.account_code).unwrap());
+        let input =
+            generate_email_auth_input_with_options(&fixture.raw_email, &account_code, &options)
+                .await
+                .unwrap();
+        serde_json::from_str(&input).unwrap()
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    async fn test_fast_path_matches_full_generation() {
+        for name in test_fixture_names() {
+            let fixture = test_fixture(name).unwrap();
+            cache_dkim_key_fetch(
+                DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+                fixture.dkim_key_fetch().unwrap(),
+            );
+            let (nullifier, public_key_hash) =
+                email_nullifier_fast(&fixture.raw_email).await.unwrap();
+            assert_eq!(nullifier, fixture.expected.email_nullifier, "{}", name);
+            assert_eq!(
+                public_key_hash, fixture.expected.public_key_hash,
+                "{}",
+                name
+            );
+            // No input is generated for a key the circuits cannot prove.
+            if fixture.public_exponent != DEFAULT_PUBLIC_EXPONENT {
+                continue;
+            }
+            let input = full_generation(&fixture).await;
+            assert_eq!(input["email_nullifier"], nullifier, "{}", name);
+            assert_eq!(input["public_key_hash"], public_key_hash, "{}", name);
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored bench_fast_path`.
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    #[ignore]
+    async fn bench_fast_path() {
+        use std::time::Instant;
+
+        const ROUNDS: u32 = 50;
+        let fixture = test_fixture("send_eth").unwrap();
+        cache_dkim_key(
+            DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+            decode_hex(&fixture.public_key).unwrap(),
+        );
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            email_nullifier_fast(&fixture.raw_email).await.unwrap();
+        }
+        let fast = start.elapsed();
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            full_generation(&fixture).await;
+        }
+        let full = start.elapsed();
+        assert!(
+            fast * 5 <= full,
+            "fast path {:?}, full generation {:?}",
+            fast,
+            full
+        );
+    }
+}
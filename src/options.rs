@@ -0,0 +1,420 @@
+use crate::profile::active_circuit_profile;
+use crate::circuit_variant::CircuitVariant;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::PathBuf;
+
+/// Options for input generation, given as a JSON object. Unset fields keep their defaults, which
+/// match the behavior of the entry points that take no options.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputGenerationOptions {
+    pub max_header_length: usize,
+    pub max_body_length: usize,
+    pub ignore_body_hash: bool,
+    pub sha_precompute_selector: Option<String>,
+    /// Directory receiving the debug artifacts of failed generations (see `dump_debug_artifacts`).
+    pub debug_dump_dir: Option<PathBuf>,
+    /// Also dump the artifacts of successful generations.
+    pub debug_dump_always: bool,
+    /// File receiving a replay bundle of the generation, whatever its outcome (see
+    /// `record_replay`).
+    pub record_replay: Option<PathBuf>,
+    /// Replace the body by a placeholder in the replay bundle. A replay of an input that checks
+    /// the body hash then fails on the body hash.
+    pub record_replay_redact_body: bool,
+    /// Remove POP3 framing from the raw email before parsing (see `strip_protocol_artifacts`).
+    pub strip_protocol_artifacts: bool,
+    /// Add the DKIM-Signature header, `bh=` and `b=` indexes to the email auth input.
+    pub include_dkim_signature_idxes: bool,
+    /// Add the email nullifier, computed from the already parsed signature.
+    pub include_nullifier: bool,
+    /// Add the public key hash, computed from the already parsed public key.
+    pub include_public_key_hash: bool,
+    /// Add the public signals the circuit outputs for the input (see `expected_public_signals`).
+    pub include_public_signals: bool,
+    /// Caller-supplied id recorded on the tracing spans and echoed in the email auth input.
+    pub correlation_id: Option<String>,
+    /// Values the email must match; generation fails on the first mismatch.
+    pub expected: Option<ExpectedValues>,
+    /// Reject raw emails with a From header the verified DKIM-Signature does not cover
+    /// (`check_from_header_coverage`), or whose canonicalized header does not hash the instances
+    /// of over-signed headers that RFC 6376 selects (`check_signed_header_selection`).
+    pub reject_unsigned_from: bool,
+    /// Fail when the generated input breaks an invariant (`check_header_digest_invariant`).
+    /// Debug builds check the invariants regardless, but only warn.
+    pub strict: bool,
+    /// Where the domain that keys the account is read from.
+    pub domain_source: DomainSource,
+    /// Match the command template and find the subject's email address and invitation code in
+    /// the decoded form of an RFC 2047 Q-encoded subject, mapped back to the signed bytes (see
+    /// `map_encoded_subject`). A B-encoded subject then fails.
+    pub decode_encoded_subject: bool,
+    /// Email auth circuit the input is for; generation fails, listing them, when the input
+    /// misses the signals it requires (see `CircuitVariant::validate`).
+    pub circuit_variant: Option<CircuitVariant>,
+    /// Fail with `ErrorCode::DnssecFailed` unless the DKIM key record is DNSSEC-validated as
+    /// secure (see `resolve_dkim_key_fetch`). Needs the `dnssec` feature.
+    pub require_dnssec: bool,
+    /// Fail with `ErrorCode::ParseEmailFailed` when the DKIM-Signature has no decimal `t=`
+    /// timestamp, instead of emitting `timestamp_idx` 0 with a warning.
+    pub require_timestamp: bool,
+}
+
+/// Source of the domain emitted as `domain_idx` and checked against `expected.from_domain`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DomainSource {
+    /// The domain of the from address, with `domain_idx` relative to the from address.
+    #[default]
+    FromHeader,
+    /// The `d=` tag of the DKIM-Signature, with `domain_idx` relative to the DKIM-Signature
+    /// header, for proxies that rewrite From but sign with the sender's domain. The input then
+    /// also carries `dkim_signature_idx` and `signing_domain`. Only meant for allowlisted
+    /// proxies: anyone can sign with a domain they own.
+    DkimDomain,
+}
+
+/// Expected values checked by `check_expected_values`. Unset values are not checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExpectedValues {
+    /// Domain of the from address, compared case-insensitively.
+    pub from_domain: Option<String>,
+    /// Template the subject must match (see `matches_command_template`).
+    pub command_template: Option<String>,
+    /// Account salt of the from address and the account code, as a hex field element.
+    pub account_salt: Option<String>,
+    /// `public_key_hash` of the DKIM key, as a hex field element. A mismatch after the key of
+    /// the email's (domain, selector) was seen to change carries a rotation hint.
+    pub public_key_hash: Option<String>,
+}
+
+impl Default for InputGenerationOptions {
+    fn default() -> Self {
+        InputGenerationOptions {
+            max_header_length: active_circuit_profile().max_header_bytes,
+            max_body_length: 64,
+            ignore_body_hash: true,
+            sha_precompute_selector: None,
+            debug_dump_dir: None,
+            debug_dump_always: false,
+            record_replay: None,
+            record_replay_redact_body: false,
+            strip_protocol_artifacts: false,
+            include_dkim_signature_idxes: false,
+            include_nullifier: false,
+            include_public_key_hash: false,
+            include_public_signals: false,
+            correlation_id: None,
+            expected: None,
+            reject_unsigned_from: true,
+            strict: false,
+            domain_source: DomainSource::FromHeader,
+            decode_encoded_subject: false,
+            circuit_variant: None,
+            require_dnssec: false,
+            require_timestamp: false,
+        }
+    }
+}
+
+struct OptionField {
+    name: &'static str,
+    expected: &'static str,
+    accepts: fn(&Value) -> bool,
+}
+
+const OPTION_FIELDS: &[OptionField] = &[
+    OptionField {
+        name: "max_header_length",
+        expected: "an unsigned integer",
+        accepts: Value::is_u64,
+    },
+    OptionField {
+        name: "max_body_length",
+        expected: "an unsigned integer",
+        accepts: Value::is_u64,
+    },
+    OptionField {
+        name: "ignore_body_hash",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+    OptionField {
+        name: "sha_precompute_selector",
+        expected: "a string or null",
+        accepts: is_string_or_null,
+    },
+    OptionField {
+        name: "debug_dump_dir",
+        expected: "a string or null",
+        accepts: is_string_or_null,
+    },
+    OptionField {
+        name: "debug_dump_always",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+    OptionField {
+        name: "record_replay",
+        expected: "a string or null",
+        accepts: is_string_or_null,
+    },
+    OptionField {
+        name: "record_replay_redact_body",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+    OptionField {
+        name: "strip_protocol_artifacts",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+    OptionField {
+        name: "include_dkim_signature_idxes",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+    OptionField {
+        name: "include_nullifier",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+    OptionField {
+        name: "include_public_key_hash",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+    OptionField {
+        name: "include_public_signals",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+    OptionField {
+        name: "correlation_id",
+        expected: "a string or null",
+        accepts: is_string_or_null,
+    },
+    OptionField {
+        name: "expected",
+        expected: "an object with from_domain, command_template and account_salt, or null",
+        accepts: is_object_or_null,
+    },
+    OptionField {
+        name: "reject_unsigned_from",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+    OptionField {
+        name: "strict",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+    OptionField {
+        name: "domain_source",
+        expected: "\"from_header\" or \"dkim_domain\"",
+        accepts: is_domain_source,
+    },
+    OptionField {
+        name: "decode_encoded_subject",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+    OptionField {
+        name: "circuit_variant",
+        expected: "\"email_auth\", \"email_auth_no_body\", \"recovery\" or null",
+        accepts: is_circuit_variant_or_null,
+    },
+    OptionField {
+        name: "require_dnssec",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+    OptionField {
+        name: "require_timestamp",
+        expected: "a boolean",
+        accepts: Value::is_boolean,
+    },
+];
+
+impl InputGenerationOptions {
+    /// Parses the options JSON. Unknown fields are reported with the nearest valid field name,
+    /// and values of the wrong type are reported with the field name and the expected type.
+    pub fn from_json(options_json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(options_json)
+            .map_err(|e| anyhow!("the options are not valid JSON: {}", e))?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| anyhow!("the options must be a JSON object"))?;
+        for (key, value) in object {
+            let field = match OPTION_FIELDS.iter().find(|field| field.name == key.as_str()) {
+                Some(field) => field,
+                None => {
+                    let nearest = OPTION_FIELDS
+                        .iter()
+                        .min_by_key(|field| edit_distance(key, field.name))
+                        .filter(|field| edit_distance(key, field.name) <= 3);
+                    return Err(match nearest {
+                        Some(field) => {
+                            anyhow!("unknown option `{}`, did you mean `{}`?", key, field.name)
+                        }
+                        None => anyhow!(
+                            "unknown option `{}`, expected one of: {}",
+                            key,
+                            option_field_names().join(", ")
+                        ),
+                    });
+                }
+            };
+            if !(field.accepts)(value) {
+                return Err(anyhow!(
+                    "option `{}` must be {} but is {}",
+                    field.name,
+                    field.expected,
+                    value
+                ));
+            }
+        }
+        let options: Self = serde_json::from_value(value)?;
+        options.validate()?;
+        Ok(options)
+    }
+
+    /// Fails, naming the option, on padding sizes the SHA-256 padding cannot fill and on a
+    /// precompute selector without a body to precompute.
+    pub fn validate(&self) -> Result<()> {
+        for (name, len) in [
+            ("max_header_length", self.max_header_length),
+            ("max_body_length", self.max_body_length),
+        ] {
+            if len == 0 || len % 64 != 0 {
+                return Err(anyhow!(
+                    "option `{}` must be a positive multiple of 64 but is {}",
+                    name,
+                    len
+                ));
+            }
+        }
+        if self.ignore_body_hash && self.sha_precompute_selector.is_some() {
+            return Err(anyhow!(
+                "option `sha_precompute_selector` needs `ignore_body_hash` to be false"
+            ));
+        }
+        if let Some(variant) = self.circuit_variant.filter(|variant| !variant.is_email_auth()) {
+            return Err(anyhow!(
+                "option `circuit_variant` must be an email auth circuit but is {}",
+                variant.name()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Describes every accepted field with its expected type and default value.
+    pub fn schema() -> Value {
+        let defaults = serde_json::to_value(Self::default()).unwrap();
+        let mut schema = Map::new();
+        for field in OPTION_FIELDS {
+            let mut entry = Map::new();
+            entry.insert("type".to_string(), Value::from(field.expected));
+            entry.insert("default".to_string(), defaults[field.name].clone());
+            schema.insert(field.name.to_string(), Value::Object(entry));
+        }
+        Value::Object(schema)
+    }
+}
+
+fn is_string_or_null(value: &Value) -> bool {
+    value.is_string() || value.is_null()
+}
+
+fn is_object_or_null(value: &Value) -> bool {
+    value.is_object() || value.is_null()
+}
+
+fn is_domain_source(value: &Value) -> bool {
+    serde_json::from_value::<DomainSource>(value.clone()).is_ok()
+}
+
+fn is_circuit_variant_or_null(value: &Value) -> bool {
+    value.is_null()
+        || serde_json::from_value::<CircuitVariant>(value.clone())
+            .map_or(false, |variant| variant.is_email_auth())
+}
+
+fn option_field_names() -> Vec<&'static str> {
+    OPTION_FIELDS.iter().map(|field| field.name).collect()
+}
+
+/// Levenshtein distance, used to suggest the intended field for a typo.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Reads an optional options JSON argument, falling back to the defaults when it is absent.
+#[cfg(feature = "node")]
+pub fn options_argument(cx: &mut FunctionContext, i: i32) -> NeonResult<InputGenerationOptions> {
+    let options = match cx.argument_opt(i) {
+        Some(options) if !options.is_a::<JsUndefined, _>(cx) => options,
+        _ => return Ok(InputGenerationOptions::default()),
+    };
+    let options = options.downcast_or_throw::<JsString, _>(cx)?.value(cx);
+    match InputGenerationOptions::from_json(&options) {
+        Ok(options) => Ok(options),
+        Err(e) => cx.throw_error(e.to_string()),
+    }
+}
+
+#[cfg(feature = "node")]
+pub fn input_generation_options_schema_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let schema = InputGenerationOptions::schema().to_string();
+    Ok(cx.string(schema))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options_from_json() {
+        let options =
+            InputGenerationOptions::from_json(r#"{"max_header_length": 2048}"#).unwrap();
+        assert_eq!(options.max_header_length, 2048);
+        assert_eq!(options.max_body_length, 64);
+
+        let err = InputGenerationOptions::from_json(r#"{"max_header_lenght": 2048}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("did you mean `max_header_length`"), "{}", err);
+
+        let err = InputGenerationOptions::from_json(r#"{"ignore_body_hash": "yes"}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`ignore_body_hash` must be a boolean"), "{}", err);
+
+        let err = InputGenerationOptions::from_json(r#"{"max_body_length": 100}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`max_body_length` must be a positive multiple of 64"), "{}", err);
+        assert!(
+            InputGenerationOptions::from_json(r#"{"sha_precompute_selector": "Transfer"}"#).is_err()
+        );
+        InputGenerationOptions::from_json(
+            r#"{"ignore_body_hash": false, "sha_precompute_selector": "Transfer"}"#,
+        )
+        .unwrap();
+    }
+}
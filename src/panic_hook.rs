@@ -0,0 +1,142 @@
+use crate::*;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Once;
+use std::task::{Context, Poll};
+
+/// What the panic hook saw of the last panic of a thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicReport {
+    pub message: String,
+    /// `file:line:column` of the `panic!`, when known.
+    pub location: Option<String>,
+    pub backtrace: String,
+}
+
+impl std::fmt::Display for PanicReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(location) = &self.location {
+            write!(f, " at {}", location)?;
+        }
+        write!(f, "\nbacktrace:\n{}", self.backtrace)
+    }
+}
+
+thread_local! {
+    static LAST_PANIC: RefCell<Option<PanicReport>> = RefCell::new(None);
+}
+
+static INSTALL_PANIC_HOOK: Once = Once::new();
+
+/// Installs, once per process, a panic hook that records the message, location and backtrace of
+/// each panic for `panic_error`, then runs the previous hook.
+pub fn install_panic_hook() {
+    INSTALL_PANIC_HOOK.call_once(|| {
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let report = PanicReport {
+                message: panic_message(info.payload()),
+                location: info.location().map(|location| location.to_string()),
+                backtrace: Backtrace::force_capture().to_string(),
+            };
+            LAST_PANIC.with(|last_panic| *last_panic.borrow_mut() = Some(report));
+            previous_hook(info);
+        }));
+    });
+}
+
+/// The report of the last panic of the current thread, if its message is the payload's.
+pub fn take_panic_report(payload: &(dyn std::any::Any + Send)) -> Option<PanicReport> {
+    LAST_PANIC
+        .with(|last_panic| last_panic.borrow_mut().take())
+        .filter(|report| report.message == panic_message(payload))
+}
+
+/// An `InternalPanic` error of a panic caught on the thread that panicked, with the location and
+/// backtrace when the hook recorded them.
+pub fn panic_error(context: &str, payload: &(dyn std::any::Any + Send)) -> anyhow::Error {
+    let message = match take_panic_report(payload) {
+        Some(report) => report.to_string(),
+        None => panic_message(payload),
+    };
+    coded(
+        ErrorCode::InternalPanic,
+        format!("{} panicked: {}", context, message),
+    )
+}
+
+/// A future that turns a panic of `future` into a `panic_error`. The panic is caught in the poll
+/// that raised it, on the thread whose report the hook recorded.
+pub struct CatchPanic<F> {
+    future: Pin<Box<F>>,
+    context: &'static str,
+}
+
+pub fn catch_panic<F: Future>(context: &'static str, future: F) -> CatchPanic<F> {
+    install_panic_hook();
+    CatchPanic {
+        future: Box::pin(future),
+        context,
+    }
+}
+
+impl<F: Future> Future for CatchPanic<F> {
+    type Output = anyhow::Result<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let context = self.context;
+        let future = self.future.as_mut();
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(panic_error(context, &*payload))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_error_carries_the_location() {
+        install_panic_hook();
+        let payload = panic::catch_unwind(|| panic!("deliberate panic {}", 1)).unwrap_err();
+        let err = panic_error("the test", &*payload);
+        assert_eq!(
+            error_code(&err, ErrorCode::CircuitInputFailed),
+            ErrorCode::InternalPanic
+        );
+        let err = err.to_string();
+        assert!(
+            err.starts_with("the test panicked: deliberate panic 1 at src/panic_hook.rs:"),
+            "{}",
+            err
+        );
+        assert!(err.contains("backtrace:"), "{}", err);
+        // The report is taken once.
+        assert_eq!(
+            panic_error("the test", &*payload).to_string(),
+            "the test panicked: deliberate panic 1"
+        );
+    }
+
+    #[cfg(feature = "async-runtime")]
+    #[tokio::test]
+    async fn test_catch_panic() {
+        assert_eq!(catch_panic("the future", async { 1 }).await.unwrap(), 1);
+        let err = catch_panic("the future", async { panic!("deliberate panic") })
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("deliberate panic at src/panic_hook.rs:"),
+            "{}",
+            err
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 
 use itertools::Itertools;
@@ -7,14 +9,31 @@ use itertools::Itertools;
 // use mail_auth::trust_dns_resolver::proto::rr::dnssec::public_key;
 // use trust_dns_resolver::error::ResolveError;
 // use mail_auth::Error;
+#[cfg(all(feature = "dns", feature = "body"))]
+use crate::body::{body_part_sizes, trailing_content};
+#[cfg(feature = "body")]
+use crate::body::{BodyPartSize, TrailingContent};
+use crate::converters::check_invisible_chars;
+use crate::cryptos::DEFAULT_PUBLIC_EXPONENT;
+use crate::error_code::*;
+use crate::logger::LOG;
 use crate::statics::*;
 use anyhow::Result;
 use hex;
 // use mail_auth::{AuthenticatedMessage, DkimOutput, DkimResult, Resolver};
 
+#[cfg(feature = "dns")]
+use crate::dkim_cache::{cached_dkim_key_fetch, DkimKeyFetch, DkimKeyId};
+#[cfg(feature = "dnssec")]
+use crate::dnssec::fetch_dkim_key_dnssec;
+#[cfg(feature = "dns")]
+use crate::hot_config::hot_config;
+#[cfg(feature = "dns")]
 use cfdkim::{canonicalize_signed_email, resolve_public_key};
+#[cfg(feature = "node")]
 use neon::prelude::*;
-use rsa::traits::PublicKeyParts;
+#[cfg(feature = "dns")]
+use tracing::Instrument;
 
 use serde::{Deserialize, Serialize};
 use zk_regex_apis::extract_substrs::*;
@@ -22,35 +41,1151 @@ use zk_regex_apis::extract_substrs::*;
 // use trust_dns_resolver::proto::rr::{RData, RecordType};
 // use trust_dns_resolver::AsyncResolver;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Index ranges that the input generators compute from a canonicalized header.
+///
+/// `domain` is relative to the from address and `email_addr_in_subject` is relative to the
+/// subject, as with the corresponding `ParsedEmail` getters. All other ranges are relative to the
+/// header. A field is `None` when its part is not present.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeaderIndexes {
+    pub from_addr: Option<(usize, usize)>,
+    pub domain: Option<(usize, usize)>,
+    pub subject: Option<(usize, usize)>,
+    pub email_addr_in_subject: Option<(usize, usize)>,
+    pub timestamp: Option<(usize, usize)>,
+    pub invitation_code: Option<(usize, usize)>,
+}
+
+/// Computes every header index without DKIM verification or DNS, so that arbitrary synthetic
+/// headers can be fed in. The input generators use this as their single source of indexes.
+pub fn extract_indexes(canonical_header: &[u8]) -> Result<HeaderIndexes> {
+    let header = std::str::from_utf8(canonical_header)?;
+    let from_addr = from_addr_idxes(header);
+    let domain = from_addr
+        .and_then(|(start, end)| first_idxes(extract_email_domain_idxes(&header[start..end])));
+    let subject = subject_idxes(header);
+    if has_empty_subject(header) {
+        slog::warn!(LOG, "the subject is empty, treating it as absent"; "code" => EMPTY_SUBJECT);
+    }
+    let email_addr_in_subject = subject
+        .and_then(|(start, end)| first_idxes(extract_email_addr_idxes(&header[start..end])));
+    let timestamp = first_idxes(extract_timestamp_idxes(header))
+        .filter(|&(start, end)| header[start..end].parse::<u64>().is_ok());
+    let regex_config =
+        serde_json::from_str(include_str!("../regexes/invitation_code.json")).unwrap();
+    let invitation_code = first_idxes(extract_substr_idxes(header, &regex_config));
+
+    Ok(HeaderIndexes {
+        from_addr,
+        domain,
+        subject,
+        email_addr_in_subject,
+        timestamp,
+        invitation_code,
+    })
+}
+
+/// Every index input generation computes for a canonicalized header, for monitoring and
+/// debugging. `domain` and `email_addr_in_subject` are relative, as in `HeaderIndexes`, and are
+/// repeated relative to the header; the subtraction of the relative indexes is checked here, so a
+/// part that is not found is `None` rather than a wrapped or zero index.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexReport {
+    pub canonicalized_header_len: usize,
+    pub from_addr: Option<(usize, usize)>,
+    pub domain: Option<(usize, usize)>,
+    pub domain_in_header: Option<(usize, usize)>,
+    pub subject: Option<(usize, usize)>,
+    pub email_addr_in_subject: Option<(usize, usize)>,
+    pub email_addr_in_subject_in_header: Option<(usize, usize)>,
+    pub timestamp: Option<(usize, usize)>,
+    pub invitation_code: Option<(usize, usize)>,
+    pub dkim_signature: Option<DkimSignatureIdxes>,
+}
+
+impl IndexReport {
+    pub fn new(canonical_header: &str) -> Result<Self> {
+        let indexes = extract_indexes(canonical_header.as_bytes())?;
+        let offset = |base: Option<(usize, usize)>, idxes: Option<(usize, usize)>| {
+            base.zip(idxes)
+                .map(|((base, _), (start, end))| (base + start, base + end))
+        };
+        Ok(IndexReport {
+            canonicalized_header_len: canonical_header.len(),
+            from_addr: indexes.from_addr,
+            domain: indexes.domain,
+            domain_in_header: offset(indexes.from_addr, indexes.domain),
+            subject: indexes.subject,
+            email_addr_in_subject: indexes.email_addr_in_subject,
+            email_addr_in_subject_in_header: offset(
+                indexes.subject,
+                indexes.email_addr_in_subject,
+            ),
+            timestamp: indexes.timestamp,
+            invitation_code: indexes.invitation_code,
+            dkim_signature: extract_dkim_signature_idxes(canonical_header).ok(),
+        })
+    }
+}
+
+/// The `IndexReport` of a raw email, without DKIM verification, DNS or circuit inputs.
+#[cfg(feature = "dns")]
+pub fn extract_email_indexes(raw_email: &str) -> Result<IndexReport> {
+    IndexReport::new(&ParsedEmail::parse_untrusted(raw_email)?.canonicalized_header)
+}
+
+/// Range of the value of the first `name:` header. The name is matched case-insensitively at the
+/// start of the header or right after a CRLF only, and the value ends before the next CRLF.
+pub fn header_value_idxes(header: &str, name: &str) -> Option<(usize, usize)> {
+    let mut line_start = 0;
+    loop {
+        let line_end = header[line_start..]
+            .find("\r\n")
+            .map_or(header.len(), |pos| line_start + pos);
+        let line = &header[line_start..line_end];
+        if line.as_bytes().get(name.len()) == Some(&b':')
+            && line[..name.len()].eq_ignore_ascii_case(name)
+        {
+            return Some((line_start + name.len() + 1, line_end));
+        }
+        if line_end == header.len() {
+            return None;
+        }
+        line_start = line_end + 2;
+    }
+}
+
+/// First of `idxes` inside the value of the `name:` header, ignoring matches of `name:`-looking
+/// text inside other header values.
+fn first_idxes_in_header<E>(
+    header: &str,
+    name: &str,
+    idxes: std::result::Result<Vec<(usize, usize)>, E>,
+) -> Option<(usize, usize)> {
+    let (value_start, value_end) = header_value_idxes(header, name)?;
+    idxes
+        .ok()?
+        .into_iter()
+        .find(|&(start, end)| start >= value_start && end <= value_end)
+}
+
+fn from_addr_idxes(header: &str) -> Option<(usize, usize)> {
+    first_idxes_in_header(header, "from", extract_from_addr_idxes(header))
+}
+
+fn from_addr_idxes_or_err(header: &str) -> Result<(usize, usize)> {
+    from_addr_idxes(header).ok_or_else(|| missing_from_addr_error(header))
+}
+
+/// Error for a header without a from address. The address regex stops at an invisible
+/// character, so an address with one is not found at all; the error then names the character.
+pub(crate) fn missing_from_addr_error(header: &str) -> anyhow::Error {
+    header_value_idxes(header, "from")
+        .and_then(|(start, end)| check_invisible_chars(&header[start..end], "From header").err())
+        .unwrap_or_else(|| anyhow::anyhow!("from address is not found in the header"))
+}
+
+/// Fails when a word of `subject` with an `@` has an invisible character. The address regex stops
+/// at the character, so such an address would otherwise be left out of the input silently.
+pub(crate) fn check_subject_email_addrs(subject: &str) -> Result<()> {
+    subject
+        .split(|c: char| c.is_ascii_whitespace())
+        .filter(|word| word.contains('@'))
+        .try_for_each(|word| check_invisible_chars(word, "email address in the subject"))
+}
+
+/// The sender of an email. `from_addr_idx` is the offset of the from address in the
+/// canonicalized header and `domain_idx` the offset of the domain in the from address, as in the
+/// circuit inputs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmailSender {
+    pub from_addr: String,
+    pub domain: String,
+    pub from_addr_idx: usize,
+    pub domain_idx: usize,
+}
+
+/// The sender of a canonicalized header. Fails when the header has no From address.
+pub fn extract_sender(canonical_header: &str) -> Result<EmailSender> {
+    let (start, end) = from_addr_idxes_or_err(canonical_header)?;
+    let from_addr = &canonical_header[start..end];
+    let (domain_start, domain_end) = *extract_email_domain_idxes(from_addr)?
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("the from address {} has no domain", from_addr))?;
+    Ok(EmailSender {
+        from_addr: from_addr.to_string(),
+        domain: from_addr[domain_start..domain_end].to_string(),
+        from_addr_idx: start,
+        domain_idx: domain_start,
+    })
+}
+
+/// Locates the subject value. It must start right after the `subject:` that begins a header, so
+/// `subject:` inside another header or inside the subject itself is never matched. A subject
+/// ending the header without the final CRLF (as in a caller-supplied canonical header) is
+/// accepted as if the CRLF were present, and the range never includes the line terminator, so
+/// the offsets relative to the subject do not depend on where the Subject header is.
+///
+/// An empty subject is treated as absent (see `has_empty_subject`).
+fn subject_idxes(header: &str) -> Option<(usize, usize)> {
+    let (value_start, value_end) = header_value_idxes(header, "subject")?;
+    if header[value_start..value_end].trim().is_empty() {
+        return None;
+    }
+    if value_end == header.len() && header[..value_start].ends_with("subject:") {
+        // The regex needs the CRLF ending the line, which this last line lacks. Its end was
+        // found by the byte search of `header_value_idxes`, so no copy of the header with a CRLF
+        // appended is matched, which would be as long as the header.
+        return Some((value_start, value_end));
+    }
+    extract_subject_all_idxes(header)
+        .ok()?
+        .into_iter()
+        .map(|(start, end)| {
+            let value = &header[start..end.min(header.len())];
+            let value = value.trim_end_matches(|c| c == '\r' || c == '\n');
+            (start, start + value.len())
+        })
+        .find(|&(start, end)| start == value_start && end <= value_end)
+}
+
+/// Code of the warning logged, and prefix of the error returned where a subject is required,
+/// when the Subject header is empty.
+pub const EMPTY_SUBJECT: &str = "EMPTY_SUBJECT";
+
+/// Whether the first Subject header has no value or only whitespace. Relaxed canonicalization
+/// reduces `Subject: ` to `subject:`, and simple canonicalization keeps the space; both are
+/// empty.
+pub fn has_empty_subject(header: &str) -> bool {
+    header_value_idxes(header, "subject")
+        .map_or(false, |(start, end)| header[start..end].trim().is_empty())
+}
+
+/// Error for a header without a usable subject.
+pub(crate) fn missing_subject_error(header: &str) -> anyhow::Error {
+    if has_empty_subject(header) {
+        anyhow::anyhow!("{}: the subject is empty", EMPTY_SUBJECT)
+    } else {
+        anyhow::anyhow!("subject is not found in the header")
+    }
+}
+
+fn subject_idxes_or_err(header: &str) -> Result<(usize, usize)> {
+    subject_idxes(header).ok_or_else(|| missing_subject_error(header))
+}
+
+/// The subject of an email. `subject_idx` and `subject_len` locate `subject` in the
+/// canonicalized header, as input generation does; a folded subject is unfolded by relaxed
+/// canonicalization. `decoded_subject` is set when the subject has RFC 2047 encoded words.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmailSubject {
+    pub subject: String,
+    pub subject_idx: usize,
+    pub subject_len: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded_subject: Option<String>,
+}
+
+/// The subject of a canonicalized header. Fails when the header has no subject, or an empty one.
+pub fn extract_subject(canonical_header: &str) -> Result<EmailSubject> {
+    let (start, end) = subject_idxes_or_err(canonical_header)?;
+    let subject = &canonical_header[start..end];
+    Ok(EmailSubject {
+        subject: subject.to_string(),
+        subject_idx: start,
+        subject_len: end - start,
+        decoded_subject: decode_encoded_words(subject),
+    })
+}
+
+/// Decodes one encoded word `=?charset?encoding?text?=` without the delimiters.
+fn decode_encoded_word(word: &str) -> Option<String> {
+    let mut parts = word.splitn(3, '?');
+    let charset = parts.next()?;
+    let encoding = parts.next()?;
+    let text = parts.next()?;
+    let bytes = match encoding {
+        "Q" | "q" => {
+            let mut bytes = vec![];
+            let mut rest = text.as_bytes();
+            while let Some((&byte, tail)) = rest.split_first() {
+                match byte {
+                    b'_' => bytes.push(b' '),
+                    b'=' => {
+                        let hex = tail.get(..2)?;
+                        bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+                        rest = &tail[2..];
+                        continue;
+                    }
+                    byte => bytes.push(byte),
+                }
+                rest = tail;
+            }
+            bytes
+        }
+        "B" | "b" => {
+            use base64::{engine::general_purpose, Engine as _};
+            general_purpose::STANDARD.decode(text).ok()?
+        }
+        _ => return None,
+    };
+    // RFC 2231 allows a language after the charset.
+    let charset = charset.split('*').next()?.to_ascii_lowercase();
+    match charset.as_str() {
+        "iso-8859-1" | "latin1" => Some(bytes.iter().map(|&byte| byte as char).collect()),
+        _ => Some(String::from_utf8_lossy(&bytes).into_owned()),
+    }
+}
+
+/// Prefix of the error for an RFC 2047 B-encoded subject in the encoded-subject mode
+/// (`InputGenerationOptions::decode_encoded_subject`). Base64 packs bits across bytes, so a
+/// decoded range has no raw range to constrain.
+pub const BASE64_SUBJECT_UNSUPPORTED: &str = "base64-encoded subject unsupported";
+
+/// A subject with RFC 2047 Q-encoded words, decoded, with the range in the raw subject of every
+/// decoded byte. The circuit constrains the raw bytes, so ranges found in `decoded` are located
+/// with `raw_range`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EncodedSubject {
+    pub decoded: String,
+    #[serde(skip)]
+    raw_ranges: Vec<(usize, usize)>,
+}
+
+impl EncodedSubject {
+    /// Decodes the encoded words of `subject` as `decode_encoded_words` does, or returns `None`
+    /// when it has none. Fails with `BASE64_SUBJECT_UNSUPPORTED` for a B-encoded word, and for
+    /// a charset other than UTF-8 and US-ASCII, whose characters are not their bytes.
+    pub fn decode(subject: &str) -> Result<Option<Self>> {
+        let invalid = |message: String| coded(ErrorCode::ParseEmailFailed, message);
+        let bytes = subject.as_bytes();
+        let mut decoded = vec![];
+        let mut raw_ranges = vec![];
+        let mut pos = 0;
+        let mut after_word = false;
+        while let Some(start) = subject[pos..].find("=?").map(|start| pos + start) {
+            let word_end = subject[start + 2..]
+                .match_indices("?=")
+                .map(|(end, _)| start + 2 + end)
+                .find(|&end| subject[start + 2..end].matches('?').count() >= 2);
+            let end = match word_end {
+                Some(end) => end,
+                None => break,
+            };
+            if !(after_word && subject[pos..start].trim().is_empty()) {
+                decoded.extend_from_slice(&bytes[pos..start]);
+                raw_ranges.extend((pos..start).map(|i| (i, i + 1)));
+            }
+            let mut parts = subject[start + 2..end].splitn(3, '?');
+            let (charset, encoding) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+            let text_start = start + 2 + charset.len() + encoding.len() + 2;
+            if encoding.eq_ignore_ascii_case("b") {
+                return Err(invalid(format!(
+                    "{}: the subject has the B-encoded word {}",
+                    BASE64_SUBJECT_UNSUPPORTED,
+                    &subject[start..end + 2]
+                )));
+            }
+            if !encoding.eq_ignore_ascii_case("q") {
+                return Err(invalid(format!("unknown RFC 2047 encoding `{}`", encoding)));
+            }
+            let charset = charset.split('*').next().unwrap_or("").to_ascii_lowercase();
+            if !["utf-8", "us-ascii"].contains(&charset.as_str()) {
+                return Err(invalid(format!("the subject charset {} is unsupported", charset)));
+            }
+            let mut i = text_start;
+            while i < end {
+                match bytes[i] {
+                    b'_' => decoded.push(b' '),
+                    b'=' => {
+                        let byte = subject
+                            .get(i + 1..i + 3)
+                            .filter(|_| i + 3 <= end)
+                            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                            .ok_or_else(|| {
+                                invalid(format!("invalid Q-encoding at subject byte {}", i))
+                            })?;
+                        decoded.push(byte);
+                        raw_ranges.push((i, i + 3));
+                        i += 3;
+                        continue;
+                    }
+                    byte => decoded.push(byte),
+                }
+                raw_ranges.push((i, i + 1));
+                i += 1;
+            }
+            after_word = true;
+            pos = end + 2;
+        }
+        if !after_word {
+            return Ok(None);
+        }
+        decoded.extend_from_slice(&bytes[pos..]);
+        raw_ranges.extend((pos..bytes.len()).map(|i| (i, i + 1)));
+        let decoded = String::from_utf8(decoded)
+            .map_err(|e| invalid(format!("the decoded subject is not UTF-8: {}", e)))?;
+        Ok(Some(EncodedSubject {
+            decoded,
+            raw_ranges,
+        }))
+    }
+
+    /// Range in the raw subject of the non-empty range `start..end` of `decoded`.
+    pub fn raw_range(&self, (start, end): (usize, usize)) -> (usize, usize) {
+        (self.raw_ranges[start].0, self.raw_ranges[end - 1].1)
+    }
+}
+
+/// `indexes` of `canonical_header` with the email address and the invitation code of an RFC 2047
+/// encoded subject searched in its decoded form and mapped back to the raw bytes, and the
+/// decoded subject. A subject without encoded words leaves `indexes` unchanged.
+pub fn map_encoded_subject(
+    canonical_header: &str,
+    mut indexes: HeaderIndexes,
+) -> Result<(HeaderIndexes, Option<EncodedSubject>)> {
+    let (subject_start, subject_end) = match indexes.subject {
+        Some(subject) => subject,
+        None => return Ok((indexes, None)),
+    };
+    let encoded = match EncodedSubject::decode(&canonical_header[subject_start..subject_end])? {
+        Some(encoded) => encoded,
+        None => return Ok((indexes, None)),
+    };
+    indexes.email_addr_in_subject = first_idxes(extract_email_addr_idxes(&encoded.decoded))
+        .map(|idxes| encoded.raw_range(idxes));
+    let regex_config =
+        serde_json::from_str(include_str!("../regexes/invitation_code.json")).unwrap();
+    if let Some((start, end)) = first_idxes(extract_substr_idxes(&encoded.decoded, &regex_config)) {
+        let (start, end) = encoded.raw_range((start, end));
+        indexes.invitation_code = Some((subject_start + start, subject_start + end));
+    }
+    Ok((indexes, Some(encoded)))
+}
+
+/// Decodes the RFC 2047 encoded words of a header value. Whitespace between two adjacent encoded
+/// words is dropped (RFC 2047 §6.2). Returns `None` when the value has no encoded word or one
+/// does not decode.
+pub fn decode_encoded_words(value: &str) -> Option<String> {
+    let mut decoded = String::new();
+    let mut rest = value;
+    let mut found = false;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let word_end = rest[start + 2..]
+            .match_indices("?=")
+            .map(|(pos, _)| start + 2 + pos)
+            // The encoding and the text are separated by `?`, so the word ends at the third one.
+            .find(|&end| rest[start + 2..end].matches('?').count() >= 2);
+        let end = match word_end {
+            Some(end) => end,
+            None => break,
+        };
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            decoded.push_str(between);
+        }
+        decoded.push_str(&decode_encoded_word(&rest[start + 2..end])?);
+        found = true;
+        after_word = true;
+        rest = &rest[end + 2..];
+    }
+    decoded.push_str(rest);
+    found.then(|| decoded)
+}
+
+/// Ranges of the DKIM-Signature header within the canonicalized header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DkimSignatureIdxes {
+    /// The whole header, from `dkim-signature:` to the end of its value.
+    pub header: (usize, usize),
+    /// The base64 value of the `bh=` tag.
+    pub body_hash: (usize, usize),
+    /// The `b=` tag, whose value is empty in the signed header.
+    pub signature_tag: (usize, usize),
+    /// The value of the `d=` tag, if the header has one.
+    pub domain: Option<(usize, usize)>,
+}
+
+/// Locates the DKIM-Signature header of a canonicalized header. The header name is matched
+/// case-insensitively at the start of a line only.
+pub fn extract_dkim_signature_idxes(canonical_header: &str) -> Result<DkimSignatureIdxes> {
+    const NAME: &str = "dkim-signature:";
+    let mut line_start = 0;
+    let start = loop {
+        if canonical_header
+            .get(line_start..line_start + NAME.len())
+            .map_or(false, |name| name.eq_ignore_ascii_case(NAME))
+        {
+            break line_start;
+        }
+        match canonical_header[line_start..].find("\r\n") {
+            Some(pos) => line_start += pos + 2,
+            None => return Err(anyhow::anyhow!("DKIM-Signature header is not found")),
+        }
+    };
+    let end = canonical_header[start..]
+        .find("\r\n")
+        .map_or(canonical_header.len(), |pos| start + pos);
+
+    let mut body_hash = None;
+    let mut signature_tag = None;
+    let mut domain = None;
+    let mut tag_start = start + NAME.len();
+    for tag in canonical_header[tag_start..end].split(';') {
+        if let Some(eq) = tag.find('=') {
+            let name = tag[..eq].trim();
+            let value = &tag[eq + 1..];
+            let value_start = tag_start + eq + 1 + (value.len() - value.trim_start().len());
+            let value_end = tag_start + eq + 1 + value.trim_end().len();
+            match name {
+                "bh" => body_hash = Some((value_start, value_end)),
+                "d" => domain = Some((value_start, value_end)),
+                "b" => {
+                    let name_start = tag_start + (tag.len() - tag.trim_start().len());
+                    signature_tag = Some((name_start, value_end.max(tag_start + eq + 1)));
+                }
+                _ => {}
+            }
+        }
+        tag_start += tag.len() + 1;
+    }
+
+    Ok(DkimSignatureIdxes {
+        header: (start, end),
+        body_hash: body_hash.ok_or_else(|| anyhow::anyhow!("bh= tag is not found"))?,
+        signature_tag: signature_tag.ok_or_else(|| anyhow::anyhow!("b= tag is not found"))?,
+        domain,
+    })
+}
+
+/// A From header of the raw email is not covered by the DKIM signature.
+///
+/// Signed header instances are selected bottom-up (RFC 6376 §5.4.2), so when the email has more
+/// From headers than `h=` lists, the uncovered ones are above the signed one: a naive parser would
+/// read an attacker's unsigned From while the signature covers the original.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsignedFromHeader {
+    pub from_headers: usize,
+    pub signed_from_headers: usize,
+}
+
+impl UnsignedFromHeader {
+    pub const CODE: &'static str = "UNSIGNED_FROM_HEADER";
+}
+
+impl std::fmt::Display for UnsignedFromHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: the email has {} From headers but the DKIM signature covers {}",
+            Self::CODE,
+            self.from_headers,
+            self.signed_from_headers
+        )
+    }
+}
+
+impl std::error::Error for UnsignedFromHeader {}
+
+/// Unfolded header fields of a raw email, with lowercase names, in order.
+pub(crate) fn raw_header_fields(raw_email: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = vec![];
+    for line in raw_email.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push_str(line);
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            fields.push((name.trim().to_ascii_lowercase(), value.to_string()));
+        }
+    }
+    fields
+}
+
+/// Where a Received header is relative to the first DKIM-Signature header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceivedPosition {
+    AboveSignature,
+    BelowSignature,
+    /// The email has no DKIM-Signature.
+    Unsigned,
+}
+
+/// One Received header of a raw email.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceivedHeader {
+    /// Position among the raw header fields, from the top.
+    pub field_index: usize,
+    /// Unfolded value, as in the raw email.
+    pub value: String,
+    pub position: ReceivedPosition,
+}
+
+/// The Received headers of a raw email, from the top, i.e. the last hop first.
+///
+/// Relays add their Received above the headers they got, so a Received below the DKIM-Signature
+/// was there when the message was signed. `received_below_signature` flags that, a sign of a
+/// re-injected message, and adds `RECEIVED_BELOW_SIGNATURE` to `warnings`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceivedChain {
+    pub hops: Vec<ReceivedHeader>,
+    pub hop_count: usize,
+    pub received_below_signature: bool,
+    pub warnings: Vec<String>,
+}
+
+impl ReceivedChain {
+    pub const RECEIVED_BELOW_SIGNATURE: &'static str = "RECEIVED_BELOW_SIGNATURE";
+}
+
+/// The Received headers of `raw_email` and where they are relative to its DKIM-Signature. The
+/// values are not parsed. A `ParsedEmail` keeps only the signed headers, so this takes the raw
+/// email.
+pub fn received_chain(raw_email: &str) -> ReceivedChain {
+    let fields = raw_header_fields(raw_email);
+    let signature_index = fields.iter().position(|(name, _)| name == "dkim-signature");
+    let hops = fields
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (name, _))| name == "received")
+        .map(|(field_index, (_, value))| ReceivedHeader {
+            field_index,
+            value: value.trim().to_string(),
+            position: match signature_index {
+                Some(signature_index) if field_index < signature_index => {
+                    ReceivedPosition::AboveSignature
+                }
+                Some(_) => ReceivedPosition::BelowSignature,
+                None => ReceivedPosition::Unsigned,
+            },
+        })
+        .collect_vec();
+    let received_below_signature = hops
+        .iter()
+        .any(|hop| hop.position == ReceivedPosition::BelowSignature);
+    let mut warnings = vec![];
+    if received_below_signature {
+        warnings.push(ReceivedChain::RECEIVED_BELOW_SIGNATURE.to_string());
+    }
+    ReceivedChain {
+        hop_count: hops.len(),
+        hops,
+        received_below_signature,
+        warnings,
+    }
+}
+
+/// A header field instance selected by an `h=` entry of a DKIM-Signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedHeader {
+    /// Lowercase field name.
+    pub name: String,
+    /// Position of the instance among the raw header fields, from the top.
+    pub field_index: usize,
+    /// Unfolded value, as in the raw email.
+    pub value: String,
+}
+
+/// Value of `tag` in the first DKIM-Signature of `fields`.
+pub(crate) fn dkim_signature_tag(fields: &[(String, String)], tag: &str) -> Option<String> {
+    let (_, signature) = fields.iter().find(|(name, _)| name == "dkim-signature")?;
+    signature_tag(signature, tag)
+}
+
+/// Value of `tag` in a DKIM-Signature value.
+fn signature_tag(signature: &str, tag: &str) -> Option<String> {
+    signature.split(';').find_map(|tag_value| {
+        let (name, value) = tag_value.split_once('=')?;
+        (name.trim() == tag).then(|| value.trim().to_string())
+    })
+}
+
+/// Tags of a DKIM-Signature value without `b=`, with the whitespace of the values removed.
+fn unsigned_signature_tags(signature: &str) -> BTreeMap<String, String> {
+    signature
+        .split(';')
+        .filter_map(|tag_value| {
+            let (name, value) = tag_value.split_once('=')?;
+            let name = name.trim();
+            (name != "b").then(|| (name.to_string(), value.split_whitespace().collect()))
+        })
+        .collect()
+}
+
+/// The DKIM-Signature of `fields` that `canonical_header` was canonicalized for, and so whose
+/// signature is verified. The canonicalized header ends with that DKIM-Signature, `b=` emptied;
+/// the other ones, such as a mailing list re-signing the email, are skipped.
+fn verified_dkim_signature<'a>(
+    fields: &'a [(String, String)],
+    canonical_header: &str,
+) -> Result<&'a str> {
+    let signed_tags = canonical_header
+        .rsplit("\r\n")
+        .next()
+        .and_then(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("dkim-signature"))
+        .map(|(_, signature)| unsigned_signature_tags(signature))
+        .ok_or_else(|| {
+            coded(
+                ErrorCode::ParseEmailFailed,
+                "the canonicalized header does not end with a DKIM-Signature",
+            )
+        })?;
+    fields
+        .iter()
+        .filter(|(name, _)| name == "dkim-signature")
+        .map(|(_, signature)| signature.as_str())
+        .find(|signature| unsigned_signature_tags(signature) == signed_tags)
+        .ok_or_else(|| {
+            coded(
+                ErrorCode::ParseEmailFailed,
+                "no DKIM-Signature of the email matches the canonicalized header",
+            )
+        })
+}
+
+/// Selects the header instances signed by `h=`, in `h=` order (RFC 6376 §5.4.2): each entry
+/// consumes the bottom-most instance of its name that an earlier entry has not consumed. An entry
+/// left without an instance selects nothing; over-signers list a header once more than it occurs
+/// so that adding an instance breaks the signature.
+fn select_signed_fields(fields: &[(String, String)], signed_headers: &str) -> Vec<SignedHeader> {
+    let mut consumed = vec![false; fields.len()];
+    let mut selected = vec![];
+    for name in signed_headers.split(':') {
+        let name = name
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        let instance = (0..fields.len())
+            .rev()
+            .find(|&i| !consumed[i] && fields[i].0 == name);
+        if let Some(i) = instance {
+            consumed[i] = true;
+            selected.push(SignedHeader {
+                name,
+                field_index: i,
+                value: fields[i].1.clone(),
+            });
+        }
+    }
+    selected
+}
+
+/// The header instances that the DKIM-Signature `canonical_header` was canonicalized for signs,
+/// in hashing order.
+///
+/// The canonicalized header lists them in this order, so for a name signed more than once the
+/// index getters, which take the first instance, refer to the bottom-most one.
+pub fn select_signed_headers(raw_email: &str, canonical_header: &str) -> Result<Vec<SignedHeader>> {
+    let fields = raw_header_fields(raw_email);
+    let signature = verified_dkim_signature(&fields, canonical_header)?;
+    let signed_headers = signature_tag(signature, "h")
+        .ok_or_else(|| {
+            coded(
+                ErrorCode::ParseEmailFailed,
+                "DKIM-Signature header with an h= tag is not found",
+            )
+        })?;
+    Ok(select_signed_fields(&fields, &signed_headers))
+}
+
+/// The key location and signed headers of one DKIM-Signature, read from the header alone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DkimInfo {
+    pub selector: String,
+    pub domain: String,
+    /// `<selector>._domainkey.<domain>`, the TXT record of the key.
+    pub dns_record: String,
+    /// Lowercase names of the `h=` tag, in order.
+    pub header_fields_signed: Vec<String>,
+}
+
+impl DkimInfo {
+    /// Parses a DKIM-Signature value. A tag without `=`, a repeated tag, or a missing `d=`, `s=`
+    /// or `h=` tag fails.
+    fn from_signature(signature: &str) -> std::result::Result<Self, String> {
+        let mut tags = BTreeMap::new();
+        for tag_value in signature.split(';') {
+            if tag_value.trim().is_empty() {
+                continue;
+            }
+            let (name, value) = tag_value
+                .split_once('=')
+                .ok_or_else(|| format!("`{}` is not a tag=value pair", tag_value.trim()))?;
+            let name = name.trim();
+            let value = value.split_whitespace().collect::<String>();
+            if tags.insert(name.to_string(), value).is_some() {
+                return Err(format!("the {}= tag is repeated", name));
+            }
+        }
+        let tag = |name: &str| {
+            tags.get(name)
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| format!("the {}= tag is missing", name))
+        };
+        let key_id = crate::dkim_cache::DkimKeyId::new(tag("d")?, tag("s")?);
+        let header_fields_signed = tag("h")?
+            .split(':')
+            .map(|name| name.to_ascii_lowercase())
+            .collect();
+        Ok(DkimInfo {
+            dns_record: key_id.record_name(),
+            selector: key_id.selector,
+            domain: key_id.domain,
+            header_fields_signed,
+        })
+    }
+}
+
+/// The `DkimInfo` of every DKIM-Signature of `raw_email`, from the top, without resolving the
+/// keys. Fails with `ErrorCode::ParseEmailFailed` when there is no DKIM-Signature or one is
+/// malformed.
+pub fn extract_dkim_info(raw_email: &str) -> Result<Vec<DkimInfo>> {
+    let signatures = raw_header_fields(&raw_header_text(raw_email.as_bytes()))
+        .into_iter()
+        .filter(|(name, _)| name == "dkim-signature")
+        .map(|(_, signature)| signature)
+        .collect_vec();
+    if signatures.is_empty() {
+        return Err(coded(
+            ErrorCode::ParseEmailFailed,
+            "no DKIM-Signature header is found",
+        ));
+    }
+    signatures
+        .iter()
+        .enumerate()
+        .map(|(i, signature)| {
+            DkimInfo::from_signature(signature).map_err(|e| {
+                coded(
+                    ErrorCode::ParseEmailFailed,
+                    format!("DKIM-Signature header {} is malformed: {}", i + 1, e),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Fails with `UnsignedFromHeader` when the raw email has a From header that the verified
+/// DKIM-Signature, the one `canonical_header` was canonicalized for, does not cover. The
+/// canonicalized header only ever contains the signed instance, so the indexes always refer to
+/// it; this check rejects the email outright.
+pub fn check_from_header_coverage(raw_email: &str, canonical_header: &str) -> Result<()> {
+    let signed_from_headers = select_signed_headers(raw_email, canonical_header)?
+        .iter()
+        .filter(|header| header.name == "from")
+        .count();
+    let from_headers = raw_header_fields(raw_email)
+        .iter()
+        .filter(|(name, _)| name == "from")
+        .count();
+    if from_headers > signed_from_headers {
+        return Err(UnsignedFromHeader {
+            from_headers,
+            signed_from_headers,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Checks that `canonical_header` starts with the instances `select_signed_headers` selects from
+/// `raw_email`, relaxed-canonicalized (RFC 6376 §3.4.2), so that the hashed header and the
+/// indexes into it refer to the right instances of over-signed headers. Only `relaxed` header
+/// canonicalization, read from the verified DKIM-Signature, is checked; `simple` keeps the raw
+/// bytes, which the unfolded fields lose.
+pub fn check_signed_header_selection(raw_email: &str, canonical_header: &str) -> Result<()> {
+    let fields = raw_header_fields(raw_email);
+    let signature = verified_dkim_signature(&fields, canonical_header)?;
+    let header_canonicalization = signature_tag(signature, "c").unwrap_or_default();
+    if !header_canonicalization.starts_with("relaxed") {
+        return Ok(());
+    }
+    let mut rest = canonical_header;
+    for header in select_signed_headers(raw_email, canonical_header)? {
+        let value = header
+            .value
+            .split(|c| c == ' ' || c == '\t')
+            .filter(|word| !word.is_empty())
+            .join(" ");
+        let line = format!("{}:{}\r\n", header.name, value);
+        rest = rest.strip_prefix(line.as_str()).ok_or_else(|| {
+            anyhow::anyhow!(
+                "the canonicalized header does not hash the signed {} header (raw field {}) in h= order",
+                header.name,
+                header.field_index
+            )
+        })?;
+    }
+    Ok(())
+}
+
+fn first_idxes<E>(idxes: std::result::Result<Vec<(usize, usize)>, E>) -> Option<(usize, usize)> {
+    idxes.ok().and_then(|idxes| idxes.first().copied())
+}
+
+/// An email canonicalized with the DKIM key of its signature. The fields are only set by the
+/// constructors of this crate, which resolve the key, and the email is not `Deserialize`, so
+/// that an `UntrustedParsedEmail` cannot be turned into one; the fields are read through the
+/// getters of the same name. Input generation verifies the signature once more.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct ParsedEmail {
-    pub canonicalized_header: String,
-    pub canonicalized_body: String,
-    pub signature: Vec<u8>,
-    pub public_key: Vec<u8>,
+    pub(crate) canonicalized_header: String,
+    pub(crate) canonicalized_body: String,
+    pub(crate) signature: Vec<u8>,
+    pub(crate) public_key: Vec<u8>,
+    /// Public exponent of the DKIM key. The signature is verified with it, but only keys with
+    /// `DEFAULT_PUBLIC_EXPONENT` can be proven with (`check_provable_exponent`).
+    pub(crate) public_exponent: u64,
+    /// Whether the DKIM key record was DNSSEC-validated; see `DkimKeyFetch::dnssec_validated`.
+    pub(crate) dnssec_validated: Option<bool>,
+}
+
+/// The fields of a `ParsedEmail` as `CachedParsedEmail` decodes them. Only
+/// `ParsedEmail::from_cached_bytes` hands the email out, after checking its signature.
+#[derive(Deserialize)]
+struct ParsedEmailFields {
+    canonicalized_header: String,
+    canonicalized_body: String,
+    signature: Vec<u8>,
+    public_key: Vec<u8>,
+    #[serde(default = "default_public_exponent")]
+    public_exponent: u64,
+    #[serde(default)]
+    dnssec_validated: Option<bool>,
+}
+
+fn default_public_exponent() -> u64 {
+    DEFAULT_PUBLIC_EXPONENT
+}
+
+fn deserialize_parsed_email<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<ParsedEmail, D::Error> {
+    let fields = ParsedEmailFields::deserialize(deserializer)?;
+    Ok(ParsedEmail {
+        canonicalized_header: fields.canonicalized_header,
+        canonicalized_body: fields.canonicalized_body,
+        signature: fields.signature,
+        public_key: fields.public_key,
+        public_exponent: fields.public_exponent,
+        dnssec_validated: fields.dnssec_validated,
+    })
+}
+
+/// The header section of a raw email, up to the first empty line, decoded lossily to look up
+/// header fields. Header fields are ASCII in practice; only a body may be in another charset.
+/// Lone CRs are read as line endings, as `normalize_header_line_endings` does.
+pub(crate) fn raw_header_text(raw_email: &[u8]) -> Cow<'_, str> {
+    fn header_text(raw_email: &[u8]) -> Cow<'_, str> {
+        let mut end = 0;
+        for line in raw_email.split_inclusive(|&byte| byte == b'\n') {
+            if line == b"\n" || line == b"\r\n" {
+                break;
+            }
+            end += line.len();
+        }
+        String::from_utf8_lossy(&raw_email[..end])
+    }
+    match normalize_header_line_endings(raw_email).0 {
+        Cow::Borrowed(raw_email) => header_text(raw_email),
+        Cow::Owned(raw_email) => Cow::Owned(header_text(&raw_email).into_owned()),
+    }
+}
+
+/// Canonicalizes the signed header and the body of a raw email, returning them with the
+/// signature. The header must be UTF-8. The email auth circuit does not hash the body, so a body
+/// in another charset is decoded lossily, with a warning; its body hash can then not be checked.
+#[cfg(feature = "dns")]
+pub(crate) fn canonicalize(raw_email: &[u8]) -> Result<(String, String, Vec<u8>)> {
+    let (raw_email, lone_crs) = normalize_header_line_endings(raw_email);
+    if lone_crs > 0 {
+        slog::warn!(LOG, "converted lone CRs in the header to CRLF"; "count" => lone_crs);
+    }
+    let (canonicalized_header, canonicalized_body, signature_bytes) =
+        tracing::info_span!("canonicalize")
+            .in_scope(|| canonicalize_signed_email(&raw_email))
+            .map_err(|e| {
+                coded(
+                    ErrorCode::ParseEmailFailed,
+                    format!("could not canonicalize the email: {:?}", e),
+                )
+            })?;
+    let canonicalized_header = String::from_utf8(canonicalized_header).map_err(|e| {
+        coded(
+            ErrorCode::ParseEmailFailed,
+            format!("the signed header is not UTF-8: {}", e),
+        )
+    })?;
+    let canonicalized_body = match String::from_utf8(canonicalized_body) {
+        Ok(body) => body,
+        Err(e) => {
+            slog::warn!(LOG, "the body is not UTF-8 and is decoded lossily";
+                "error" => e.utf8_error().to_string());
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
+        }
+    };
+    Ok((
+        canonicalized_header,
+        canonicalized_body,
+        signature_bytes.into_iter().collect_vec(),
+    ))
+}
+
+/// The big-endian modulus of the DKIM key of `raw_email`, from the key cache or else over DNS.
+/// A resolved key is cached.
+#[cfg(feature = "dns")]
+pub async fn resolve_dkim_public_key(raw_email: &[u8]) -> Result<Vec<u8>> {
+    Ok(resolve_dkim_key_fetch(raw_email, false).await?.public_key)
+}
+
+/// `resolve_dkim_public_key` with the DNSSEC outcome of the fetch. The key record is validated
+/// when `require_dnssec` or `HotConfig::dnssec_validation` is set, and a cached key that was not
+/// validated is then fetched again. With `require_dnssec`, a key that is not validated as secure
+/// fails with `ErrorCode::DnssecFailed`.
+#[cfg(feature = "dns")]
+pub async fn resolve_dkim_key_fetch(
+    raw_email: &[u8],
+    require_dnssec: bool,
+) -> Result<DkimKeyFetch> {
+    let key_id = DkimKeyId::from_raw_email(&raw_header_text(raw_email));
+    let validate = require_dnssec || hot_config().dnssec_validation;
+    let cached = key_id
+        .as_ref()
+        .and_then(cached_dkim_key_fetch)
+        .filter(|fetch| !validate || fetch.dnssec_validated.is_some());
+    let fetch = match (cached, &key_id) {
+        (Some(fetch), _) => fetch,
+        (None, Some(key_id)) if validate => fetch_dkim_key_dnssec_or_fail(key_id).await?,
+        (None, None) if validate => {
+            return Err(coded(
+                ErrorCode::DnssecFailed,
+                "DNSSEC validation needs the d= and s= tags of the DKIM-Signature",
+            ))
+        }
+        (None, _) => resolve_dkim_key_over_dns(raw_email, key_id.as_ref()).await?,
+    };
+    if let Some(key_id) = &key_id {
+        fetch.check_dnssec(key_id, require_dnssec)?;
+    }
+    Ok(fetch)
+}
+
+#[cfg(feature = "dnssec")]
+async fn fetch_dkim_key_dnssec_or_fail(key_id: &DkimKeyId) -> Result<DkimKeyFetch> {
+    fetch_dkim_key_dnssec(key_id).await
+}
+
+#[cfg(all(feature = "dns", not(feature = "dnssec")))]
+async fn fetch_dkim_key_dnssec_or_fail(_key_id: &DkimKeyId) -> Result<DkimKeyFetch> {
+    Err(coded(
+        ErrorCode::DnssecFailed,
+        "DNSSEC validation needs the `dnssec` feature",
+    ))
+}
+
+/// Resolves the DKIM key of `raw_email` over DNS without DNSSEC, and caches it.
+#[cfg(feature = "dns")]
+async fn resolve_dkim_key_over_dns(
+    raw_email: &[u8],
+    key_id: Option<&DkimKeyId>,
+) -> Result<DkimKeyFetch> {
+    let logger = slog::Logger::root(slog::Discard, slog::o!());
+    let raw_email = normalize_header_line_endings(raw_email).0;
+    let public_key = resolve_public_key(&logger, &raw_email)
+        .instrument(tracing::info_span!("dkim_fetch"))
+        .await
+        .map_err(|e| {
+            let key = key_id.map_or(String::new(), |key_id| {
+                format!(" {}._domainkey.{}", key_id.selector, key_id.domain)
+            });
+            coded(
+                ErrorCode::DkimDnsFailed,
+                format!("could not resolve the DKIM key{}: {:?}", key, e),
+            )
+        })?;
+    let fetch = match public_key {
+        cfdkim::DkimPublicKey::Rsa(pk) => DkimKeyFetch::from_rsa_public_key(&pk),
+        _ => {
+            return Err(coded(
+                ErrorCode::ParseEmailFailed,
+                "not supportted public key type.",
+            ))
+        }
+    };
+    if let Some(key_id) = key_id {
+        crate::dkim_cache::cache_dkim_key_fetch(key_id.clone(), fetch.clone());
+    }
+    Ok(fetch)
 }
 
 impl ParsedEmail {
+    #[cfg(feature = "dns")]
     pub async fn new_from_raw_email(raw_email: &str) -> Result<Self> {
-        let logger = slog::Logger::root(slog::Discard, slog::o!());
-        let public_key = resolve_public_key(&logger, raw_email.as_bytes())
-            .await
-            .unwrap();
-        let public_key = match public_key {
-            cfdkim::DkimPublicKey::Rsa(pk) => pk,
-            _ => panic!("not supportted public key type."),
-        };
-        let (canonicalized_header, canonicalized_body, signature_bytes) =
-            canonicalize_signed_email(raw_email.as_bytes()).unwrap();
+        Self::new_from_raw_bytes(raw_email.as_bytes()).await
+    }
+
+    /// Parses and resolves the key of the exact on-the-wire bytes of an email. Unlike a string,
+    /// the bytes can carry an 8-bit body in any charset without being re-encoded, which would
+    /// break the DKIM signature. Prefer this for emails that are not known to be UTF-8.
+    #[cfg(feature = "dns")]
+    pub async fn new_from_raw_bytes(raw_email: &[u8]) -> Result<Self> {
+        let fetch = resolve_dkim_key_fetch(raw_email, false).await?;
+        Ok(ParsedEmail {
+            public_exponent: fetch.public_exponent,
+            dnssec_validated: fetch.dnssec_validated,
+            ..Self::new_from_raw_bytes_with_key(raw_email, fetch.public_key)?
+        })
+    }
+
+    /// `new_from_raw_bytes` with the big-endian modulus of the DKIM key already known, without
+    /// any network access. The key is taken to have the exponent `DEFAULT_PUBLIC_EXPONENT`.
+    #[cfg(feature = "dns")]
+    pub fn new_from_raw_bytes_with_key(raw_email: &[u8], public_key: Vec<u8>) -> Result<Self> {
+        let (canonicalized_header, canonicalized_body, signature) = canonicalize(raw_email)?;
         let parsed_email = ParsedEmail {
-            canonicalized_header: String::from_utf8(canonicalized_header)?,
-            canonicalized_body: String::from_utf8(canonicalized_body)?,
-            signature: signature_bytes.into_iter().collect_vec(),
-            public_key: public_key.n().to_bytes_be(),
+            canonicalized_header,
+            canonicalized_body,
+            signature,
+            public_key,
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
         };
         Ok(parsed_email)
     }
 
+    pub fn canonicalized_header(&self) -> &str {
+        &self.canonicalized_header
+    }
+
+    pub fn canonicalized_body(&self) -> &str {
+        &self.canonicalized_body
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
+
+    /// The big-endian modulus of the DKIM key.
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    pub fn public_exponent(&self) -> u64 {
+        self.public_exponent
+    }
+
+    pub fn dnssec_validated(&self) -> Option<bool> {
+        self.dnssec_validated
+    }
+
     pub fn signature_string(&self) -> String {
         "0x".to_string() + hex::encode(&self.signature).as_str()
     }
@@ -59,25 +1194,45 @@ impl ParsedEmail {
         "0x".to_string() + hex::encode(&self.public_key).as_str()
     }
 
+    /// Verifies the signature of the canonicalized header with the modulus and the exponent of
+    /// the DKIM key.
+    pub fn verify_signature(&self) -> Result<()> {
+        crate::cryptos::verify_rsa_sha256_with_exponent(
+            self.canonicalized_header.as_bytes(),
+            &self.signature,
+            &self.public_key,
+            self.public_exponent,
+        )
+    }
+
+    pub fn get_header_indexes(&self) -> Result<HeaderIndexes> {
+        extract_indexes(self.canonicalized_header.as_bytes())
+    }
+
+    pub fn get_dkim_signature_idxes(&self) -> Result<DkimSignatureIdxes> {
+        extract_dkim_signature_idxes(&self.canonicalized_header)
+    }
+
     pub fn get_from_addr(&self) -> Result<String> {
-        let idxes = extract_from_addr_idxes(&self.canonicalized_header)?[0];
+        let idxes = from_addr_idxes_or_err(&self.canonicalized_header)?;
         let str = self.canonicalized_header[idxes.0..idxes.1].to_string();
         Ok(str)
     }
 
     pub fn get_from_addr_idxes(&self) -> Result<(usize, usize)> {
-        let idxes = extract_from_addr_idxes(&self.canonicalized_header)?[0];
-        Ok(idxes)
+        from_addr_idxes_or_err(&self.canonicalized_header)
     }
 
     pub fn get_to_addr(&self) -> Result<String> {
-        let idxes = extract_to_addr_idxes(&self.canonicalized_header)?[0];
+        let header = &self.canonicalized_header;
+        let idxes = first_idxes_in_header(header, "to", extract_to_addr_idxes(header))
+            .ok_or_else(|| anyhow::anyhow!("to address is not found in the header"))?;
         let str = self.canonicalized_header[idxes.0..idxes.1].to_string();
         Ok(str)
     }
 
     pub fn get_email_domain(&self) -> Result<String> {
-        let idxes = extract_from_addr_idxes(&self.canonicalized_header)?[0];
+        let idxes = from_addr_idxes_or_err(&self.canonicalized_header)?;
         let from_addr = self.canonicalized_header[idxes.0..idxes.1].to_string();
         let idxes = extract_email_domain_idxes(&from_addr)?[0];
         let str = from_addr[idxes.0..idxes.1].to_string();
@@ -85,21 +1240,22 @@ impl ParsedEmail {
     }
 
     pub fn get_email_domain_idxes(&self) -> Result<(usize, usize)> {
-        let idxes = extract_from_addr_idxes(&self.canonicalized_header)?[0];
-        let str = self.canonicalized_header[idxes.0..idxes.1].to_string();
-        let idxes = extract_email_domain_idxes(&str)?[0];
-        Ok(idxes)
+        let idxes = from_addr_idxes_or_err(&self.canonicalized_header)?;
+        let str = &self.canonicalized_header[idxes.0..idxes.1];
+        extract_email_domain_idxes(str)?
+            .first()
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("email domain is not found in the from address"))
     }
 
     pub fn get_subject_all(&self) -> Result<String> {
-        let idxes = extract_subject_all_idxes(&self.canonicalized_header)?[0];
+        let idxes = subject_idxes_or_err(&self.canonicalized_header)?;
         let str = self.canonicalized_header[idxes.0..idxes.1].to_string();
         Ok(str)
     }
 
     pub fn get_subject_all_idxes(&self) -> Result<(usize, usize)> {
-        let idxes = extract_subject_all_idxes(&self.canonicalized_header)?[0];
-        Ok(idxes)
+        subject_idxes_or_err(&self.canonicalized_header)
     }
 
     pub fn get_body(&self) -> Result<String> {
@@ -114,9 +1270,40 @@ impl ParsedEmail {
 
     pub fn get_timestamp_idxes(&self) -> Result<(usize, usize)> {
         let idxes = extract_timestamp_idxes(&self.canonicalized_header)?[0];
+        // Keep the idx getter consistent with `get_timestamp`: an index pointing at a value that
+        // cannot be parsed is as good as no timestamp at all.
+        self.canonicalized_header[idxes.0..idxes.1].parse::<u64>()?;
         Ok(idxes)
     }
 
+    /// Returns the timestamp index, or `None` with a warning when the timestamp is absent or
+    /// unparseable, so that input generation can degrade instead of failing.
+    pub fn get_timestamp_idxes_or_warn(&self) -> Option<(usize, usize)> {
+        match self.get_timestamp_idxes() {
+            Ok(idxes) => Some(idxes),
+            Err(e) => {
+                slog::warn!(
+                    LOG,
+                    "timestamp is not available, proceeding without it";
+                    "error" => e.to_string()
+                );
+                None
+            }
+        }
+    }
+
+    /// Returns the unix timestamp of the `Date` header, parsed with [`parse_email_date`]. The
+    /// circuit only proves the DKIM `t=` timestamp, so this is for callers that fall back to
+    /// the Date header; `None` when it is absent or cannot be parsed.
+    pub fn get_date(&self) -> Option<u64> {
+        self.canonicalized_header.split("\r\n").find_map(|line| {
+            match line.get(..5) {
+                Some(name) if name.eq_ignore_ascii_case("date:") => parse_email_date(&line[5..]),
+                _ => None,
+            }
+        })
+    }
+
     pub fn get_invitation_code(&self) -> Result<String> {
         let regex_config =
             serde_json::from_str(include_str!("../regexes/invitation_code.json")).unwrap();
@@ -127,23 +1314,25 @@ impl ParsedEmail {
 
     pub fn get_invitation_code_idxes(&self) -> Result<(usize, usize)> {
         let regex_config =
-            serde_json::from_str(include_str!("../regexes/invitation_code.json")).unwrap();
-        let idxes = extract_substr_idxes(&self.canonicalized_header, &regex_config)?[0];
-        Ok(idxes)
+            serde_json::from_str(include_str!("../regexes/invitation_code.json"))?;
+        extract_substr_idxes(&self.canonicalized_header, &regex_config)?
+            .first()
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("invitation code is not found in the header"))
     }
 
     pub fn get_email_addr_in_subject(&self) -> Result<String> {
-        let idxes = extract_subject_all_idxes(&self.canonicalized_header)?[0];
-        let subject = self.canonicalized_header[idxes.0..idxes.1].to_string();
-        let idxes = extract_email_addr_idxes(&subject)?[0];
+        let idxes = subject_idxes_or_err(&self.canonicalized_header)?;
+        let subject = &self.canonicalized_header[idxes.0..idxes.1];
+        let idxes = extract_email_addr_idxes(subject)?[0];
         let str = subject[idxes.0..idxes.1].to_string();
         Ok(str)
     }
 
     pub fn get_email_addr_in_subject_idxes(&self) -> Result<(usize, usize)> {
-        let idxes = extract_subject_all_idxes(&self.canonicalized_header)?[0];
-        let subject = self.canonicalized_header[idxes.0..idxes.1].to_string();
-        let idxes = extract_email_addr_idxes(&subject)?[0];
+        let idxes = subject_idxes_or_err(&self.canonicalized_header)?;
+        let subject = &self.canonicalized_header[idxes.0..idxes.1];
+        let idxes = extract_email_addr_idxes(subject)?[0];
         Ok(idxes)
     }
 
@@ -154,6 +1343,516 @@ impl ParsedEmail {
     }
 }
 
+/// Version of the `ParsedEmail` cache format. Bump it whenever `CachedParsedEmail` or
+/// `ParsedEmail` change shape.
+pub const PARSED_EMAIL_CACHE_VERSION: u32 = 3;
+
+/// The versioned envelope of the cache format. The payload stays opaque until the version is
+/// known, so that an old reader rejects a newer payload instead of misreading it.
+#[derive(Serialize, Deserialize)]
+struct CacheEnvelope {
+    version: u32,
+    payload: Vec<u8>,
+}
+
+/// A `ParsedEmail` cached between processes, with the DKIM public key resolved at parse time, so
+/// that no DNS lookup is needed downstream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedParsedEmail {
+    /// Read through `ParsedEmail::from_cached_bytes`, which checks the signature.
+    #[serde(deserialize_with = "deserialize_parsed_email")]
+    pub(crate) parsed_email: ParsedEmail,
+    /// Whether the header signature verified against `parsed_email.public_key` when cached.
+    pub dkim_verified: bool,
+}
+
+impl CachedParsedEmail {
+    pub fn new(parsed_email: ParsedEmail) -> Self {
+        let dkim_verified = parsed_email.verify_signature().is_ok();
+        CachedParsedEmail {
+            parsed_email,
+            dkim_verified,
+        }
+    }
+
+    /// Encodes the bincode envelope `{version, payload}`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let envelope = CacheEnvelope {
+            version: PARSED_EMAIL_CACHE_VERSION,
+            payload: bincode::serialize(self)?,
+        };
+        Ok(bincode::serialize(&envelope)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let envelope: CacheEnvelope = bincode::deserialize(bytes)
+            .map_err(|e| anyhow::anyhow!("the cached email is not a cache envelope: {}", e))?;
+        if envelope.version != PARSED_EMAIL_CACHE_VERSION {
+            return Err(anyhow::anyhow!(
+                "the cached email has format version {} but only version {} is supported",
+                envelope.version,
+                PARSED_EMAIL_CACHE_VERSION
+            ));
+        }
+        bincode::deserialize(&envelope.payload)
+            .map_err(|e| anyhow::anyhow!("the cached email payload is invalid: {}", e))
+    }
+}
+
+impl ParsedEmail {
+    /// Encodes the email for another process; see `CachedParsedEmail`.
+    pub fn to_cached_bytes(&self) -> Result<Vec<u8>> {
+        CachedParsedEmail::new(self.clone()).to_bytes()
+    }
+
+    /// Decodes an email encoded by `to_cached_bytes`. Fails for an unknown format version, or
+    /// with `ErrorCode::ParseEmailFailed` for an email whose signature did not verify when cached
+    /// or does not verify now: the `dkim_verified` flag of a cache entry is not trusted.
+    pub fn from_cached_bytes(bytes: &[u8]) -> Result<Self> {
+        let cached = CachedParsedEmail::from_bytes(bytes)?;
+        if !cached.dkim_verified {
+            return Err(coded(
+                ErrorCode::ParseEmailFailed,
+                "the cached email failed DKIM verification when it was cached",
+            ));
+        }
+        cached.parsed_email.verify_signature().map_err(|e| {
+            coded(
+                ErrorCode::ParseEmailFailed,
+                format!("the DKIM signature of the cached email does not verify: {}", e),
+            )
+        })?;
+        Ok(cached.parsed_email)
+    }
+}
+
+/// Tags of the first DKIM-Signature of `fields`, keyed by tag name.
+#[cfg(feature = "dns")]
+fn dkim_signature_tags(fields: &[(String, String)]) -> BTreeMap<String, String> {
+    let signature = match fields.iter().find(|(name, _)| name == "dkim-signature") {
+        Some((_, signature)) => signature,
+        None => return BTreeMap::new(),
+    };
+    signature
+        .split(';')
+        .filter_map(|tag_value| {
+            let (name, value) = tag_value.split_once('=')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// An email parsed by `ParsedEmail::parse_untrusted`, without resolving its DKIM key.
+///
+/// Nothing here is authenticated: the signature has not been checked, so every field, index and
+/// tag is only what the sender wrote. The canonicalized forms are those a verifier would hash,
+/// which makes them fine for extraction and diagnostics. There is no public key, and input
+/// generation only takes a `ParsedEmail`, so an untrusted email cannot reach a circuit input.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UntrustedParsedEmail {
+    pub canonicalized_header: String,
+    pub canonicalized_body: String,
+    pub signature: Vec<u8>,
+    /// Tags of the first DKIM-Signature, such as `d`, `s` and `h`.
+    pub dkim_tags: BTreeMap<String, String>,
+    /// Leaf parts of the canonicalized body.
+    #[cfg(feature = "body")]
+    pub body_parts: Vec<BodyPartSize>,
+    /// Content after the closing delimiter of a multipart body.
+    #[cfg(feature = "body")]
+    pub trailing_content: Option<TrailingContent>,
+    pub received_chain: ReceivedChain,
+}
+
+impl ParsedEmail {
+    /// Parses `raw_email` without any network access and without verifying it. See
+    /// `UntrustedParsedEmail` for what the result can be used for.
+    #[cfg(feature = "dns")]
+    pub fn parse_untrusted(raw_email: &str) -> Result<UntrustedParsedEmail> {
+        Self::parse_untrusted_bytes(raw_email.as_bytes())
+    }
+
+    /// `parse_untrusted` for the exact bytes of the email; see `new_from_raw_bytes`.
+    #[cfg(feature = "dns")]
+    pub fn parse_untrusted_bytes(raw_email: &[u8]) -> Result<UntrustedParsedEmail> {
+        let (canonicalized_header, canonicalized_body, signature) = canonicalize(raw_email)?;
+        let raw_header = raw_header_text(raw_email);
+        let fields = raw_header_fields(&raw_header)
+            .into_iter()
+            .map(|(name, value)| (name, value.trim().to_string()))
+            .collect_vec();
+        Ok(UntrustedParsedEmail {
+            canonicalized_header,
+            #[cfg(feature = "body")]
+            body_parts: body_part_sizes(&fields, &canonicalized_body),
+            #[cfg(feature = "body")]
+            trailing_content: trailing_content(&fields, &canonicalized_body),
+            canonicalized_body,
+            signature,
+            dkim_tags: dkim_signature_tags(&fields),
+            received_chain: received_chain(&raw_header),
+        })
+    }
+}
+
+impl UntrustedParsedEmail {
+    pub fn signature_string(&self) -> String {
+        "0x".to_string() + hex::encode(&self.signature).as_str()
+    }
+
+    pub fn get_header_indexes(&self) -> Result<HeaderIndexes> {
+        extract_indexes(self.canonicalized_header.as_bytes())
+    }
+
+    pub fn get_dkim_signature_idxes(&self) -> Result<DkimSignatureIdxes> {
+        extract_dkim_signature_idxes(&self.canonicalized_header)
+    }
+
+    pub fn get_from_addr(&self) -> Result<String> {
+        let idxes = from_addr_idxes_or_err(&self.canonicalized_header)?;
+        Ok(self.canonicalized_header[idxes.0..idxes.1].to_string())
+    }
+
+    pub fn get_subject_all(&self) -> Result<String> {
+        let idxes = subject_idxes_or_err(&self.canonicalized_header)?;
+        Ok(self.canonicalized_header[idxes.0..idxes.1].to_string())
+    }
+
+    /// Encodes the email in the `CachedParsedEmail` format, marked as not verified and without a
+    /// public key, so that `ParsedEmail::from_cached_bytes` refuses it.
+    pub fn to_cached_bytes(&self) -> Result<Vec<u8>> {
+        CachedParsedEmail {
+            parsed_email: ParsedEmail {
+                canonicalized_header: self.canonicalized_header.clone(),
+                canonicalized_body: self.canonicalized_body.clone(),
+                signature: self.signature.clone(),
+                public_key: vec![],
+                public_exponent: DEFAULT_PUBLIC_EXPONENT,
+                dnssec_validated: None,
+            },
+            dkim_verified: false,
+        }
+        .to_bytes()
+    }
+}
+
+/// Outcome of `verify_dkim`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DkimVerification {
+    /// The header signature and the body hash both verify.
+    pub valid: bool,
+    pub domain: String,
+    pub selector: String,
+    /// The `a=` tag, e.g. `rsa-sha256`.
+    pub algorithm: String,
+    /// Why `valid` is false.
+    pub failure: Option<String>,
+    /// See `ParsedEmail::dnssec_validated`.
+    pub dnssec_validated: Option<bool>,
+    /// Public exponent of the DKIM key. A key without `DEFAULT_PUBLIC_EXPONENT` verifies but
+    /// cannot be proven with.
+    pub public_exponent: u64,
+    /// `dmarc_dkim_alignment` of the email, without consulting the DMARC policy. `None` when the
+    /// From domain or the `d=` tag cannot be read.
+    pub dmarc_alignment: Option<crate::dmarc::AlignmentVerdict>,
+}
+
+impl DkimVerification {
+    /// `self` if it is `valid`, else its `failure` as a `DkimVerificationFailed` error.
+    pub fn into_verified(self) -> Result<Self> {
+        match &self.failure {
+            Some(failure) => Err(coded(
+                ErrorCode::DkimVerificationFailed,
+                format!(
+                    "DKIM verification failed for {} (selector {}): {}",
+                    self.domain, self.selector, failure
+                ),
+            )),
+            None => Ok(self),
+        }
+    }
+}
+
+/// Verifies the first DKIM signature of `raw_email` without generating any circuit input. Fails
+/// with `DkimVerificationFailed` when the email has no DKIM-Signature, or when its key cannot be
+/// resolved; a signature or body hash that does not verify is reported in `failure`.
+#[cfg(all(feature = "dns", feature = "body"))]
+pub async fn verify_dkim(raw_email: &str) -> Result<DkimVerification> {
+    let fields = raw_header_fields(raw_email);
+    let tag = |name: &str| {
+        dkim_signature_tag(&fields, name).ok_or_else(|| {
+            coded(
+                ErrorCode::DkimVerificationFailed,
+                format!("the email has no DKIM-Signature header with the {}= tag", name),
+            )
+        })
+    };
+    let (domain, selector, algorithm) = (tag("d")?, tag("s")?, tag("a")?);
+    let parsed_email = ParsedEmail::new_from_raw_email(raw_email).await?;
+    let failure = if let Err(e) = parsed_email.verify_signature() {
+        Some(e.to_string())
+    } else if !crate::body::check_body_hash(&parsed_email)? {
+        Some(match crate::body::body_hash_hint(&parsed_email, &fields)? {
+            Some(hint) => format!("the body hash does not match the body: {}", hint),
+            None => "the body hash does not match the body".to_string(),
+        })
+    } else {
+        None
+    };
+    Ok(DkimVerification {
+        valid: failure.is_none(),
+        domain,
+        selector,
+        algorithm,
+        failure,
+        dnssec_validated: parsed_email.dnssec_validated,
+        public_exponent: parsed_email.public_exponent,
+        dmarc_alignment: crate::dmarc::dmarc_dkim_alignment(&parsed_email).ok(),
+    })
+}
+
+/// The hex `public_key_hash` of the DKIM key of `raw_email`, as `publicKeyHash` computes it from
+/// the modulus of `ParsedEmail::public_key`. The key comes from the key cache or else DNS; the
+/// signature is not verified.
+#[cfg(feature = "dns")]
+pub async fn public_key_hash_from_email(raw_email: &str) -> Result<String> {
+    let fields = raw_header_fields(raw_email);
+    for name in ["d", "s"] {
+        if dkim_signature_tag(&fields, name).is_none() {
+            return Err(coded(
+                ErrorCode::ParseEmailFailed,
+                format!(
+                    "the email has no DKIM-Signature header with the {}= tag",
+                    name
+                ),
+            ));
+        }
+    }
+    let parsed_email = ParsedEmail::new_from_raw_email(raw_email).await?;
+    let hash_field = public_key_hash_from_modulus(&parsed_email.public_key).map_err(|e| {
+        coded(
+            ErrorCode::CryptoFailed,
+            format!("public_key_hash failed: {}", e),
+        )
+    })?;
+    Ok(crate::converters::field2hex(&hash_field))
+}
+
+/// Removes POP3 artifacts from a raw email piped straight from a fetcher: a leading `+OK` status
+/// line, the terminating `.` line and the dot-stuffing of lines starting with `.` (RFC 1939 §3).
+///
+/// Lines are only un-dot-stuffed when a status or terminating line shows that the input is a POP3
+/// capture. Returns the sanitized email and one warning per kind of artifact removed.
+pub fn strip_protocol_artifacts(raw_email: &str) -> (String, Vec<String>) {
+    let (sanitized, warnings) = strip_protocol_artifacts_bytes(raw_email.as_bytes());
+    // Only whole ASCII lines and leading dots are removed, so the result is still UTF-8.
+    (String::from_utf8_lossy(&sanitized).into_owned(), warnings)
+}
+
+/// `strip_protocol_artifacts` for the bytes of an email.
+pub fn strip_protocol_artifacts_bytes(raw_email: &[u8]) -> (Vec<u8>, Vec<String>) {
+    let mut warnings = vec![];
+    let mut lines = raw_email.split_inclusive(|&byte| byte == b'\n').collect_vec();
+    fn trim_line_end(mut line: &[u8]) -> &[u8] {
+        while let Some((b'\r' | b'\n', rest)) = line.split_last() {
+            line = rest;
+        }
+        line
+    }
+
+    let has_status_line = lines.first().map_or(false, |line| line.starts_with(b"+OK"));
+    if has_status_line {
+        warnings.push(format!(
+            "removed the POP3 status line {:?}",
+            String::from_utf8_lossy(trim_line_end(lines[0]))
+        ));
+        lines.remove(0);
+    }
+    let has_terminator = lines
+        .last()
+        .map_or(false, |line| trim_line_end(line) == b".");
+    if has_terminator {
+        warnings.push("removed the POP3 terminating line".to_string());
+        lines.pop();
+    }
+
+    let mut unstuffed = 0;
+    let sanitized = lines
+        .into_iter()
+        .map(|line| {
+            if (has_status_line || has_terminator) && line.starts_with(b"..") {
+                unstuffed += 1;
+                &line[1..]
+            } else {
+                line
+            }
+        })
+        .collect_vec()
+        .concat();
+    if unstuffed > 0 {
+        warnings.push(format!("un-dot-stuffed {} lines", unstuffed));
+    }
+    (sanitized, warnings)
+}
+
+/// Converts the lone CRs (CR not followed by LF) of the header section of `raw_email` to CRLF,
+/// returning the email and the number of lone CRs. Some mailbox exports turn the CRLF of folded
+/// header lines into a lone CR, which canonicalization would keep as a byte of the value; the
+/// signer saw CRLF, so the conversion restores the signed form. The body is left as is.
+pub fn normalize_header_line_endings(raw_email: &[u8]) -> (Cow<'_, [u8]>, usize) {
+    let mut lone_crs = vec![];
+    let mut line_start = true;
+    for (i, &byte) in raw_email.iter().enumerate() {
+        if line_start && (raw_email[i..].starts_with(b"\r\n") || byte == b'\n') {
+            break;
+        }
+        let lone_cr = byte == b'\r' && raw_email.get(i + 1) != Some(&b'\n');
+        if lone_cr {
+            lone_crs.push(i);
+        }
+        line_start = lone_cr || byte == b'\n';
+    }
+    if lone_crs.is_empty() {
+        return (Cow::Borrowed(raw_email), 0);
+    }
+    let mut normalized = Vec::with_capacity(raw_email.len() + lone_crs.len());
+    let mut copied = 0;
+    for &i in &lone_crs {
+        normalized.extend_from_slice(&raw_email[copied..=i]);
+        normalized.push(b'\n');
+        copied = i + 1;
+    }
+    normalized.extend_from_slice(&raw_email[copied..]);
+    (Cow::Owned(normalized), lone_crs.len())
+}
+
+/// Parses an RFC 5322 `Date` header value into a unix timestamp.
+///
+/// Besides the current syntax, the obsolete forms of RFC 5322 §4.3 are accepted: two- and
+/// three-digit years, single-digit days, comments, the named US zones and the military zones
+/// (which are treated as `-0000`). A missing zone is treated as UTC. Returns `None` for anything
+/// else, including out-of-range dates such as "32 Dec".
+pub fn parse_email_date(value: &str) -> Option<u64> {
+    // Drop comments such as "(UTC)".
+    let mut cleaned = String::with_capacity(value.len());
+    let mut depth = 0usize;
+    for c in value.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ if depth == 0 => cleaned.push(c),
+            _ => {}
+        }
+    }
+    // Drop the optional day-of-week.
+    let cleaned = match cleaned.find(',') {
+        Some(pos) => &cleaned[pos + 1..],
+        None => cleaned.as_str(),
+    };
+
+    let mut tokens = cleaned.split_whitespace();
+    let day: u32 = parse_digits(tokens.next()?)?;
+    let month: u32 = match tokens.next()?.to_ascii_lowercase().as_str() {
+        "jan" => 1,
+        "feb" => 2,
+        "mar" => 3,
+        "apr" => 4,
+        "may" => 5,
+        "jun" => 6,
+        "jul" => 7,
+        "aug" => 8,
+        "sep" => 9,
+        "oct" => 10,
+        "nov" => 11,
+        "dec" => 12,
+        _ => return None,
+    };
+    let year_str = tokens.next()?;
+    let year: i64 = parse_digits(year_str)?;
+    let year = match year_str.len() {
+        2 if year < 50 => 2000 + year,
+        2 | 3 => 1900 + year,
+        4 => year,
+        _ => return None,
+    };
+
+    let mut time = tokens.next()?.split(':');
+    let hour: i64 = parse_digits(time.next()?)?;
+    let minute: i64 = parse_digits(time.next()?)?;
+    let second: i64 = match time.next() {
+        Some(second) => parse_digits(second)?,
+        None => 0,
+    };
+    if time.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let offset = match tokens.next() {
+        Some(zone) => parse_email_date_zone(zone)?,
+        None => 0,
+    };
+    if tokens.next().is_some() || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    let timestamp =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    timestamp.try_into().ok()
+}
+
+/// Parses a number of ASCII digits only, without the sign `str::parse` accepts.
+fn parse_digits<T: std::str::FromStr>(digits: &str) -> Option<T> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Returns the zone offset in seconds east of UTC.
+fn parse_email_date_zone(zone: &str) -> Option<i64> {
+    let bytes = zone.as_bytes();
+    if bytes.len() == 5 && (bytes[0] == b'+' || bytes[0] == b'-') {
+        // `get` rather than indexing: a non-ASCII zone of 5 bytes has no char boundary there.
+        let hours: i64 = parse_digits(zone.get(1..3)?)?;
+        let minutes: i64 = parse_digits(zone.get(3..5)?)?;
+        let offset = hours * 3600 + minutes * 60;
+        return Some(if bytes[0] == b'-' { -offset } else { offset });
+    }
+    let hours = match zone.to_ascii_lowercase().as_str() {
+        "ut" | "gmt" => 0,
+        "edt" => -4,
+        "est" | "cdt" => -5,
+        "cst" | "mdt" => -6,
+        "mst" | "pdt" => -7,
+        "pst" => -8,
+        // Military zones have historically been misinterpreted and must be treated as "-0000".
+        military if military.len() == 1 && military != "j" && bytes[0].is_ascii_alphabetic() => 0,
+        _ => return None,
+    };
+    Some(hours * 3600)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Number of days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(feature = "node")]
 pub fn parse_email_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let raw_email = cx.argument::<JsString>(0)?.value(&mut cx);
     let channel = cx.channel();
@@ -180,6 +1879,13 @@ pub fn parse_email_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
 
                     let public_key = cx.string(&public_key_str);
                     obj.set(&mut cx, "publicKey", public_key)?;
+                    let public_exponent = cx.number(parsed_email.public_exponent as f64);
+                    obj.set(&mut cx, "publicExponent", public_exponent)?;
+                    let dnssec_validated: Handle<JsValue> = match parsed_email.dnssec_validated {
+                        Some(validated) => cx.boolean(validated).upcast(),
+                        None => cx.null().upcast(),
+                    };
+                    obj.set(&mut cx, "dnssecValidated", dnssec_validated)?;
                     // let dkim_domain = cx.string(&parsed_email.dkim_domain);
                     // obj.set(&mut cx, "dkimDomain", dkim_domain)?;
                     Ok(obj)
@@ -194,6 +1900,241 @@ pub fn parse_email_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
     Ok(promise)
 }
 
+/// `verifyDkim(email)` resolves to the JSON `DkimVerification` of a valid signature and rejects
+/// otherwise.
+#[cfg(feature = "node")]
+pub fn verify_dkim_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let raw_email = cx.argument::<JsString>(0)?.value(&mut cx);
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(&mut cx)?;
+
+    rt.spawn(async move {
+        let verification = verify_dkim(&raw_email)
+            .await
+            .and_then(DkimVerification::into_verified)
+            .and_then(|verification| Ok(serde_json::to_string(&verification)?));
+        deferred.settle_with(&channel, move |mut cx| match verification {
+            Ok(verification) => Ok(cx.string(verification)),
+            Err(err) => throw_coded(
+                &mut cx,
+                error_code(&err, ErrorCode::ParseEmailFailed),
+                format!("Could not verify DKIM: {}", err),
+            ),
+        });
+    });
+
+    Ok(promise)
+}
+
+/// `publicKeyHashFromEmail(email)` resolves to the hex `public_key_hash_from_email`.
+#[cfg(feature = "node")]
+pub fn public_key_hash_from_email_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let raw_email = cx.argument::<JsString>(0)?.value(&mut cx);
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(&mut cx)?;
+
+    rt.spawn(async move {
+        let result = public_key_hash_from_email(&raw_email).await;
+        deferred.settle_with(&channel, move |mut cx| match result {
+            Ok(public_key_hash) => Ok(cx.string(public_key_hash)),
+            Err(err) => throw_coded(
+                &mut cx,
+                error_code(&err, ErrorCode::ParseEmailFailed),
+                format!("Could not compute the public key hash: {}", err),
+            ),
+        });
+    });
+
+    Ok(promise)
+}
+
+/// The canonicalized header of `raw_email`, with `ParsedEmail::parse_untrusted` when
+/// `header_only` is set, skipping the DKIM key lookup.
+#[cfg(feature = "node")]
+async fn canonicalized_header_of(raw_email: &[u8], header_only: bool) -> Result<String> {
+    if header_only {
+        ParsedEmail::parse_untrusted_bytes(raw_email).map(|email| email.canonicalized_header)
+    } else {
+        ParsedEmail::new_from_raw_bytes(raw_email)
+            .await
+            .map(|email| email.canonicalized_header)
+    }
+}
+
+/// The optional `headerOnly` boolean argument `i`.
+#[cfg(feature = "node")]
+fn header_only_argument(cx: &mut FunctionContext, i: i32) -> NeonResult<bool> {
+    match cx.argument_opt(i) {
+        Some(header_only) if !header_only.is_a::<JsUndefined, _>(cx) => {
+            Ok(header_only.downcast_or_throw::<JsBoolean, _>(cx)?.value(cx))
+        }
+        _ => Ok(false),
+    }
+}
+
+/// `extractSender(email, headerOnly?)` resolves to the JSON `EmailSender`. With `headerOnly` the
+/// email is parsed with `ParsedEmail::parse_untrusted`, skipping the DKIM key lookup, and the
+/// sender is not authenticated.
+#[cfg(feature = "node")]
+pub fn extract_sender_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let raw_email = cx.argument::<JsString>(0)?.value(&mut cx);
+    let header_only = header_only_argument(&mut cx, 1)?;
+    extract_sender_js(&mut cx, raw_email.into_bytes(), header_only)
+}
+
+/// `extractSenderBytes(buffer, headerOnly?)`: `extractSender` for the exact bytes of the email.
+#[cfg(feature = "node")]
+pub fn extract_sender_bytes_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let raw_email = cx.argument::<JsBuffer>(0)?;
+    let raw_email = raw_email.as_slice(&cx).to_vec();
+    let header_only = header_only_argument(&mut cx, 1)?;
+    extract_sender_js(&mut cx, raw_email, header_only)
+}
+
+#[cfg(feature = "node")]
+fn extract_sender_js<'a>(
+    cx: &mut FunctionContext<'a>,
+    raw_email: Vec<u8>,
+    header_only: bool,
+) -> JsResult<'a, JsPromise> {
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(cx)?;
+
+    rt.spawn(async move {
+        let canonicalized_header = canonicalized_header_of(&raw_email, header_only).await;
+        let sender = canonicalized_header.and_then(|canonicalized_header| {
+            extract_sender(&canonicalized_header)
+                .map_err(|e| coded(ErrorCode::ParseEmailFailed, e))
+                .and_then(|sender| Ok(serde_json::to_string(&sender)?))
+        });
+        deferred.settle_with(&channel, move |mut cx| match sender {
+            Ok(sender) => Ok(cx.string(sender)),
+            Err(err) => throw_coded(
+                &mut cx,
+                error_code(&err, ErrorCode::ParseEmailFailed),
+                format!("Could not extract the sender: {}", err),
+            ),
+        });
+    });
+
+    Ok(promise)
+}
+
+/// `extractSubject(email, headerOnly?)` resolves to the JSON `EmailSubject`, parsing the email as
+/// `extractSender` does.
+#[cfg(feature = "node")]
+pub fn extract_subject_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let raw_email = cx.argument::<JsString>(0)?.value(&mut cx);
+    let header_only = header_only_argument(&mut cx, 1)?;
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(&mut cx)?;
+
+    rt.spawn(async move {
+        let canonicalized_header =
+            canonicalized_header_of(raw_email.as_bytes(), header_only).await;
+        let subject = canonicalized_header.and_then(|canonicalized_header| {
+            extract_subject(&canonicalized_header)
+                .map_err(|e| coded(ErrorCode::ParseEmailFailed, e))
+                .and_then(|subject| Ok(serde_json::to_string(&subject)?))
+        });
+        deferred.settle_with(&channel, move |mut cx| match subject {
+            Ok(subject) => Ok(cx.string(subject)),
+            Err(err) => throw_coded(
+                &mut cx,
+                error_code(&err, ErrorCode::ParseEmailFailed),
+                format!("Could not extract the subject: {}", err),
+            ),
+        });
+    });
+
+    Ok(promise)
+}
+
+/// `receivedChain(rawEmail)` returns the JSON `ReceivedChain` of the email.
+#[cfg(feature = "node")]
+pub fn received_chain_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let raw_email = cx.argument::<JsString>(0)?.value(&mut cx);
+    match serde_json::to_string(&received_chain(&raw_email)) {
+        Ok(chain) => Ok(cx.string(chain)),
+        Err(e) => cx.throw_error(e.to_string()),
+    }
+}
+
+/// `extractEmailIndexes(rawEmail)` returns the JSON `IndexReport` of the email.
+#[cfg(feature = "node")]
+pub fn extract_email_indexes_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let raw_email = cx.argument::<JsString>(0)?.value(&mut cx);
+    match extract_email_indexes(&raw_email).and_then(|report| Ok(serde_json::to_string(&report)?)) {
+        Ok(report) => Ok(cx.string(report)),
+        Err(e) => throw_coded(
+            &mut cx,
+            error_code(&e, ErrorCode::ParseEmailFailed),
+            e.to_string(),
+        ),
+    }
+}
+
+/// `extractDkimInfo(rawEmail)` returns the JSON array of the `DkimInfo` of every DKIM-Signature,
+/// without DNS.
+#[cfg(feature = "node")]
+pub fn extract_dkim_info_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let raw_email = cx.argument::<JsString>(0)?.value(&mut cx);
+    match extract_dkim_info(&raw_email).and_then(|info| Ok(serde_json::to_string(&info)?)) {
+        Ok(info) => Ok(cx.string(info)),
+        Err(e) => throw_coded(
+            &mut cx,
+            error_code(&e, ErrorCode::ParseEmailFailed),
+            e.to_string(),
+        ),
+    }
+}
+
+#[cfg(feature = "node")]
+fn idxes_to_js<'a>(
+    cx: &mut FunctionContext<'a>,
+    idxes: Option<(usize, usize)>,
+) -> JsResult<'a, JsValue> {
+    match idxes {
+        Some((start_idx, end_idx)) => {
+            let start_end_array = JsArray::new(cx, 2u32);
+            let start_idx = cx.number(start_idx as f64);
+            start_end_array.set(cx, 0, start_idx)?;
+            let end_idx = cx.number(end_idx as f64);
+            start_end_array.set(cx, 1, end_idx)?;
+            Ok(start_end_array.upcast())
+        }
+        None => Ok(cx.null().upcast()),
+    }
+}
+
+#[cfg(feature = "node")]
+pub fn extract_indexes_node(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let canonical_header = cx.argument::<JsString>(0)?.value(&mut cx);
+    let indexes = match extract_indexes(canonical_header.as_bytes()) {
+        Ok(indexes) => indexes,
+        Err(e) => return cx.throw_error(e.to_string()),
+    };
+    let obj = cx.empty_object();
+    let from_addr = idxes_to_js(&mut cx, indexes.from_addr)?;
+    obj.set(&mut cx, "fromAddr", from_addr)?;
+    let domain = idxes_to_js(&mut cx, indexes.domain)?;
+    obj.set(&mut cx, "domain", domain)?;
+    let subject = idxes_to_js(&mut cx, indexes.subject)?;
+    obj.set(&mut cx, "subject", subject)?;
+    let email_addr_in_subject = idxes_to_js(&mut cx, indexes.email_addr_in_subject)?;
+    obj.set(&mut cx, "emailAddrInSubject", email_addr_in_subject)?;
+    let timestamp = idxes_to_js(&mut cx, indexes.timestamp)?;
+    obj.set(&mut cx, "timestamp", timestamp)?;
+    let invitation_code = idxes_to_js(&mut cx, indexes.invitation_code)?;
+    obj.set(&mut cx, "invitationCode", invitation_code)?;
+    Ok(obj)
+}
+
+#[cfg(feature = "node")]
 pub fn extract_invitation_code_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let regex_config =
@@ -214,6 +2155,7 @@ pub fn extract_invitation_code_idxes_node(mut cx: FunctionContext) -> JsResult<J
     Ok(js_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_timestamp_int_node(mut cx: FunctionContext) -> JsResult<JsNumber> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let substr_idxes = match extract_timestamp_idxes(&input_str) {
@@ -229,6 +2171,7 @@ pub fn extract_timestamp_int_node(mut cx: FunctionContext) -> JsResult<JsNumber>
     Ok(timestamp_int)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_invitation_code_with_prefix_idxes_node(
     mut cx: FunctionContext,
 ) -> JsResult<JsArray> {
@@ -250,3 +2193,693 @@ pub fn extract_invitation_code_with_prefix_idxes_node(
     }
     Ok(js_array)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_parse_email_date() {
+        assert_eq!(
+            parse_email_date(" Wed, 13 Dec 2023 10:00:00 +0000"),
+            Some(1702461600)
+        );
+        assert_eq!(
+            parse_email_date("Wed, 13 Dec 2023 11:00:00 +0100 (CET)"),
+            Some(1702461600)
+        );
+        // Obsolete forms.
+        assert_eq!(parse_email_date("Fri, 5 Mar 99 03:30:00 EST"), Some(920640600));
+        assert_eq!(parse_email_date("1 Feb 24 00:00 Z"), Some(1706745600));
+        assert_eq!(parse_email_date("Wed, 13 Dec 2023 10:00:00"), Some(1702461600));
+        assert_eq!(parse_email_date("13 Dec 123 09:00:00 A"), Some(1702458000));
+        // Garbage.
+        assert_eq!(parse_email_date("Wed, 32 Dec 2023 10:00:00 +0000"), None);
+        assert_eq!(parse_email_date("yesterday, around noon"), None);
+        assert_eq!(parse_email_date("Wed, 13 Dec 2023 -1:00:00 +0000"), None);
+        assert_eq!(parse_email_date("Wed, 13 Dec 2023 10:00:00 +0é0"), None);
+    }
+
+    #[test]
+    fn test_date_fixtures() {
+        for (name, date) in [
+            ("send_eth", Some(1702461600)),
+            ("send_eth_date_two_digit_year", Some(1702461600)),
+            ("send_eth_date_single_digit_day", Some(1701770400)),
+            ("send_eth_date_military_zone", Some(1702461600)),
+            ("send_eth_date_garbage", None),
+        ] {
+            let fixture = test_fixture(name).unwrap();
+            let parsed_email = fixture.parsed_email().unwrap();
+            assert_eq!(parsed_email.get_date(), date, "{}", name);
+            // The Date header is not part of the input, so input generation never fails on it.
+            let account_code = AccountCode(hex2field(&fixture.account_code).unwrap());
+            let options = InputGenerationOptions::default();
+            VerifiedEmail::from_parsed_email(parsed_email)
+                .unwrap()
+                .extract(&account_code, &options)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_strip_protocol_artifacts() {
+        let raw_email = "+OK 120 octets\r\nfrom:alice@gmail.com\r\n\r\n..hidden\r\n.\r\n";
+        let (sanitized, warnings) = strip_protocol_artifacts(raw_email);
+        assert_eq!(sanitized, "from:alice@gmail.com\r\n\r\n.hidden\r\n");
+        assert_eq!(warnings.len(), 3);
+
+        // Without POP3 framing, leading dots are part of the message.
+        let raw_email = "from:alice@gmail.com\r\n\r\n..kept\r\n";
+        let (sanitized, warnings) = strip_protocol_artifacts(raw_email);
+        assert_eq!(sanitized, raw_email);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_extract_dkim_signature_idxes() {
+        let header = "from:alice@gmail.com\r\nsubject:hi\r\ndkim-signature:v=1; a=rsa-sha256; bh=47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=; h=from:subject; b=";
+        let idxes = extract_dkim_signature_idxes(header).unwrap();
+        assert!(header[idxes.header.0..].starts_with("dkim-signature:"));
+        assert_eq!(idxes.header.1, header.len());
+        assert_eq!(
+            &header[idxes.body_hash.0..idxes.body_hash.1],
+            "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+        );
+        assert_eq!(&header[idxes.signature_tag.0..idxes.signature_tag.1], "b=");
+        assert_eq!(idxes.domain, None);
+        let header = "dkim-signature:v=1; d= mail.example ; bh=; b=";
+        let idxes = extract_dkim_signature_idxes(header).unwrap();
+        let domain = idxes.domain.unwrap();
+        assert_eq!(&header[domain.0..domain.1], "mail.example");
+
+        assert!(extract_dkim_signature_idxes("subject:dkim-signature: b=\r\n").is_err());
+    }
+
+    #[test]
+    fn test_extract_indexes() {
+        let header = "from:Alice <alice@gmail.com>\r\nsubject:Send 1 ETH to bob@example.com\r\n";
+        let indexes = extract_indexes(header.as_bytes()).unwrap();
+
+        let (start, end) = indexes.from_addr.unwrap();
+        let from_addr = &header[start..end];
+        assert_eq!(from_addr, "alice@gmail.com");
+        let (start, end) = indexes.domain.unwrap();
+        assert_eq!(&from_addr[start..end], "gmail.com");
+        let (start, end) = indexes.subject.unwrap();
+        let subject = &header[start..end];
+        assert_eq!(subject, "Send 1 ETH to bob@example.com");
+        let (start, end) = indexes.email_addr_in_subject.unwrap();
+        assert_eq!(&subject[start..end], "bob@example.com");
+        assert_eq!(indexes.timestamp, None);
+
+        assert_eq!(extract_indexes(b"x-other:value\r\n").unwrap(), HeaderIndexes::default());
+    }
+
+    #[test]
+    fn test_header_getters_are_anchored_to_header_starts() {
+        let header = concat!(
+            "x-note:from:mallory@evil.com subject:Send 9 ETH\r\n",
+            "from:Alice <alice@gmail.com>\r\n",
+            "subject:Re: subject: urgent from:bob@example.com\r\n",
+        );
+        let parsed_email = ParsedEmail {
+            canonicalized_header: header.to_string(),
+            canonicalized_body: String::new(),
+            signature: vec![],
+            public_key: vec![],
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
+        };
+        assert_eq!(parsed_email.get_from_addr().unwrap(), "alice@gmail.com");
+        assert_eq!(parsed_email.get_email_domain().unwrap(), "gmail.com");
+        assert_eq!(
+            parsed_email.get_subject_all().unwrap(),
+            "Re: subject: urgent from:bob@example.com"
+        );
+        let indexes = extract_indexes(header.as_bytes()).unwrap();
+        let (start, end) = indexes.subject.unwrap();
+        assert_eq!(start, header.find("Re: subject").unwrap());
+        assert_eq!(&header[start..end], "Re: subject: urgent from:bob@example.com");
+
+        assert_eq!(header_value_idxes("x-subject:a\r\n", "subject"), None);
+        assert_eq!(header_value_idxes("a:b\r\nSUBJECT:c", "subject"), Some((13, 14)));
+    }
+
+    #[test]
+    fn test_subject_relative_idxes_do_not_depend_on_position() {
+        let subject = "subject:Send 1 ETH to bob@example.com";
+        let from = "from:alice@gmail.com";
+        let to = "to:carol@example.org";
+        let dkim = "dkim-signature:v=1; a=rsa-sha256; d=gmail.com; s=20230601; bh=abc=; b=";
+        let headers = [
+            format!("{}\r\n{}\r\n{}\r\n", subject, from, to),
+            format!("{}\r\n{}\r\n{}\r\n", from, subject, to),
+            format!("{}\r\n{}\r\n{}\r\n", from, to, subject),
+            format!("{}\r\n{}\r\n{}", from, to, subject),
+            format!("{}\r\n{}\r\n{}\r\n{}", from, to, subject, dkim),
+        ];
+        for header in headers.iter() {
+            let indexes = extract_indexes(header.as_bytes()).unwrap();
+            let (start, end) = indexes.subject.unwrap();
+            assert_eq!(&header[start..end], "Send 1 ETH to bob@example.com", "{:?}", header);
+            assert_eq!(indexes.email_addr_in_subject, Some((14, 29)), "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn test_invisible_chars_in_addresses_and_commands() {
+        let account_code = fixture_account_code();
+        for c in ['\u{200B}', '\u{200C}', '\u{00A0}', '\u{FEFF}'] {
+            for email_addr in [format!("ali{}ce@gmail.com", c), format!("alice@gm{}ail.com", c)] {
+                let err = check_invisible_chars(&email_addr, "from address")
+                    .unwrap_err()
+                    .to_string();
+                assert!(err.contains(&format!("U+{:04X}", c as u32)), "{}", err);
+
+                let header = format!("from:{}\r\nsubject:Send 1 ETH\r\n", email_addr);
+                let parsed_email = fixture_signed_email(&header, "");
+                let options = InputGenerationOptions::default();
+                // The from address regex stops at the character, and the error says so.
+                assert_eq!(parsed_email.get_header_indexes().unwrap().from_addr, None);
+                let err = generate_email_auth_input_from_parsed_email(
+                    &parsed_email,
+                    &account_code,
+                    &options,
+                )
+                .unwrap_err()
+                .to_string();
+                assert!(
+                    err.contains(&format!(
+                        "the From header contains the invisible character U+{:04X}",
+                        c as u32
+                    )),
+                    "{}",
+                    err
+                );
+
+                let header = format!(
+                    "from:alice@gmail.com\r\nsubject:Send 1 ETH to {}\r\n",
+                    email_addr
+                );
+                let err = generate_email_auth_input_from_parsed_email(
+                    &fixture_signed_email(&header, ""),
+                    &account_code,
+                    &options,
+                )
+                .unwrap_err()
+                .to_string();
+                assert!(err.contains("email address in the subject"), "{}", err);
+            }
+
+            let params = format!(r#"["1{}"]"#, c);
+            let email = ParsedEmail {
+                canonicalized_header: "subject:Send 1\r\n".to_string(),
+                canonicalized_body: String::new(),
+                signature: vec![],
+                public_key: vec![],
+                public_exponent: DEFAULT_PUBLIC_EXPONENT,
+                dnssec_validated: None,
+            };
+            let err = reconstruct_command("Send {uint}", &params, &email, AddressFormat::Checksum)
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("command parameter"), "{}", err);
+        }
+        assert!(check_invisible_chars("alice@gmail.com", "from address").is_ok());
+        // The characters cannot be stripped from the signed header, so there is no such policy.
+        let options_json = r#"{"invisible_char_policy": "strip_and_warn"}"#;
+        let err = InputGenerationOptions::from_json(options_json).unwrap_err();
+        assert!(err.to_string().contains("unknown option `invisible_char_policy`"), "{}", err);
+    }
+
+    #[test]
+    fn test_check_from_header_coverage() {
+        let dkim = "DKIM-Signature: v=1; a=rsa-sha256; d=gmail.com; s=20230601;\r\n\th=from:to:subject; bh=Zm9v; b=YmFy\r\n";
+        let signed = "From: Alice <alice@gmail.com>\r\nTo: bob@example.com\r\nSubject: Hi\r\n";
+        let injected = "From: Mallory <mallory@evil.com>\r\n";
+        let body = "\r\nbody\r\n";
+        let canonical = "dkim-signature:v=1; a=rsa-sha256; d=gmail.com; s=20230601; \
+                         h=from:to:subject; bh=Zm9v; b=";
+
+        let email = format!("{}{}{}", dkim, signed, body);
+        assert!(check_from_header_coverage(&email, canonical).is_ok());
+        for email in [
+            format!("{}{}{}{}", injected, dkim, signed, body),
+            format!("{}{}{}{}", dkim, signed, injected, body),
+        ] {
+            let err = check_from_header_coverage(&email, canonical).unwrap_err();
+            assert_eq!(
+                err.downcast::<UnsignedFromHeader>().unwrap(),
+                UnsignedFromHeader {
+                    from_headers: 2,
+                    signed_from_headers: 1
+                }
+            );
+        }
+
+        // Over-signing covers the extra instance.
+        let oversigned = dkim.replace("h=from:to", "h=from:from:to");
+        let email = format!("{}{}{}{}", oversigned, injected, signed, body);
+        let canonical = canonical.replace("h=from:to", "h=from:from:to");
+        assert!(check_from_header_coverage(&email, &canonical).is_ok());
+    }
+
+    #[test]
+    fn test_signed_headers_of_the_verified_signature() {
+        // A mailing list re-signs the email above the Gmail signature, over other headers.
+        let list = "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=lists.example.org;\r\n\
+                    \ts=list; h=list-id:subject; bh=TGlzdA==; b=TGlzdA==\r\n";
+        let gmail = "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=gmail.com;\r\n\
+                     \ts=20230601; h=from:to:subject; bh=Zm9v; b=YmFy\r\n";
+        let raw = format!(
+            "{}{}List-Id: <list.example.org>\r\nFrom: Alice <alice@gmail.com>\r\n\
+             To: list@example.org\r\nSubject: Hi\r\n\r\nbody\r\n",
+            list, gmail
+        );
+        let gmail_canonical = "from:Alice <alice@gmail.com>\r\nto:list@example.org\r\n\
+                               subject:Hi\r\ndkim-signature:v=1; a=rsa-sha256; \
+                               c=relaxed/relaxed; d=gmail.com; s=20230601; h=from:to:subject; \
+                               bh=Zm9v; b=";
+        let list_canonical = "list-id:<list.example.org>\r\nsubject:Hi\r\n\
+                              dkim-signature:v=1; a=rsa-sha256; c=relaxed/relaxed; \
+                              d=lists.example.org; s=list; h=list-id:subject; bh=TGlzdA==; b=";
+
+        let names = |canonical: &str| {
+            select_signed_headers(&raw, canonical)
+                .unwrap()
+                .into_iter()
+                .map(|header| header.name)
+                .collect_vec()
+        };
+        assert_eq!(names(gmail_canonical), ["from", "to", "subject"]);
+        assert_eq!(names(list_canonical), ["list-id", "subject"]);
+        assert!(check_from_header_coverage(&raw, gmail_canonical).is_ok());
+        assert!(check_signed_header_selection(&raw, gmail_canonical).is_ok());
+        // The list signature does not cover From, whatever the Gmail one signs.
+        let err = check_from_header_coverage(&raw, list_canonical).unwrap_err();
+        assert!(err.is::<UnsignedFromHeader>(), "{}", err);
+
+        // A canonicalized header for no DKIM-Signature of the email is rejected.
+        let other = gmail_canonical.replace("s=20230601", "s=20161025");
+        for canonical in [other.as_str(), "from:Alice <alice@gmail.com>\r\n"] {
+            let err = select_signed_headers(&raw, canonical).unwrap_err();
+            assert_eq!(error_code(&err, ErrorCode::InternalPanic), ErrorCode::ParseEmailFailed);
+        }
+    }
+
+    #[test]
+    fn test_select_signed_headers_consumes_from_the_bottom() {
+        let email = |h: &str, fields: &[&str]| {
+            format!(
+                "DKIM-Signature: v=1; c=relaxed/relaxed; h={}; bh=Zm9v; b=YmFy\r\n{}\r\nbody\r\n",
+                h,
+                fields.join("")
+            )
+        };
+        let canonical_for = |h: &str| {
+            format!("dkim-signature:v=1; c=relaxed/relaxed; h={}; bh=Zm9v; b=", h)
+        };
+        let selected = |h: &str, fields: &[&str]| {
+            select_signed_headers(&email(h, fields), &canonical_for(h))
+                .unwrap()
+                .iter()
+                .map(|header| (header.name.clone(), header.field_index))
+                .collect_vec()
+        };
+        let top = "From: top@example.com\r\n";
+        let bottom = "From: bottom@example.com\r\n";
+        let subject = "Subject: Hi\r\n";
+        let from = |i: usize| ("from".to_string(), i);
+        let subject_at = |i: usize| ("subject".to_string(), i);
+
+        // Over-signing a single instance: the second entry selects nothing.
+        assert_eq!(selected("from:from:subject", &[top, subject]), [from(1), subject_at(2)]);
+        // Two instances: bottom first, then the next one up, whatever the entry order.
+        for h in ["from:from:subject", "from:subject:from", "From : FROM:subject"] {
+            let selected = selected(h, &[top, subject, bottom]);
+            let froms = selected
+                .iter()
+                .filter(|(name, _)| name == "from")
+                .collect_vec();
+            assert_eq!(froms, [&from(3), &from(1)], "{}", h);
+        }
+        assert_eq!(
+            selected("subject:from:subject:from", &[top, bottom, subject]),
+            [subject_at(3), from(2), from(1)]
+        );
+
+        // The canonicalized header of an over-signed email hashes the bottom instance first,
+        // and so the From indexes point into it.
+        let raw = email("from:from:subject", &[top, subject, bottom]);
+        let canonical = "from:bottom@example.com\r\nfrom:top@example.com\r\nsubject:Hi\r\n\
+                         dkim-signature:v=1; c=relaxed/relaxed; h=from:from:subject; bh=Zm9v; b=";
+        assert!(check_signed_header_selection(&raw, canonical).is_ok());
+        let indexes = extract_indexes(canonical.as_bytes()).unwrap();
+        assert_eq!(
+            &canonical[indexes.from_addr.unwrap().0..indexes.from_addr.unwrap().1],
+            "bottom@example.com"
+        );
+        let top_first = format!(
+            "from:top@example.com\r\nfrom:bottom@example.com\r\nsubject:Hi\r\n{}",
+            canonical_for("from:from:subject")
+        );
+        assert!(check_signed_header_selection(&raw, &top_first).is_err());
+    }
+
+    #[cfg(feature = "dns")]
+    #[test]
+    fn test_parse_latin1_email_bytes() {
+        use base64::{engine::general_purpose, Engine as _};
+        use rsa::pkcs1v15::SigningKey;
+        use rsa::signature::{SignatureEncoding, Signer};
+        use rsa::traits::PublicKeyParts;
+        use sha2::{Digest, Sha256};
+
+        let body = b"caf\xe9 au lait\r\n";
+        let body_hash = general_purpose::STANDARD.encode(Sha256::digest(body));
+        let email = |signature: &str| {
+            let header = format!(
+                "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com; s=s1;\r\n\
+                 \th=from:subject; bh={}; b={}\r\n\
+                 From: alice@example.com\r\nSubject: Hi\r\n\r\n",
+                body_hash, signature
+            );
+            [header.as_bytes(), body].concat()
+        };
+        let private_key = rsa::RsaPrivateKey::new(&mut rand_core::OsRng, 1024).unwrap();
+        let unsigned = ParsedEmail::parse_untrusted_bytes(&email("")).unwrap();
+        let signature = SigningKey::<Sha256>::new(private_key.clone())
+            .sign(unsigned.canonicalized_header.as_bytes())
+            .to_vec();
+
+        let raw_email = email(&general_purpose::STANDARD.encode(&signature));
+        assert!(std::str::from_utf8(&raw_email).is_err());
+        let parsed = ParsedEmail::parse_untrusted_bytes(&raw_email).unwrap();
+        assert_eq!(parsed.canonicalized_header, unsigned.canonicalized_header);
+        assert_eq!(parsed.signature, signature);
+        assert!(verify_rsa_sha256(
+            parsed.canonicalized_header.as_bytes(),
+            &parsed.signature,
+            &private_key.n().to_bytes_be(),
+        )
+        .is_ok());
+        assert_eq!(parsed.dkim_tags["bh"], body_hash);
+    }
+
+    #[test]
+    fn test_received_chain() {
+        let dkim = "DKIM-Signature: v=1; a=rsa-sha256; d=gmail.com; s=20230601;\r\n\th=from; bh=Zm9v; b=YmFy\r\n";
+        let normal = format!(
+            "Received: by mx.example.com with SMTP id a1;\r\n        Wed, 13 Dec 2023 10:00:02 +0000\r\n\
+             Received: from mail-sor-f41.google.com by mx.example.com; Wed, 13 Dec 2023 10:00:01 +0000\r\n\
+             {}From: alice@gmail.com\r\nSubject: Hi\r\n\r\nbody\r\n",
+            dkim
+        );
+        let chain = received_chain(&normal);
+        assert_eq!(chain.hop_count, 2);
+        assert!(!chain.received_below_signature);
+        assert!(chain.warnings.is_empty());
+        assert_eq!(
+            chain.hops[0].value,
+            "by mx.example.com with SMTP id a1;        Wed, 13 Dec 2023 10:00:02 +0000"
+        );
+        assert_eq!(chain.hops[1].field_index, 1);
+        assert!(chain
+            .hops
+            .iter()
+            .all(|hop| hop.position == ReceivedPosition::AboveSignature));
+
+        // Signed once, then submitted again through another relay with the old trace kept.
+        let reinjected = format!(
+            "Received: from relay.evil.example by mx.example.com; Thu, 14 Dec 2023 09:00:00 +0000\r\n\
+             {}Received: from mail-sor-f41.google.com by mx.example.com; Wed, 13 Dec 2023 10:00:01 +0000\r\n\
+             From: alice@gmail.com\r\nSubject: Hi\r\n\r\nbody\r\n",
+            dkim
+        );
+        let chain = received_chain(&reinjected);
+        assert_eq!(chain.hop_count, 2);
+        assert!(chain.received_below_signature);
+        assert_eq!(chain.warnings, [ReceivedChain::RECEIVED_BELOW_SIGNATURE]);
+        assert_eq!(chain.hops[1].position, ReceivedPosition::BelowSignature);
+        assert_eq!(chain.hops[1].field_index, 2);
+
+        let chain = received_chain("Received: by mx.example.com\r\nSubject: Hi\r\n\r\n");
+        assert_eq!(chain.hops[0].position, ReceivedPosition::Unsigned);
+        assert!(!chain.received_below_signature);
+    }
+
+    #[test]
+    fn test_extract_sender() {
+        let header = "to:bob@example.com\r\nfrom:Alice <alice@gmail.com>\r\nsubject:Hi\r\n";
+        let sender = extract_sender(header).unwrap();
+        assert_eq!(sender.from_addr, "alice@gmail.com");
+        assert_eq!(sender.domain, "gmail.com");
+        assert_eq!(&header[sender.from_addr_idx..][..15], "alice@gmail.com");
+        assert_eq!(sender.domain_idx, 6);
+
+        assert!(extract_sender("to:bob@example.com\r\nsubject:Hi\r\n").is_err());
+    }
+
+    #[test]
+    fn test_extract_subject() {
+        let header = "from:alice@gmail.com\r\nsubject:=?utf-8?Q?Caf=C3=A9?= =?utf-8?B?IMOgIDk=?=\r\n";
+        let subject = extract_subject(header).unwrap();
+        assert_eq!(subject.subject, "=?utf-8?Q?Caf=C3=A9?= =?utf-8?B?IMOgIDk=?=");
+        assert_eq!(&header[subject.subject_idx..][..subject.subject_len], subject.subject);
+        assert_eq!(subject.decoded_subject.as_deref(), Some("Café à 9"));
+
+        let subject = extract_subject("subject:Send 1 ETH to bob@example.com").unwrap();
+        assert_eq!(subject.subject_len, 29);
+        assert_eq!(subject.decoded_subject, None);
+        assert_eq!(
+            decode_encoded_words("Re: =?iso-8859-1?q?na=EFve_idea?= ok").as_deref(),
+            Some("Re: naïve idea ok")
+        );
+        assert!(extract_subject("from:alice@gmail.com\r\n").is_err());
+    }
+
+    #[test]
+    fn test_encoded_subject() {
+        let subject = "Re: =?UTF-8?Q?Send_1_=E2=82=AC?= =?utf-8?q?_to_bob=40example.com?=";
+        let encoded = EncodedSubject::decode(subject).unwrap().unwrap();
+        assert_eq!(encoded.decoded, "Re: Send 1 € to bob@example.com");
+        let euro = encoded.decoded.find('€').unwrap();
+        let (start, end) = encoded.raw_range((euro, euro + '€'.len_utf8()));
+        assert_eq!(&subject[start..end], "=E2=82=AC");
+        let at = encoded.decoded.find('@').unwrap();
+        let (start, end) = encoded.raw_range((at - 3, at + 1));
+        assert_eq!(&subject[start..end], "bob=40");
+        assert_eq!(EncodedSubject::decode("Send 1 ETH").unwrap(), None);
+
+        for (subject, expected) in [
+            ("=?utf-8?b?U2VuZA==?=", BASE64_SUBJECT_UNSUPPORTED),
+            ("=?iso-8859-1?q?na=EFve?=", "charset iso-8859-1 is unsupported"),
+            ("=?utf-8?q?bad=4?=", "invalid Q-encoding"),
+        ] {
+            let err = EncodedSubject::decode(subject).unwrap_err();
+            assert_eq!(
+                error_code(&err, ErrorCode::CircuitInputFailed),
+                ErrorCode::ParseEmailFailed
+            );
+            assert!(err.to_string().contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_empty_subject_is_absent() {
+        let email = |subject_line: &str| ParsedEmail {
+            canonicalized_header: format!(
+                "from:alice@gmail.com\r\n{}\r\ndate:Wed, 13 Dec 2023 10:00:00 +0000\r\n",
+                subject_line
+            ),
+            canonicalized_body: String::new(),
+            signature: vec![],
+            public_key: vec![],
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
+        };
+        // "Subject:" as is, "Subject: " after simple canonicalization (relaxed reduces it to
+        // the first), and only whitespace.
+        for subject_line in ["subject:", "Subject: ", "subject: \t "] {
+            let email = email(subject_line);
+            assert!(has_empty_subject(&email.canonicalized_header));
+            assert_eq!(email.get_header_indexes().unwrap().subject, None);
+            let err = email.get_subject_all_idxes().unwrap_err().to_string();
+            assert!(err.starts_with(EMPTY_SUBJECT), "{}", err);
+        }
+
+        let email = email("subject:Send 1 ETH");
+        assert!(!has_empty_subject(&email.canonicalized_header));
+        assert_eq!(email.get_subject_all().unwrap(), "Send 1 ETH");
+
+        let absent = ParsedEmail {
+            canonicalized_header: "from:alice@gmail.com\r\n".to_string(),
+            ..email
+        };
+        let err = absent.get_subject_all_idxes().unwrap_err().to_string();
+        assert!(!err.contains(EMPTY_SUBJECT), "{}", err);
+    }
+
+    #[cfg(feature = "dns")]
+    #[test]
+    fn test_extract_email_indexes() {
+        let fixture = crate::test_fixtures::test_fixture("send_eth").unwrap();
+        let report = extract_email_indexes(&fixture.raw_email).unwrap();
+        let header = &fixture.canonicalized_header;
+        assert_eq!(report.canonicalized_header_len, header.len());
+        let slice = |idxes: Option<(usize, usize)>| idxes.map(|(start, end)| &header[start..end]);
+        assert_eq!(slice(report.domain_in_header), Some("fixtures.zkemail.example"));
+        assert_eq!(
+            slice(report.email_addr_in_subject_in_header),
+            Some("bob@example.com")
+        );
+        let (subject_start, _) = report.subject.unwrap();
+        let (start, _) = report.email_addr_in_subject.unwrap();
+        assert_eq!(
+            report.email_addr_in_subject_in_header.unwrap().0,
+            subject_start + start
+        );
+        assert!(report.dkim_signature.unwrap().domain.is_some());
+
+        let report = serde_json::to_value(&report).unwrap();
+        assert!(report["invitation_code"].is_null());
+        assert!(report["timestamp"].is_null());
+    }
+
+    #[cfg(feature = "dns")]
+    #[test]
+    fn test_lone_crs_in_folded_headers() {
+        let fixture = crate::test_fixtures::test_fixture("send_eth").unwrap();
+        // A Thunderbird mbox export folds the DKIM-Signature and Subject with bare CRs.
+        let exported = fixture
+            .raw_email
+            .replacen("a=rsa-sha256; ", "a=rsa-sha256;\r ", 1)
+            .replacen("Send 1 ETH to ", "Send 1 ETH to\r ", 1);
+        assert_eq!(exported.matches("\r ").count(), 2);
+        let (normalized, lone_crs) = normalize_header_line_endings(exported.as_bytes());
+        assert_eq!(lone_crs, 2);
+        assert!(!String::from_utf8_lossy(&normalized).contains("\r "));
+        // Without the conversion the CR stays in the canonicalized header.
+        let (header, _, _) = canonicalize_signed_email(exported.as_bytes()).unwrap();
+        assert_ne!(header, fixture.canonicalized_header.as_bytes());
+
+        let parsed_email = ParsedEmail::parse_untrusted(&exported).unwrap();
+        assert_eq!(
+            parsed_email.canonicalized_header,
+            fixture.canonicalized_header
+        );
+        verify_rsa_sha256(
+            parsed_email.canonicalized_header.as_bytes(),
+            &parsed_email.signature,
+            &hex::decode(&fixture.public_key).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            extract_email_indexes(&exported).unwrap(),
+            extract_email_indexes(&fixture.raw_email).unwrap()
+        );
+
+        // Lone CRs of the body are left alone.
+        let email = "Subject: Hi\r\n\r\nline\rline\r\n";
+        let (normalized, lone_crs) = normalize_header_line_endings(email.as_bytes());
+        assert_eq!((normalized.as_ref(), lone_crs), (email.as_bytes(), 0));
+    }
+
+    #[test]
+    fn test_extract_dkim_info() {
+        let email = "DKIM-Signature: v=1; a=rsa-sha256; d=Example.com.; s=Sel1;\r\n\
+                     \th=From:To:\r\n Subject; bh=; b=\r\n\
+                     DKIM-Signature: v=1; d=esp.example; s=s2; h=from; b=\r\n\
+                     From: alice@example.com\r\n\r\nbody\r\n";
+        let info = extract_dkim_info(email).unwrap();
+        assert_eq!(
+            info[0],
+            DkimInfo {
+                selector: "sel1".to_string(),
+                domain: "example.com".to_string(),
+                dns_record: "sel1._domainkey.example.com".to_string(),
+                header_fields_signed: vec!["from".into(), "to".into(), "subject".into()],
+            }
+        );
+        assert_eq!(info[1].dns_record, "s2._domainkey.esp.example");
+
+        for (signature, expected) in [
+            ("d=example.com; h=from", "the s= tag is missing"),
+            ("d=example.com; s=; h=from", "the s= tag is missing"),
+            ("d=a; d=b; s=s; h=from", "the d= tag is repeated"),
+            ("d=example.com; s=s; h=from; garbage", "`garbage` is not a tag=value pair"),
+        ] {
+            let email = format!("DKIM-Signature: {}\r\n\r\n", signature);
+            let err = extract_dkim_info(&email).unwrap_err();
+            assert_eq!(
+                error_code(&err, ErrorCode::CircuitInputFailed),
+                ErrorCode::ParseEmailFailed
+            );
+            assert!(err.to_string().contains(expected), "{}", err);
+        }
+        let err = extract_dkim_info("From: alice@example.com\r\n\r\n").unwrap_err();
+        assert!(err.to_string().contains("no DKIM-Signature"), "{}", err);
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime", feature = "body"))]
+    #[tokio::test]
+    async fn test_verify_dkim_without_signature() {
+        let email = "From: alice@gmail.com\r\nSubject: Hi\r\n\r\nbody\r\n";
+        let err = verify_dkim(email).await.unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::ParseEmailFailed),
+            ErrorCode::DkimVerificationFailed
+        );
+        let err = err.to_string();
+        assert!(err.contains("no DKIM-Signature"), "{}", err);
+        // A signature without a selector is rejected before any DNS lookup.
+        let email = format!("DKIM-Signature: v=1; a=rsa-sha256; d=gmail.com; bh=Zm9v; b=YmFy\r\n{}", email);
+        let err = verify_dkim(&email).await.unwrap_err().to_string();
+        assert!(err.contains("s= tag"), "{}", err);
+    }
+
+    #[cfg(all(feature = "dns", feature = "async-runtime"))]
+    #[tokio::test]
+    async fn test_public_key_hash_from_email() {
+        let fixture = test_fixture("send_eth").unwrap();
+        let public_key_n = decode_hex(&fixture.public_key).unwrap();
+        cache_dkim_key(
+            DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+            public_key_n.clone(),
+        );
+        let public_key_hash = public_key_hash_from_email(&fixture.raw_email).await.unwrap();
+        assert_eq!(public_key_hash, fixture.expected.public_key_hash);
+        // `publicKeyHash` hashes the reversed big-endian modulus.
+        let reversed = public_key_n.into_iter().rev().collect::<Vec<_>>();
+        assert_eq!(
+            public_key_hash,
+            field2hex(&crate::cryptos::public_key_hash(&reversed).unwrap())
+        );
+
+        let err = public_key_hash_from_email("From: alice@gmail.com\r\n\r\nbody\r\n")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::DkimDnsFailed),
+            ErrorCode::ParseEmailFailed
+        );
+        assert!(err.to_string().contains("no DKIM-Signature"), "{}", err);
+
+        // `.invalid` never resolves (RFC 2606).
+        let email = "DKIM-Signature: v=1; a=rsa-sha256; d=relayer-utils.invalid; s=s1;\r\n\
+                     \th=from; bh=Zm9v; b=YmFy\r\nFrom: alice@relayer-utils.invalid\r\n\
+                     \r\nbody\r\n";
+        let err = public_key_hash_from_email(email).await.unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::ParseEmailFailed),
+            ErrorCode::DkimDnsFailed
+        );
+        assert!(
+            err.to_string().contains("s1._domainkey.relayer-utils.invalid"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,100 @@
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use crate::*;
+use halo2curves::ff::PrimeField;
+use serde_json::{json, Value};
+
+/// Describes the build target and the enabled features, so that an app can detect an artifact
+/// built with the wrong feature set.
+pub fn platform_info() -> Value {
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "arch": std::env::consts::ARCH,
+        "os": std::env::consts::OS,
+        "circuit_profile": active_circuit_profile(),
+        "features": {
+            "dns": cfg!(feature = "dns"),
+            "async_runtime": cfg!(feature = "async-runtime"),
+            "dnssec": cfg!(feature = "dnssec"),
+            "body": cfg!(feature = "body"),
+            "node": cfg!(feature = "node"),
+        },
+    })
+}
+
+/// Identifies the loaded library: the crate version, the git commit it was built from, the
+/// default circuit parameters, the scalar field modulus and the Poseidon `config_fingerprint`
+/// (`null` if Poseidon fails), so that a deployed artifact can be matched against the circuits.
+pub fn version_info() -> Value {
+    let options = InputGenerationOptions::default();
+    let profile = active_circuit_profile();
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_commit": GIT_COMMIT,
+        "circuit_profile": profile.name,
+        "defaults": {
+            "max_header_length": options.max_header_length,
+            "max_body_length": options.max_body_length,
+            "max_header_padded_bytes": profile.max_header_bytes,
+            "max_body_padded_bytes": profile.max_body_bytes,
+            "max_subject_bytes": profile.max_subject_bytes,
+            "max_email_addr_bytes": profile.max_email_addr_bytes,
+        },
+        "field_modulus": Fr::MODULUS,
+        "poseidon_fingerprint": config_fingerprint()
+            .ok()
+            .map(|fingerprint| "0x".to_string() + &hex::encode(fingerprint)),
+    })
+}
+
+#[cfg(feature = "node")]
+pub fn platform_info_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let platform_info = platform_info().to_string();
+    Ok(cx.string(platform_info))
+}
+
+/// `versionInfo()` returns the JSON of `version_info`.
+#[cfg(feature = "node")]
+pub fn version_info_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let version_info = version_info().to_string();
+    Ok(cx.string(version_info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_info_matches_the_manifest() {
+        let info: Value = serde_json::from_str(&version_info().to_string()).unwrap();
+        let manifest_version = include_str!("../Cargo.toml")
+            .lines()
+            .find_map(|line| line.strip_prefix("version = "))
+            .unwrap()
+            .trim_matches('"');
+        assert_eq!(info["version"], manifest_version);
+        let commit = info["git_commit"].as_str().unwrap();
+        assert!(
+            commit == "unknown"
+                || (commit.len() == 40 && commit.bytes().all(|byte| byte.is_ascii_hexdigit())),
+            "{}",
+            commit
+        );
+        assert_eq!(
+            info["field_modulus"],
+            "0x30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000001"
+        );
+        assert_eq!(
+            info["defaults"]["max_header_length"],
+            InputGenerationOptions::default().max_header_length
+        );
+        assert_eq!(
+            info["defaults"]["max_subject_bytes"],
+            active_circuit_profile().max_subject_bytes
+        );
+        assert_eq!(
+            info["poseidon_fingerprint"],
+            "0x".to_string() + &hex::encode(config_fingerprint().unwrap())
+        );
+    }
+}
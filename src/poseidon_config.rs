@@ -0,0 +1,74 @@
+//! The Poseidon parameter set used by this crate.
+//!
+//! `poseidon_rs` implements Poseidon over the BN254 scalar field with the circomlib parameters:
+//! width `t = inputs + 1` for 1 to 16 inputs, S-box `x^5`, 8 full rounds, and the circomlib
+//! partial round counts and round constants/MDS matrices. Salts, commitments and hashes are
+//! checked by the circuits, so any deviation from circomlib makes them unusable.
+//!
+//! `poseidon_rs` does not expose its constants, so `config_fingerprint` hashes the outputs of a
+//! fixed set of inputs for every supported arity. Any change to the constants or the arity
+//! handling changes the fingerprint.
+use crate::*;
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use sha2::{Digest, Sha256};
+
+/// Largest number of inputs hashed by a single Poseidon permutation.
+pub const POSEIDON_MAX_ARITY: usize = 16;
+
+/// Known answers of Poseidon over `[1, .., arity]`, as produced by circomlibjs.
+pub const CIRCOMLIB_KNOWN_ANSWERS: [(usize, &str); 4] = [
+    (
+        1,
+        "0x29176100eaa962bdc1fe6c654d6a3c130e96a4d1168b33848b897dc502820133",
+    ),
+    (
+        2,
+        "0x115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a",
+    ),
+    (
+        3,
+        "0x0e7732d89e6939c0ff03d5e58dab6302f3230e269dc5b968f725df34ab36d732",
+    ),
+    (
+        4,
+        "0x299c867db6c1fdd79dcefa40e4510b9837e60ebb1ce0663dbaa525df65250465",
+    ),
+];
+
+fn sequential_inputs(arity: usize) -> Vec<Fr> {
+    (1..=arity as u64).map(Fr::from).collect()
+}
+
+/// SHA-256 over the Poseidon hashes of `[1, .., arity]` for every arity from 1 to
+/// `POSEIDON_MAX_ARITY`.
+pub fn config_fingerprint() -> Result<[u8; 32], PoseidonError> {
+    let mut hasher = Sha256::new();
+    for arity in 1..=POSEIDON_MAX_ARITY {
+        let hash = poseidon_fields(&sequential_inputs(arity))?;
+        hasher.update(field2hex(&hash).as_bytes());
+    }
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(feature = "node")]
+pub fn poseidon_config_fingerprint_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let fingerprint = match config_fingerprint() {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => return cx.throw_error(&format!("config_fingerprint failed: {}", e)),
+    };
+    Ok(cx.string("0x".to_string() + &hex::encode(fingerprint)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circomlib_known_answers() {
+        for (arity, expected) in CIRCOMLIB_KNOWN_ANSWERS {
+            let hash = poseidon_fields(&sequential_inputs(arity)).unwrap();
+            assert_eq!(field2hex(&hash), expected, "arity {}", arity);
+        }
+    }
+}
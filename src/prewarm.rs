@@ -0,0 +1,184 @@
+use crate::*;
+use anyhow::Result;
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// Duration of one `prewarm` stage.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrewarmStage {
+    pub name: String,
+    pub micros: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrewarmReport {
+    pub stages: Vec<PrewarmStage>,
+    /// Keys that could not be prefetched, with the error. Prewarming goes on without them.
+    pub failed_keys: Vec<(DkimKeyId, String)>,
+}
+
+impl PrewarmReport {
+    fn time<T>(&mut self, name: &str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.stages.push(PrewarmStage {
+            name: name.to_string(),
+            micros: saturating_safe_integer(start.elapsed().as_micros()),
+        });
+        result
+    }
+}
+
+/// A tiny email that goes through every stage of input generation.
+fn prewarm_fixture() -> ParsedEmail {
+    fixture_signed_email(
+        "from:alice@gmail.com\r\nsubject:Send 1 ETH to bob@example.com\r\n\
+         date:Wed, 13 Dec 2023 10:00:00 +0000\r\n",
+        "",
+    )
+}
+
+/// Runs the first-call work that does not need DNS: the runtime, the Poseidon constants, the
+/// regexes and one synthetic input generation.
+fn prewarm_offline(report: &mut PrewarmReport) -> Result<()> {
+    #[cfg(feature = "async-runtime")]
+    report.time("runtime", shared_runtime)?;
+    report
+        .time("poseidon", config_fingerprint)
+        .map_err(|e| anyhow::anyhow!("poseidon failed: {}", e))?;
+    let fixture = prewarm_fixture();
+    report.time("regexes", || fixture.get_header_indexes())?;
+    let account_code = fixture_account_code();
+    report.time("generation", || {
+        generate_email_auth_input_from_parsed_email(
+            &fixture,
+            &account_code,
+            &InputGenerationOptions::default(),
+        )
+    })?;
+    Ok(())
+}
+
+/// Initializes the lazy state that otherwise lands on the first request, and prefetches the DKIM
+/// keys of `keys` into the key cache with `resolver`. Returns the time taken by each stage.
+pub fn prewarm(resolver: &dyn TxtResolver, keys: &[DkimKeyId]) -> Result<PrewarmReport> {
+    let mut report = PrewarmReport::default();
+    prewarm_offline(&mut report)?;
+    let mut failed_keys = vec![];
+    report.time("dkim_keys", || {
+        for key_id in keys {
+            if let Err(e) = resolve_dkim_key_cached(resolver, key_id) {
+                failed_keys.push((key_id.clone(), e.to_string()));
+            }
+        }
+    });
+    report.failed_keys = failed_keys;
+    Ok(report)
+}
+
+/// `prewarm`, resolving the keys over DNS as `ParsedEmail::new_from_raw_email` does.
+#[cfg(feature = "dns")]
+pub async fn prewarm_with_dns(keys: &[DkimKeyId]) -> Result<PrewarmReport> {
+    let mut report = PrewarmReport::default();
+    prewarm_offline(&mut report)?;
+    let start = Instant::now();
+    for key_id in keys {
+        if let Err(e) = fetch_dkim_key_cached(key_id).await {
+            report.failed_keys.push((key_id.clone(), e.to_string()));
+        }
+    }
+    report.stages.push(PrewarmStage {
+        name: "dkim_keys".to_string(),
+        micros: saturating_safe_integer(start.elapsed().as_micros()),
+    });
+    Ok(report)
+}
+
+/// `prewarm(keysJson?)` takes a JSON array of `{domain, selector}` and resolves to the JSON
+/// `PrewarmReport`.
+#[cfg(feature = "node")]
+pub fn prewarm_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let keys = match cx.argument_opt(0) {
+        Some(keys) if !keys.is_a::<JsUndefined, _>(&mut cx) => {
+            let keys = keys.downcast_or_throw::<JsString, _>(&mut cx)?.value(&mut cx);
+            match serde_json::from_str::<Vec<DkimKeyId>>(&keys) {
+                Ok(keys) => keys
+                    .iter()
+                    .map(|key_id| DkimKeyId::new(&key_id.domain, &key_id.selector))
+                    .collect(),
+                Err(e) => return cx.throw_error(format!("invalid DKIM key list: {}", e)),
+            }
+        }
+        _ => vec![],
+    };
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(&mut cx)?;
+
+    rt.spawn(async move {
+        let report = prewarm_with_dns(&keys)
+            .await
+            .and_then(|report| Ok(serde_json::to_string(&report)?));
+        deferred.settle_with(&channel, move |mut cx| match report {
+            Ok(report) => Ok(cx.string(report)),
+            Err(err) => cx.throw_error(format!("Could not prewarm: {}", err)),
+        });
+    });
+
+    Ok(promise)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose, Engine as _};
+    use rsa::pkcs8::EncodePublicKey;
+    use rsa::traits::PublicKeyParts;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingResolver {
+        record: String,
+        lookups: AtomicUsize,
+    }
+
+    impl TxtResolver for CountingResolver {
+        fn resolve_txt(&self, name: &str) -> Result<Vec<String>> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            Ok(if name == "prewarm._domainkey.example.com" {
+                vec![self.record.clone()]
+            } else {
+                vec![]
+            })
+        }
+    }
+
+    #[test]
+    fn test_prewarm_fills_the_key_cache() {
+        let private_key = rsa::RsaPrivateKey::new(&mut rand_core::OsRng, 1024).unwrap();
+        let der = private_key.to_public_key().to_public_key_der().unwrap();
+        let resolver = CountingResolver {
+            record: format!(
+                "v=DKIM1; k=rsa; p={}",
+                general_purpose::STANDARD.encode(der.as_bytes())
+            ),
+            lookups: AtomicUsize::new(0),
+        };
+        let prewarmed = DkimKeyId::new("Example.com", "prewarm");
+        let missing = DkimKeyId::new("example.com", "missing");
+
+        let report = prewarm(&resolver, &[prewarmed.clone(), missing.clone()]).unwrap();
+        let stages = report.stages.iter().map(|stage| stage.name.as_str()).collect::<Vec<_>>();
+        assert!(stages.ends_with(&["poseidon", "regexes", "generation", "dkim_keys"]));
+        assert_eq!(report.failed_keys.len(), 1);
+        assert_eq!(report.failed_keys[0].0, missing);
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 2);
+
+        // The prewarmed key is served from the cache.
+        let public_key = resolve_dkim_key_cached(&resolver, &prewarmed).unwrap();
+        assert_eq!(public_key, private_key.n().to_bytes_be());
+        assert_eq!(cached_dkim_key(&prewarmed), Some(public_key));
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::circuit::{MAX_BODY_PADDED_BYTES, MAX_HEADER_PADDED_BYTES, MAX_SUBJECT_BYTES};
+use crate::cryptos::MAX_EMAIL_ADDR_BYTES;
+use crate::json_number::MAX_EMITTED_INDEX;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+/// Size limits of one circuit compilation. The constants in `circuit` and `cryptos` are the
+/// values of the default `v1_small` profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitProfile {
+    pub name: String,
+    /// Padded header length; a multiple of 64, the SHA-256 block size.
+    pub max_header_bytes: usize,
+    /// Padded body length; a multiple of 64.
+    pub max_body_bytes: usize,
+    /// Longest subject accepted as a command.
+    pub max_subject_bytes: usize,
+    /// Padded email address length. It determines the account salts, so every profile of a
+    /// deployment must agree on it.
+    pub max_email_addr_bytes: usize,
+}
+
+impl Default for CircuitProfile {
+    fn default() -> Self {
+        CircuitProfile {
+            name: "v1_small".to_string(),
+            max_header_bytes: MAX_HEADER_PADDED_BYTES,
+            max_body_bytes: MAX_BODY_PADDED_BYTES,
+            max_subject_bytes: MAX_SUBJECT_BYTES,
+            max_email_addr_bytes: MAX_EMAIL_ADDR_BYTES,
+        }
+    }
+}
+
+impl CircuitProfile {
+    pub const NAMES: [&'static str; 2] = ["v1_small", "v1_large"];
+
+    /// The built-in profiles: `v1_small` (header 1024, command 605 bytes) and `v1_large` (header
+    /// 2048, command 256 bytes).
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "v1_small" => Some(Self::default()),
+            "v1_large" => Some(CircuitProfile {
+                name: "v1_large".to_string(),
+                max_header_bytes: 2048,
+                max_subject_bytes: 256,
+                ..Self::default()
+            }),
+            _ => None,
+        }
+    }
+
+    /// Parses a profile name (`"v1_large"`) or an object with every field set explicitly.
+    pub fn from_json(profile_json: &str) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(profile_json)
+            .map_err(|e| anyhow!("the circuit profile is not valid JSON: {}", e))?;
+        let profile = match value {
+            serde_json::Value::String(name) => Self::named(&name).ok_or_else(|| {
+                anyhow!(
+                    "unknown circuit profile `{}`, expected one of: {}",
+                    name,
+                    Self::NAMES.join(", ")
+                )
+            })?,
+            value => serde_json::from_value(value)
+                .map_err(|e| anyhow!("invalid circuit profile: {}", e))?,
+        };
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (name, len) in [
+            ("max_header_bytes", self.max_header_bytes),
+            ("max_body_bytes", self.max_body_bytes),
+        ] {
+            if len == 0 || len % 64 != 0 {
+                return Err(anyhow!("{} must be a positive multiple of 64 but is {}", name, len));
+            }
+        }
+        if self.max_subject_bytes == 0 || self.max_email_addr_bytes == 0 {
+            return Err(anyhow!("max_subject_bytes and max_email_addr_bytes must be positive"));
+        }
+        // The emitted indexes and lengths are bounded by these, so checking them here keeps
+        // `EmailAuthInput::check_emitted_indexes` from failing at generation time.
+        for (name, len) in [
+            ("max_header_bytes", self.max_header_bytes),
+            ("max_body_bytes", self.max_body_bytes),
+            ("max_subject_bytes", self.max_subject_bytes),
+            ("max_email_addr_bytes", self.max_email_addr_bytes),
+        ] {
+            if len > MAX_EMITTED_INDEX {
+                return Err(anyhow!(
+                    "{} is {}, over the limit of {} for emitted indexes and lengths",
+                    name,
+                    len,
+                    MAX_EMITTED_INDEX
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+static ACTIVE_PROFILE: Lazy<RwLock<CircuitProfile>> =
+    Lazy::new(|| RwLock::new(CircuitProfile::default()));
+
+/// The profile read by validation and padding; `v1_small` unless changed with
+/// `set_circuit_profile`.
+pub fn active_circuit_profile() -> CircuitProfile {
+    ACTIVE_PROFILE
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Selects the profile for the whole process. Meant to be called once at initialization.
+pub fn set_circuit_profile(profile: CircuitProfile) -> Result<()> {
+    profile.validate()?;
+    *ACTIVE_PROFILE.write().unwrap_or_else(|e| e.into_inner()) = profile;
+    Ok(())
+}
+
+#[cfg(feature = "node")]
+pub fn set_circuit_profile_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let profile_json = cx.argument::<JsString>(0)?.value(&mut cx);
+    let profile = match CircuitProfile::from_json(&profile_json) {
+        Ok(profile) => profile,
+        Err(e) => return cx.throw_error(e.to_string()),
+    };
+    if let Err(e) = set_circuit_profile(profile) {
+        return cx.throw_error(e.to_string());
+    }
+    let profile = serde_json::to_string(&active_circuit_profile()).unwrap();
+    Ok(cx.string(profile))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_profiles() {
+        let large = CircuitProfile::from_json(r#""v1_large""#).unwrap();
+        assert_eq!(large.max_header_bytes, 2048);
+        assert_eq!(large, CircuitProfile::named("v1_large").unwrap());
+        assert!(CircuitProfile::from_json(r#""v2""#).is_err());
+        let custom = CircuitProfile::from_json(
+            r#"{"name": "custom", "max_header_bytes": 1536, "max_body_bytes": 64,
+                "max_subject_bytes": 100, "max_email_addr_bytes": 256}"#,
+        )
+        .unwrap();
+        assert_eq!(custom.max_subject_bytes, 100);
+        assert!(CircuitProfile::from_json(
+            r#"{"name": "custom", "max_header_bytes": 1000, "max_body_bytes": 64,
+                "max_subject_bytes": 100, "max_email_addr_bytes": 256}"#,
+        )
+        .is_err());
+        let err = CircuitProfile::from_json(
+            r#"{"name": "absurd", "max_header_bytes": 4294967296, "max_body_bytes": 64,
+                "max_subject_bytes": 100, "max_email_addr_bytes": 256}"#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("max_header_bytes is 4294967296"), "{}", err);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::*;
+use anyhow::{anyhow, Result};
+use halo2curves::ff::Field;
+#[cfg(feature = "node")]
+use neon::prelude::*;
+
+/// Maximum number of domain bytes the email auth circuit reveals.
+pub const DOMAIN_BYTES: usize = 255;
+/// Number of fields the domain is packed into.
+pub const DOMAIN_FIELDS: usize = (DOMAIN_BYTES + 30) / 31;
+/// Number of fields the masked command, of at most `MAX_SUBJECT_BYTES` bytes, is packed into.
+pub const COMMAND_FIELDS: usize = (MAX_SUBJECT_BYTES + 30) / 31;
+/// Number of public signals of the email auth circuit: the domain fields, the public key hash,
+/// the email nullifier, the timestamp, the masked command fields, the account salt and
+/// `is_code_exist`.
+pub const PUBLIC_SIGNALS_LEN: usize = DOMAIN_FIELDS + COMMAND_FIELDS + 5;
+
+/// `bytes` packed as the circuit packs them: 31 bytes per field, little-endian, padded with zero
+/// fields to `num_fields`.
+pub fn pack_bytes_into_fields(bytes: &[u8], num_fields: usize) -> Result<Vec<Fr>> {
+    if bytes.len() > num_fields * 31 {
+        return Err(anyhow!(
+            "{} bytes do not fit in {} fields",
+            bytes.len(),
+            num_fields
+        ));
+    }
+    let mut fields = bytes2fields(bytes);
+    fields.resize(num_fields, Fr::zero());
+    Ok(fields)
+}
+
+/// The subject as the circuit reveals it, with the email address in it and the invitation code
+/// with its prefix replaced by zero bytes, and whether there was an invitation code.
+pub fn masked_command(subject: &str) -> Result<(Vec<u8>, bool)> {
+    let (masked_ranges, is_code_exist) = masked_ranges(subject)?;
+    Ok((mask(subject, masked_ranges), is_code_exist))
+}
+
+/// `masked_command` of a raw subject with RFC 2047 Q-encoded words: the email address and the
+/// invitation code are found in the decoded subject, and their raw bytes are masked.
+pub fn masked_encoded_command(
+    raw_subject: &str,
+    encoded_subject: &EncodedSubject,
+) -> Result<(Vec<u8>, bool)> {
+    let (masked_ranges, is_code_exist) = masked_ranges(&encoded_subject.decoded)?;
+    let masked_ranges = masked_ranges
+        .into_iter()
+        .map(|range| encoded_subject.raw_range(range))
+        .collect();
+    Ok((mask(raw_subject, masked_ranges), is_code_exist))
+}
+
+/// Ranges of the email address and the invitation code with its prefix in `subject`, and whether
+/// there was an invitation code.
+fn masked_ranges(subject: &str) -> Result<(Vec<(usize, usize)>, bool)> {
+    let regex_config =
+        serde_json::from_str(include_str!("../regexes/invitation_code_with_prefix.json"))?;
+    let email_addr = extract_email_addr_idxes(subject)
+        .ok()
+        .and_then(|idxes| idxes.first().copied());
+    let code = extract_substr_idxes(subject, &regex_config)
+        .ok()
+        .and_then(|idxes| idxes.first().copied());
+    Ok((email_addr.into_iter().chain(code).collect(), code.is_some()))
+}
+
+fn mask(subject: &str, masked_ranges: Vec<(usize, usize)>) -> Vec<u8> {
+    let mut masked = subject.as_bytes().to_vec();
+    for (start, end) in masked_ranges {
+        masked[start..end].iter_mut().for_each(|byte| *byte = 0);
+    }
+    masked
+}
+
+impl ExtractedIndexes {
+    /// The public signals the email auth circuit outputs for the input, in the order of the
+    /// verifier contract, as decimal field strings. A missing timestamp is 0.
+    pub fn public_signals(&self) -> Result<Vec<String>> {
+        let parsed_email = self.email().parsed_email();
+        let header = &parsed_email.canonicalized_header;
+        let (from_start, from_end) = self
+            .header_indexes
+            .from_addr
+            .ok_or_else(|| anyhow!("from address is not found in the header"))?;
+        let from_addr = &header[from_start..from_end];
+        let domain = match &self.signing_domain {
+            Some(signing_domain) => signing_domain.as_str(),
+            None => {
+                let (start, end) = self
+                    .header_indexes
+                    .domain
+                    .ok_or_else(|| anyhow!("email domain is not found in the from address"))?;
+                &from_addr[start..end]
+            }
+        };
+        let (subject_start, subject_end) = self
+            .header_indexes
+            .subject
+            .ok_or_else(|| missing_subject_error(header))?;
+        let subject = &header[subject_start..subject_end];
+        let (masked_command, is_code_exist) = match &self.encoded_subject {
+            Some(encoded_subject) => masked_encoded_command(subject, encoded_subject)?,
+            None => masked_command(subject)?,
+        };
+        let timestamp = match self.header_indexes.timestamp {
+            Some((start, end)) => header[start..end].parse::<u64>()?,
+            None => 0,
+        };
+
+        let poseidon_err = |e: PoseidonError| anyhow!("poseidon failed: {}", e);
+        let account_salt = AccountSalt::new(
+            &PaddedEmailAddr::from_email_addr(from_addr),
+            *self.account_code(),
+        )
+        .map_err(poseidon_err)?;
+
+        let mut signals = pack_bytes_into_fields(domain.as_bytes(), DOMAIN_FIELDS)
+            .map_err(|e| anyhow!("the domain is too long: {}", e))?;
+        signals.push(
+            public_key_hash_from_modulus(&parsed_email.public_key).map_err(poseidon_err)?,
+        );
+        signals.push(
+            email_nullifier_from_signature(&parsed_email.signature).map_err(poseidon_err)?,
+        );
+        signals.push(Fr::from(timestamp));
+        signals.extend(
+            pack_bytes_into_fields(&masked_command, COMMAND_FIELDS)
+                .map_err(|e| anyhow!("the command is too long: {}", e))?,
+        );
+        signals.push(account_salt.0);
+        signals.push(Fr::from(is_code_exist as u64));
+        Ok(signals.iter().map(field2decimal).collect())
+    }
+}
+
+/// Verifies `email` and returns the public signals the email auth circuit outputs for its input;
+/// see `ExtractedIndexes::public_signals`.
+#[cfg(feature = "dns")]
+pub async fn expected_public_signals(
+    email: &str,
+    account_code: &AccountCode,
+    options: &InputGenerationOptions,
+) -> Result<Vec<String>> {
+    let parsed_headers = RawEmail::from(email)
+        .check(options)?
+        .parse()?
+        .check(options)?;
+    let fetch = parsed_headers.resolve_key_fetch(options.require_dnssec).await?;
+    parsed_headers
+        .verify_fetched(fetch)?
+        .extract(account_code, options)?
+        .public_signals()
+}
+
+/// `expectedPublicSignals(email, accountCode, options?)` resolves to the JSON array of the
+/// public signals.
+#[cfg(feature = "node")]
+pub fn expected_public_signals_node(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let email = cx.argument::<JsString>(0)?.value(&mut cx);
+    let account_code = cx.argument::<JsString>(1)?.value(&mut cx);
+    let account_code = AccountCode::from(hex2field_node(&mut cx, &account_code)?);
+    let options = options_argument(&mut cx, 2)?;
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    let rt = runtime(&mut cx)?;
+
+    rt.spawn(async move {
+        let public_signals = expected_public_signals(&email, &account_code, &options)
+            .await
+            .and_then(|signals| Ok(serde_json::to_string(&signals)?));
+        deferred.settle_with(&channel, move |mut cx| match public_signals {
+            Ok(public_signals) => Ok(cx.string(public_signals)),
+            Err(err) => throw_coded(
+                &mut cx,
+                error_code(&err, ErrorCode::CircuitInputFailed),
+                format!("Could not compute the public signals: {}", err),
+            ),
+        });
+    });
+
+    Ok(promise)
+}
+
+#[cfg(all(test, feature = "dns", feature = "async-runtime"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_expected_public_signals() {
+        let fixture = test_fixture("send_eth").unwrap();
+        cache_dkim_key(
+            DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+            decode_hex(&fixture.public_key).unwrap(),
+        );
+        let account_code = AccountCode(hex2field(&fixture.account_code).unwrap());
+        let options = InputGenerationOptions::default();
+        let signals = expected_public_signals(&fixture.raw_email, &account_code, &options)
+            .await
+            .unwrap();
+        assert_eq!(signals.len(), PUBLIC_SIGNALS_LEN);
+        let decimal = |hex: &str| field2decimal(&hex2field(hex).unwrap());
+        let mut expected = vec!["0".to_string(); PUBLIC_SIGNALS_LEN];
+        // "fixtures.zkemail.example", packed little-endian.
+        expected[0] = "2486899202975202173949604344112920763208782959965959121254".to_string();
+        expected[DOMAIN_FIELDS] = decimal(&fixture.expected.public_key_hash);
+        expected[DOMAIN_FIELDS + 1] = decimal(&fixture.expected.email_nullifier);
+        // The fixture is signed without a t= tag, so the timestamp is 0.
+        // "Send 1 ETH to " with "bob@example.com" masked.
+        expected[DOMAIN_FIELDS + 3] = "657867372644267193039310208001363".to_string();
+        expected[PUBLIC_SIGNALS_LEN - 2] = decimal(&fixture.expected.account_salt);
+        assert_eq!(signals, expected);
+
+        // The signals are included in the input when asked for.
+        let options = InputGenerationOptions {
+            include_public_signals: true,
+            ..Default::default()
+        };
+        let input =
+            generate_email_auth_input_with_options(&fixture.raw_email, &account_code, &options)
+                .await
+                .unwrap();
+        let input: serde_json::Value = serde_json::from_str(&input).unwrap();
+        assert_eq!(input["public_signals"], serde_json::json!(signals));
+    }
+
+    #[test]
+    fn test_masked_command() {
+        let subject = "Accept guardian request for 0x0C06688e61C06466E2a5C6fE4E15c359260a33f3 \
+                       Code 01eb9b204cc24c3baee11accc37d253a9c53e92b1a2cc07763475c135d575b76";
+        let (masked, is_code_exist) = masked_command(subject).unwrap();
+        assert!(is_code_exist);
+        let revealed = "Accept guardian request for 0x0C06688e61C06466E2a5C6fE4E15c359260a33f3";
+        assert_eq!(&masked[..revealed.len()], revealed.as_bytes());
+        assert!(masked[revealed.len()..].iter().all(|&byte| byte == 0));
+        assert_eq!(
+            pack_bytes_into_fields(&masked, COMMAND_FIELDS).unwrap()[..4]
+                .iter()
+                .map(field2decimal)
+                .collect::<Vec<_>>(),
+            [
+                "85638166165486563165766183137636008855073949224068235243737729636579369793",
+                "101077460653826816287150842033667683316707587278162246637626262625526886467",
+                "3703704039325709874",
+                "0",
+            ]
+        );
+
+        let (masked, is_code_exist) = masked_command("Send 1 ETH to bob@example.com").unwrap();
+        assert!(!is_code_exist);
+        assert_eq!(masked, b"Send 1 ETH to \0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        assert!(pack_bytes_into_fields(&[1; 32], 1).is_err());
+    }
+}
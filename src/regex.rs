@@ -1,7 +1,16 @@
+//! Node bindings for the zk-regex extractors.
+//!
+//! The patterns are zk-regex DFA definitions shared with the circuits, not `regex` crate
+//! patterns: they have no flags, no `.` and no `^`/`$` line semantics, and every header pattern
+//! anchors itself with an explicit `(\r\n|^)` before the header name. They must stay identical
+//! to the circuits' definitions, so changes are made upstream; the tests below lock their
+//! behavior on tricky headers.
+#[cfg(feature = "node")]
 use neon::prelude::*;
 pub use zk_regex_apis::extract_substrs::*;
 pub use zk_regex_apis::padding::*;
 
+#[cfg(feature = "node")]
 pub fn pad_string_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let string = cx.argument::<JsString>(0)?.value(&mut cx);
     let padded_bytes_size = cx.argument::<JsNumber>(1)?.value(&mut cx) as usize;
@@ -14,6 +23,7 @@ pub fn pad_string_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     Ok(padded_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_substr_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let regex_config_str = cx.argument::<JsString>(1)?.value(&mut cx);
@@ -37,6 +47,7 @@ pub fn extract_substr_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     Ok(js_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_email_addr_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let substr_idxes = match extract_email_addr_idxes(&input_str) {
@@ -55,6 +66,7 @@ pub fn extract_email_addr_idxes_node(mut cx: FunctionContext) -> JsResult<JsArra
     Ok(js_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_email_domain_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let substr_idxes = match extract_email_domain_idxes(&input_str) {
@@ -73,6 +85,7 @@ pub fn extract_email_domain_idxes_node(mut cx: FunctionContext) -> JsResult<JsAr
     Ok(js_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_email_addr_with_name_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let substr_idxes = match extract_email_addr_with_name_idxes(&input_str) {
@@ -91,6 +104,7 @@ pub fn extract_email_addr_with_name_idxes_node(mut cx: FunctionContext) -> JsRes
     Ok(js_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_from_all_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let substr_idxes = match extract_from_all_idxes(&input_str) {
@@ -109,6 +123,7 @@ pub fn extract_from_all_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray>
     Ok(js_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_from_addr_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let substr_idxes = match extract_from_addr_idxes(&input_str) {
@@ -127,6 +142,7 @@ pub fn extract_from_addr_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray
     Ok(js_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_to_all_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
 
@@ -152,6 +168,7 @@ pub fn extract_to_all_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     Ok(js_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_to_addr_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let substr_idxes = match extract_to_addr_idxes(&input_str) {
@@ -170,6 +187,7 @@ pub fn extract_to_addr_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray>
     Ok(js_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_subject_all_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let substr_idxes = match extract_subject_all_idxes(&input_str) {
@@ -188,6 +206,7 @@ pub fn extract_subject_all_idxes_node(mut cx: FunctionContext) -> JsResult<JsArr
     Ok(js_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_body_hash_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let substr_idxes = match extract_body_hash_idxes(&input_str) {
@@ -206,6 +225,7 @@ pub fn extract_body_hash_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray
     Ok(js_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_timestamp_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let substr_idxes = match extract_timestamp_idxes(&input_str) {
@@ -224,6 +244,7 @@ pub fn extract_timestamp_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray
     Ok(js_array)
 }
 
+#[cfg(feature = "node")]
 pub fn extract_message_id_idxes_node(mut cx: FunctionContext) -> JsResult<JsArray> {
     let input_str = cx.argument::<JsString>(0)?.value(&mut cx);
     let substr_idxes = match extract_message_id_idxes(&input_str) {
@@ -241,3 +262,77 @@ pub fn extract_message_id_idxes_node(mut cx: FunctionContext) -> JsResult<JsArra
     }
     Ok(js_array)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Map, Value};
+    use std::fs;
+    use std::path::Path;
+
+    /// Golden match ranges of every header pattern over `TRICKY_HEADERS`, relative to the crate
+    /// root. Like the conformance vectors, it is rewritten only with `UPDATE_VECTORS=1`.
+    const GOLDEN_MATCHES_PATH: &str = "regexes/golden_matches.json";
+
+    /// Headers where a pattern could match across CRLF or outside the intended header.
+    const TRICKY_HEADERS: [&str; 8] = [
+        "from:alice@gmail.com\r\nto:bob@example.com\r\nsubject:Send 1 ETH\r\n",
+        "subject:Send 1 ETH\r\nfrom:Alice <alice@gmail.com>\r\n",
+        "x-note:from:mallory@evil.com\r\nfrom:alice@gmail.com\r\n",
+        "from:alice@gmail.com\r\nsubject:Re: subject: urgent from:bob@example.com\r\n",
+        "from:alice@gmail.com\r\nx-subject:not the subject\r\nsubject:the subject\r\n",
+        "from:alice@gmail.com\r\nsubject:line one\r\n to:folded@example.com\r\n",
+        "from:alice@gmail.com\r\nsubject:ends without crlf",
+        "to:bob@example.com\r\ndkim-signature:v=1; a=rsa-sha256; d=gmail.com; s=s1; t=1694989812; bh=Zm9v; h=from:to; b=",
+    ];
+
+    /// A pattern that does not match has no ranges; any other extractor error is returned.
+    fn ranges(
+        idxes: Result<Vec<(usize, usize)>, ExtractSubstrssError>,
+    ) -> Result<Value, ExtractSubstrssError> {
+        match idxes {
+            Ok(idxes) => Ok(json!(idxes)),
+            Err(ExtractSubstrssError::SubstringOfEntireNotFound(..)) => Ok(json!([])),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Runs each named header pattern against `header`.
+    fn header_pattern_matches(header: &str) -> Result<Value, ExtractSubstrssError> {
+        let mut matches = Map::new();
+        matches.insert("from_all".into(), ranges(extract_from_all_idxes(header))?);
+        matches.insert("from_addr".into(), ranges(extract_from_addr_idxes(header))?);
+        matches.insert("to_all".into(), ranges(extract_to_all_idxes(header))?);
+        matches.insert("to_addr".into(), ranges(extract_to_addr_idxes(header))?);
+        matches.insert("subject_all".into(), ranges(extract_subject_all_idxes(header))?);
+        matches.insert("body_hash".into(), ranges(extract_body_hash_idxes(header))?);
+        matches.insert("timestamp".into(), ranges(extract_timestamp_idxes(header))?);
+        matches.insert("message_id".into(), ranges(extract_message_id_idxes(header))?);
+        Ok(Value::Object(matches))
+    }
+
+    #[test]
+    fn test_header_patterns_match_golden_ranges() {
+        let golden = TRICKY_HEADERS
+            .iter()
+            .map(|header| {
+                let matches = header_pattern_matches(header)
+                    .unwrap_or_else(|e| panic!("extraction failed on {:?}: {}", header, e));
+                json!({ "header": header, "matches": matches })
+            })
+            .collect::<Vec<_>>();
+        let generated = serde_json::to_string_pretty(&golden).unwrap() + "\n";
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(GOLDEN_MATCHES_PATH);
+        if std::env::var("UPDATE_VECTORS").as_deref() == Ok("1") {
+            fs::write(&path, generated).unwrap();
+            return;
+        }
+        let committed = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("{} cannot be read: {}", GOLDEN_MATCHES_PATH, e));
+        assert_eq!(
+            committed, generated,
+            "the header patterns changed behavior; review and update {}",
+            GOLDEN_MATCHES_PATH
+        );
+    }
+}
@@ -0,0 +1,318 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::*;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Version of the replay bundle format, bumped on incompatible changes.
+pub const REPLAY_FORMAT_VERSION: u32 = 1;
+
+/// The DKIM key observed for a domain and selector: its big-endian modulus as `0x` hex, or the
+/// error of its resolution. An email without a DKIM-Signature has an empty domain and selector.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolverAnswer {
+    pub domain: String,
+    pub selector: String,
+    pub public_key: Option<String>,
+    pub error: Option<String>,
+}
+
+/// How a generation ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ReplayOutcome {
+    /// The input was generated; its JSON is identified by its SHA-256.
+    Input { sha256: String },
+    /// The generation failed with the `ErrorCode` and message.
+    Error { code: u8, message: String },
+}
+
+impl ReplayOutcome {
+    pub fn of(result: &Result<String>) -> Self {
+        match result {
+            Ok(input) => ReplayOutcome::Input {
+                sha256: hex::encode(Sha256::digest(input.as_bytes())),
+            },
+            Err(e) => ReplayOutcome::Error {
+                code: error_code(e, ErrorCode::CircuitInputFailed).as_u8(),
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ReplayOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayOutcome::Input { sha256 } => write!(f, "the input with SHA-256 {}", sha256),
+            ReplayOutcome::Error { code, message } => write!(f, "error {}: {}", code, message),
+        }
+    }
+}
+
+/// Everything needed to reproduce one generation without the mailbox or the network, written by
+/// the `record_replay` option as CBOR.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayBundle {
+    pub format_version: u32,
+    pub crate_version: String,
+    pub raw_email: Vec<u8>,
+    /// The body of `raw_email` was replaced by a placeholder (`record_replay_redact_body`).
+    pub body_redacted: bool,
+    pub account_code: String,
+    /// `InputGenerationOptions` JSON, without the recording and debug dump options.
+    pub options_json: String,
+    pub resolver_answers: Vec<ResolverAnswer>,
+    pub outcome: ReplayOutcome,
+}
+
+impl ReplayBundle {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        ciborium::into_writer(self, &mut bytes)
+            .map_err(|e| anyhow!("could not encode the replay bundle: {}", e))?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bundle: ReplayBundle =
+            ciborium::from_reader(bytes).map_err(|e| anyhow!("not a replay bundle: {}", e))?;
+        if bundle.format_version != REPLAY_FORMAT_VERSION {
+            return Err(anyhow!(
+                "replay bundle format version {} is not supported, expected {}",
+                bundle.format_version,
+                REPLAY_FORMAT_VERSION
+            ));
+        }
+        Ok(bundle)
+    }
+}
+
+/// `raw_email` with the body after the first empty line replaced by a placeholder giving its
+/// length.
+fn redact_body(raw_email: &[u8]) -> Vec<u8> {
+    let mut header_len = 0;
+    for line in raw_email.split_inclusive(|&byte| byte == b'\n') {
+        header_len += line.len();
+        if line == b"\n" || line == b"\r\n" {
+            let mut redacted = raw_email[..header_len].to_vec();
+            redacted.extend_from_slice(
+                format!("[redacted {} bytes]\r\n", raw_email.len() - header_len).as_bytes(),
+            );
+            return redacted;
+        }
+    }
+    raw_email.to_vec()
+}
+
+/// The DKIM key the generation of `raw_email` observed: the key of the parsed email, the DNS
+/// error, or the cached key when parsing failed after the key was fetched.
+fn observed_answers(
+    raw_email: &[u8],
+    parsed_email: Option<&Result<ParsedEmail>>,
+) -> Vec<ResolverAnswer> {
+    let key_id = DkimKeyId::from_raw_email(&raw_header_text(raw_email));
+    let (public_key, error) = match parsed_email {
+        Some(Ok(parsed_email)) => (Some(parsed_email.public_key_string()), None),
+        Some(Err(e)) if error_code(e, ErrorCode::ParseEmailFailed) == ErrorCode::DkimDnsFailed => {
+            (None, Some(e.to_string()))
+        }
+        _ => (
+            key_id
+                .as_ref()
+                .and_then(cached_dkim_key)
+                .map(|public_key| format!("0x{}", hex::encode(public_key))),
+            None,
+        ),
+    };
+    if public_key.is_none() && error.is_none() {
+        return vec![];
+    }
+    let (domain, selector) = key_id.map_or((String::new(), String::new()), |key_id| {
+        (key_id.domain, key_id.selector)
+    });
+    vec![ResolverAnswer {
+        domain,
+        selector,
+        public_key,
+        error,
+    }]
+}
+
+/// Writes the replay bundle of one generation of `raw_email` to `path`.
+pub fn record_replay(
+    path: &Path,
+    raw_email: &[u8],
+    account_code: &AccountCode,
+    options: &InputGenerationOptions,
+    parsed_email: Option<&Result<ParsedEmail>>,
+    result: &Result<String>,
+) -> Result<()> {
+    let replayed_options = InputGenerationOptions {
+        record_replay: None,
+        record_replay_redact_body: false,
+        debug_dump_dir: None,
+        debug_dump_always: false,
+        ..options.clone()
+    };
+    let bundle = ReplayBundle {
+        format_version: REPLAY_FORMAT_VERSION,
+        crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        raw_email: if options.record_replay_redact_body {
+            redact_body(raw_email)
+        } else {
+            raw_email.to_vec()
+        },
+        body_redacted: options.record_replay_redact_body,
+        account_code: field2hex(&account_code.0),
+        options_json: serde_json::to_string(&replayed_options)?,
+        resolver_answers: observed_answers(raw_email, parsed_email),
+        outcome: ReplayOutcome::of(result),
+    };
+    fs::write(path, bundle.to_bytes()?)?;
+    Ok(())
+}
+
+/// `ParsedEmail::new_from_raw_bytes` with the DKIM key, or its resolution error, taken from
+/// `answers` instead of DNS.
+#[cfg(feature = "dns")]
+pub fn parse_with_recorded_keys(
+    raw_email: &[u8],
+    answers: &[ResolverAnswer],
+) -> Result<ParsedEmail> {
+    let public_key = recorded_public_key(raw_email, answers)?;
+    ParsedEmail::new_from_raw_bytes_with_key(raw_email, public_key)
+}
+
+/// `resolve_dkim_public_key` answered from `answers` instead of the cache and DNS.
+pub fn recorded_public_key(raw_email: &[u8], answers: &[ResolverAnswer]) -> Result<Vec<u8>> {
+    let (domain, selector) = DkimKeyId::from_raw_email(&raw_header_text(raw_email))
+        .map_or((String::new(), String::new()), |key_id| {
+            (key_id.domain, key_id.selector)
+        });
+    let record_name = format!("{}._domainkey.{}", selector, domain);
+    let answer = answers
+        .iter()
+        .find(|answer| answer.domain == domain && answer.selector == selector)
+        .ok_or_else(|| {
+            coded(
+                ErrorCode::DkimDnsFailed,
+                format!("no resolver answer was recorded for {}", record_name),
+            )
+        })?;
+    match (&answer.public_key, &answer.error) {
+        (Some(public_key), _) => decode_hex(public_key),
+        (None, Some(error)) => Err(coded(ErrorCode::DkimDnsFailed, error)),
+        (None, None) => Err(coded(
+            ErrorCode::DkimDnsFailed,
+            format!("no DKIM key was recorded for {}", record_name),
+        )),
+    }
+}
+
+/// Re-runs the generation of `bundle` with its recorded resolver answers, and fails unless it ends
+/// with the recorded outcome. The hot config of the process applies, as it did to the recording.
+#[cfg(feature = "dns")]
+pub async fn replay(bundle: &ReplayBundle) -> Result<ReplayOutcome> {
+    if bundle.crate_version != env!("CARGO_PKG_VERSION") {
+        slog::warn!(LOG, "the replay bundle was recorded by another version";
+            "recorded" => &bundle.crate_version);
+    }
+    let options = InputGenerationOptions::from_json(&bundle.options_json)?;
+    let account_code = AccountCode(hex2field(&bundle.account_code)?);
+    let result = generate_email_auth_input_with_options_inner(
+        &bundle.raw_email,
+        &account_code,
+        &options,
+        Some(&bundle.resolver_answers),
+    )
+    .await;
+    let outcome = ReplayOutcome::of(&result);
+    if outcome != bundle.outcome {
+        return Err(anyhow!(
+            "the replay ended with {} but the recorded run ended with {}",
+            outcome,
+            bundle.outcome
+        ));
+    }
+    Ok(outcome)
+}
+
+/// `replay` of the bundle written to `path`.
+#[cfg(feature = "dns")]
+pub async fn replay_from_file(path: &Path) -> Result<ReplayOutcome> {
+    let bytes = fs::read(path)
+        .map_err(|e| anyhow!("could not read the replay bundle {}: {}", path.display(), e))?;
+    replay(&ReplayBundle::from_bytes(&bytes)?).await
+}
+
+#[cfg(all(test, feature = "dns", feature = "async-runtime"))]
+mod tests {
+    use super::*;
+    use rand_core::{OsRng, RngCore};
+
+    #[tokio::test]
+    async fn test_replay_reproduces_the_recorded_outcome() {
+        let fixture = test_fixture("send_eth").unwrap();
+        // The recording resolves the key from the cache instead of DNS.
+        cache_dkim_key(
+            DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+            decode_hex(&fixture.public_key).unwrap(),
+        );
+        let path = std::env::temp_dir().join(format!("relayer-utils-{}.replay", OsRng.next_u64()));
+        let account_code = AccountCode(hex2field(&fixture.account_code).unwrap());
+        let options = InputGenerationOptions {
+            record_replay: Some(path.clone()),
+            max_header_length: 64,
+            ..Default::default()
+        };
+        let err =
+            generate_email_auth_input_with_options(&fixture.raw_email, &account_code, &options)
+                .await
+                .unwrap_err();
+        let bundle = ReplayBundle::from_bytes(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(
+            bundle.outcome,
+            ReplayOutcome::Error {
+                code: ErrorCode::CircuitInputFailed.as_u8(),
+                message: err.to_string(),
+            }
+        );
+        assert_eq!(
+            bundle.resolver_answers[0].public_key,
+            Some(format!("0x{}", fixture.public_key))
+        );
+        assert_eq!(replay_from_file(&path).await.unwrap(), bundle.outcome);
+
+        let tampered = ReplayBundle {
+            outcome: ReplayOutcome::Input {
+                sha256: "00".to_string(),
+            },
+            ..bundle.clone()
+        };
+        let err = replay(&tampered).await.unwrap_err().to_string();
+        assert!(err.contains("the recorded run ended with"), "{}", err);
+
+        // A successful run, recorded without the body.
+        let options = InputGenerationOptions {
+            record_replay: Some(path.clone()),
+            record_replay_redact_body: true,
+            ..Default::default()
+        };
+        let input =
+            generate_email_auth_input_with_options(&fixture.raw_email, &account_code, &options)
+                .await
+                .unwrap();
+        let bundle = ReplayBundle::from_bytes(&fs::read(&path).unwrap()).unwrap();
+        assert!(bundle.body_redacted);
+        assert!(!String::from_utf8_lossy(&bundle.raw_email).contains("Please send"));
+        assert_eq!(
+            replay(&bundle).await.unwrap(),
+            ReplayOutcome::of(&Ok(input))
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,749 @@
+use crate::*;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use serde::Serialize;
+use std::borrow::Cow;
+
+/// An email as received. It is the first stage of input generation:
+///
+/// `RawEmail` -> `ParsedHeaders` -> `VerifiedEmail` -> `ExtractedIndexes` -> `CircuitInputs`
+///
+/// Each stage consumes the previous one and returns the next, so that a caller can stop after
+/// any of them. The one-shot generators, such as `generate_email_auth_input_with_options`, are
+/// compositions of the stages and return the same input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawEmail(Vec<u8>);
+
+impl RawEmail {
+    /// Takes the exact on-the-wire bytes of the email; see `ParsedEmail::new_from_raw_bytes`.
+    pub fn new(raw_email: Vec<u8>) -> Self {
+        RawEmail(raw_email)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// Applies what comes before parsing: the hot config limits, then `strip_protocol_artifacts`
+    /// when the options ask for it.
+    pub fn check(self, options: &InputGenerationOptions) -> Result<Self> {
+        hot_config().check_email(&self.0)?;
+        let raw_email = if options.strip_protocol_artifacts {
+            let (sanitized, warnings) = strip_protocol_artifacts_bytes(&self.0);
+            for warning in warnings {
+                slog::warn!(LOG, "{}", warning);
+            }
+            RawEmail(sanitized)
+        } else {
+            self
+        };
+        Ok(raw_email)
+    }
+
+    /// Canonicalizes the signed header and the body, without any network access.
+    #[cfg(feature = "dns")]
+    pub fn parse(self) -> Result<ParsedHeaders> {
+        let (canonicalized_header, canonicalized_body, signature) = canonicalize(&self.0)?;
+        Ok(ParsedHeaders {
+            raw_email: self,
+            canonicalized_header,
+            canonicalized_body,
+            signature,
+        })
+    }
+}
+
+impl From<Vec<u8>> for RawEmail {
+    fn from(raw_email: Vec<u8>) -> Self {
+        RawEmail(raw_email)
+    }
+}
+
+impl From<&str> for RawEmail {
+    fn from(raw_email: &str) -> Self {
+        RawEmail(raw_email.as_bytes().to_vec())
+    }
+}
+
+/// A canonicalized email whose signature is not verified yet. Like `UntrustedParsedEmail`, it is
+/// fine for extraction and diagnostics, and only `verify` leads on to a circuit input.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ParsedHeaders {
+    #[serde(skip)]
+    raw_email: RawEmail,
+    pub canonicalized_header: String,
+    pub canonicalized_body: String,
+    pub signature: Vec<u8>,
+}
+
+impl ParsedHeaders {
+    /// The email the headers were parsed from, after `RawEmail::check`.
+    pub fn raw_email(&self) -> &RawEmail {
+        &self.raw_email
+    }
+
+    /// Applies the checks of `reject_unsigned_from` when the options ask for them: every From
+    /// header must be covered by the DKIM-Signature the header was canonicalized for, and the
+    /// canonicalized header must hash the signed headers in `h=` order.
+    pub fn check(self, options: &InputGenerationOptions) -> Result<Self> {
+        if options.reject_unsigned_from {
+            let raw_header = raw_header_text(&self.raw_email.0);
+            check_from_header_coverage(&raw_header, &self.canonicalized_header)?;
+            check_signed_header_selection(&raw_header, &self.canonicalized_header)?;
+        }
+        Ok(self)
+    }
+
+    /// The DKIM key record of the first DKIM-Signature, if it has `d=` and `s=` tags.
+    pub fn dkim_key_id(&self) -> Option<DkimKeyId> {
+        DkimKeyId::from_raw_email(&raw_header_text(&self.raw_email.0))
+    }
+
+    pub fn header_indexes(&self) -> Result<HeaderIndexes> {
+        extract_indexes(self.canonicalized_header.as_bytes())
+    }
+
+    /// `resolve_dkim_public_key` of the email.
+    #[cfg(feature = "dns")]
+    pub async fn resolve_public_key(&self) -> Result<Vec<u8>> {
+        resolve_dkim_public_key(&self.raw_email.0).await
+    }
+
+    /// `resolve_dkim_key_fetch` of the email.
+    #[cfg(feature = "dns")]
+    pub async fn resolve_key_fetch(&self, require_dnssec: bool) -> Result<DkimKeyFetch> {
+        resolve_dkim_key_fetch(&self.raw_email.0, require_dnssec).await
+    }
+
+    /// Verifies the signature of the canonicalized header with `public_key`, the big-endian
+    /// modulus of the DKIM key. Fails with `ErrorCode::ParseEmailFailed` when it does not verify.
+    pub fn verify(self, public_key: Vec<u8>) -> Result<VerifiedEmail> {
+        self.verify_fetched(DkimKeyFetch {
+            public_key,
+            public_exponent: DEFAULT_PUBLIC_EXPONENT,
+            dnssec_validated: None,
+        })
+    }
+
+    /// `verify` with a fetched key, whose DNSSEC outcome the verified email keeps.
+    pub fn verify_fetched(self, fetch: DkimKeyFetch) -> Result<VerifiedEmail> {
+        let DkimKeyFetch {
+            public_key,
+            public_exponent,
+            dnssec_validated,
+        } = fetch;
+        VerifiedEmail::from_parsed_email(ParsedEmail {
+            canonicalized_header: self.canonicalized_header,
+            canonicalized_body: self.canonicalized_body,
+            signature: self.signature,
+            public_key,
+            public_exponent,
+            dnssec_validated,
+        })
+    }
+
+    /// `verify` with the key from `resolve_public_key`.
+    #[cfg(feature = "dns")]
+    pub async fn resolve_and_verify(self) -> Result<VerifiedEmail> {
+        let fetch = self.resolve_key_fetch(false).await?;
+        self.verify_fetched(fetch)
+    }
+}
+
+/// An email whose header signature verifies with its DKIM key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct VerifiedEmail(ParsedEmail);
+
+impl VerifiedEmail {
+    /// Verifies the signature of the canonicalized header of `parsed_email` with its key. Fails
+    /// with `ErrorCode::ParseEmailFailed` when it does not verify. The key must still be the key
+    /// of the signer, as `ParsedEmail::new_from_raw_bytes` resolves it.
+    pub fn from_parsed_email(parsed_email: ParsedEmail) -> Result<Self> {
+        parsed_email.verify_signature().map_err(|e| {
+            coded(
+                ErrorCode::ParseEmailFailed,
+                format!("the DKIM signature does not verify: {}", e),
+            )
+        })?;
+        Ok(VerifiedEmail(parsed_email))
+    }
+
+    pub fn parsed_email(&self) -> &ParsedEmail {
+        &self.0
+    }
+
+    pub fn into_parsed_email(self) -> ParsedEmail {
+        self.0
+    }
+
+    /// Computes the indexes the email auth input needs and checks them against `options`: the
+    /// from address, the subject and its length, the domain of `domain_source`, the invisible
+    /// characters, the `expected` values and the timestamp of `require_timestamp`.
+    pub fn extract(
+        self,
+        account_code: &AccountCode,
+        options: &InputGenerationOptions,
+    ) -> Result<ExtractedIndexes> {
+        options
+            .validate()
+            .map_err(|e| coded(ErrorCode::InvalidArgument, e))?;
+        let _span = tracing::info_span!("indexes").entered();
+        let header = &self.0.canonicalized_header;
+        let indexes = self.0.get_header_indexes()?;
+        let (indexes, encoded_subject) = if options.decode_encoded_subject {
+            map_encoded_subject(header, indexes)?
+        } else {
+            (indexes, None)
+        };
+        let from_addr_idx = indexes
+            .from_addr
+            .ok_or_else(|| missing_from_addr_error(header))?
+            .0;
+        let subject_idx = indexes
+            .subject
+            .ok_or_else(|| missing_subject_error(header))?
+            .0;
+        let profile = active_circuit_profile();
+        check_subject_len(&indexes, &profile)?;
+        if let Some((start, end)) = indexes.subject {
+            check_subject_email_addrs(&header[start..end])?;
+        }
+        let dkim_signature_idxes = if options.include_dkim_signature_idxes
+            || options.domain_source == DomainSource::DkimDomain
+            || !options.ignore_body_hash
+        {
+            Some(self.0.get_dkim_signature_idxes()?)
+        } else {
+            None
+        };
+        let (domain_idx, signing_domain) = match (options.domain_source, dkim_signature_idxes) {
+            (DomainSource::DkimDomain, Some(dkim_signature_idxes)) => {
+                let (start, end) = dkim_signature_idxes
+                    .domain
+                    .ok_or_else(|| anyhow!("d= tag is not found in the DKIM-Signature"))?;
+                let signing_domain = header[start..end].to_string();
+                let from_domain = from_addr
+                    .zip(indexes.domain)
+                    .map_or("", |(from_addr, (start, end))| &from_addr[start..end]);
+                slog::warn!(
+                    LOG,
+                    "the DKIM signing domain replaces the from domain";
+                    "signing_domain" => &signing_domain,
+                    "from_domain" => from_domain
+                );
+                (start - dkim_signature_idxes.header.0, Some(signing_domain))
+            }
+            _ => {
+                let domain_idx = indexes
+                    .domain
+                    .ok_or_else(|| anyhow!("email domain is not found in the from address"))?
+                    .0;
+                (domain_idx, None)
+            }
+        };
+        if let Some(expected) = &options.expected {
+            // The template is matched against the decoded subject.
+            let expected = match (&encoded_subject, &expected.command_template) {
+                (Some(encoded_subject), Some(template)) => {
+                    if !matches_command_template(template, &encoded_subject.decoded) {
+                        return Err(ExpectationMismatch::CommandTemplate {
+                            template: template.clone(),
+                            command: encoded_subject.decoded.clone(),
+                        }
+                        .into());
+                    }
+                    Cow::Owned(ExpectedValues {
+                        command_template: None,
+                        ..expected.clone()
+                    })
+                }
+                _ => Cow::Borrowed(expected),
+            };
+            let expected = &*expected;
+            match (&signing_domain, &expected.from_domain) {
+                (Some(signing_domain), Some(expected_domain)) => {
+                    if !signing_domain.eq_ignore_ascii_case(expected_domain) {
+                        return Err(ExpectationMismatch::FromDomain {
+                            expected: expected_domain.clone(),
+                            actual: signing_domain.clone(),
+                        }
+                        .into());
+                    }
+                    let expected = ExpectedValues {
+                        from_domain: None,
+                        ..expected.clone()
+                    };
+                    check_expected_values(header, &indexes, account_code, &expected)?;
+                }
+                _ => check_expected_values(header, &indexes, account_code, expected)?,
+            }
+            check_expected_public_key_hash(&self.0, expected)?;
+        }
+        let code_idx = indexes.invitation_code.map_or(0, |idxes| idxes.0);
+        let timestamp_idx = match indexes.timestamp {
+            Some(idxes) => idxes.0,
+            None if options.require_timestamp => {
+                return Err(coded(
+                    ErrorCode::ParseEmailFailed,
+                    "the DKIM-Signature has no valid t= timestamp, but require_timestamp is set",
+                ));
+            }
+            // Only for the warning with the cause: both getters agree on whether there is one.
+            None => self
+                .0
+                .get_timestamp_idxes_or_warn()
+                .map_or(0, |idxes| idxes.0),
+        };
+        Ok(ExtractedIndexes {
+            header_indexes: indexes,
+            dkim_signature_idxes,
+            from_addr_idx,
+            subject_idx,
+            domain_idx,
+            timestamp_idx,
+            code_idx,
+            signing_domain,
+            encoded_subject,
+            email: self,
+            account_code: *account_code,
+        })
+    }
+}
+
+/// The indexes of a verified email, as the email auth input emits them. `domain_idx` is relative
+/// to the DKIM-Signature header with `DomainSource::DkimDomain`, and to the from address
+/// otherwise. A missing timestamp or invitation code has the index 0.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtractedIndexes {
+    #[serde(skip)]
+    email: VerifiedEmail,
+    #[serde(skip)]
+    account_code: AccountCode,
+    pub header_indexes: HeaderIndexes,
+    /// Set when the options emit or use the DKIM-Signature ranges.
+    pub dkim_signature_idxes: Option<DkimSignatureIdxes>,
+    pub from_addr_idx: usize,
+    pub subject_idx: usize,
+    pub domain_idx: usize,
+    pub timestamp_idx: usize,
+    pub code_idx: usize,
+    /// The `d=` domain, set when it replaces the from domain.
+    pub signing_domain: Option<String>,
+    /// The decoded subject, set in the encoded-subject mode for an encoded subject.
+    pub encoded_subject: Option<EncodedSubject>,
+}
+
+impl ExtractedIndexes {
+    pub fn email(&self) -> &VerifiedEmail {
+        &self.email
+    }
+
+    pub fn account_code(&self) -> &AccountCode {
+        &self.account_code
+    }
+
+    /// Builds the email auth input: the nullifier and the public key hash when the options ask
+    /// for them, then the padded header and body. `options` must be those of `extract`.
+    pub fn circuit_inputs(self, options: &InputGenerationOptions) -> Result<CircuitInputs> {
+        check_provable_exponent(self.email.0.public_exponent)?;
+        let public_signals = if options.include_public_signals {
+            Some(self.public_signals()?)
+        } else {
+            None
+        };
+        let parsed_email = &self.email.0;
+        let poseidon_err = |e: PoseidonError| anyhow!("poseidon failed: {}", e);
+        let email_nullifier = if options.include_nullifier {
+            Some(field2hex(
+                &email_nullifier_from_signature(&parsed_email.signature).map_err(poseidon_err)?,
+            ))
+        } else {
+            None
+        };
+        let public_key_hash = if options.include_public_key_hash {
+            Some(field2hex(
+                &public_key_hash_from_modulus(&parsed_email.public_key).map_err(poseidon_err)?,
+            ))
+        } else {
+            None
+        };
+
+        check_cancelled(options.correlation_id.as_deref(), "the circuit inputs")?;
+        let header = &parsed_email.canonicalized_header;
+        let (body, body_hash) = match self.dkim_signature_idxes {
+            Some(idxes) if !options.ignore_body_hash => (
+                parsed_email.canonicalized_body.as_bytes().to_vec(),
+                header[idxes.body_hash.0..idxes.body_hash.1].to_string(),
+            ),
+            _ => (vec![], String::new()),
+        };
+        let circuit_input_params = CircuitInputParams::new(
+            body,
+            header.as_bytes().to_vec(),
+            body_hash,
+            vec_u8_to_bigint(parsed_email.signature.clone()),
+            vec_u8_to_bigint(parsed_email.public_key.clone()),
+            options.sha_precompute_selector.clone(),
+            Some(options.max_header_length),
+            Some(options.max_body_length),
+            Some(options.ignore_body_hash),
+        );
+        let email_circuit_inputs = tracing::info_span!("circuit")
+            .in_scope(|| try_generate_circuit_inputs(circuit_input_params))?;
+        let body_hash_idx = match &email_circuit_inputs.body_hash_idx {
+            Some(idx) => Some(idx.parse::<usize>()?),
+            None => None,
+        };
+        if options.strict || cfg!(debug_assertions) {
+            let invariant = header_digest_invariant_of(&email_circuit_inputs, parsed_email);
+            match invariant {
+                Err(e) if options.strict => {
+                    return Err(coded(ErrorCode::CircuitInputFailed, e));
+                }
+                Err(e) => {
+                    slog::warn!(LOG, "the generated input breaks an invariant";
+                        "error" => e.to_string())
+                }
+                Ok(()) => {}
+            }
+        }
+
+        let dkim_signature_idxes = self.dkim_signature_idxes.filter(|_| {
+            options.include_dkim_signature_idxes
+                || options.domain_source == DomainSource::DkimDomain
+        });
+        let email_auth_input = EmailAuthInput {
+            padded_header: email_circuit_inputs.in_padded,
+            public_key: email_circuit_inputs.pubkey,
+            signature: email_circuit_inputs.signature,
+            padded_header_len: email_circuit_inputs.in_len_padded_bytes,
+            account_code: field2hex(&self.account_code.0),
+            from_addr_idx: self.from_addr_idx,
+            subject_idx: self.subject_idx,
+            domain_idx: self.domain_idx,
+            timestamp_idx: self.timestamp_idx,
+            code_idx: self.code_idx,
+            dkim_signature_idx: dkim_signature_idxes.map(|idxes| idxes.header.0),
+            dkim_body_hash_idx: dkim_signature_idxes.map(|idxes| idxes.body_hash.0),
+            dkim_signature_tag_idx: dkim_signature_idxes.map(|idxes| idxes.signature_tag.0),
+            precomputed_sha: email_circuit_inputs.precomputed_sha,
+            padded_body: email_circuit_inputs.in_body_padded,
+            padded_body_len: email_circuit_inputs.in_body_len_padded_bytes,
+            body_hash_idx,
+            email_nullifier,
+            public_key_hash,
+            public_signals,
+            signing_domain: self.signing_domain,
+            correlation_id: options.correlation_id.clone(),
+        };
+        email_auth_input.check_emitted_indexes()?;
+        if let Some(variant) = options.circuit_variant {
+            variant.validate(&serde_json::to_value(&email_auth_input)?)?;
+        }
+        Ok(CircuitInputs(email_auth_input))
+    }
+}
+
+/// The email auth input of an email, the last stage.
+#[derive(Serialize)]
+#[serde(transparent)]
+pub struct CircuitInputs(EmailAuthInput);
+
+impl CircuitInputs {
+    pub fn email_auth_input(&self) -> &EmailAuthInput {
+        &self.0
+    }
+
+    pub fn into_email_auth_input(self) -> EmailAuthInput {
+        self.0
+    }
+
+    /// The input JSON, as the one-shot generators return it.
+    pub fn to_json(&self) -> Result<String> {
+        check_cancelled(self.0.correlation_id.as_deref(), "serialization")?;
+        tracing::info_span!("serialize").in_scope(|| {
+            let json = to_json_with_capacity(&self.0, self.0.serialized_len_hint())?;
+            tracing::debug!(len = json.len(), "serialized email auth input");
+            Ok(json)
+        })
+    }
+}
+
+/// The canonicalized header of an email and whether it fits in the padded header of the circuit,
+/// to debug index mismatches against what the relayer expected to sign.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CanonicalizedHeaderReport {
+    pub canonicalized_header: String,
+    /// Length in bytes.
+    pub length: usize,
+    /// Length after SHA-256 padding, which must not exceed `max_header_length`.
+    pub padded_length: usize,
+    pub max_header_length: usize,
+    pub fits: bool,
+}
+
+/// The canonicalized header of `raw_email` exactly as input generation with `options` hashes and
+/// pads it. No DKIM key is resolved and nothing is verified.
+#[cfg(feature = "dns")]
+pub fn canonicalized_header_report(
+    raw_email: &[u8],
+    options: &InputGenerationOptions,
+) -> Result<CanonicalizedHeaderReport> {
+    let parsed_headers = RawEmail::new(raw_email.to_vec())
+        .check(options)?
+        .parse()?
+        .check(options)?;
+    let canonicalized_header = parsed_headers.canonicalized_header;
+    let length = canonicalized_header.len();
+    let padded_length = sha256_padded_len(length);
+    Ok(CanonicalizedHeaderReport {
+        canonicalized_header,
+        length,
+        padded_length,
+        max_header_length: options.max_header_length,
+        fits: padded_length <= options.max_header_length,
+    })
+}
+
+/// `canonicalizedHeader(email, options?)` returns the JSON `CanonicalizedHeaderReport`.
+#[cfg(feature = "node")]
+pub fn canonicalized_header_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let raw_email = cx.argument::<JsString>(0)?.value(&mut cx);
+    let options = options_argument(&mut cx, 1)?;
+    let report = canonicalized_header_report(raw_email.as_bytes(), &options)
+        .and_then(|report| Ok(serde_json::to_string(&report)?));
+    match report {
+        Ok(report) => Ok(cx.string(report)),
+        Err(e) => throw_coded(
+            &mut cx,
+            error_code(&e, ErrorCode::ParseEmailFailed),
+            format!("Could not canonicalize the header: {}", e),
+        ),
+    }
+}
+
+#[cfg(all(test, feature = "dns", feature = "async-runtime"))]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    #[tokio::test]
+    async fn test_stages_compose_to_the_one_shot_inputs() {
+        let fixture = test_fixture("accept_guardian").unwrap();
+        let public_key = decode_hex(&fixture.public_key).unwrap();
+        cache_dkim_key(
+            DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+            public_key.clone(),
+        );
+        let account_code = AccountCode(hex2field(&fixture.account_code).unwrap());
+        let stages = |options: &InputGenerationOptions| -> Result<String> {
+            let parsed_headers = RawEmail::from(fixture.raw_email.as_str())
+                .check(options)?
+                .parse()?
+                .check(options)?;
+            assert_eq!(
+                parsed_headers.canonicalized_header,
+                fixture.canonicalized_header
+            );
+            assert_eq!(
+                parsed_headers.dkim_key_id(),
+                Some(DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector))
+            );
+            let extracted = parsed_headers
+                .verify(public_key.clone())?
+                .extract(&account_code, options)?;
+            assert_eq!(
+                extracted.header_indexes,
+                extract_indexes(fixture.canonicalized_header.as_bytes())?
+            );
+            extracted.circuit_inputs(options)?.to_json()
+        };
+
+        let options = InputGenerationOptions::default();
+        let input = stages(&options).unwrap();
+        assert_eq!(
+            Some(hex::encode(Sha256::digest(input.as_bytes()))),
+            fixture.expected.input_sha256
+        );
+        assert_eq!(
+            generate_email_auth_input(&fixture.raw_email, &account_code)
+                .await
+                .unwrap(),
+            input
+        );
+        let options = InputGenerationOptions {
+            include_nullifier: true,
+            include_public_key_hash: true,
+            include_dkim_signature_idxes: true,
+            domain_source: DomainSource::DkimDomain,
+            ignore_body_hash: false,
+            max_body_length: 1024,
+            ..Default::default()
+        };
+        let input = stages(&options).unwrap();
+        assert_eq!(
+            generate_email_auth_input_with_options(&fixture.raw_email, &account_code, &options)
+                .await
+                .unwrap(),
+            input
+        );
+        let parsed_email = ParsedEmail::new_from_raw_email(&fixture.raw_email)
+            .await
+            .unwrap();
+        assert_eq!(
+            generate_email_auth_input_from_parsed_email(&parsed_email, &account_code, &options)
+                .unwrap(),
+            input
+        );
+        assert_eq!(
+            generate_input_from_cached(
+                &parsed_email.to_cached_bytes().unwrap(),
+                &account_code,
+                &options
+            )
+            .unwrap(),
+            input
+        );
+
+        // A caller can stop after any stage; a key that does not verify stops at `verify`.
+        let parsed_headers = RawEmail::from(fixture.raw_email.as_str()).parse().unwrap();
+        let mut wrong_key = public_key;
+        wrong_key[0] ^= 1;
+        let err = parsed_headers.verify(wrong_key).unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::CircuitInputFailed),
+            ErrorCode::ParseEmailFailed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encoded_subject_mode() {
+        let setup = |fixture: &TestFixture| {
+            cache_dkim_key(
+                DkimKeyId::new(&fixture.dkim_domain, &fixture.dkim_selector),
+                decode_hex(&fixture.public_key).unwrap(),
+            );
+            AccountCode(hex2field(&fixture.account_code).unwrap())
+        };
+        let options = |decode_encoded_subject| InputGenerationOptions {
+            expected: Some(ExpectedValues {
+                command_template: Some("Send {uint} ETH to {string}".to_string()),
+                ..Default::default()
+            }),
+            include_public_signals: true,
+            decode_encoded_subject,
+            ..Default::default()
+        };
+
+        let fixture = test_fixture("send_eth_q_encoded").unwrap();
+        let account_code = setup(&fixture);
+        let raw_subject = "=?utf-8?q?Send_1_ETH_to_bob=40example.com?=";
+        let extract = |options: &InputGenerationOptions| -> Result<ExtractedIndexes> {
+            RawEmail::from(fixture.raw_email.as_str())
+                .parse()?
+                .verify(decode_hex(&fixture.public_key)?)?
+                .extract(&account_code, options)
+        };
+        // The template does not match the encoded bytes.
+        let err = extract(&options(false)).unwrap_err();
+        assert!(err.downcast_ref::<ExpectationMismatch>().is_some(), "{}", err);
+
+        let extracted = extract(&options(true)).unwrap();
+        let encoded_subject = extracted.encoded_subject.clone().unwrap();
+        assert_eq!(encoded_subject.decoded, "Send 1 ETH to bob@example.com");
+        // The indexes are those of the signed, encoded bytes.
+        let header = &fixture.canonicalized_header;
+        let (subject_start, subject_end) = extracted.header_indexes.subject.unwrap();
+        assert_eq!(&header[subject_start..subject_end], raw_subject);
+        let (start, end) = extracted.header_indexes.email_addr_in_subject.unwrap();
+        assert_eq!(&raw_subject[start..end], "bob=40example.com");
+        let (masked, is_code_exist) =
+            masked_encoded_command(raw_subject, &encoded_subject).unwrap();
+        assert!(!is_code_exist);
+        assert_eq!(masked[..start], raw_subject.as_bytes()[..start]);
+        assert!(masked[start..end].iter().all(|&byte| byte == 0));
+        assert_eq!(masked[end..], *b"?=");
+        assert_eq!(
+            extracted.public_signals().unwrap()[DOMAIN_FIELDS + 3..PUBLIC_SIGNALS_LEN - 2],
+            pack_bytes_into_fields(&masked, COMMAND_FIELDS)
+                .unwrap()
+                .iter()
+                .map(field2decimal)
+                .collect::<Vec<_>>()[..]
+        );
+        let mode = options(true);
+        let input = generate_email_auth_input_with_options(&fixture.raw_email, &account_code, &mode)
+            .await
+            .unwrap();
+        assert!(input.contains("public_signals"));
+
+        // A B-encoded subject has no byte mapping and is rejected in the mode only.
+        let fixture = test_fixture("send_eth_b_encoded").unwrap();
+        let account_code = setup(&fixture);
+        let err = generate_email_auth_input_with_options(&fixture.raw_email, &account_code, &mode)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains(BASE64_SUBJECT_UNSUPPORTED), "{}", err);
+        let options = InputGenerationOptions::default();
+        generate_email_auth_input_with_options(&fixture.raw_email, &account_code, &options)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_canonicalized_header_report() {
+        let fixture = test_fixture("send_eth").unwrap();
+        let options = InputGenerationOptions::default();
+        let report = canonicalized_header_report(fixture.raw_email.as_bytes(), &options).unwrap();
+        assert_eq!(report.canonicalized_header, fixture.canonicalized_header);
+        assert_eq!(report.length, fixture.canonicalized_header.len());
+        assert!(report.fits);
+
+        // Tabs and folded lines are unfolded and collapsed by relaxed canonicalization.
+        let folded = fixture.raw_email.replace(
+            "Subject: Send 1 ETH to bob@example.com",
+            "Subject:\tSend 1 ETH\r\n to\t\tbob@example.com \t",
+        );
+        let folded = folded.replace("To: relayer", "To:\r\n\trelayer");
+        assert_ne!(folded, fixture.raw_email);
+        let report = canonicalized_header_report(folded.as_bytes(), &options).unwrap();
+        assert_eq!(report.canonicalized_header, fixture.canonicalized_header);
+
+        let options = InputGenerationOptions {
+            max_header_length: 64,
+            ..Default::default()
+        };
+        let report = canonicalized_header_report(folded.as_bytes(), &options).unwrap();
+        assert_eq!(report.padded_length, sha256_padded_len(report.length));
+        assert_eq!(report.max_header_length, 64);
+        assert!(!report.fits);
+    }
+
+    #[test]
+    fn test_require_timestamp() {
+        // The fixtures are signed without a `t=` tag.
+        let fixture = test_fixture("send_eth").unwrap();
+        let account_code = AccountCode(hex2field(&fixture.account_code).unwrap());
+        let email = VerifiedEmail::from_parsed_email(fixture.parsed_email().unwrap()).unwrap();
+        let options = InputGenerationOptions::default();
+        let indexes = email.clone().extract(&account_code, &options).unwrap();
+        assert_eq!(indexes.timestamp_idx, 0);
+
+        let options = InputGenerationOptions {
+            require_timestamp: true,
+            ..Default::default()
+        };
+        let err = email.extract(&account_code, &options).unwrap_err();
+        assert_eq!(
+            error_code(&err, ErrorCode::InternalPanic),
+            ErrorCode::ParseEmailFailed
+        );
+        assert!(err.to_string().contains("require_timestamp"), "{}", err);
+    }
+}
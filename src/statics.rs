@@ -1,9 +1,264 @@
+#[cfg(feature = "node")]
 use neon::prelude::*;
-use once_cell::sync::OnceCell;
+#[cfg(feature = "async-runtime")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "async-runtime")]
+use std::time::Duration;
+#[cfg(feature = "async-runtime")]
 use tokio::runtime::Runtime;
 
-pub fn runtime<'a, C: Context<'a>>(cx: &mut C) -> NeonResult<&'static Runtime> {
-    static RUNTIME: OnceCell<Runtime> = OnceCell::new();
+/// Git commit the library was built from, baked in by `build.rs`; `unknown` for a build outside a
+/// git checkout.
+pub const GIT_COMMIT: &str = env!("RELAYER_UTILS_GIT_COMMIT");
 
-    RUNTIME.get_or_try_init(|| Runtime::new().or_else(|err| cx.throw_error(err.to_string())))
+/// Error returned by every call that needs the runtime after `shutdown_runtime`.
+pub const SHUT_DOWN_ERROR: &str = "relayer-utils has been shut down";
+
+/// Time given to in-flight tasks by `shutdown_runtime` before the runtime is dropped.
+#[cfg(feature = "async-runtime")]
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[cfg(feature = "async-runtime")]
+enum RuntimeState {
+    Uninitialized,
+    Running(Arc<Runtime>),
+    ShutDown,
+}
+
+#[cfg(feature = "async-runtime")]
+static RUNTIME: Mutex<RuntimeState> = Mutex::new(RuntimeState::Uninitialized);
+
+#[cfg(feature = "async-runtime")]
+fn runtime_state() -> std::sync::MutexGuard<'static, RuntimeState> {
+    RUNTIME.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns the shared runtime, creating it on first use.
+///
+/// Fails with `SHUT_DOWN_ERROR` after `shutdown_runtime` until `reinit_runtime` is called, or
+/// when the runtime cannot be created (e.g. threads can no longer be spawned).
+#[cfg(feature = "async-runtime")]
+pub fn shared_runtime() -> anyhow::Result<Arc<Runtime>> {
+    let mut state = runtime_state();
+    match &*state {
+        RuntimeState::Running(rt) => Ok(rt.clone()),
+        RuntimeState::ShutDown => Err(anyhow::anyhow!(SHUT_DOWN_ERROR)),
+        RuntimeState::Uninitialized => {
+            let rt = Arc::new(
+                Runtime::new()
+                    .map_err(|e| anyhow::anyhow!("could not create the Tokio runtime: {}", e))?,
+            );
+            *state = RuntimeState::Running(rt.clone());
+            Ok(rt)
+        }
+    }
+}
+
+/// Drains and drops the shared runtime. Later calls fail with `SHUT_DOWN_ERROR`.
+#[cfg(feature = "async-runtime")]
+pub fn shutdown_runtime() {
+    let state = std::mem::replace(&mut *runtime_state(), RuntimeState::ShutDown);
+    if let RuntimeState::Running(rt) = state {
+        if let Ok(rt) = Arc::try_unwrap(rt) {
+            rt.shutdown_timeout(SHUTDOWN_TIMEOUT);
+        }
+    }
+}
+
+/// Allows the runtime to be created again after `shutdown_runtime`.
+#[cfg(feature = "async-runtime")]
+pub fn reinit_runtime() {
+    let mut state = runtime_state();
+    if let RuntimeState::ShutDown = *state {
+        *state = RuntimeState::Uninitialized;
+    }
+}
+
+/// Does the setup that the first call would otherwise pay for: the logger, the tracing bridge
+/// and the shared runtime. Idempotent and safe to call from any thread; fails like
+/// `shared_runtime`.
+#[cfg(feature = "async-runtime")]
+pub fn init_library() -> anyhow::Result<()> {
+    lazy_static::initialize(&crate::LOG);
+    crate::install_panic_hook();
+    crate::install_slog_bridge();
+    shared_runtime().map(|_| ())
+}
+
+#[cfg(feature = "node")]
+pub fn runtime<'a, C: Context<'a>>(cx: &mut C) -> NeonResult<Arc<Runtime>> {
+    shared_runtime().or_else(|err| cx.throw_error(err.to_string()))
+}
+
+/// The config of `init`: the `HotConfig` fields, and the settings only read at initialization:
+/// `audit_log_path`, the JSONL file that the audit records are appended to, and
+/// `proving_cost_weights`, the `ProvingCostWeights` of `estimateProvingCost`, and
+/// `circuit_profile`, a profile name or object as accepted by `CircuitProfile::from_json`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InitConfig {
+    pub hot_config: crate::HotConfig,
+    pub audit_log_path: Option<std::path::PathBuf>,
+    pub proving_cost_weights: Option<crate::ProvingCostWeights>,
+    pub circuit_profile: Option<crate::CircuitProfile>,
+}
+
+impl InitConfig {
+    pub fn from_json(config_json: &str) -> anyhow::Result<Self> {
+        let mut config: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(config_json)
+                .map_err(|e| anyhow::anyhow!("invalid init config: {}", e))?;
+        let audit_log_path = match config.remove("audit_log_path") {
+            Some(path) => serde_json::from_value(path)
+                .map_err(|e| anyhow::anyhow!("invalid audit_log_path: {}", e))?,
+            None => None,
+        };
+        let proving_cost_weights = match config.remove("proving_cost_weights") {
+            Some(weights) => {
+                let weights: crate::ProvingCostWeights = serde_json::from_value(weights)
+                    .map_err(|e| anyhow::anyhow!("invalid proving_cost_weights: {}", e))?;
+                weights.validate()?;
+                Some(weights)
+            }
+            None => None,
+        };
+        let circuit_profile = match config.remove("circuit_profile") {
+            Some(profile) => Some(crate::CircuitProfile::from_json(&profile.to_string())?),
+            None => None,
+        };
+        let hot_config =
+            crate::HotConfig::from_json(&serde_json::Value::Object(config).to_string())?;
+        Ok(InitConfig {
+            hot_config,
+            audit_log_path,
+            proving_cost_weights,
+            circuit_profile,
+        })
+    }
+}
+
+/// `init(configJson?)` runs `init_library` and applies the `InitConfig` in `configJson`, its hot
+/// settings as `reloadConfig` does, for services that configure the library at startup. The
+/// module already initializes itself when loaded, so this is only needed for the config or to
+/// surface an initialization failure.
+#[cfg(feature = "node")]
+pub fn init_node(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let config = match cx.argument_opt(0) {
+        Some(config) if !config.is_a::<JsUndefined, _>(&mut cx) => {
+            let config = config
+                .downcast_or_throw::<JsString, _>(&mut cx)?
+                .value(&mut cx);
+            match InitConfig::from_json(&config) {
+                Ok(config) => Some(config),
+                Err(e) => {
+                    return crate::throw_coded(
+                        &mut cx,
+                        crate::ErrorCode::InvalidArgument,
+                        e.to_string(),
+                    )
+                }
+            }
+        }
+        _ => None,
+    };
+    if let Err(e) = init_library() {
+        return cx.throw_error(e.to_string());
+    }
+    if let Some(config) = config {
+        if let Some(path) = &config.audit_log_path {
+            let sink = match crate::JsonlAuditSink::open(path) {
+                Ok(sink) => Arc::new(sink),
+                Err(e) => {
+                    return crate::throw_coded(
+                        &mut cx,
+                        crate::ErrorCode::InvalidArgument,
+                        e.to_string(),
+                    )
+                }
+            };
+            if let Err(e) = crate::install_audit_sink(Some(sink), crate::AUDIT_QUEUE_CAPACITY) {
+                return cx.throw_error(e.to_string());
+            }
+        }
+        if let Some(weights) = config.proving_cost_weights {
+            if let Err(e) = crate::set_proving_cost_weights(weights) {
+                return crate::throw_coded(
+                    &mut cx,
+                    crate::ErrorCode::InvalidArgument,
+                    e.to_string(),
+                );
+            }
+        }
+        if let Some(profile) = config.circuit_profile {
+            if let Err(e) = crate::set_circuit_profile(profile) {
+                return crate::throw_coded(
+                    &mut cx,
+                    crate::ErrorCode::InvalidArgument,
+                    e.to_string(),
+                );
+            }
+        }
+        crate::reload_config(config.hot_config);
+    }
+    Ok(cx.undefined())
+}
+
+#[cfg(feature = "node")]
+pub fn shutdown_node(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    shutdown_runtime();
+    Ok(cx.undefined())
+}
+
+#[cfg(feature = "node")]
+pub fn reinit_node(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    reinit_runtime();
+    Ok(cx.undefined())
+}
+
+#[cfg(all(test, feature = "async-runtime"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_config() {
+        let config = InitConfig::from_json(
+            r#"{"audit_log_path": "/var/log/audit.jsonl", "max_email_bytes": 1024}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.audit_log_path,
+            Some(std::path::PathBuf::from("/var/log/audit.jsonl"))
+        );
+        assert_eq!(config.hot_config.max_email_bytes, Some(1024));
+        assert_eq!(config.proving_cost_weights, None);
+        assert_eq!(InitConfig::from_json("{}").unwrap(), InitConfig::default());
+        assert!(InitConfig::from_json(r#"{"audit_log_path": 1}"#).is_err());
+        assert!(InitConfig::from_json(r#"{"audit_log": "x"}"#).is_err());
+
+        let config = InitConfig::from_json(
+            r#"{"proving_cost_weights": {"header": 2.0, "body": 0.5, "key": 0.0}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.proving_cost_weights,
+            Some(crate::ProvingCostWeights {
+                header: 2.0,
+                body: 0.5,
+                key: 0.0,
+            })
+        );
+        for weights in [
+            r#"{"header": -1.0, "body": 1.0, "key": 1.0}"#,
+            r#"{"header": 1.0, "body": 1.0}"#,
+            r#"{"header": 1.0, "body": 1.0, "key": 1.0, "limbs": 1.0}"#,
+        ] {
+            let config = format!(r#"{{"proving_cost_weights": {}}}"#, weights);
+            assert!(InitConfig::from_json(&config).is_err(), "{}", weights);
+        }
+
+        let config = InitConfig::from_json(r#"{"circuit_profile": "v1_large"}"#).unwrap();
+        assert_eq!(config.circuit_profile, crate::CircuitProfile::named("v1_large"));
+        assert_eq!(config.hot_config, crate::HotConfig::default());
+        let err = InitConfig::from_json(r#"{"circuit_profile": "v2"}"#).unwrap_err();
+        assert!(err.to_string().contains("unknown circuit profile"), "{}", err);
+    }
 }
@@ -0,0 +1,360 @@
+use crate::*;
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+#[cfg(feature = "node")]
+use neon::prelude::*;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::{DecodePrivateKey, EncodePublicKey};
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPrivateKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Signing keys of the fixtures, by the name a fixture selects with `signing_key`. They are test
+/// keys published with the crate, so they sign nothing but the fixtures. `e3` has the public
+/// exponent 3, which verifies but cannot be proven with.
+const FIXTURE_PRIVATE_KEYS_PEM: [(&str, &str); 2] = [
+    ("default", include_str!("../fixtures/dkim_private_key.pem")),
+    ("e3", include_str!("../fixtures/dkim_private_key_e3.pem")),
+];
+
+/// The fixtures of `fixtures/`, embedded at build time.
+const FIXTURES: [(&str, &str); 11] = [
+    ("send_eth", include_str!("../fixtures/send_eth.json")),
+    (
+        "accept_guardian",
+        include_str!("../fixtures/accept_guardian.json"),
+    ),
+    // The send_eth subject, RFC 2047 encoded as some mobile clients send it.
+    (
+        "send_eth_q_encoded",
+        include_str!("../fixtures/send_eth_q_encoded.json"),
+    ),
+    (
+        "send_eth_b_encoded",
+        include_str!("../fixtures/send_eth_b_encoded.json"),
+    ),
+    // A multipart body with a virus scan notice after the closing delimiter, once as the signer
+    // saw it and once appended by a gateway after signing.
+    (
+        "send_eth_footer_signed",
+        include_str!("../fixtures/send_eth_footer_signed.json"),
+    ),
+    (
+        "send_eth_footer_after_signing",
+        include_str!("../fixtures/send_eth_footer_after_signing.json"),
+    ),
+    // Signed with a key of public exponent 3, under its own selector.
+    ("send_eth_e3", include_str!("../fixtures/send_eth_e3.json")),
+    // The send_eth email with a Date of each obsolete form of RFC 5322 §4.3, and with garbage.
+    (
+        "send_eth_date_two_digit_year",
+        include_str!("../fixtures/send_eth_date_two_digit_year.json"),
+    ),
+    (
+        "send_eth_date_single_digit_day",
+        include_str!("../fixtures/send_eth_date_single_digit_day.json"),
+    ),
+    (
+        "send_eth_date_military_zone",
+        include_str!("../fixtures/send_eth_date_military_zone.json"),
+    ),
+    (
+        "send_eth_date_garbage",
+        include_str!("../fixtures/send_eth_date_garbage.json"),
+    ),
+];
+
+/// A fixture file. Every value is written to the email as is, so the header values must already
+/// be in relaxed canonical form (single spaces, no trailing whitespace), and `body` must end with
+/// one CRLF. `content_type` is an unsigned header field, and `appended_after_signing` is added
+/// to the body after the signature is computed, so that the body hash no longer verifies.
+/// `signing_key` names one of `FIXTURE_PRIVATE_KEYS_PEM`, `default` when absent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixtureSpec {
+    from: String,
+    to: String,
+    subject: String,
+    date: String,
+    body: String,
+    domain: String,
+    selector: String,
+    account_code: String,
+    #[serde(default)]
+    content_type: Option<String>,
+    #[serde(default)]
+    appended_after_signing: String,
+    #[serde(default)]
+    signing_key: Option<String>,
+}
+
+/// Outputs computed from a fixture, as the library computes them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixtureOutputs {
+    /// SHA-256 of the email auth input JSON, or `None` when the key cannot be proven with (see
+    /// `check_provable_exponent`).
+    pub input_sha256: Option<String>,
+    pub account_salt: String,
+    pub email_nullifier: String,
+    pub public_key_hash: String,
+}
+
+/// A DKIM-signed email that verifies without network access: a test resolver serves
+/// `dkim_txt_record` for `{dkim_selector}._domainkey.{dkim_domain}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestFixture {
+    pub name: String,
+    pub raw_email: String,
+    pub canonicalized_header: String,
+    /// Big-endian signature of the canonicalized header, the `b=` tag, as hex.
+    pub signature: String,
+    pub dkim_domain: String,
+    pub dkim_selector: String,
+    pub dkim_txt_record: String,
+    /// Big-endian modulus of the DKIM key, as hex.
+    pub public_key: String,
+    pub public_exponent: u64,
+    pub account_code: String,
+    pub expected: FixtureOutputs,
+}
+
+/// The named key of `FIXTURE_PRIVATE_KEYS_PEM`.
+fn fixture_private_key(key_name: &str) -> Result<RsaPrivateKey> {
+    let private_key_pem = FIXTURE_PRIVATE_KEYS_PEM
+        .iter()
+        .find(|(name, _)| *name == key_name)
+        .map(|(_, pem)| *pem)
+        .ok_or_else(|| anyhow!("unknown fixture signing key `{}`", key_name))?;
+    RsaPrivateKey::from_pkcs8_pem(private_key_pem)
+        .map_err(|e| anyhow!("invalid fixture key: {}", e))
+}
+
+pub fn test_fixture_names() -> Vec<&'static str> {
+    FIXTURES.iter().map(|(name, _)| *name).collect()
+}
+
+/// Builds the named fixture, signing it with the fixture key. Everything is deterministic, so the
+/// same fixture is returned on every call.
+pub fn test_fixture(name: &str) -> Result<TestFixture> {
+    let spec = FIXTURES
+        .iter()
+        .find(|(fixture_name, _)| *fixture_name == name)
+        .map(|(_, spec)| serde_json::from_str::<FixtureSpec>(spec))
+        .ok_or_else(|| {
+            anyhow!(
+                "unknown test fixture `{}`, expected one of: {}",
+                name,
+                test_fixture_names().join(", ")
+            )
+        })??;
+    let private_key = fixture_private_key(spec.signing_key.as_deref().unwrap_or("default"))?;
+
+    let body_hash = general_purpose::STANDARD.encode(Sha256::digest(spec.body.as_bytes()));
+    let dkim_tags = format!(
+        "v=1; a=rsa-sha256; c=relaxed/relaxed; d={}; s={}; h=from:to:subject:date; bh={}; b=",
+        spec.domain, spec.selector, body_hash
+    );
+    let canonicalized_header = format!(
+        "from:{}\r\nto:{}\r\nsubject:{}\r\ndate:{}\r\ndkim-signature:{}",
+        spec.from, spec.to, spec.subject, spec.date, dkim_tags
+    );
+    let signature = SigningKey::<Sha256>::new(private_key.clone())
+        .sign(canonicalized_header.as_bytes())
+        .to_vec();
+    let content_type = spec
+        .content_type
+        .as_ref()
+        .map_or(String::new(), |content_type| {
+            format!("Content-Type: {}\r\n", content_type)
+        });
+    let raw_email = format!(
+        "DKIM-Signature: {}{}\r\nFrom: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n{}\r\n{}{}",
+        dkim_tags,
+        general_purpose::STANDARD.encode(&signature),
+        spec.from,
+        spec.to,
+        spec.subject,
+        spec.date,
+        content_type,
+        spec.body,
+        spec.appended_after_signing
+    );
+
+    let public_key = private_key.to_public_key();
+    let public_key_der = public_key
+        .to_public_key_der()
+        .map_err(|e| anyhow!("invalid fixture key: {}", e))?;
+    let public_key_n = public_key.n().to_bytes_be();
+    let public_exponent = rsa_public_exponent(&public_key);
+    let account_code = AccountCode(hex2field(&spec.account_code)?);
+    let expected = fixture_outputs(
+        &canonicalized_header,
+        &signature,
+        &public_key_n,
+        public_exponent,
+        &account_code,
+    )?;
+    Ok(TestFixture {
+        name: name.to_string(),
+        raw_email,
+        canonicalized_header,
+        signature: hex::encode(&signature),
+        dkim_domain: spec.domain,
+        dkim_selector: spec.selector,
+        dkim_txt_record: format!(
+            "v=DKIM1; k=rsa; p={}",
+            general_purpose::STANDARD.encode(public_key_der.as_bytes())
+        ),
+        public_key: hex::encode(&public_key_n),
+        public_exponent,
+        account_code: field2hex(&account_code.0),
+        expected,
+    })
+}
+
+impl TestFixture {
+    /// The DKIM key of the fixture, as a test resolver would fetch it.
+    pub fn dkim_key_fetch(&self) -> Result<DkimKeyFetch> {
+        Ok(DkimKeyFetch {
+            public_key: decode_hex(&self.public_key)?,
+            public_exponent: self.public_exponent,
+            dnssec_validated: None,
+        })
+    }
+
+    /// The verified email of the fixture, without its body.
+    pub fn parsed_email(&self) -> Result<ParsedEmail> {
+        Ok(ParsedEmail {
+            canonicalized_header: self.canonicalized_header.clone(),
+            canonicalized_body: String::new(),
+            signature: decode_hex(&self.signature)?,
+            public_key: decode_hex(&self.public_key)?,
+            public_exponent: self.public_exponent,
+            dnssec_validated: None,
+        })
+    }
+}
+
+/// The account code of the `send_eth` fixtures, for tests that need any account code.
+pub const FIXTURE_ACCOUNT_CODE: &str =
+    "0x22a2d51a892f866cf3c6cc4e138ba87a8a5059a1d80dea5b8ee8232034a105b7";
+
+/// `FIXTURE_ACCOUNT_CODE` as an `AccountCode`.
+pub fn fixture_account_code() -> AccountCode {
+    AccountCode(hex2field(FIXTURE_ACCOUNT_CODE).expect("FIXTURE_ACCOUNT_CODE is a field element"))
+}
+
+/// An email whose canonicalized header is signed with the default fixture key, for inputs of
+/// emails that no fixture describes. The key signs nothing but test emails, so nothing generated
+/// from it is accepted for a real domain.
+pub fn fixture_signed_email(canonicalized_header: &str, canonicalized_body: &str) -> ParsedEmail {
+    let private_key = fixture_private_key("default").expect("the default fixture key is valid");
+    ParsedEmail {
+        canonicalized_header: canonicalized_header.to_string(),
+        canonicalized_body: canonicalized_body.to_string(),
+        signature: SigningKey::<Sha256>::new(private_key.clone())
+            .sign(canonicalized_header.as_bytes())
+            .to_vec(),
+        public_key: private_key.n().to_bytes_be(),
+        public_exponent: DEFAULT_PUBLIC_EXPONENT,
+        dnssec_validated: None,
+    }
+}
+
+fn fixture_outputs(
+    canonicalized_header: &str,
+    signature: &[u8],
+    public_key_n: &[u8],
+    public_exponent: u64,
+    account_code: &AccountCode,
+) -> Result<FixtureOutputs> {
+    let poseidon_err = |e: PoseidonError| anyhow!("poseidon failed: {}", e);
+    let input_sha256 = match check_provable_exponent(public_exponent) {
+        Ok(()) => {
+            let input = generate_email_auth_input_from_canonical(
+                canonicalized_header.as_bytes(),
+                signature,
+                public_key_n,
+                account_code,
+            )?;
+            Some(hex::encode(Sha256::digest(input.as_bytes())))
+        }
+        Err(_) => None,
+    };
+    let from_addr = extract_sender(canonicalized_header)?.from_addr;
+    let account_salt =
+        AccountSalt::new(&PaddedEmailAddr::from_email_addr(&from_addr), *account_code)
+            .map_err(poseidon_err)?;
+    Ok(FixtureOutputs {
+        input_sha256,
+        account_salt: field2hex(&account_salt.0),
+        email_nullifier: field2hex(
+            &email_nullifier_from_signature(signature).map_err(poseidon_err)?,
+        ),
+        public_key_hash: field2hex(
+            &public_key_hash_from_modulus(public_key_n).map_err(poseidon_err)?,
+        ),
+    })
+}
+
+/// `testFixture(name)` returns the JSON `TestFixture`.
+#[cfg(feature = "node")]
+pub fn test_fixture_node(mut cx: FunctionContext) -> JsResult<JsString> {
+    let name = cx.argument::<JsString>(0)?.value(&mut cx);
+    match test_fixture(&name).and_then(|fixture| Ok(serde_json::to_string(&fixture)?)) {
+        Ok(fixture) => Ok(cx.string(fixture)),
+        Err(e) => throw_coded(&mut cx, ErrorCode::InvalidArgument, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "dns")]
+    #[test]
+    fn test_fixtures_match_live_computation() {
+        for name in test_fixture_names() {
+            let fixture = test_fixture(name).unwrap();
+            assert_eq!(fixture, test_fixture(name).unwrap());
+
+            // The raw email canonicalizes to the header that was signed.
+            let parsed_email = ParsedEmail::parse_untrusted(&fixture.raw_email).unwrap();
+            assert_eq!(
+                parsed_email.canonicalized_header,
+                fixture.canonicalized_header
+            );
+            let public_key = rsa_public_key_from_dkim_txt(&fixture.dkim_txt_record).unwrap();
+            let public_key_n = public_key.n().to_bytes_be();
+            assert_eq!(hex::encode(&public_key_n), fixture.public_key);
+            assert_eq!(rsa_public_exponent(&public_key), fixture.public_exponent);
+            verify_rsa_sha256_with_exponent(
+                fixture.canonicalized_header.as_bytes(),
+                &parsed_email.signature,
+                &public_key_n,
+                fixture.public_exponent,
+            )
+            .unwrap();
+
+            let account_code = AccountCode(hex2field(&fixture.account_code).unwrap());
+            let outputs = fixture_outputs(
+                &parsed_email.canonicalized_header,
+                &parsed_email.signature,
+                &public_key_n,
+                fixture.public_exponent,
+                &account_code,
+            )
+            .unwrap();
+            assert_eq!(outputs, fixture.expected, "{}", name);
+            assert_eq!(
+                outputs.input_sha256.is_some(),
+                fixture.public_exponent == DEFAULT_PUBLIC_EXPONENT,
+                "{}",
+                name
+            );
+        }
+        assert!(test_fixture("missing").is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::logger::LOG;
+use std::fmt::{self, Write};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Span field carrying the caller-supplied correlation id.
+pub const CORRELATION_ID_FIELD: &str = "correlation_id";
+
+/// Formats the fields of a span or event. Spans keep theirs in their extensions.
+#[derive(Default)]
+struct FieldFormatter {
+    message: String,
+    fields: String,
+    correlation_id: Option<String>,
+}
+
+impl Visit for FieldFormatter {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == CORRELATION_ID_FIELD {
+            self.correlation_id = Some(value.to_string());
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// Forwards `tracing` events to `LOG`, tagged with the enclosing span names and the innermost
+/// correlation id.
+pub struct SlogLayer;
+
+impl<S> Layer<S> for SlogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = FieldFormatter::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<FieldFormatter>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = FieldFormatter::default();
+        event.record(&mut fields);
+        let mut spans = vec![];
+        let mut correlation_id = None;
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                spans.push(span.name());
+                if let Some(span_fields) = span.extensions().get::<FieldFormatter>() {
+                    if span_fields.correlation_id.is_some() {
+                        correlation_id = span_fields.correlation_id.clone();
+                    }
+                }
+            }
+        }
+        let message = fields.message + &fields.fields;
+        let spans = spans.join(":");
+        let correlation_id = correlation_id.unwrap_or_default();
+        match *event.metadata().level() {
+            Level::ERROR => {
+                slog::error!(LOG, "{}", message; "spans" => spans, "correlation_id" => correlation_id)
+            }
+            Level::WARN => {
+                slog::warn!(LOG, "{}", message; "spans" => spans, "correlation_id" => correlation_id)
+            }
+            Level::INFO => {
+                slog::info!(LOG, "{}", message; "spans" => spans, "correlation_id" => correlation_id)
+            }
+            Level::DEBUG => {
+                slog::debug!(LOG, "{}", message; "spans" => spans, "correlation_id" => correlation_id)
+            }
+            Level::TRACE => {
+                slog::trace!(LOG, "{}", message; "spans" => spans, "correlation_id" => correlation_id)
+            }
+        }
+    }
+}
+
+/// Installs `SlogLayer` as the global subscriber. Does nothing if one is already installed.
+pub fn install_slog_bridge() {
+    let _ = tracing::subscriber::set_global_default(tracing_subscriber::registry().with(SlogLayer));
+}
+
+/// Root span of one email auth input generation.
+pub fn email_auth_input_span(correlation_id: Option<&str>) -> tracing::Span {
+    tracing::info_span!("email_auth_input", correlation_id = correlation_id)
+}
+
+#[cfg(all(test, feature = "async-runtime"))]
+mod tests {
+    use super::*;
+    use crate::*;
+    use std::sync::{Arc, Mutex};
+    use tracing::Instrument;
+
+    #[derive(Clone, Default)]
+    struct Capture {
+        /// (span name, parent span name)
+        spans: Arc<Mutex<Vec<(String, Option<String>)>>>,
+        /// (event message, innermost correlation id)
+        events: Arc<Mutex<Vec<(String, Option<String>)>>>,
+    }
+
+    impl<S> Layer<S> for Capture
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let span = ctx.span(id).unwrap();
+            let parent = span.parent().map(|parent| parent.name().to_string());
+            self.spans
+                .lock()
+                .unwrap()
+                .push((span.name().to_string(), parent));
+        }
+
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let mut fields = FieldFormatter::default();
+            event.record(&mut fields);
+            let correlation_id = ctx.event_scope(event).and_then(|scope| {
+                scope.from_root().fold(None, |correlation_id, span| {
+                    span.extensions()
+                        .get::<FieldFormatter>()
+                        .and_then(|fields| fields.correlation_id.clone())
+                        .or(correlation_id)
+                })
+            });
+            self.events
+                .lock()
+                .unwrap()
+                .push((fields.message, correlation_id));
+        }
+    }
+
+    #[test]
+    fn test_spans_carry_the_correlation_id() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(SlogLayer)
+            .with(capture.clone());
+        let parsed_email =
+            fixture_signed_email("from:alice@gmail.com\r\nsubject:Send 1 ETH\r\n", "");
+        let options = InputGenerationOptions {
+            correlation_id: Some("req-42".to_string()),
+            ..Default::default()
+        };
+        let account_code = fixture_account_code();
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let input = tracing::subscriber::with_default(subscriber, || {
+            rt.block_on(
+                async {
+                    tokio::task::yield_now().await;
+                    generate_email_auth_input_from_parsed_email(
+                        &parsed_email,
+                        &account_code,
+                        &options,
+                    )
+                }
+                .instrument(email_auth_input_span(options.correlation_id.as_deref())),
+            )
+        })
+        .unwrap();
+
+        let spans = capture.spans.lock().unwrap().clone();
+        let root = "email_auth_input".to_string();
+        assert!(spans.contains(&(root.clone(), None)));
+        for stage in ["indexes", "circuit", "serialize"] {
+            assert!(
+                spans.contains(&(stage.to_string(), Some(root.clone()))),
+                "{:?}",
+                spans
+            );
+        }
+        let events = capture.events.lock().unwrap().clone();
+        assert!(events
+            .iter()
+            .any(|(_, correlation_id)| correlation_id.as_deref() == Some("req-42")));
+        let input: serde_json::Value = serde_json::from_str(&input).unwrap();
+        assert_eq!(input["correlation_id"], "req-42");
+    }
+}
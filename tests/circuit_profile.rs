@@ -0,0 +1,35 @@
+//! Generates the same emails under `v1_small` and `v1_large`. It is its own test binary: the
+//! active profile is process-wide, so switching it would change the limits of every other test.
+use relayer_utils::*;
+
+fn generate(header: &str) -> anyhow::Result<String> {
+    let parsed_email = fixture_signed_email(header, "");
+    // The default options read the active profile, so they are built after selecting it.
+    let options = InputGenerationOptions::default();
+    generate_email_auth_input_from_parsed_email(&parsed_email, &fixture_account_code(), &options)
+}
+
+#[test]
+fn generate_under_both_profiles() {
+    // A 300-byte subject fits v1_small (605 bytes) but not v1_large (256 bytes).
+    let long_subject = format!("from:alice@gmail.com\r\nsubject:{}\r\n", "A".repeat(300));
+    // A 1500-byte header fits v1_large (2048 bytes) but not v1_small (1024 bytes).
+    let long_header = format!(
+        "from:alice@gmail.com\r\nx-filler:{}\r\nsubject:Send 1 ETH\r\n",
+        "f".repeat(1450)
+    );
+
+    assert_eq!(active_circuit_profile(), CircuitProfile::default());
+    generate(&long_subject).unwrap();
+    assert!(generate(&long_header).is_err());
+
+    set_circuit_profile(CircuitProfile::named("v1_large").unwrap()).unwrap();
+    let err = generate(&long_subject).unwrap_err();
+    assert_eq!(
+        error_code(&err, ErrorCode::InternalPanic),
+        ErrorCode::CircuitInputFailed
+    );
+    assert!(err.to_string().contains("300") && err.to_string().contains("256"), "{}", err);
+    let input: serde_json::Value = serde_json::from_str(&generate(&long_header).unwrap()).unwrap();
+    assert_eq!(input["padded_header"].as_array().unwrap().len(), 2048);
+}
@@ -0,0 +1,62 @@
+//! Helpers shared by the integration test binaries.
+#![allow(dead_code)]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Global allocator tracking the current and peak allocated bytes. A test binary opts in with
+/// `#[global_allocator] static ALLOCATOR: PeakTracking = PeakTracking;`.
+pub struct PeakTracking;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakTracking {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(current, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Peak bytes allocated while running `f`, above what was allocated before. The result of `f`
+/// is returned along with the peak.
+pub fn peak_allocation_of<T, F: FnOnce() -> T>(f: F) -> (T, usize) {
+    let baseline = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+    let result = f();
+    (result, PEAK.load(Ordering::SeqCst) - baseline)
+}
+
+/// Maps a fixture read-only, so that multi-MB emails do not count as heap allocations.
+pub fn map_fixture(path: &Path) -> memmap2::Mmap {
+    let file = File::open(path).unwrap_or_else(|e| panic!("cannot open {:?}: {}", path, e));
+    // Safety: fixtures are not modified while the tests run.
+    unsafe { memmap2::Mmap::map(&file) }.unwrap_or_else(|e| panic!("cannot map {:?}: {}", path, e))
+}
+
+/// A DKIM-signed-looking email whose body has about `body_bytes` bytes of base64 lines, like an
+/// inline image. The signature is not valid; it only has to canonicalize.
+pub fn synthetic_large_email(body_bytes: usize) -> String {
+    let line = "QUJDREVGR0hJSktMTU5PUFFSU1RVVldYWVphYmNkZWZnaGlqa2xtbm9wcXJzdHV2d3h5ejAxMjM0\r\n";
+    let mut email = String::with_capacity(body_bytes + 1024);
+    email.push_str(
+        "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=gmail.com; s=20230601;\r\n",
+    );
+    email.push_str("\th=from:to:subject; bh=Zm9v; b=YmFy\r\n");
+    email.push_str("From: Alice <alice@gmail.com>\r\n");
+    email.push_str("To: bob@example.com\r\n");
+    email.push_str("Subject: Send 1 ETH to bob@example.com\r\n");
+    email.push_str("\r\n");
+    while email.len() < body_bytes {
+        email.push_str(line);
+    }
+    email
+}
@@ -0,0 +1,60 @@
+//! Smoke tests for the principal feature combinations:
+//!
+//! - `cargo test --no-default-features --test feature_matrix` (offline core only)
+//! - `cargo test --no-default-features --features dns --test feature_matrix`
+//! - `cargo test --no-default-features --features body --test feature_matrix`
+//! - `cargo test --test feature_matrix` (default, i.e. `node`)
+//!
+//! The `Features` workflow checks that every feature builds on its own.
+use relayer_utils::*;
+
+#[test]
+fn core_items_are_always_available() {
+    let _ = generate_email_auth_input_from_canonical;
+    let _ = generate_email_auth_input_from_parsed_email;
+    let _ = extract_indexes;
+    let _ = InputGenerationOptions::default();
+    let padded_email_addr = PaddedEmailAddr::from_email_addr("alice@gmail.com");
+    assert_eq!(padded_email_addr.padded_bytes.len(), MAX_EMAIL_ADDR_BYTES);
+}
+
+#[test]
+fn platform_info_reports_the_feature_set() {
+    let info = platform_info();
+    assert_eq!(info["features"]["dns"], cfg!(feature = "dns"));
+    assert_eq!(info["features"]["async_runtime"], cfg!(feature = "async-runtime"));
+    assert_eq!(info["features"]["dnssec"], cfg!(feature = "dnssec"));
+    assert_eq!(info["features"]["body"], cfg!(feature = "body"));
+    assert_eq!(info["features"]["node"], cfg!(feature = "node"));
+    assert_eq!(info["os"], std::env::consts::OS);
+}
+
+#[cfg(feature = "dns")]
+#[test]
+fn dns_items_are_available() {
+    let _ = ParsedEmail::new_from_raw_email;
+    let _ = generate_email_auth_input;
+    let _ = generate_email_auth_input_with_options;
+}
+
+#[cfg(feature = "body")]
+#[test]
+fn body_items_are_available() {
+    let _ = body_part_sizes;
+    let _ = check_body_hash;
+    let _ = find_in_body;
+}
+
+#[cfg(all(feature = "dns", feature = "body"))]
+#[test]
+fn dns_body_items_are_available() {
+    let _ = verify_dkim;
+    let _ = verify_body_contains;
+}
+
+#[cfg(feature = "node")]
+#[test]
+fn node_items_are_available() {
+    let _ = generate_email_auth_input_node;
+    let _ = parse_email_node;
+}
@@ -0,0 +1,141 @@
+//! Large-email tier: multi-MB emails through stripping, canonicalization and input generation,
+//! within wall-clock and peak-allocation ceilings. Ignored by default; run with
+//! `cargo test --release --test large -- --ignored`.
+//!
+//! Fixtures are the `.eml` files of `$RELAYER_UTILS_LARGE_FIXTURES` when set, otherwise a
+//! synthetic 20 MB email written to a temporary file, removed when the test ends. Both are
+//! memory-mapped, so the fixture itself does not count towards the allocation ceiling.
+mod common;
+
+use common::{map_fixture, peak_allocation_of, synthetic_large_email, PeakTracking};
+use relayer_utils::*;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[global_allocator]
+static ALLOCATOR: PeakTracking = PeakTracking;
+
+const SYNTHETIC_BODY_BYTES: usize = 20 << 20;
+/// Per stage, in release builds. Generous enough for a loaded CI machine but far below what a
+/// quadratic pass over 20 MB takes.
+const STAGE_CEILING: Duration = Duration::from_secs(5);
+/// Peak heap usage of a stage, as a multiple of the email size.
+const ALLOCATION_FACTOR: usize = 4;
+/// Peak heap usage of input generation, which checks the body hash: the circuit input holds
+/// every padded body byte as a decimal string, a `String` of about 27 heap bytes, before the
+/// JSON adds 4 more.
+const INPUT_ALLOCATION_FACTOR: usize = 48;
+
+/// Removes the synthetic fixture when dropped, even when an assertion fails first.
+struct TempFixture(PathBuf);
+
+impl Drop for TempFixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// The fixture paths, and the guard of the synthetic fixture when one was written.
+fn fixtures() -> (Vec<PathBuf>, Option<TempFixture>) {
+    if let Ok(dir) = std::env::var("RELAYER_UTILS_LARGE_FIXTURES") {
+        let mut paths = std::fs::read_dir(&dir)
+            .unwrap_or_else(|e| panic!("cannot read {}: {}", dir, e))
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "eml"))
+            .collect::<Vec<_>>();
+        paths.sort();
+        return (paths, None);
+    }
+    let path = std::env::temp_dir().join(format!("relayer-utils-large-{}.eml", std::process::id()));
+    let guard = TempFixture(path.clone());
+    std::fs::write(&path, synthetic_large_email(SYNTHETIC_BODY_BYTES)).unwrap();
+    (vec![path], Some(guard))
+}
+
+/// Runs `f`, checking its duration and peak allocation against the ceilings for an input of
+/// `input_len` bytes.
+fn within_ceilings<T, F: FnOnce() -> T>(stage: &str, input_len: usize, f: F) -> T {
+    within_ceilings_of(stage, input_len, ALLOCATION_FACTOR, f)
+}
+
+/// `within_ceilings` with a peak allocation of `allocation_factor` times the input size.
+fn within_ceilings_of<T, F: FnOnce() -> T>(
+    stage: &str,
+    input_len: usize,
+    allocation_factor: usize,
+    f: F,
+) -> T {
+    let start = Instant::now();
+    let (result, peak) = peak_allocation_of(f);
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed < STAGE_CEILING,
+        "{} took {:?} for {} bytes",
+        stage,
+        elapsed,
+        input_len
+    );
+    assert!(
+        peak <= allocation_factor * input_len.max(1 << 20),
+        "{} peaked at {} bytes for {} bytes",
+        stage,
+        peak,
+        input_len
+    );
+    result
+}
+
+#[test]
+#[ignore]
+fn large_emails_within_ceilings() {
+    let (paths, _guard) = fixtures();
+    for path in paths {
+        let fixture = map_fixture(&path);
+        let raw_email = std::str::from_utf8(&fixture).unwrap();
+        let len = raw_email.len();
+
+        let (raw_email, _) = within_ceilings("strip_protocol_artifacts", len, || {
+            strip_protocol_artifacts(raw_email)
+        });
+        let parsed_email = canonicalize(&raw_email, len);
+        // The check finds the signature by the DKIM-Signature the canonicalized header ends with.
+        #[cfg(feature = "dns")]
+        within_ceilings("check_from_header_coverage", len, || {
+            check_from_header_coverage(&raw_email, parsed_email.canonicalized_header()).unwrap()
+        });
+        // The body is part of the input, so that it is padded and hashed at full size.
+        let options = InputGenerationOptions {
+            ignore_body_hash: false,
+            max_body_length: sha256_padded_len(parsed_email.canonicalized_body().len()),
+            ..InputGenerationOptions::default()
+        };
+        let factor = INPUT_ALLOCATION_FACTOR;
+        let input = within_ceilings_of("generate_email_auth_input", len, factor, || {
+            let account_code = fixture_account_code();
+            generate_email_auth_input_from_parsed_email(&parsed_email, &account_code, &options)
+                .unwrap()
+        });
+        assert!(input.contains("padded_header"), "{:?}", path);
+        assert!(input.contains("padded_body"), "{:?}", path);
+    }
+}
+
+#[cfg(feature = "dns")]
+fn canonicalize(raw_email: &str, len: usize) -> ParsedEmail {
+    let (header, body, signature) = within_ceilings("canonicalize_signed_email", len, || {
+        cfdkim::canonicalize_signed_email(raw_email.as_bytes()).unwrap()
+    });
+    assert!(body.len() <= len && !signature.is_empty());
+    // The key of the fixture is not known, so the header is signed again with a fixture key.
+    fixture_signed_email(
+        std::str::from_utf8(&header).unwrap(),
+        std::str::from_utf8(&body).unwrap(),
+    )
+}
+
+/// Without `dns` there is no canonicalizer; the header and body are split as they are.
+#[cfg(not(feature = "dns"))]
+fn canonicalize(raw_email: &str, _len: usize) -> ParsedEmail {
+    let (header, body) = raw_email.split_once("\r\n\r\n").unwrap();
+    fixture_signed_email(&format!("{}\r\n", header), body)
+}
@@ -0,0 +1,25 @@
+//! Shuts the shared runtime down and brings it back. It is its own test binary: while the runtime
+//! is shut down, every other test that needs it would fail.
+#![cfg(feature = "async-runtime")]
+
+use relayer_utils::*;
+use std::sync::Arc;
+
+#[test]
+fn shutdown_and_reinit_runtime() {
+    let rt = shared_runtime().unwrap();
+    assert_eq!(rt.block_on(async { 1 + 1 }), 2);
+    drop(rt);
+
+    shutdown_runtime();
+    let err = shared_runtime().unwrap_err();
+    assert_eq!(err.to_string(), SHUT_DOWN_ERROR);
+
+    assert_eq!(init_library().unwrap_err().to_string(), SHUT_DOWN_ERROR);
+    reinit_runtime();
+    let rt = shared_runtime().unwrap();
+    assert_eq!(rt.block_on(async { 2 + 2 }), 4);
+    init_library().unwrap();
+    init_library().unwrap();
+    assert!(Arc::ptr_eq(&rt, &shared_runtime().unwrap()));
+}
@@ -0,0 +1,27 @@
+//! Peak allocation of `to_json_with_capacity` against `serde_json::to_string`.
+use relayer_utils::to_json_with_capacity;
+mod common;
+
+use common::{peak_allocation_of, PeakTracking};
+
+#[global_allocator]
+static ALLOCATOR: PeakTracking = PeakTracking;
+
+#[test]
+fn presized_serialization_lowers_peak_allocation() {
+    // Shaped like a large padded header: one short decimal string per byte.
+    let padded_header = (0..1 << 20)
+        .map(|i| (i % 256).to_string())
+        .collect::<Vec<_>>();
+    let hint = padded_header.iter().map(|s| s.len() + 3).sum::<usize>() + 2;
+
+    let (_, two_phase) = peak_allocation_of(|| serde_json::to_string(&padded_header).unwrap());
+    let (_, presized) =
+        peak_allocation_of(|| to_json_with_capacity(&padded_header, hint).unwrap());
+    assert!(
+        presized * 4 < two_phase * 3,
+        "presized peak {} is not meaningfully below two-phase peak {}",
+        presized,
+        two_phase
+    );
+}